@@ -0,0 +1,721 @@
+use auth_database::entities::api_keys::{ApiKeysRepository, ApiKeysRepositoryExt};
+use auth_database::entities::credential_roles::{
+    CreateCredentialRolesDAO, CredentialRolesBy, CredentialRolesDAO, CredentialRolesRepository,
+    CredentialRolesWhere,
+};
+use auth_database::entities::credentials::{
+    CredentialsBy, CredentialsDAO, CredentialsField, CredentialsRepository,
+};
+use auth_database::entities::sessions::{
+    SessionsBy, SessionsDAO, SessionsField, SessionsRepository, SessionsRepositoryExt,
+};
+use auth_database::unit_of_work::unit_of_work;
+use auth_token::{scope, Revocation, REVOCATIONS_CHANNEL};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use core_database::entities::history::{acting_as, Audited};
+use core_database::entities::movies::{
+    CreateMovieDAO, MovieBy, MovieField, MovieRepository, UpdateMovieDAO,
+};
+use core_database::entities::users::{UserBy, UserField, UserRepository, UserRepositoryExt};
+use core_database::query::{Direction, Filter, Query};
+use core_database::router::{DatabaseConfig, DatabaseRouter};
+use core_database::traits::{DatabaseError, EntityRepository};
+use core_database::types::{DateTime, Utc, Uuid};
+use grpc_interfaces::auth::CreateCredentialsRequest;
+use redis::AsyncCommands;
+use rustflix_core::auth_gateway::{AuthGateway, AuthGatewayConfig, TonicAuthGateway};
+use rustflix_core::catalog::{self, Format, ImportReport};
+use rustflix_core::repository::PgCoreRepository;
+use rustflix_core::service::Core;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+mod output;
+
+/// Movies loaded by `seed` when no file is given
+const SEED_MOVIES: &str = include_str!("../seed/movies.jsonl");
+
+/// Operates Rustflix: accounts, roles, sessions, the catalog and the databases
+#[derive(Parser, Debug)]
+#[command(name = "rustflix-admin")]
+struct Cli {
+    #[command(flatten)]
+    connections: Connections,
+
+    /// Prints JSON instead of tab separated text, for scripts
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Operator the changes to users and movies are recorded as made by, e.g. `ops:ana`
+    #[arg(long, global = true, env = "ADMIN_ACTOR")]
+    actor: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Only the ones a command talks to are required
+#[derive(Args, Debug)]
+struct Connections {
+    /// Core database URL, for users and movies
+    #[arg(long, env = "GRAPHQL_DATABASE_URL")]
+    core_database_url: Option<String>,
+
+    /// Auth database URL, for credentials, roles and sessions
+    #[arg(long, env = "AUTH_POSTGRES_URL")]
+    auth_database_url: Option<String>,
+
+    /// Auth service gRPC endpoints, comma separated, for creating accounts
+    #[arg(long, env = "GRAPHQL_AUTH_GRPC_PORT", value_delimiter = ',')]
+    auth_grpc_endpoints: Vec<String>,
+
+    /// Redis the services hear revocations on. Without it revoked sessions
+    /// are trusted until the session caches expire.
+    #[arg(long, env = "REDIS_SESSION_URL")]
+    redis_url: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Catalog users and their credentials
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Sign-in credentials, a user's shares its id
+    #[command(subcommand)]
+    Credentials(CredentialsCommand),
+    /// Privileged scopes held by a credential, however it signs in
+    #[command(subcommand)]
+    Roles(RolesCommand),
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+    #[command(subcommand)]
+    Movies(MoviesCommand),
+    /// Applies the pending migrations of the databases given
+    Migrate,
+    /// Loads demo movies, or the ones of a catalog file, updating the titles taken
+    Seed {
+        /// `.csv` or `.jsonl`, the built-in movies when missing
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum UsersCommand {
+    /// Creates the credential through the auth service, then the user
+    Create {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        name: String,
+        /// e.g. `1990-01-31T00:00:00Z`
+        #[arg(long)]
+        birthday: DateTime<Utc>,
+    },
+    List {
+        #[command(flatten)]
+        page: Page,
+        /// Deactivated users too
+        #[arg(long)]
+        all: bool,
+    },
+    /// Deactivates the user and its credential, signing it out everywhere
+    Deactivate { id: Uuid },
+}
+
+#[derive(Subcommand, Debug)]
+enum CredentialsCommand {
+    /// Creates a credential without a user, e.g. for a partner that only uses API keys
+    Create {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        password: String,
+    },
+    List {
+        #[command(flatten)]
+        page: Page,
+        /// Deactivated credentials too
+        #[arg(long)]
+        all: bool,
+    },
+    /// Signs the credential out everywhere and revokes its API keys, its user is kept
+    Deactivate { id: Uuid },
+}
+
+#[derive(Subcommand, Debug)]
+enum RolesCommand {
+    /// Grants roles to a credential, the ones it already holds are kept
+    Grant {
+        credential_id: Uuid,
+        #[arg(long = "role", required = true)]
+        roles: Vec<Role>,
+    },
+    /// Roles held by a credential
+    List { credential_id: Uuid },
+    /// Takes roles away from a credential, API keys lose the matching scopes with them
+    Revoke {
+        credential_id: Uuid,
+        #[arg(long = "role", required = true)]
+        roles: Vec<Role>,
+    },
+}
+
+/// Named after the scope it unlocks, everyone can read movies already
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Admin,
+    MoviesWrite,
+    UsersRead,
+}
+
+impl Role {
+    fn scope(self) -> &'static str {
+        match self {
+            Role::Admin => scope::ADMIN,
+            Role::MoviesWrite => scope::MOVIES_WRITE,
+            Role::UsersRead => scope::USERS_READ,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionsCommand {
+    /// Sessions of a credential, newest first
+    List {
+        credential_id: Uuid,
+        /// Revoked and expired sessions too
+        #[arg(long)]
+        all: bool,
+    },
+    /// Signs a session out
+    Revoke { session_id: Uuid },
+    /// Signs every session of a credential out, its API keys keep working
+    RevokeAll { credential_id: Uuid },
+}
+
+#[derive(Subcommand, Debug)]
+enum MoviesCommand {
+    Create {
+        #[arg(long)]
+        title: String,
+        #[arg(long)]
+        description: String,
+        #[arg(long = "genre")]
+        genres: Vec<String>,
+        #[arg(long = "cast")]
+        cast_members: Vec<String>,
+        /// Listed in the catalog but can't be watched yet
+        #[arg(long)]
+        unavailable: bool,
+    },
+    Get {
+        id: Uuid,
+    },
+    List {
+        #[command(flatten)]
+        page: Page,
+        /// Only the titles containing it
+        #[arg(long)]
+        title: Option<String>,
+    },
+    /// Changes the title or the description, unless the movie moved past `--version`
+    Update {
+        id: Uuid,
+        #[arg(long)]
+        title: Option<String>,
+        #[arg(long)]
+        description: Option<String>,
+        /// Version the change is based on, as printed by `get`
+        #[arg(long)]
+        version: i64,
+    },
+    Delete {
+        id: Uuid,
+    },
+}
+
+#[derive(Args, Debug)]
+struct Page {
+    #[arg(long, default_value_t = 0)]
+    offset: u32,
+    #[arg(long, default_value_t = 50)]
+    limit: u32,
+}
+
+impl Connections {
+    async fn core_database(&self) -> Result<DatabaseRouter, Box<dyn Error>> {
+        let url = self
+            .core_database_url
+            .as_deref()
+            .ok_or("--core-database-url or GRAPHQL_DATABASE_URL is required")?;
+        Ok(DatabaseConfig::new(url).connect().await?)
+    }
+
+    async fn auth_database(&self) -> Result<DatabaseRouter, Box<dyn Error>> {
+        let url = self
+            .auth_database_url
+            .as_deref()
+            .ok_or("--auth-database-url or AUTH_POSTGRES_URL is required")?;
+        Ok(DatabaseConfig::new(url).connect().await?)
+    }
+
+    fn auth_gateway(&self) -> Result<TonicAuthGateway, Box<dyn Error>> {
+        if self.auth_grpc_endpoints.is_empty() {
+            return Err("--auth-grpc-endpoints or GRAPHQL_AUTH_GRPC_PORT is required".into());
+        }
+        Ok(TonicAuthGateway::new(AuthGatewayConfig::new(
+            self.auth_grpc_endpoints.clone(),
+        ))?)
+    }
+
+    /// Tells the services to drop the revoked sessions they cached, like the auth service does
+    async fn announce(&self, revocation: Revocation) {
+        let Some(redis_url) = &self.redis_url else {
+            eprintln!(
+                "no redis url, {:?} is only seen once the session caches expire",
+                revocation
+            );
+            return;
+        };
+        let result: redis::RedisResult<()> = async {
+            let client = redis::Client::open(redis_url.as_str())?;
+            let mut connection = client.get_async_connection().await?;
+            connection
+                .publish(REVOCATIONS_CHANNEL, revocation.to_message())
+                .await
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("could not publish revocation {:?}: {}", revocation, e);
+        }
+    }
+}
+
+/// Who the history records a change as made by, changes can't be anonymous
+fn required_actor(actor: Option<&str>) -> Result<String, Box<dyn Error>> {
+    match actor.map(str::trim) {
+        Some(actor) if !actor.is_empty() => Ok(actor.to_string()),
+        _ => Err("--actor or ADMIN_ACTOR is required to change users and movies".into()),
+    }
+}
+
+fn credential_json(credential: &CredentialsDAO) -> Value {
+    json!({
+        "id": credential.id.to_string(),
+        "email": credential.email,
+        "active": credential.active,
+    })
+}
+
+fn session_json(session: &SessionsDAO) -> Value {
+    json!({
+        "id": session.id.to_string(),
+        "credential_id": session.credential_id.to_string(),
+        "created_at": session.created_at.to_rfc3339(),
+        "expires_at": session.expires_at.to_rfc3339(),
+        "active": session.active,
+        "device_label": session.device_label,
+        "ip_address": session.ip_address,
+    })
+}
+
+fn role_json(role: &CredentialRolesDAO) -> Value {
+    json!({
+        "credential_id": role.credential_id.to_string(),
+        "role": role.role,
+        "created_at": role.created_at.to_rfc3339(),
+    })
+}
+
+fn report_json(report: &ImportReport) -> Value {
+    json!({
+        "inserted": report.inserted,
+        "updated": report.updated,
+        "unchanged": report.unchanged,
+        "skipped": report.errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+        "dry_run": report.dry_run,
+    })
+}
+
+/// Same as the auth service closing an account, all or nothing
+async fn close_credential(auth: &DatabaseRouter, credential_id: Uuid) -> Result<(), DatabaseError> {
+    unit_of_work(auth.write(), |tx| {
+        Box::pin(async move {
+            SessionsRepository::revoke_all(&mut *tx, credential_id, None).await?;
+            ApiKeysRepository::revoke_all(&mut *tx, credential_id).await?;
+            CredentialsRepository::delete(&mut *tx, CredentialsBy::Id(credential_id)).await?;
+            Ok(())
+        })
+    })
+    .await
+}
+
+async fn users(
+    connections: &Connections,
+    actor: Option<&str>,
+    command: UsersCommand,
+) -> Result<Value, Box<dyn Error>> {
+    match command {
+        UsersCommand::Create {
+            email,
+            password,
+            name,
+            birthday,
+        } => {
+            let core = Core::new(
+                connections.auth_gateway()?,
+                PgCoreRepository::new(connections.core_database().await?),
+            );
+            let user = core
+                .create_account(email, password, name, birthday, None)
+                .await?;
+            Ok(json!({
+                "id": user.id.to_string(),
+                "name": user.name,
+                "birthday": user.birthday.to_rfc3339(),
+                "active": user.active,
+                "version": user.version,
+            }))
+        }
+        UsersCommand::List { page, all } => {
+            let db = connections.core_database().await?;
+            let mut query = Query::new()
+                .order_by(UserField::CreatedAt, Direction::Asc)
+                .page(page.offset, page.limit);
+            if !all {
+                query = query.filter(Filter::eq(UserField::Active, true));
+            }
+            let users = UserRepository::get_all(db.read(), query).await?;
+            Ok(users.iter().map(Audited::to_json).collect())
+        }
+        UsersCommand::Deactivate { id } => {
+            let actor = required_actor(actor)?;
+            let core = connections.core_database().await?;
+            let auth = connections.auth_database().await?;
+            // Same order as `Core::close_account`, the user first and reactivated
+            // if the credential can't be closed, so it's never left active and locked out.
+            let deactivate = UserRepository::delete(core.write(), UserBy::Id(id));
+            let user = acting_as(actor.clone(), deactivate).await?;
+            if let Err(e) = close_credential(&auth, id).await {
+                let reactivate = UserRepository::reactivate(core.write(), UserBy::Id(id));
+                if let Err(compensation) = acting_as(actor, reactivate).await {
+                    eprintln!(
+                        "user {} left deactivated with an open credential: {:?}",
+                        id, compensation
+                    );
+                }
+                return Err(e.into());
+            }
+            connections
+                .announce(Revocation::Credential(id.to_string()))
+                .await;
+            Ok(user.to_json())
+        }
+    }
+}
+
+async fn credentials(
+    connections: &Connections,
+    command: CredentialsCommand,
+) -> Result<Value, Box<dyn Error>> {
+    match command {
+        CredentialsCommand::Create { email, password } => {
+            let credential = connections
+                .auth_gateway()?
+                .create_credential(CreateCredentialsRequest {
+                    email: email.clone(),
+                    password,
+                    idempotency_key: String::new(),
+                })
+                .await?;
+            Ok(json!({ "id": credential.user_id, "email": email, "active": true }))
+        }
+        CredentialsCommand::List { page, all } => {
+            let db = connections.auth_database().await?;
+            let mut query = Query::new()
+                .order_by(CredentialsField::Email, Direction::Asc)
+                .page(page.offset, page.limit);
+            if !all {
+                query = query.filter(Filter::eq(CredentialsField::Active, true));
+            }
+            let credentials = CredentialsRepository::get_all(db.read(), query).await?;
+            Ok(credentials.iter().map(credential_json).collect())
+        }
+        CredentialsCommand::Deactivate { id } => {
+            let db = connections.auth_database().await?;
+            close_credential(&db, id).await?;
+            connections
+                .announce(Revocation::Credential(id.to_string()))
+                .await;
+            let credential = CredentialsRepository::get(db.write(), CredentialsBy::Id(id)).await?;
+            Ok(credential_json(&credential))
+        }
+    }
+}
+
+async fn roles(connections: &Connections, command: RolesCommand) -> Result<Value, Box<dyn Error>> {
+    let db = connections.auth_database().await?;
+    let scopes = |roles: Vec<Role>| {
+        let mut scopes: Vec<String> = roles.iter().map(|r| r.scope().to_string()).collect();
+        scopes.sort();
+        scopes.dedup();
+        scopes
+    };
+    let credential_id = match command {
+        RolesCommand::Grant {
+            credential_id,
+            roles,
+        } => {
+            let credential =
+                CredentialsRepository::get(db.read(), CredentialsBy::Id(credential_id)).await?;
+            if !credential.active {
+                return Err(format!("credential {} is deactivated", credential_id).into());
+            }
+            let roles = scopes(roles);
+            unit_of_work(db.write(), |tx| {
+                Box::pin(async move {
+                    for role in roles {
+                        let key = CredentialRolesBy::Role {
+                            credential_id,
+                            role: role.clone(),
+                        };
+                        if CredentialRolesRepository::try_get(&mut *tx, key)
+                            .await?
+                            .is_none()
+                        {
+                            CredentialRolesRepository::insert(
+                                &mut *tx,
+                                CreateCredentialRolesDAO {
+                                    credential_id,
+                                    role,
+                                },
+                            )
+                            .await?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
+            // Cached sessions carry the roles they were validated with
+            connections
+                .announce(Revocation::Credential(credential_id.to_string()))
+                .await;
+            credential_id
+        }
+        RolesCommand::List { credential_id } => credential_id,
+        RolesCommand::Revoke {
+            credential_id,
+            roles,
+        } => {
+            CredentialsRepository::get(db.read(), CredentialsBy::Id(credential_id)).await?;
+            let roles = scopes(roles);
+            unit_of_work(db.write(), |tx| {
+                Box::pin(async move {
+                    for role in roles {
+                        CredentialRolesRepository::delete(
+                            &mut *tx,
+                            CredentialRolesBy::Role {
+                                credential_id,
+                                role,
+                            },
+                        )
+                        .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
+            connections
+                .announce(Revocation::Credential(credential_id.to_string()))
+                .await;
+            credential_id
+        }
+    };
+
+    // The primary, replicas may not have seen the change yet
+    let roles = CredentialRolesRepository::get_all(
+        db.write(),
+        CredentialRolesWhere::CredentialId(credential_id),
+    )
+    .await?;
+    Ok(roles.iter().map(role_json).collect())
+}
+
+async fn sessions(
+    connections: &Connections,
+    command: SessionsCommand,
+) -> Result<Value, Box<dyn Error>> {
+    let db = connections.auth_database().await?;
+    match command {
+        SessionsCommand::List { credential_id, all } => {
+            let mut filter = Filter::eq(SessionsField::CredentialId, credential_id);
+            if !all {
+                filter = filter
+                    .and(Filter::eq(SessionsField::Active, true))
+                    .and(Filter::gt(SessionsField::ExpiresAt, Utc::now()));
+            }
+            let query = Query::new()
+                .filter(filter)
+                .order_by(SessionsField::CreatedAt, Direction::Desc);
+            let sessions = SessionsRepository::get_all(db.read(), query).await?;
+            Ok(sessions.iter().map(session_json).collect())
+        }
+        SessionsCommand::Revoke { session_id } => {
+            let session =
+                SessionsRepository::delete(db.write(), SessionsBy::Id(session_id)).await?;
+            connections
+                .announce(Revocation::Session(session_id.to_string()))
+                .await;
+            Ok(session_json(&session))
+        }
+        SessionsCommand::RevokeAll { credential_id } => {
+            let revoked = SessionsRepository::revoke_all(db.write(), credential_id, None).await?;
+            connections
+                .announce(Revocation::Credential(credential_id.to_string()))
+                .await;
+            Ok(json!({ "revoked": revoked }))
+        }
+    }
+}
+
+async fn movies(
+    connections: &Connections,
+    actor: Option<&str>,
+    command: MoviesCommand,
+) -> Result<Value, Box<dyn Error>> {
+    let db = connections.core_database().await?;
+    let movie = match command {
+        MoviesCommand::Create {
+            title,
+            description,
+            genres,
+            cast_members,
+            unavailable,
+        } => {
+            let insert = MovieRepository::insert(
+                db.write(),
+                CreateMovieDAO {
+                    title,
+                    description,
+                    genres,
+                    cast_members,
+                    available: !unavailable,
+                },
+            );
+            acting_as(required_actor(actor)?, insert).await?
+        }
+        MoviesCommand::Get { id } => MovieRepository::get(db.read(), MovieBy::Id(id)).await?,
+        MoviesCommand::List { page, title } => {
+            let mut query = Query::new()
+                .order_by(MovieField::Title, Direction::Asc)
+                .page(page.offset, page.limit);
+            if let Some(title) = title {
+                query = query.filter(Filter::contains(MovieField::Title, title));
+            }
+            let movies = MovieRepository::get_all(db.read(), query).await?;
+            return Ok(movies.iter().map(Audited::to_json).collect());
+        }
+        MoviesCommand::Update {
+            id,
+            title,
+            description,
+            version,
+        } => {
+            let actor = required_actor(actor)?;
+            // Read from the primary, a replica may not have the version being updated yet
+            let current = MovieRepository::get(db.write(), MovieBy::Id(id)).await?;
+            let update = MovieRepository::update(
+                db.write(),
+                MovieBy::Id(id),
+                UpdateMovieDAO {
+                    title: title.unwrap_or(current.title),
+                    description: description.unwrap_or(current.description),
+                    version,
+                },
+            );
+            acting_as(actor, update).await?
+        }
+        MoviesCommand::Delete { id } => {
+            let delete = MovieRepository::delete(db.write(), MovieBy::Id(id));
+            acting_as(required_actor(actor)?, delete).await?
+        }
+    };
+    Ok(movie.to_json())
+}
+
+async fn migrate(connections: &Connections) -> Result<Value, Box<dyn Error>> {
+    if connections.core_database_url.is_none() && connections.auth_database_url.is_none() {
+        return Err("--core-database-url or --auth-database-url is required".into());
+    }
+    let mut migrated = Vec::new();
+    if connections.core_database_url.is_some() {
+        core_database::migrate(connections.core_database().await?.write()).await?;
+        migrated.push("core");
+    }
+    if connections.auth_database_url.is_some() {
+        auth_database::migrate(connections.auth_database().await?.write()).await?;
+        migrated.push("auth");
+    }
+    Ok(json!({ "migrated": migrated }))
+}
+
+async fn seed(
+    connections: &Connections,
+    actor: Option<&str>,
+    file: Option<PathBuf>,
+) -> Result<Value, Box<dyn Error>> {
+    let actor = required_actor(actor)?;
+    let (format, source): (Format, Box<dyn Read>) = match file {
+        Some(file) => {
+            let format = Format::from_path(&file)
+                .ok_or_else(|| format!("can't tell the format of {}", file.display()))?;
+            (format, Box::new(File::open(file)?))
+        }
+        None => (Format::JsonLines, Box::new(SEED_MOVIES.as_bytes())),
+    };
+    let repository = PgCoreRepository::new(connections.core_database().await?);
+    let import = catalog::import(&repository, format, source, false);
+    let report = acting_as(actor, import).await?;
+    Ok(report_json(&report))
+}
+
+async fn run(cli: Cli) -> Result<Value, Box<dyn Error>> {
+    let connections = &cli.connections;
+    let actor = cli.actor.as_deref();
+    match cli.command {
+        Command::Users(command) => users(connections, actor, command).await,
+        Command::Credentials(command) => credentials(connections, command).await,
+        Command::Roles(command) => roles(connections, command).await,
+        Command::Sessions(command) => sessions(connections, command).await,
+        Command::Movies(command) => movies(connections, actor, command).await,
+        Command::Migrate => migrate(connections).await,
+        Command::Seed { file } => seed(connections, actor, file).await,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let args = Cli::parse();
+    let json = args.json;
+
+    match run(args).await {
+        Ok(value) => println!("{}", output::render(&value, json)),
+        Err(e) => {
+            if json {
+                println!("{}", json!({ "error": e.to_string() }));
+            } else {
+                eprintln!("{}", e);
+            }
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use serde_json::Value;
+
+/// Renders what a command returned, as JSON for scripts or as tab separated text
+pub fn render(value: &Value, json: bool) -> String {
+    if json {
+        return value.to_string();
+    }
+    match value {
+        Value::Array(rows) => table(rows),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| format!("{}\t{}", key, cell(value)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        other => cell(other),
+    }
+}
+
+/// A header with the keys of the first row, then a line per row
+fn table(rows: &[Value]) -> String {
+    let Some(Value::Object(first)) = rows.first() else {
+        return rows.iter().map(cell).collect::<Vec<_>>().join("\n");
+    };
+    let columns: Vec<&String> = first.keys().collect();
+    let mut lines = vec![columns
+        .iter()
+        .map(|c| c.as_str())
+        .collect::<Vec<_>>()
+        .join("\t")];
+    for row in rows {
+        lines.push(
+            columns
+                .iter()
+                .map(|column| row.get(column.as_str()).map(cell).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join("\t"),
+        );
+    }
+    lines.join("\n")
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let movies = json!([
+            {"title": "Ran", "genres": ["Drama", "War"], "version": 1},
+            {"title": "Heat", "genres": [], "version": 2, "extra": true},
+        ]);
+        assert_eq!(
+            render(&movies, false),
+            "genres\ttitle\tversion\nDrama,War\tRan\t1\n\tHeat\t2"
+        );
+        assert_eq!(render(&movies, true), movies.to_string());
+
+        let key = json!({"id": "1", "expires_at": null});
+        assert_eq!(render(&key, false), "expires_at\t\nid\t1");
+        assert_eq!(render(&json!([]), false), "");
+        assert_eq!(render(&json!("done"), false), "done");
+    }
+}
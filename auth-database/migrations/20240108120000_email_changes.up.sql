@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS email_changes (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    credential_id UUID NOT NULL,
+    email VARCHAR NOT NULL,
+    active BOOLEAN DEFAULT TRUE,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
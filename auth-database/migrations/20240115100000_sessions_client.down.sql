@@ -0,0 +1,3 @@
+ALTER TABLE sessions DROP COLUMN IF EXISTS device_label;
+ALTER TABLE sessions DROP COLUMN IF EXISTS ip_address;
+ALTER TABLE sessions DROP COLUMN IF EXISTS user_agent;
\ No newline at end of file
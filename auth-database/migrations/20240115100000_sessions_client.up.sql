@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS user_agent VARCHAR;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS ip_address VARCHAR;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS device_label VARCHAR;
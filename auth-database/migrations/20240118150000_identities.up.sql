@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS identities (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    credential_id UUID NOT NULL,
+    provider VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    email VARCHAR,
+    active BOOLEAN DEFAULT TRUE,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE,
+    CONSTRAINT uq_identities_provider_subject UNIQUE (provider, subject)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    credential_id UUID NOT NULL,
+    name VARCHAR NOT NULL,
+    prefix VARCHAR NOT NULL,
+    key_hash VARCHAR NOT NULL,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    active BOOLEAN DEFAULT TRUE,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE,
+    CONSTRAINT uq_api_keys_prefix UNIQUE (prefix)
+);
@@ -0,0 +1,21 @@
+-- No foreign key on credential_id, the audit trail outlives the credentials it mentions.
+CREATE TABLE IF NOT EXISTS auth_events (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    event_type VARCHAR NOT NULL,
+    credential_id UUID,
+    ip_address VARCHAR,
+    user_agent VARCHAR,
+    outcome VARCHAR NOT NULL,
+    detail VARCHAR
+);
+
+CREATE OR REPLACE FUNCTION auth_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'auth_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER auth_events_append_only
+    BEFORE UPDATE OR DELETE ON auth_events
+    FOR EACH ROW EXECUTE FUNCTION auth_events_append_only();
@@ -0,0 +1 @@
+ALTER TABLE credentials DROP COLUMN IF EXISTS idempotency_key;
\ No newline at end of file
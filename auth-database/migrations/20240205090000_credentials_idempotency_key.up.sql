@@ -0,0 +1,2 @@
+-- Set when the client retries account creation safely, a repeated key returns the credential it created.
+ALTER TABLE credentials ADD COLUMN IF NOT EXISTS idempotency_key VARCHAR UNIQUE;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE sessions DROP COLUMN IF EXISTS identity_provider;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Provider the session was signed in through, a fresh one stands in for the password of sensitive changes.
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS identity_provider VARCHAR;
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS credential_roles;
\ No newline at end of file
@@ -0,0 +1,8 @@
+-- Privileges granted to a credential on top of what every user can do, named after the scope they unlock.
+CREATE TABLE IF NOT EXISTS credential_roles (
+    credential_id UUID NOT NULL,
+    role VARCHAR NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (credential_id, role),
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- False for credentials created by an identity provider sign in, nobody knows their password.
+ALTER TABLE credentials ADD COLUMN IF NOT EXISTS has_password BOOLEAN NOT NULL DEFAULT TRUE;
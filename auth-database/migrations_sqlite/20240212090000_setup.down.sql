@@ -0,0 +1,7 @@
+DROP TABLE IF EXISTS auth_events;
+DROP TABLE IF EXISTS api_keys;
+DROP TABLE IF EXISTS revoked_tokens;
+DROP TABLE IF EXISTS identities;
+DROP TABLE IF EXISTS email_changes;
+DROP TABLE IF EXISTS sessions;
+DROP TABLE IF EXISTS credentials;
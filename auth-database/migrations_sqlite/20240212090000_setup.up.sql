@@ -0,0 +1,90 @@
+-- SQLite counterpart of the Postgres schema in `migrations/`, for local development and demos.
+-- Uuids are stored as blobs and timestamps as RFC 3339 text, both generated by the repositories.
+CREATE TABLE IF NOT EXISTS credentials (
+    id BLOB NOT NULL PRIMARY KEY,
+    email VARCHAR NOT NULL UNIQUE,
+    password VARCHAR NOT NULL,
+    active BOOLEAN DEFAULT TRUE,
+    deactivated_at DATETIME,
+    idempotency_key VARCHAR UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id BLOB NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL,
+    credential_id BLOB NOT NULL,
+    active BOOLEAN DEFAULT TRUE,
+    user_agent VARCHAR,
+    ip_address VARCHAR,
+    device_label VARCHAR,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS email_changes (
+    id BLOB NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL,
+    credential_id BLOB NOT NULL,
+    email VARCHAR NOT NULL,
+    active BOOLEAN DEFAULT TRUE,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS identities (
+    id BLOB NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL,
+    credential_id BLOB NOT NULL,
+    provider VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    email VARCHAR,
+    active BOOLEAN DEFAULT TRUE,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE,
+    CONSTRAINT uq_identities_provider_subject UNIQUE (provider, subject)
+);
+
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti BLOB NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+
+-- `scopes` are space separated, SQLite has no arrays
+CREATE TABLE IF NOT EXISTS api_keys (
+    id BLOB NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL,
+    credential_id BLOB NOT NULL,
+    name VARCHAR NOT NULL,
+    prefix VARCHAR NOT NULL,
+    key_hash VARCHAR NOT NULL,
+    scopes TEXT NOT NULL DEFAULT '',
+    expires_at DATETIME,
+    last_used_at DATETIME,
+    active BOOLEAN DEFAULT TRUE,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE,
+    CONSTRAINT uq_api_keys_prefix UNIQUE (prefix)
+);
+
+-- No foreign key on credential_id, the audit trail outlives the credentials it mentions.
+CREATE TABLE IF NOT EXISTS auth_events (
+    id BLOB NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL,
+    event_type VARCHAR NOT NULL,
+    credential_id BLOB,
+    ip_address VARCHAR,
+    user_agent VARCHAR,
+    outcome VARCHAR NOT NULL,
+    detail VARCHAR
+);
+
+CREATE TRIGGER IF NOT EXISTS auth_events_no_update
+    BEFORE UPDATE ON auth_events
+BEGIN
+    SELECT RAISE(ABORT, 'auth_events is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS auth_events_no_delete
+    BEFORE DELETE ON auth_events
+BEGIN
+    SELECT RAISE(ABORT, 'auth_events is append-only');
+END;
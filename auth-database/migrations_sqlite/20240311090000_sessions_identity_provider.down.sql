@@ -0,0 +1 @@
+ALTER TABLE sessions DROP COLUMN identity_provider;
\ No newline at end of file
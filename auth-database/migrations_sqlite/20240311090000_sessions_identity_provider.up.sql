@@ -0,0 +1 @@
+ALTER TABLE sessions ADD COLUMN identity_provider VARCHAR;
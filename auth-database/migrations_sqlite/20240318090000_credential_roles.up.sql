@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS credential_roles (
+    credential_id BLOB NOT NULL,
+    role VARCHAR NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (credential_id, role),
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE credentials ADD COLUMN has_password BOOLEAN NOT NULL DEFAULT TRUE;
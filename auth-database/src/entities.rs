@@ -1,2 +1,8 @@
+pub mod api_keys;
+pub mod auth_events;
+pub mod credential_roles;
 pub mod credentials;
+pub mod email_changes;
+pub mod identities;
+pub mod revoked_tokens;
 pub mod sessions;
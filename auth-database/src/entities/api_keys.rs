@@ -0,0 +1,309 @@
+use crate::{
+    connection::{Database, Postgres},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Long lived key for service to service and partner access, only its hash is stored.
+/// `prefix` is the public part of the key and is used to look it up.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct ApiKeysDAO {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub credential_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub active: bool,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateApiKeysDAO {
+    pub credential_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateApiKeysDAO {
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApiKeysBy {
+    Id(Uuid),
+    Prefix(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApiKeysWhere {
+    CredentialId(Uuid),
+}
+
+#[derive(Debug)]
+pub struct ApiKeysRepository;
+
+#[async_trait::async_trait]
+pub trait ApiKeysRepositoryExt<Db: Database> {
+    /// Revokes every active key of a credential, returns how many were revoked.
+    async fn revoke_all<'c, E: Executor<'c, Db>>(
+        db: E,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError>;
+}
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Postgres,
+        ApiKeysDAO,
+        CreateApiKeysDAO,
+        UpdateApiKeysDAO,
+        ApiKeysBy,
+        ApiKeysWhere,
+    > for ApiKeysRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateApiKeysDAO,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, ApiKeysDAO>("INSERT INTO api_keys (credential_id, name, prefix, key_hash, scopes, expires_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+            .bind(input.credential_id)
+            .bind(input.name)
+            .bind(input.prefix)
+            .bind(input.key_hash)
+            .bind(input.scopes)
+            .bind(input.expires_at)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: ApiKeysBy,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let missing = not_found("ApiKey", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysBy::Id(uuid) => {
+                sqlx::query_as::<_, ApiKeysDAO>("UPDATE api_keys SET active = false WHERE id = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(uuid)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+            ApiKeysBy::Prefix(prefix) => {
+                sqlx::query_as::<_, ApiKeysDAO>("UPDATE api_keys SET active = false WHERE prefix = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(prefix)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+        }
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: ApiKeysBy,
+        update: UpdateApiKeysDAO,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let missing = not_found("ApiKey", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysBy::Id(uuid) => {
+                sqlx::query_as::<_, ApiKeysDAO>("UPDATE api_keys SET last_used_at = $2 WHERE id = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(uuid)
+                    .bind(update.last_used_at)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+            ApiKeysBy::Prefix(prefix) => {
+                sqlx::query_as::<_, ApiKeysDAO>("UPDATE api_keys SET last_used_at = $2 WHERE prefix = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(prefix)
+                    .bind(update.last_used_at)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+        }
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: ApiKeysBy,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let missing = not_found("ApiKey", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysBy::Id(uuid) => sqlx::query_as::<_, ApiKeysDAO>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+            ApiKeysBy::Prefix(prefix) => sqlx::query_as::<_, ApiKeysDAO>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE prefix = $1 LIMIT 1;",
+            )
+            .bind(prefix)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: ApiKeysBy,
+    ) -> Result<Option<ApiKeysDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+            ApiKeysBy::Prefix(prefix) => sqlx::query_as(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE prefix = $1 LIMIT 1;",
+            )
+            .bind(prefix)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: ApiKeysWhere,
+    ) -> Result<Vec<ApiKeysDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysWhere::CredentialId(uuid) => sqlx::query_as::<_, ApiKeysDAO>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE credential_id = $1 ORDER BY created_at;",
+            )
+            .bind(uuid)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ApiKeysRepositoryExt<Postgres> for ApiKeysRepository {
+    async fn revoke_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query(
+            "UPDATE api_keys SET active = false WHERE credential_id = $1 AND active = true;",
+        )
+        .bind(credential_id)
+        .execute(&mut *conn)
+        .await
+        .map(|r| r.rows_affected())
+        .map_err(DatabaseError::from)
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::api_keys::{
+        ApiKeysBy, ApiKeysRepository, ApiKeysRepositoryExt, ApiKeysWhere, CreateApiKeysDAO,
+        UpdateApiKeysDAO,
+    };
+    use crate::entities::credentials::{CreateCredentialsDAO, CredentialsRepository};
+    use crate::testing::test_pool;
+    use crate::traits::EntityRepository;
+    use database::types::{Utc, Uuid};
+
+    #[tokio::test]
+    async fn test_db() {
+        let pool = test_pool().await;
+
+        let credential = CredentialsRepository::insert(
+            &pool,
+            CreateCredentialsDAO {
+                email: format!("{}@gmail.com", Uuid::new_v4()),
+                password: "hash".to_string(),
+                idempotency_key: None,
+                has_password: true,
+            },
+        )
+        .await
+        .expect("Could not create credential");
+
+        // create
+        let prefix = Uuid::new_v4().simple().to_string();
+        let key = ApiKeysRepository::insert(
+            &pool,
+            CreateApiKeysDAO {
+                credential_id: credential.id,
+                name: "catalog sync".to_string(),
+                prefix: prefix.clone(),
+                key_hash: "hash".to_string(),
+                scopes: vec!["movies:read".to_string()],
+                expires_at: None,
+            },
+        )
+        .await
+        .expect("Could not create api key");
+        assert!(key.active);
+        assert_eq!(key.scopes, vec!["movies:read".to_string()]);
+        assert_eq!(key.last_used_at, None);
+
+        // get
+        let found = ApiKeysRepository::get(&pool, ApiKeysBy::Prefix(prefix.clone()))
+            .await
+            .expect("Api key not found");
+        assert_eq!(key, found);
+
+        // update
+        let now = Utc::now();
+        let updated = ApiKeysRepository::update(
+            &pool,
+            ApiKeysBy::Id(key.id),
+            UpdateApiKeysDAO {
+                last_used_at: Some(now),
+            },
+        )
+        .await
+        .expect("Could not update api key");
+        assert!(updated.last_used_at.is_some());
+
+        // get all
+        let keys = ApiKeysRepository::get_all(&pool, ApiKeysWhere::CredentialId(credential.id))
+            .await
+            .unwrap();
+        assert_eq!(keys.len(), 1);
+
+        // revoke all
+        let revoked = ApiKeysRepository::revoke_all(&pool, credential.id)
+            .await
+            .unwrap();
+        assert_eq!(revoked, 1);
+
+        // delete
+        let deleted = ApiKeysRepository::delete(&pool, ApiKeysBy::Id(key.id))
+            .await
+            .expect("Could not delete api key");
+        assert!(!deleted.active);
+    }
+}
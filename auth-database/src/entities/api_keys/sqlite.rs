@@ -0,0 +1,220 @@
+use super::{
+    ApiKeysBy, ApiKeysDAO, ApiKeysRepository, ApiKeysRepositoryExt, ApiKeysWhere, CreateApiKeysDAO,
+    UpdateApiKeysDAO,
+};
+use crate::{
+    connection::Sqlite,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+/// `ApiKeysDAO` as stored, SQLite has no arrays so `scopes` are space separated like OAuth scopes
+#[derive(sqlx::FromRow)]
+struct ApiKeysRow {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    credential_id: Uuid,
+    name: String,
+    prefix: String,
+    key_hash: String,
+    scopes: String,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    active: bool,
+}
+
+impl From<ApiKeysRow> for ApiKeysDAO {
+    fn from(row: ApiKeysRow) -> Self {
+        Self {
+            id: row.id,
+            created_at: row.created_at,
+            credential_id: row.credential_id,
+            name: row.name,
+            prefix: row.prefix,
+            key_hash: row.key_hash,
+            scopes: row.scopes.split_whitespace().map(str::to_string).collect(),
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            active: row.active,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        ApiKeysDAO,
+        CreateApiKeysDAO,
+        UpdateApiKeysDAO,
+        ApiKeysBy,
+        ApiKeysWhere,
+    > for ApiKeysRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateApiKeysDAO,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let api_key = sqlx::query_as::<_, ApiKeysRow>("INSERT INTO api_keys (id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+            .bind(Uuid::new_v4())
+            .bind(Utc::now())
+            .bind(input.credential_id)
+            .bind(input.name)
+            .bind(input.prefix)
+            .bind(input.key_hash)
+            .bind(input.scopes.join(" "))
+            .bind(input.expires_at)
+            .fetch_one(&mut *tx)
+            .await
+            .map(ApiKeysDAO::from)?;
+        tx.commit().await?;
+        Ok(api_key)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: ApiKeysBy,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let missing = not_found("ApiKey", &key);
+        let mut tx = db.begin().await?;
+        let api_key = match key {
+            ApiKeysBy::Id(uuid) => {
+                sqlx::query_as::<_, ApiKeysRow>("UPDATE api_keys SET active = false WHERE id = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(uuid)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map(ApiKeysDAO::from)
+                    .map_err(missing)?
+            }
+            ApiKeysBy::Prefix(prefix) => {
+                sqlx::query_as::<_, ApiKeysRow>("UPDATE api_keys SET active = false WHERE prefix = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(prefix)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map(ApiKeysDAO::from)
+                    .map_err(missing)?
+            }
+        };
+        tx.commit().await?;
+        Ok(api_key)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: ApiKeysBy,
+        update: UpdateApiKeysDAO,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let missing = not_found("ApiKey", &key);
+        let mut tx = db.begin().await?;
+        let api_key = match key {
+            ApiKeysBy::Id(uuid) => {
+                sqlx::query_as::<_, ApiKeysRow>("UPDATE api_keys SET last_used_at = $2 WHERE id = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(uuid)
+                    .bind(update.last_used_at)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map(ApiKeysDAO::from)
+                    .map_err(missing)?
+            }
+            ApiKeysBy::Prefix(prefix) => {
+                sqlx::query_as::<_, ApiKeysRow>("UPDATE api_keys SET last_used_at = $2 WHERE prefix = $1 RETURNING id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active;")
+                    .bind(prefix)
+                    .bind(update.last_used_at)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map(ApiKeysDAO::from)
+                    .map_err(missing)?
+            }
+        };
+        tx.commit().await?;
+        Ok(api_key)
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: ApiKeysBy,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let missing = not_found("ApiKey", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysBy::Id(uuid) => sqlx::query_as::<_, ApiKeysRow>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map(ApiKeysDAO::from)
+            .map_err(missing),
+            ApiKeysBy::Prefix(prefix) => sqlx::query_as::<_, ApiKeysRow>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE prefix = $1 LIMIT 1;",
+            )
+            .bind(prefix)
+            .fetch_one(&mut *conn)
+            .await
+            .map(ApiKeysDAO::from)
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: ApiKeysBy,
+    ) -> Result<Option<ApiKeysDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysBy::Id(uuid) => sqlx::query_as::<_, ApiKeysRow>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map(|row| row.map(ApiKeysDAO::from))
+            .map_err(DatabaseError::from),
+            ApiKeysBy::Prefix(prefix) => sqlx::query_as::<_, ApiKeysRow>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE prefix = $1 LIMIT 1;",
+            )
+            .bind(prefix)
+            .fetch_optional(&mut *conn)
+            .await
+            .map(|row| row.map(ApiKeysDAO::from))
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: ApiKeysWhere,
+    ) -> Result<Vec<ApiKeysDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            ApiKeysWhere::CredentialId(uuid) => sqlx::query_as::<_, ApiKeysRow>(
+                "SELECT id, created_at, credential_id, name, prefix, key_hash, scopes, expires_at, last_used_at, active FROM api_keys WHERE credential_id = $1 ORDER BY created_at;",
+            )
+            .bind(uuid)
+            .fetch_all(&mut *conn)
+            .await
+            .map(|rows| rows.into_iter().map(ApiKeysDAO::from).collect())
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ApiKeysRepositoryExt<Sqlite> for ApiKeysRepository {
+    async fn revoke_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let revoked = sqlx::query(
+            "UPDATE api_keys SET active = false WHERE credential_id = $1 AND active = true;",
+        )
+        .bind(credential_id)
+        .execute(&mut *tx)
+        .await
+        .map(|r| r.rows_affected())?;
+        tx.commit().await?;
+        Ok(revoked)
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    connection::Postgres,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Security relevant event, e.g. a sign in attempt. The table is append-only.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct AuthEventsDAO {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub event_type: String,
+    pub credential_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub outcome: String,
+    pub detail: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateAuthEventsDAO {
+    pub event_type: String,
+    pub credential_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub outcome: String,
+    pub detail: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateAuthEventsDAO {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthEventsBy {
+    Id(Uuid),
+}
+
+/// Every field narrows the search, `None` matches anything
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AuthEventsFilter {
+    pub event_type: Option<String>,
+    pub credential_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthEventsWhere {
+    /// Newest first
+    Page {
+        filter: AuthEventsFilter,
+        offset: u32,
+        limit: u32,
+    },
+}
+
+#[derive(Debug)]
+pub struct AuthEventsRepository;
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Postgres,
+        AuthEventsDAO,
+        CreateAuthEventsDAO,
+        UpdateAuthEventsDAO,
+        AuthEventsBy,
+        AuthEventsWhere,
+    > for AuthEventsRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateAuthEventsDAO,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, AuthEventsDAO>("INSERT INTO auth_events (event_type, credential_id, ip_address, user_agent, outcome, detail) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail;")
+            .bind(input.event_type)
+            .bind(input.credential_id)
+            .bind(input.ip_address)
+            .bind(input.user_agent)
+            .bind(input.outcome)
+            .bind(input.detail)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: AuthEventsBy,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "auth events are append-only".to_string(),
+        ))
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: AuthEventsBy,
+        _update: UpdateAuthEventsDAO,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "auth events are append-only".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: AuthEventsBy,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        let missing = not_found("AuthEvent", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            AuthEventsBy::Id(uuid) => sqlx::query_as::<_, AuthEventsDAO>(
+                "SELECT id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail FROM auth_events WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: AuthEventsBy,
+    ) -> Result<Option<AuthEventsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            AuthEventsBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail FROM auth_events WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: AuthEventsWhere,
+    ) -> Result<Vec<AuthEventsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            AuthEventsWhere::Page {
+                filter,
+                offset,
+                limit,
+            } => sqlx::query_as::<_, AuthEventsDAO>(
+                "SELECT id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail FROM auth_events WHERE ($1::varchar IS NULL OR event_type = $1) AND ($2::uuid IS NULL OR credential_id = $2) AND ($3::varchar IS NULL OR ip_address = $3) AND ($4::varchar IS NULL OR outcome = $4) AND ($5::timestamptz IS NULL OR created_at >= $5) AND ($6::timestamptz IS NULL OR created_at < $6) ORDER BY created_at DESC, id OFFSET $7 LIMIT $8;",
+            )
+            .bind(filter.event_type)
+            .bind(filter.credential_id)
+            .bind(filter.ip_address)
+            .bind(filter.outcome)
+            .bind(filter.since)
+            .bind(filter.until)
+            .bind(offset as i64)
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::auth_events::{
+        AuthEventsBy, AuthEventsFilter, AuthEventsRepository, AuthEventsWhere, CreateAuthEventsDAO,
+        UpdateAuthEventsDAO,
+    };
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use database::types::{Utc, Uuid};
+
+    #[tokio::test]
+    async fn test_db() {
+        let pool = test_pool().await;
+        let since = Utc::now();
+        let credential_id = Uuid::new_v4();
+
+        // create
+        for outcome in ["failure", "failure", "success"] {
+            AuthEventsRepository::insert(
+                &pool,
+                CreateAuthEventsDAO {
+                    event_type: "sign_in".to_string(),
+                    credential_id: Some(credential_id),
+                    ip_address: Some("10.0.0.1".to_string()),
+                    user_agent: None,
+                    outcome: outcome.to_string(),
+                    detail: None,
+                },
+            )
+            .await
+            .expect("Could not record auth event");
+        }
+
+        // filter
+        let failures = AuthEventsRepository::get_all(
+            &pool,
+            AuthEventsWhere::Page {
+                filter: AuthEventsFilter {
+                    credential_id: Some(credential_id),
+                    outcome: Some("failure".to_string()),
+                    since: Some(since),
+                    ..Default::default()
+                },
+                offset: 0,
+                limit: 10,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(failures.len(), 2);
+
+        // paginate, newest first
+        let page = AuthEventsRepository::get_all(
+            &pool,
+            AuthEventsWhere::Page {
+                filter: AuthEventsFilter {
+                    credential_id: Some(credential_id),
+                    ..Default::default()
+                },
+                offset: 0,
+                limit: 1,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].outcome, "success");
+
+        // get
+        let found = AuthEventsRepository::get(&pool, AuthEventsBy::Id(page[0].id))
+            .await
+            .expect("Auth event not found");
+        assert_eq!(found, page[0]);
+
+        // append-only
+        let update =
+            AuthEventsRepository::update(&pool, AuthEventsBy::Id(found.id), UpdateAuthEventsDAO {})
+                .await;
+        assert!(matches!(update, Err(DatabaseError::Unsupported(_))));
+        let delete = AuthEventsRepository::delete(&pool, AuthEventsBy::Id(found.id)).await;
+        assert!(matches!(delete, Err(DatabaseError::Unsupported(_))));
+        let result = sqlx::query("DELETE FROM auth_events WHERE id = $1;")
+            .bind(found.id)
+            .execute(&pool)
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use super::{
+    AuthEventsBy, AuthEventsDAO, AuthEventsRepository, AuthEventsWhere, CreateAuthEventsDAO,
+    UpdateAuthEventsDAO,
+};
+use crate::{
+    connection::Sqlite,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{Utc, Uuid},
+};
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        AuthEventsDAO,
+        CreateAuthEventsDAO,
+        UpdateAuthEventsDAO,
+        AuthEventsBy,
+        AuthEventsWhere,
+    > for AuthEventsRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateAuthEventsDAO,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let event = sqlx::query_as::<_, AuthEventsDAO>("INSERT INTO auth_events (id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail;")
+            .bind(Uuid::new_v4())
+            .bind(Utc::now())
+            .bind(input.event_type)
+            .bind(input.credential_id)
+            .bind(input.ip_address)
+            .bind(input.user_agent)
+            .bind(input.outcome)
+            .bind(input.detail)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(event)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: AuthEventsBy,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "auth events are append-only".to_string(),
+        ))
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: AuthEventsBy,
+        _update: UpdateAuthEventsDAO,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "auth events are append-only".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: AuthEventsBy,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        let missing = not_found("AuthEvent", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            AuthEventsBy::Id(uuid) => sqlx::query_as::<_, AuthEventsDAO>(
+                "SELECT id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail FROM auth_events WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: AuthEventsBy,
+    ) -> Result<Option<AuthEventsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            AuthEventsBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail FROM auth_events WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: AuthEventsWhere,
+    ) -> Result<Vec<AuthEventsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            AuthEventsWhere::Page {
+                filter,
+                offset,
+                limit,
+            } => sqlx::query_as::<_, AuthEventsDAO>(
+                "SELECT id, created_at, event_type, credential_id, ip_address, user_agent, outcome, detail FROM auth_events WHERE ($1 IS NULL OR event_type = $1) AND ($2 IS NULL OR credential_id = $2) AND ($3 IS NULL OR ip_address = $3) AND ($4 IS NULL OR outcome = $4) AND ($5 IS NULL OR created_at >= $5) AND ($6 IS NULL OR created_at < $6) ORDER BY created_at DESC, id LIMIT $8 OFFSET $7;",
+            )
+            .bind(filter.event_type)
+            .bind(filter.credential_id)
+            .bind(filter.ip_address)
+            .bind(filter.outcome)
+            .bind(filter.since)
+            .bind(filter.until)
+            .bind(offset as i64)
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    connection::Postgres,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Privilege granted to a credential on top of what every user can do,
+/// named after the scope it unlocks, e.g. `admin`.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CredentialRolesDAO {
+    pub credential_id: Uuid,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateCredentialRolesDAO {
+    pub credential_id: Uuid,
+    pub role: String,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateCredentialRolesDAO {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CredentialRolesBy {
+    Role { credential_id: Uuid, role: String },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CredentialRolesWhere {
+    CredentialId(Uuid),
+}
+
+#[derive(Debug)]
+pub struct CredentialRolesRepository;
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Postgres,
+        CredentialRolesDAO,
+        CreateCredentialRolesDAO,
+        UpdateCredentialRolesDAO,
+        CredentialRolesBy,
+        CredentialRolesWhere,
+    > for CredentialRolesRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateCredentialRolesDAO,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, CredentialRolesDAO>(
+            "INSERT INTO credential_roles (credential_id, role) VALUES ($1, $2) RETURNING credential_id, role, created_at;",
+        )
+        .bind(input.credential_id)
+        .bind(input.role)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: CredentialRolesBy,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        let missing = not_found("CredentialRole", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialRolesBy::Role {
+                credential_id,
+                role,
+            } => sqlx::query_as::<_, CredentialRolesDAO>(
+                "DELETE FROM credential_roles WHERE credential_id = $1 AND role = $2 RETURNING credential_id, role, created_at;",
+            )
+            .bind(credential_id)
+            .bind(role)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: CredentialRolesBy,
+        _update: UpdateCredentialRolesDAO,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        Err(DatabaseError::InvalidQuery(
+            "roles are never updated, they are granted or revoked".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: CredentialRolesBy,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        let missing = not_found("CredentialRole", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialRolesBy::Role {
+                credential_id,
+                role,
+            } => sqlx::query_as::<_, CredentialRolesDAO>(
+                "SELECT credential_id, role, created_at FROM credential_roles WHERE credential_id = $1 AND role = $2 LIMIT 1;",
+            )
+            .bind(credential_id)
+            .bind(role)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: CredentialRolesBy,
+    ) -> Result<Option<CredentialRolesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialRolesBy::Role {
+                credential_id,
+                role,
+            } => sqlx::query_as(
+                "SELECT credential_id, role, created_at FROM credential_roles WHERE credential_id = $1 AND role = $2 LIMIT 1;",
+            )
+            .bind(credential_id)
+            .bind(role)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: CredentialRolesWhere,
+    ) -> Result<Vec<CredentialRolesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialRolesWhere::CredentialId(credential_id) => {
+                sqlx::query_as::<_, CredentialRolesDAO>(
+                    "SELECT credential_id, role, created_at FROM credential_roles WHERE credential_id = $1 ORDER BY role;",
+                )
+                .bind(credential_id)
+                .fetch_all(&mut *conn)
+                .await
+                .map_err(DatabaseError::from)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::credential_roles::{
+        CreateCredentialRolesDAO, CredentialRolesBy, CredentialRolesRepository,
+        CredentialRolesWhere, UpdateCredentialRolesDAO,
+    };
+    use crate::entities::credentials::{CreateCredentialsDAO, CredentialsRepository};
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use database::types::Uuid;
+
+    #[tokio::test]
+    async fn test_db() {
+        let pool = test_pool().await;
+
+        let credential = CredentialsRepository::insert(
+            &pool,
+            CreateCredentialsDAO {
+                email: format!("{}@gmail.com", Uuid::new_v4()),
+                password: String::from("password"),
+                idempotency_key: None,
+                has_password: true,
+            },
+        )
+        .await
+        .expect("Could not create credential");
+        let admin = || CredentialRolesBy::Role {
+            credential_id: credential.id,
+            role: "admin".to_string(),
+        };
+
+        // grant
+        let role = CredentialRolesRepository::insert(
+            &pool,
+            CreateCredentialRolesDAO {
+                credential_id: credential.id,
+                role: "admin".to_string(),
+            },
+        )
+        .await
+        .expect("Could not grant role");
+        assert_eq!(role.role, "admin");
+
+        // granting twice is refused
+        let duplicated = CredentialRolesRepository::insert(
+            &pool,
+            CreateCredentialRolesDAO {
+                credential_id: credential.id,
+                role: "admin".to_string(),
+            },
+        )
+        .await;
+        assert!(matches!(
+            duplicated,
+            Err(DatabaseError::UniqueViolation { .. })
+        ));
+
+        // get and list
+        let found = CredentialRolesRepository::get(&pool, admin())
+            .await
+            .expect("Role not found");
+        assert_eq!(role, found);
+        CredentialRolesRepository::insert(
+            &pool,
+            CreateCredentialRolesDAO {
+                credential_id: credential.id,
+                role: "movies:write".to_string(),
+            },
+        )
+        .await
+        .expect("Could not grant role");
+        let roles = CredentialRolesRepository::get_all(
+            &pool,
+            CredentialRolesWhere::CredentialId(credential.id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            roles.iter().map(|r| r.role.as_str()).collect::<Vec<_>>(),
+            vec!["admin", "movies:write"]
+        );
+
+        // roles are never updated
+        let update =
+            CredentialRolesRepository::update(&pool, admin(), UpdateCredentialRolesDAO {}).await;
+        assert!(matches!(update, Err(DatabaseError::InvalidQuery(_))));
+
+        // revoke
+        let revoked = CredentialRolesRepository::delete(&pool, admin())
+            .await
+            .expect("Could not revoke role");
+        assert_eq!(role, revoked);
+        assert!(CredentialRolesRepository::try_get(&pool, admin())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            CredentialRolesRepository::delete(&pool, admin()).await,
+            Err(DatabaseError::NotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use super::{
+    CreateCredentialRolesDAO, CredentialRolesBy, CredentialRolesDAO, CredentialRolesRepository,
+    CredentialRolesWhere, UpdateCredentialRolesDAO,
+};
+use crate::{
+    connection::Sqlite,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::Utc,
+};
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        CredentialRolesDAO,
+        CreateCredentialRolesDAO,
+        UpdateCredentialRolesDAO,
+        CredentialRolesBy,
+        CredentialRolesWhere,
+    > for CredentialRolesRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateCredentialRolesDAO,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let role = sqlx::query_as::<_, CredentialRolesDAO>(
+            "INSERT INTO credential_roles (credential_id, role, created_at) VALUES ($1, $2, $3) RETURNING credential_id, role, created_at;",
+        )
+        .bind(input.credential_id)
+        .bind(input.role)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(role)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialRolesBy,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        let missing = not_found("CredentialRole", &key);
+        let mut tx = db.begin().await?;
+        let role = match key {
+            CredentialRolesBy::Role {
+                credential_id,
+                role,
+            } => sqlx::query_as::<_, CredentialRolesDAO>(
+                "DELETE FROM credential_roles WHERE credential_id = $1 AND role = $2 RETURNING credential_id, role, created_at;",
+            )
+            .bind(credential_id)
+            .bind(role)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(missing)?,
+        };
+        tx.commit().await?;
+        Ok(role)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: CredentialRolesBy,
+        _update: UpdateCredentialRolesDAO,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        Err(DatabaseError::InvalidQuery(
+            "roles are never updated, they are granted or revoked".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialRolesBy,
+    ) -> Result<CredentialRolesDAO, DatabaseError> {
+        let missing = not_found("CredentialRole", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialRolesBy::Role {
+                credential_id,
+                role,
+            } => sqlx::query_as::<_, CredentialRolesDAO>(
+                "SELECT credential_id, role, created_at FROM credential_roles WHERE credential_id = $1 AND role = $2 LIMIT 1;",
+            )
+            .bind(credential_id)
+            .bind(role)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialRolesBy,
+    ) -> Result<Option<CredentialRolesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialRolesBy::Role {
+                credential_id,
+                role,
+            } => sqlx::query_as(
+                "SELECT credential_id, role, created_at FROM credential_roles WHERE credential_id = $1 AND role = $2 LIMIT 1;",
+            )
+            .bind(credential_id)
+            .bind(role)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialRolesWhere,
+    ) -> Result<Vec<CredentialRolesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialRolesWhere::CredentialId(credential_id) => {
+                sqlx::query_as::<_, CredentialRolesDAO>(
+                    "SELECT credential_id, role, created_at FROM credential_roles WHERE credential_id = $1 ORDER BY role;",
+                )
+                .bind(credential_id)
+                .fetch_all(&mut *conn)
+                .await
+                .map_err(DatabaseError::from)
+            }
+        }
+    }
+}
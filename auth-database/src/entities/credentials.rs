@@ -1,8 +1,13 @@
 use crate::{
-    connection::{Pool, Postgres},
-    traits::{DatabaseError, EntityRepository},
-    types::Uuid,
+    connection::{Database, Postgres},
+    query::{Field, Query, ValueKind},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
 };
+use sqlx::QueryBuilder;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct CredentialsDAO {
@@ -10,18 +15,24 @@ pub struct CredentialsDAO {
     pub email: String,
     pub password: String,
     pub active: bool,
+    /// False when nobody knows the password, e.g. created by an identity provider sign in
+    pub has_password: bool,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct CreateCredentialsDAO {
     pub email: String,
     pub password: String,
+    pub idempotency_key: Option<String>,
+    pub has_password: bool,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct UpdateCredentialsDAO {
+    pub email: String,
     pub password: String,
     pub active: bool,
+    pub has_password: bool,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -30,14 +41,57 @@ pub enum CredentialsBy {
     Email(String),
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum CredentialsWhere {
-    Active(bool),
+/// The password hash can't be queried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialsField {
+    Id,
+    Email,
+    Active,
+}
+
+impl Field for CredentialsField {
+    const KEY: Self = CredentialsField::Id;
+
+    fn column(self) -> &'static str {
+        match self {
+            CredentialsField::Id => "id",
+            CredentialsField::Email => "email",
+            CredentialsField::Active => "active",
+        }
+    }
+
+    fn kind(self) -> ValueKind {
+        match self {
+            CredentialsField::Id => ValueKind::Uuid,
+            CredentialsField::Email => ValueKind::Text,
+            CredentialsField::Active => ValueKind::Bool,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct CredentialsRepository;
 
+#[async_trait::async_trait]
+pub trait CredentialsRepositoryExt<Db: Database> {
+    /// Permanently removes credentials deactivated before `before`, their sessions go with them.
+    /// Returns how many credentials were erased.
+    async fn erase_deactivated<'c, E: Executor<'c, Db>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Credential created by the request carrying `idempotency_key`
+    async fn try_get_created_with<'c, E: Executor<'c, Db>>(
+        db: E,
+        idempotency_key: &'c str,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError>;
+
+    /// Permanently removes a credential that never signed in, e.g. one created for an
+    /// account that could not be completed. Returns whether it was removed.
+    async fn erase_unused<'c, E: Executor<'c, Db>>(db: E, id: Uuid) -> Result<bool, DatabaseError>;
+}
+
 #[async_trait::async_trait]
 impl
     EntityRepository<
@@ -46,136 +100,207 @@ impl
         CreateCredentialsDAO,
         UpdateCredentialsDAO,
         CredentialsBy,
-        CredentialsWhere,
+        Query<CredentialsField>,
     > for CredentialsRepository
 {
-    async fn insert(
-        db: &Pool<Postgres>,
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
         input: CreateCredentialsDAO,
     ) -> Result<CredentialsDAO, DatabaseError> {
-        sqlx::query_as::<_, CredentialsDAO>("INSERT INTO credentials (email, password) VALUES ($1, $2) RETURNING id, email, password, active;")
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, CredentialsDAO>("INSERT INTO credentials (email, password, idempotency_key, has_password) VALUES ($1, $2, $3, $4) RETURNING id, email, password, active, has_password;")
             .bind(input.email)
             .bind(input.password)
-            .fetch_one(db)
+            .bind(input.idempotency_key)
+            .bind(input.has_password)
+            .fetch_one(&mut *conn)
             .await
             .map_err(DatabaseError::from)
     }
 
-    async fn delete(
-        db: &Pool<Postgres>,
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
         key: CredentialsBy,
     ) -> Result<CredentialsDAO, DatabaseError> {
+        let missing = not_found("Credential", &key);
+        let mut conn = db.acquire().await?;
         match key {
             CredentialsBy::Id(uuid) => {
-                sqlx::query_as::<_, CredentialsDAO>("UPDATE credentials SET active = false WHERE id = $1 RETURNING id, email, password, active;")
+                sqlx::query_as::<_, CredentialsDAO>("UPDATE credentials SET active = false, deactivated_at = now() WHERE id = $1 RETURNING id, email, password, active, has_password;")
                     .bind(uuid)
-                    .fetch_one(db)
+                    .fetch_one(&mut *conn)
                     .await
-                    .map_err(DatabaseError::from)
+                    .map_err(missing)
             },
             CredentialsBy::Email(email) => {
-                sqlx::query_as::<_, CredentialsDAO>("UPDATE credentials SET active = false WHERE email = $1 RETURNING id, password, email, active;")
+                sqlx::query_as::<_, CredentialsDAO>("UPDATE credentials SET active = false, deactivated_at = now() WHERE email = $1 RETURNING id, email, password, active, has_password;")
                     .bind(email)
-                    .fetch_one(db)
+                    .fetch_one(&mut *conn)
                     .await
-                    .map_err(DatabaseError::from)
+                    .map_err(missing)
             },
 
         }
     }
 
-    async fn update(
-        db: &Pool<Postgres>,
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        db: E,
         key: CredentialsBy,
         update: UpdateCredentialsDAO,
     ) -> Result<CredentialsDAO, DatabaseError> {
+        let missing = not_found("Credential", &key);
+        let mut conn = db.acquire().await?;
         match key {
             CredentialsBy::Id(id) => sqlx::query_as::<_, CredentialsDAO>(
-                "UPDATE credentials SET password = $2, active = $3 WHERE id = $1 RETURNING id, email, password, active;",
+                "UPDATE credentials SET email = $2, password = $3, active = $4, has_password = $5 WHERE id = $1 RETURNING id, email, password, active, has_password;",
             )
             .bind(id)
+                .bind(update.email)
                 .bind(update.password)
             .bind(update.active)
-            .fetch_one(db)
+            .bind(update.has_password)
+            .fetch_one(&mut *conn)
             .await
-            .map_err(DatabaseError::from),
+            .map_err(missing),
             CredentialsBy::Email(email) => sqlx::query_as::<_, CredentialsDAO>(
-                "UPDATE credentials SET password = $2, active = $3 WHERE email = $1 RETURNING id, email, password, active;",
+                "UPDATE credentials SET email = $2, password = $3, active = $4, has_password = $5 WHERE email = $1 RETURNING id, email, password, active, has_password;",
             )
                 .bind(email)
+                .bind(update.email)
                 .bind(update.password)
                 .bind(update.active)
-                .fetch_one(db)
+                .bind(update.has_password)
+                .fetch_one(&mut *conn)
                 .await
-                .map_err(DatabaseError::from),
+                .map_err(missing),
         }
     }
 
-    async fn get(db: &Pool<Postgres>, key: CredentialsBy) -> Result<CredentialsDAO, DatabaseError> {
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: CredentialsBy,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        let missing = not_found("Credential", &key);
+        let mut conn = db.acquire().await?;
         match key {
             CredentialsBy::Id(id) => sqlx::query_as::<_, CredentialsDAO>(
-                "SELECT id, email, password, active FROM credentials WHERE id = $1 LIMIT 1;",
+                "SELECT id, email, password, active, has_password FROM credentials WHERE id = $1 LIMIT 1;",
             )
             .bind(id)
-            .fetch_one(db)
+            .fetch_one(&mut *conn)
             .await
-            .map_err(DatabaseError::from),
+            .map_err(missing),
             CredentialsBy::Email(email) => sqlx::query_as::<_, CredentialsDAO>(
-                "SELECT id, email, password, active FROM credentials WHERE email = $1 LIMIT 1;",
+                "SELECT id, email, password, active, has_password FROM credentials WHERE email = $1 LIMIT 1;",
             )
             .bind(email)
-            .fetch_one(db)
+            .fetch_one(&mut *conn)
             .await
-            .map_err(DatabaseError::from),
+            .map_err(missing),
         }
     }
 
-    async fn try_get(
-        db: &Pool<Postgres>,
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
         key: CredentialsBy,
     ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
         match key {
             CredentialsBy::Id(uuid) => {
-                sqlx::query_as("SELECT id, email, password, active FROM credentials WHERE id = $1;")
+                sqlx::query_as("SELECT id, email, password, active, has_password FROM credentials WHERE id = $1;")
                     .bind(uuid)
-                    .fetch_optional(db)
+                    .fetch_optional(&mut *conn)
                     .await
                     .map_err(DatabaseError::from)
             }
             CredentialsBy::Email(email) => sqlx::query_as(
-                "SELECT id, email, password, active FROM credentials WHERE email = $1;",
+                "SELECT id, email, password, active, has_password FROM credentials WHERE email = $1;",
             )
             .bind(email)
-            .fetch_optional(db)
+            .fetch_optional(&mut *conn)
             .await
             .map_err(DatabaseError::from),
         }
     }
 
-    async fn get_all(
-        _db: &Pool<Postgres>,
-        _key: CredentialsWhere,
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        query: Query<CredentialsField>,
     ) -> Result<Vec<CredentialsDAO>, DatabaseError> {
-        todo!()
+        let mut select =
+            QueryBuilder::new("SELECT id, email, password, active, has_password FROM credentials");
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<CredentialsDAO>()
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialsRepositoryExt<Postgres> for CredentialsRepository {
+    async fn erase_deactivated<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query(
+            "DELETE FROM credentials WHERE active = false AND deactivated_at IS NOT NULL AND deactivated_at < $1;",
+        )
+        .bind(before)
+        .execute(&mut *conn)
+        .await
+        .map(|r| r.rows_affected())
+        .map_err(DatabaseError::from)
+    }
+
+    async fn try_get_created_with<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        idempotency_key: &'c str,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as(
+            "SELECT id, email, password, active, has_password FROM credentials WHERE idempotency_key = $1;",
+        )
+        .bind(idempotency_key)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    async fn erase_unused<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query(
+            "DELETE FROM credentials c WHERE c.id = $1 AND NOT EXISTS (SELECT 1 FROM sessions s WHERE s.credential_id = c.id);",
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map(|r| r.rows_affected() > 0)
+        .map_err(DatabaseError::from)
     }
 }
 
 #[cfg(feature = "integration")]
 #[cfg(test)]
 mod tests {
-    use crate::connection::PgPool;
     use crate::entities::credentials::{
-        CreateCredentialsDAO, CredentialsBy, CredentialsRepository, UpdateCredentialsDAO,
+        CreateCredentialsDAO, CredentialsBy, CredentialsField, CredentialsRepository,
+        CredentialsRepositoryExt, UpdateCredentialsDAO,
     };
-    use crate::traits::EntityRepository;
-    use dotenv;
+    use crate::query::{Filter, Query};
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use crate::types::{Utc, Uuid};
 
     #[tokio::test]
     async fn test_db() {
-        dotenv::dotenv().ok();
-        let url =
-            std::env::var("TEST_AUTH_DATABASE_URL").expect("TEST_AUTH_DATABASE_URL must be set");
-        let pool = PgPool::connect(&url).await.unwrap();
+        let pool = test_pool().await;
 
         // create credential
         let response = CredentialsRepository::insert(
@@ -183,11 +308,27 @@ mod tests {
             CreateCredentialsDAO {
                 email: "kira".to_string(),
                 password: String::from("password"),
+                idempotency_key: None,
+                has_password: true,
             },
         )
         .await
         .expect("Could not create credential");
 
+        // the email is taken
+        let taken = CredentialsRepository::insert(
+            &pool,
+            CreateCredentialsDAO {
+                email: "kira".to_string(),
+                password: String::from("password"),
+                idempotency_key: None,
+                has_password: true,
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(taken, DatabaseError::UniqueViolation { .. }));
+
         // get user
         let found = CredentialsRepository::get(&pool, CredentialsBy::Id(response.id))
             .await
@@ -214,8 +355,10 @@ mod tests {
             &pool,
             CredentialsBy::Id(response.id),
             UpdateCredentialsDAO {
+                email: "kira".to_string(),
                 password: "other password".to_string(),
                 active: true,
+                has_password: true,
             },
         )
         .await
@@ -225,11 +368,85 @@ mod tests {
         assert_eq!(updated.password, "other password");
         assert!(updated.active);
 
+        // query by email and status
+        let kira = Query::new().filter(Filter::eq(CredentialsField::Email, "kira"));
+        let active = CredentialsRepository::get_all(
+            &pool,
+            kira.clone()
+                .filter(Filter::eq(CredentialsField::Active, true)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(active, vec![updated.clone()]);
+        let mismatch = CredentialsRepository::get_all(
+            &pool,
+            kira.filter(Filter::eq(CredentialsField::Active, "yes")),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(mismatch, DatabaseError::InvalidQuery(_)));
+
         // delete
         let deleted = CredentialsRepository::delete(&pool, CredentialsBy::Id(response.id))
             .await
             .expect("Could not delete an user");
         assert_eq!(response.id, deleted.id);
         assert!(!deleted.active);
+
+        // erase deactivated credentials
+        let erased = CredentialsRepository::erase_deactivated(&pool, Utc::now())
+            .await
+            .expect("Could not erase credentials");
+        assert!(erased >= 1);
+        let found = CredentialsRepository::try_get(&pool, CredentialsBy::Id(response.id))
+            .await
+            .unwrap();
+        assert!(found.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_and_erase_unused() {
+        let pool = test_pool().await;
+        let key = Uuid::new_v4().to_string();
+
+        let created = CredentialsRepository::insert(
+            &pool,
+            CreateCredentialsDAO {
+                email: format!("{}@idempotency.com", key),
+                password: String::from("password"),
+                idempotency_key: Some(key.clone()),
+                has_password: true,
+            },
+        )
+        .await
+        .unwrap();
+        let found = CredentialsRepository::try_get_created_with(&pool, &key)
+            .await
+            .unwrap();
+        assert_eq!(found, Some(created.clone()));
+
+        // the key can't be used twice
+        let result = CredentialsRepository::insert(
+            &pool,
+            CreateCredentialsDAO {
+                email: format!("other-{}@idempotency.com", key),
+                password: String::from("password"),
+                idempotency_key: Some(key.clone()),
+                has_password: true,
+            },
+        )
+        .await;
+        assert!(result.is_err());
+
+        assert!(CredentialsRepository::erase_unused(&pool, created.id)
+            .await
+            .unwrap());
+        assert!(!CredentialsRepository::erase_unused(&pool, created.id)
+            .await
+            .unwrap());
+        let found = CredentialsRepository::try_get_created_with(&pool, &key)
+            .await
+            .unwrap();
+        assert!(found.is_none());
     }
 }
@@ -0,0 +1,215 @@
+use super::{
+    CreateCredentialsDAO, CredentialsBy, CredentialsDAO, CredentialsField, CredentialsRepository,
+    CredentialsRepositoryExt, UpdateCredentialsDAO,
+};
+use crate::{
+    connection::Sqlite,
+    query::Query,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+use sqlx::QueryBuilder;
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        CredentialsDAO,
+        CreateCredentialsDAO,
+        UpdateCredentialsDAO,
+        CredentialsBy,
+        Query<CredentialsField>,
+    > for CredentialsRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateCredentialsDAO,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let credential = sqlx::query_as::<_, CredentialsDAO>("INSERT INTO credentials (id, email, password, idempotency_key, has_password) VALUES ($1, $2, $3, $4, $5) RETURNING id, email, password, active, has_password;")
+            .bind(Uuid::new_v4())
+            .bind(input.email)
+            .bind(input.password)
+            .bind(input.idempotency_key)
+            .bind(input.has_password)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(credential)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialsBy,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        let missing = not_found("Credential", &key);
+        let mut tx = db.begin().await?;
+        let credential = match key {
+            CredentialsBy::Id(uuid) => {
+                sqlx::query_as::<_, CredentialsDAO>("UPDATE credentials SET active = false, deactivated_at = $2 WHERE id = $1 RETURNING id, email, password, active, has_password;")
+                    .bind(uuid)
+                    .bind(Utc::now())
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            },
+            CredentialsBy::Email(email) => {
+                sqlx::query_as::<_, CredentialsDAO>("UPDATE credentials SET active = false, deactivated_at = $2 WHERE email = $1 RETURNING id, email, password, active, has_password;")
+                    .bind(email)
+                    .bind(Utc::now())
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            },
+
+        };
+        tx.commit().await?;
+        Ok(credential)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialsBy,
+        update: UpdateCredentialsDAO,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        let missing = not_found("Credential", &key);
+        let mut tx = db.begin().await?;
+        let credential = match key {
+            CredentialsBy::Id(id) => sqlx::query_as::<_, CredentialsDAO>(
+                "UPDATE credentials SET email = $2, password = $3, active = $4, has_password = $5 WHERE id = $1 RETURNING id, email, password, active, has_password;",
+            )
+            .bind(id)
+                .bind(update.email)
+                .bind(update.password)
+            .bind(update.active)
+            .bind(update.has_password)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(missing)?,
+            CredentialsBy::Email(email) => sqlx::query_as::<_, CredentialsDAO>(
+                "UPDATE credentials SET email = $2, password = $3, active = $4, has_password = $5 WHERE email = $1 RETURNING id, email, password, active, has_password;",
+            )
+                .bind(email)
+                .bind(update.email)
+                .bind(update.password)
+                .bind(update.active)
+                .bind(update.has_password)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(missing)?,
+        };
+        tx.commit().await?;
+        Ok(credential)
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialsBy,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        let missing = not_found("Credential", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialsBy::Id(id) => sqlx::query_as::<_, CredentialsDAO>(
+                "SELECT id, email, password, active, has_password FROM credentials WHERE id = $1 LIMIT 1;",
+            )
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+            CredentialsBy::Email(email) => sqlx::query_as::<_, CredentialsDAO>(
+                "SELECT id, email, password, active, has_password FROM credentials WHERE email = $1 LIMIT 1;",
+            )
+            .bind(email)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: CredentialsBy,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            CredentialsBy::Id(uuid) => {
+                sqlx::query_as("SELECT id, email, password, active, has_password FROM credentials WHERE id = $1;")
+                    .bind(uuid)
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .map_err(DatabaseError::from)
+            }
+            CredentialsBy::Email(email) => sqlx::query_as(
+                "SELECT id, email, password, active, has_password FROM credentials WHERE email = $1;",
+            )
+            .bind(email)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        query: Query<CredentialsField>,
+    ) -> Result<Vec<CredentialsDAO>, DatabaseError> {
+        let mut select =
+            QueryBuilder::new("SELECT id, email, password, active, has_password FROM credentials");
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<CredentialsDAO>()
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialsRepositoryExt<Sqlite> for CredentialsRepository {
+    async fn erase_deactivated<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let erased = sqlx::query(
+            "DELETE FROM credentials WHERE active = false AND deactivated_at IS NOT NULL AND deactivated_at < $1;",
+        )
+        .bind(before)
+        .execute(&mut *tx)
+        .await
+        .map(|r| r.rows_affected())?;
+        tx.commit().await?;
+        Ok(erased)
+    }
+
+    async fn try_get_created_with<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        idempotency_key: &'c str,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as(
+            "SELECT id, email, password, active, has_password FROM credentials WHERE idempotency_key = $1;",
+        )
+        .bind(idempotency_key)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    async fn erase_unused<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let erased = sqlx::query(
+            "DELETE FROM credentials WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM sessions WHERE sessions.credential_id = credentials.id);",
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map(|r| r.rows_affected() > 0)?;
+        tx.commit().await?;
+        Ok(erased)
+    }
+}
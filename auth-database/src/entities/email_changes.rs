@@ -0,0 +1,226 @@
+use crate::{
+    connection::Postgres,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct EmailChangesDAO {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub credential_id: Uuid,
+    pub email: String,
+    pub active: bool,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateEmailChangesDAO {
+    pub expires_at: DateTime<Utc>,
+    pub credential_id: Uuid,
+    pub email: String,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateEmailChangesDAO {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmailChangesBy {
+    Id(Uuid),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmailChangesWhere {
+    CredentialId(Uuid),
+}
+
+#[derive(Debug)]
+pub struct EmailChangesRepository;
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Postgres,
+        EmailChangesDAO,
+        CreateEmailChangesDAO,
+        UpdateEmailChangesDAO,
+        EmailChangesBy,
+        EmailChangesWhere,
+    > for EmailChangesRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateEmailChangesDAO,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, EmailChangesDAO>("INSERT INTO email_changes (expires_at, credential_id, email) VALUES ($1, $2, $3) RETURNING id, created_at, expires_at, credential_id, email, active;")
+            .bind(input.expires_at)
+            .bind(input.credential_id)
+            .bind(input.email)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: EmailChangesBy,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        let missing = not_found("EmailChange", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            EmailChangesBy::Id(uuid) => {
+                sqlx::query_as::<_, EmailChangesDAO>("UPDATE email_changes SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, email, active;")
+                    .bind(uuid)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+        }
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: EmailChangesBy,
+        _update: UpdateEmailChangesDAO,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        Err(DatabaseError::InvalidQuery(
+            "email changes are never updated, confirming one deactivates it".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: EmailChangesBy,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        let missing = not_found("EmailChange", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            EmailChangesBy::Id(uuid) => sqlx::query_as::<_, EmailChangesDAO>(
+                "SELECT id, created_at, expires_at, credential_id, email, active FROM email_changes WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: EmailChangesBy,
+    ) -> Result<Option<EmailChangesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            EmailChangesBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, created_at, expires_at, credential_id, email, active FROM email_changes WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: EmailChangesWhere,
+    ) -> Result<Vec<EmailChangesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            EmailChangesWhere::CredentialId(uuid) => sqlx::query_as::<_, EmailChangesDAO>(
+                "SELECT id, created_at, expires_at, credential_id, email, active FROM email_changes WHERE credential_id = $1 ORDER BY created_at;",
+            )
+            .bind(uuid)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::credentials::{CreateCredentialsDAO, CredentialsRepository};
+    use crate::entities::email_changes::{
+        CreateEmailChangesDAO, EmailChangesBy, EmailChangesRepository, EmailChangesWhere,
+        UpdateEmailChangesDAO,
+    };
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use database::types::Utc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_db() {
+        let pool = test_pool().await;
+
+        let credential = CredentialsRepository::insert(
+            &pool,
+            CreateCredentialsDAO {
+                email: "email_changes@gmail.com".to_string(),
+                password: String::from("password"),
+                idempotency_key: None,
+                has_password: true,
+            },
+        )
+        .await
+        .expect("Could not create credential");
+
+        // create email change
+        let change = EmailChangesRepository::insert(
+            &pool,
+            CreateEmailChangesDAO {
+                expires_at: Utc::now() + Duration::from_secs(60 * 5),
+                credential_id: credential.id,
+                email: "new_email_changes@gmail.com".to_string(),
+            },
+        )
+        .await
+        .expect("Could not create email change");
+
+        assert_eq!(change.credential_id, credential.id);
+        assert_eq!(change.email, "new_email_changes@gmail.com");
+        assert!(change.active);
+
+        // get email change
+        let found = EmailChangesRepository::get(&pool, EmailChangesBy::Id(change.id))
+            .await
+            .expect("Email change not found");
+        assert_eq!(change, found);
+
+        // try_get email change
+        let found = EmailChangesRepository::try_get(&pool, EmailChangesBy::Id(change.id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change, found);
+
+        // list email changes by credential
+        let all =
+            EmailChangesRepository::get_all(&pool, EmailChangesWhere::CredentialId(credential.id))
+                .await
+                .unwrap();
+        assert_eq!(all, vec![change.clone()]);
+
+        // email changes are never updated
+        let update = EmailChangesRepository::update(
+            &pool,
+            EmailChangesBy::Id(change.id),
+            UpdateEmailChangesDAO {},
+        )
+        .await;
+        assert!(matches!(update, Err(DatabaseError::InvalidQuery(_))));
+
+        // delete
+        let deleted = EmailChangesRepository::delete(&pool, EmailChangesBy::Id(change.id))
+            .await
+            .expect("Could not delete email change");
+        assert_eq!(change.id, deleted.id);
+        assert!(!deleted.active);
+    }
+}
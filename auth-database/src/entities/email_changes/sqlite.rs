@@ -0,0 +1,116 @@
+use super::{
+    CreateEmailChangesDAO, EmailChangesBy, EmailChangesDAO, EmailChangesRepository,
+    EmailChangesWhere, UpdateEmailChangesDAO,
+};
+use crate::{
+    connection::Sqlite,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{Utc, Uuid},
+};
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        EmailChangesDAO,
+        CreateEmailChangesDAO,
+        UpdateEmailChangesDAO,
+        EmailChangesBy,
+        EmailChangesWhere,
+    > for EmailChangesRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateEmailChangesDAO,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let change = sqlx::query_as::<_, EmailChangesDAO>("INSERT INTO email_changes (id, created_at, expires_at, credential_id, email) VALUES ($1, $2, $3, $4, $5) RETURNING id, created_at, expires_at, credential_id, email, active;")
+            .bind(Uuid::new_v4())
+            .bind(Utc::now())
+            .bind(input.expires_at)
+            .bind(input.credential_id)
+            .bind(input.email)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(change)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: EmailChangesBy,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        let missing = not_found("EmailChange", &key);
+        let mut tx = db.begin().await?;
+        let change = match key {
+            EmailChangesBy::Id(uuid) => {
+                sqlx::query_as::<_, EmailChangesDAO>("UPDATE email_changes SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, email, active;")
+                    .bind(uuid)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            }
+        };
+        tx.commit().await?;
+        Ok(change)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: EmailChangesBy,
+        _update: UpdateEmailChangesDAO,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        Err(DatabaseError::InvalidQuery(
+            "email changes are never updated, confirming one deactivates it".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: EmailChangesBy,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        let missing = not_found("EmailChange", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            EmailChangesBy::Id(uuid) => sqlx::query_as::<_, EmailChangesDAO>(
+                "SELECT id, created_at, expires_at, credential_id, email, active FROM email_changes WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: EmailChangesBy,
+    ) -> Result<Option<EmailChangesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            EmailChangesBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, created_at, expires_at, credential_id, email, active FROM email_changes WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: EmailChangesWhere,
+    ) -> Result<Vec<EmailChangesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            EmailChangesWhere::CredentialId(uuid) => sqlx::query_as::<_, EmailChangesDAO>(
+                "SELECT id, created_at, expires_at, credential_id, email, active FROM email_changes WHERE credential_id = $1 ORDER BY created_at;",
+            )
+            .bind(uuid)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
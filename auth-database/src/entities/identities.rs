@@ -0,0 +1,293 @@
+use crate::{
+    connection::Postgres,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Account at an external identity provider linked to a credential,
+/// `subject` is the provider's stable id for the user.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct IdentitiesDAO {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub credential_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub active: bool,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateIdentitiesDAO {
+    pub credential_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateIdentitiesDAO {
+    pub email: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IdentitiesBy {
+    Id(Uuid),
+    Subject { provider: String, subject: String },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IdentitiesWhere {
+    CredentialId(Uuid),
+}
+
+#[derive(Debug)]
+pub struct IdentitiesRepository;
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Postgres,
+        IdentitiesDAO,
+        CreateIdentitiesDAO,
+        UpdateIdentitiesDAO,
+        IdentitiesBy,
+        IdentitiesWhere,
+    > for IdentitiesRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, IdentitiesDAO>("INSERT INTO identities (credential_id, provider, subject, email) VALUES ($1, $2, $3, $4) RETURNING id, created_at, credential_id, provider, subject, email, active;")
+            .bind(input.credential_id)
+            .bind(input.provider)
+            .bind(input.subject)
+            .bind(input.email)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: IdentitiesBy,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let missing = not_found("Identity", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesBy::Id(uuid) => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET active = false WHERE id = $1 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(uuid)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+            IdentitiesBy::Subject { provider, subject } => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET active = false WHERE provider = $1 AND subject = $2 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(provider)
+                    .bind(subject)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+        }
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: IdentitiesBy,
+        update: UpdateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let missing = not_found("Identity", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesBy::Id(uuid) => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET email = $2 WHERE id = $1 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(uuid)
+                    .bind(update.email)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+            IdentitiesBy::Subject { provider, subject } => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET email = $3 WHERE provider = $1 AND subject = $2 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(provider)
+                    .bind(subject)
+                    .bind(update.email)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            }
+        }
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: IdentitiesBy,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let missing = not_found("Identity", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesBy::Id(uuid) => sqlx::query_as::<_, IdentitiesDAO>(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+            IdentitiesBy::Subject { provider, subject } => sqlx::query_as::<_, IdentitiesDAO>(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE provider = $1 AND subject = $2 LIMIT 1;",
+            )
+            .bind(provider)
+            .bind(subject)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: IdentitiesBy,
+    ) -> Result<Option<IdentitiesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+            IdentitiesBy::Subject { provider, subject } => sqlx::query_as(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE provider = $1 AND subject = $2 LIMIT 1;",
+            )
+            .bind(provider)
+            .bind(subject)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: IdentitiesWhere,
+    ) -> Result<Vec<IdentitiesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesWhere::CredentialId(uuid) => sqlx::query_as::<_, IdentitiesDAO>(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE credential_id = $1 ORDER BY created_at;",
+            )
+            .bind(uuid)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::credentials::{CreateCredentialsDAO, CredentialsRepository};
+    use crate::entities::identities::{
+        CreateIdentitiesDAO, IdentitiesBy, IdentitiesRepository, IdentitiesWhere,
+        UpdateIdentitiesDAO,
+    };
+    use crate::testing::test_pool;
+    use crate::traits::EntityRepository;
+
+    #[tokio::test]
+    async fn test_db() {
+        let pool = test_pool().await;
+
+        let credential = CredentialsRepository::insert(
+            &pool,
+            CreateCredentialsDAO {
+                email: "identities@gmail.com".to_string(),
+                password: String::from("password"),
+                idempotency_key: None,
+                has_password: true,
+            },
+        )
+        .await
+        .expect("Could not create credential");
+
+        // create identity
+        let identity = IdentitiesRepository::insert(
+            &pool,
+            CreateIdentitiesDAO {
+                credential_id: credential.id,
+                provider: "test".to_string(),
+                subject: credential.id.to_string(),
+                email: Some("identities@gmail.com".to_string()),
+            },
+        )
+        .await
+        .expect("Could not create identity");
+
+        assert_eq!(identity.credential_id, credential.id);
+        assert!(identity.active);
+
+        // the same subject can't be linked twice
+        let duplicated = IdentitiesRepository::insert(
+            &pool,
+            CreateIdentitiesDAO {
+                credential_id: credential.id,
+                provider: "test".to_string(),
+                subject: credential.id.to_string(),
+                email: None,
+            },
+        )
+        .await;
+        assert!(duplicated.is_err());
+
+        // get identity by provider subject
+        let found = IdentitiesRepository::get(
+            &pool,
+            IdentitiesBy::Subject {
+                provider: "test".to_string(),
+                subject: credential.id.to_string(),
+            },
+        )
+        .await
+        .expect("Identity not found");
+        assert_eq!(identity, found);
+
+        // try_get identity
+        let found = IdentitiesRepository::try_get(&pool, IdentitiesBy::Id(identity.id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity, found);
+
+        // update email
+        let updated = IdentitiesRepository::update(
+            &pool,
+            IdentitiesBy::Id(identity.id),
+            UpdateIdentitiesDAO { email: None },
+        )
+        .await
+        .expect("Could not update identity");
+        assert_eq!(updated.email, None);
+
+        // list identities of the credential
+        let all =
+            IdentitiesRepository::get_all(&pool, IdentitiesWhere::CredentialId(credential.id))
+                .await
+                .unwrap();
+        assert_eq!(all, vec![updated.clone()]);
+
+        // delete
+        let deleted = IdentitiesRepository::delete(&pool, IdentitiesBy::Id(identity.id))
+            .await
+            .expect("Could not delete identity");
+        assert!(!deleted.active);
+    }
+}
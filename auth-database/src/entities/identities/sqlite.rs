@@ -0,0 +1,161 @@
+use super::{
+    CreateIdentitiesDAO, IdentitiesBy, IdentitiesDAO, IdentitiesRepository, IdentitiesWhere,
+    UpdateIdentitiesDAO,
+};
+use crate::{
+    connection::Sqlite,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{Utc, Uuid},
+};
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        IdentitiesDAO,
+        CreateIdentitiesDAO,
+        UpdateIdentitiesDAO,
+        IdentitiesBy,
+        IdentitiesWhere,
+    > for IdentitiesRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let identity = sqlx::query_as::<_, IdentitiesDAO>("INSERT INTO identities (id, created_at, credential_id, provider, subject, email) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, created_at, credential_id, provider, subject, email, active;")
+            .bind(Uuid::new_v4())
+            .bind(Utc::now())
+            .bind(input.credential_id)
+            .bind(input.provider)
+            .bind(input.subject)
+            .bind(input.email)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(identity)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: IdentitiesBy,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let missing = not_found("Identity", &key);
+        let mut tx = db.begin().await?;
+        let identity = match key {
+            IdentitiesBy::Id(uuid) => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET active = false WHERE id = $1 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(uuid)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            }
+            IdentitiesBy::Subject { provider, subject } => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET active = false WHERE provider = $1 AND subject = $2 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(provider)
+                    .bind(subject)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            }
+        };
+        tx.commit().await?;
+        Ok(identity)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: IdentitiesBy,
+        update: UpdateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let missing = not_found("Identity", &key);
+        let mut tx = db.begin().await?;
+        let identity = match key {
+            IdentitiesBy::Id(uuid) => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET email = $2 WHERE id = $1 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(uuid)
+                    .bind(update.email)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            }
+            IdentitiesBy::Subject { provider, subject } => {
+                sqlx::query_as::<_, IdentitiesDAO>("UPDATE identities SET email = $3 WHERE provider = $1 AND subject = $2 RETURNING id, created_at, credential_id, provider, subject, email, active;")
+                    .bind(provider)
+                    .bind(subject)
+                    .bind(update.email)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            }
+        };
+        tx.commit().await?;
+        Ok(identity)
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: IdentitiesBy,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let missing = not_found("Identity", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesBy::Id(uuid) => sqlx::query_as::<_, IdentitiesDAO>(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+            IdentitiesBy::Subject { provider, subject } => sqlx::query_as::<_, IdentitiesDAO>(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE provider = $1 AND subject = $2 LIMIT 1;",
+            )
+            .bind(provider)
+            .bind(subject)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: IdentitiesBy,
+    ) -> Result<Option<IdentitiesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+            IdentitiesBy::Subject { provider, subject } => sqlx::query_as(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE provider = $1 AND subject = $2 LIMIT 1;",
+            )
+            .bind(provider)
+            .bind(subject)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: IdentitiesWhere,
+    ) -> Result<Vec<IdentitiesDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            IdentitiesWhere::CredentialId(uuid) => sqlx::query_as::<_, IdentitiesDAO>(
+                "SELECT id, created_at, credential_id, provider, subject, email, active FROM identities WHERE credential_id = $1 ORDER BY created_at;",
+            )
+            .bind(uuid)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{
+    connection::{Database, Postgres},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Refresh token that can't be used anymore, kept until it would have expired anyway
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct RevokedTokensDAO {
+    pub jti: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateRevokedTokensDAO {
+    pub jti: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateRevokedTokensDAO {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RevokedTokensBy {
+    Jti(Uuid),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RevokedTokensWhere {
+    ExpiresBefore(DateTime<Utc>),
+}
+
+#[derive(Debug)]
+pub struct RevokedTokensRepository;
+
+#[async_trait::async_trait]
+pub trait RevokedTokensRepositoryExt<Db: Database> {
+    /// Adds the token to the denylist, returns false when it was already there,
+    /// which means the token is being used twice.
+    async fn revoke<'c, E: Executor<'c, Db>>(
+        db: E,
+        jti: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError>;
+
+    /// Drops tokens that expired before `before`, they are rejected on their own by then.
+    /// Returns how many entries were deleted.
+    async fn purge<'c, E: Executor<'c, Db>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+}
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Postgres,
+        RevokedTokensDAO,
+        CreateRevokedTokensDAO,
+        UpdateRevokedTokensDAO,
+        RevokedTokensBy,
+        RevokedTokensWhere,
+    > for RevokedTokensRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateRevokedTokensDAO,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, RevokedTokensDAO>(
+            "INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) RETURNING jti, created_at, expires_at;",
+        )
+        .bind(input.jti)
+        .bind(input.expires_at)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: RevokedTokensBy,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        let missing = not_found("RevokedToken", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            RevokedTokensBy::Jti(jti) => sqlx::query_as::<_, RevokedTokensDAO>(
+                "DELETE FROM revoked_tokens WHERE jti = $1 RETURNING jti, created_at, expires_at;",
+            )
+            .bind(jti)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: RevokedTokensBy,
+        _update: UpdateRevokedTokensDAO,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        Err(DatabaseError::InvalidQuery(
+            "revoked tokens are never updated, they stay revoked until purged".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: RevokedTokensBy,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        let missing = not_found("RevokedToken", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            RevokedTokensBy::Jti(jti) => sqlx::query_as::<_, RevokedTokensDAO>(
+                "SELECT jti, created_at, expires_at FROM revoked_tokens WHERE jti = $1 LIMIT 1;",
+            )
+            .bind(jti)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: RevokedTokensBy,
+    ) -> Result<Option<RevokedTokensDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            RevokedTokensBy::Jti(jti) => sqlx::query_as(
+                "SELECT jti, created_at, expires_at FROM revoked_tokens WHERE jti = $1 LIMIT 1;",
+            )
+            .bind(jti)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: RevokedTokensWhere,
+    ) -> Result<Vec<RevokedTokensDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            RevokedTokensWhere::ExpiresBefore(before) => sqlx::query_as::<_, RevokedTokensDAO>(
+                "SELECT jti, created_at, expires_at FROM revoked_tokens WHERE expires_at < $1 ORDER BY expires_at;",
+            )
+            .bind(before)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RevokedTokensRepositoryExt<Postgres> for RevokedTokensRepository {
+    async fn revoke<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        jti: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query("INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT (jti) DO NOTHING;")
+            .bind(jti)
+            .bind(expires_at)
+            .execute(&mut *conn)
+            .await
+            .map(|r| r.rows_affected() == 1)
+            .map_err(DatabaseError::from)
+    }
+
+    async fn purge<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1;")
+            .bind(before)
+            .execute(&mut *conn)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::revoked_tokens::{
+        CreateRevokedTokensDAO, RevokedTokensBy, RevokedTokensRepository,
+        RevokedTokensRepositoryExt, RevokedTokensWhere, UpdateRevokedTokensDAO,
+    };
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use database::types::{Utc, Uuid};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_db() {
+        let pool = test_pool().await;
+
+        // create
+        let token = RevokedTokensRepository::insert(
+            &pool,
+            CreateRevokedTokensDAO {
+                jti: Uuid::new_v4(),
+                expires_at: Utc::now() + Duration::from_secs(60),
+            },
+        )
+        .await
+        .expect("Could not revoke token");
+
+        // get
+        let found = RevokedTokensRepository::get(&pool, RevokedTokensBy::Jti(token.jti))
+            .await
+            .expect("Revoked token not found");
+        assert_eq!(token, found);
+
+        // revoked tokens are never updated
+        let update = RevokedTokensRepository::update(
+            &pool,
+            RevokedTokensBy::Jti(token.jti),
+            UpdateRevokedTokensDAO {},
+        )
+        .await;
+        assert!(matches!(update, Err(DatabaseError::InvalidQuery(_))));
+
+        // revoking twice is reported
+        let revoked = RevokedTokensRepository::revoke(&pool, token.jti, token.expires_at)
+            .await
+            .unwrap();
+        assert!(!revoked);
+        let jti = Uuid::new_v4();
+        let revoked = RevokedTokensRepository::revoke(&pool, jti, Utc::now())
+            .await
+            .unwrap();
+        assert!(revoked);
+
+        // purge drops expired entries only
+        let expired =
+            RevokedTokensRepository::get_all(&pool, RevokedTokensWhere::ExpiresBefore(Utc::now()))
+                .await
+                .unwrap();
+        assert!(expired.iter().any(|t| t.jti == jti));
+        RevokedTokensRepository::purge(&pool, Utc::now())
+            .await
+            .unwrap();
+        assert!(
+            RevokedTokensRepository::try_get(&pool, RevokedTokensBy::Jti(jti))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // delete
+        let deleted = RevokedTokensRepository::delete(&pool, RevokedTokensBy::Jti(token.jti))
+            .await
+            .expect("Could not delete revoked token");
+        assert_eq!(token, deleted);
+    }
+}
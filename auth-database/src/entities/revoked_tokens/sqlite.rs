@@ -0,0 +1,150 @@
+use super::{
+    CreateRevokedTokensDAO, RevokedTokensBy, RevokedTokensDAO, RevokedTokensRepository,
+    RevokedTokensRepositoryExt, RevokedTokensWhere, UpdateRevokedTokensDAO,
+};
+use crate::{
+    connection::Sqlite,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        RevokedTokensDAO,
+        CreateRevokedTokensDAO,
+        UpdateRevokedTokensDAO,
+        RevokedTokensBy,
+        RevokedTokensWhere,
+    > for RevokedTokensRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateRevokedTokensDAO,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let token = sqlx::query_as::<_, RevokedTokensDAO>(
+            "INSERT INTO revoked_tokens (jti, created_at, expires_at) VALUES ($1, $2, $3) RETURNING jti, created_at, expires_at;",
+        )
+        .bind(input.jti)
+        .bind(Utc::now())
+        .bind(input.expires_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(token)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: RevokedTokensBy,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        let missing = not_found("RevokedToken", &key);
+        let mut tx = db.begin().await?;
+        let token = match key {
+            RevokedTokensBy::Jti(jti) => sqlx::query_as::<_, RevokedTokensDAO>(
+                "DELETE FROM revoked_tokens WHERE jti = $1 RETURNING jti, created_at, expires_at;",
+            )
+            .bind(jti)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(missing)?,
+        };
+        tx.commit().await?;
+        Ok(token)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: RevokedTokensBy,
+        _update: UpdateRevokedTokensDAO,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        Err(DatabaseError::InvalidQuery(
+            "revoked tokens are never updated, they stay revoked until purged".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: RevokedTokensBy,
+    ) -> Result<RevokedTokensDAO, DatabaseError> {
+        let missing = not_found("RevokedToken", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            RevokedTokensBy::Jti(jti) => sqlx::query_as::<_, RevokedTokensDAO>(
+                "SELECT jti, created_at, expires_at FROM revoked_tokens WHERE jti = $1 LIMIT 1;",
+            )
+            .bind(jti)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: RevokedTokensBy,
+    ) -> Result<Option<RevokedTokensDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            RevokedTokensBy::Jti(jti) => sqlx::query_as(
+                "SELECT jti, created_at, expires_at FROM revoked_tokens WHERE jti = $1 LIMIT 1;",
+            )
+            .bind(jti)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: RevokedTokensWhere,
+    ) -> Result<Vec<RevokedTokensDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            RevokedTokensWhere::ExpiresBefore(before) => sqlx::query_as::<_, RevokedTokensDAO>(
+                "SELECT jti, created_at, expires_at FROM revoked_tokens WHERE expires_at < $1 ORDER BY expires_at;",
+            )
+            .bind(before)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RevokedTokensRepositoryExt<Sqlite> for RevokedTokensRepository {
+    async fn revoke<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        jti: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let revoked = sqlx::query("INSERT INTO revoked_tokens (jti, created_at, expires_at) VALUES ($1, $2, $3) ON CONFLICT (jti) DO NOTHING;")
+            .bind(jti)
+            .bind(Utc::now())
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await
+            .map(|r| r.rows_affected() == 1)?;
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn purge<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let purged = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1;")
+            .bind(before)
+            .execute(&mut *tx)
+            .await
+            .map(|r| r.rows_affected())?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+}
@@ -1,8 +1,13 @@
 use crate::{
-    connection::{Pool, Postgres},
-    traits::{DatabaseError, EntityRepository},
+    connection::{Database, Postgres},
+    query::{Field, Query, ValueKind},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
     types::{DateTime, Utc, Uuid},
 };
+use sqlx::QueryBuilder;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct SessionsDAO {
@@ -11,10 +16,17 @@ pub struct SessionsDAO {
     pub expires_at: DateTime<Utc>,
     pub credential_id: Uuid,
     pub active: bool,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_label: Option<String>,
+    /// Set when the session was signed in through an external identity provider
+    pub identity_provider: Option<String>,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
-pub struct UpdateSessionsDAO {}
+pub struct UpdateSessionsDAO {
+    pub expires_at: DateTime<Utc>,
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum SessionsBy {
@@ -22,20 +34,68 @@ pub enum SessionsBy {
     CredentialId(Uuid),
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum SessionsWhere {
-    CredentialId(Uuid),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionsField {
+    Id,
+    CreatedAt,
+    ExpiresAt,
+    CredentialId,
+    Active,
+}
+
+impl Field for SessionsField {
+    const KEY: Self = SessionsField::Id;
+
+    fn column(self) -> &'static str {
+        match self {
+            SessionsField::Id => "id",
+            SessionsField::CreatedAt => "created_at",
+            SessionsField::ExpiresAt => "expires_at",
+            SessionsField::CredentialId => "credential_id",
+            SessionsField::Active => "active",
+        }
+    }
+
+    fn kind(self) -> ValueKind {
+        match self {
+            SessionsField::Id | SessionsField::CredentialId => ValueKind::Uuid,
+            SessionsField::CreatedAt | SessionsField::ExpiresAt => ValueKind::Timestamp,
+            SessionsField::Active => ValueKind::Bool,
+        }
+    }
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct CreateSessionsDAO {
     pub expires_at: DateTime<Utc>,
     pub credential_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_label: Option<String>,
+    pub identity_provider: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct SessionsRepository;
 
+#[async_trait::async_trait]
+pub trait SessionsRepositoryExt<Db: Database> {
+    /// Revokes every active session of a credential, optionally keeping one of them alive.
+    /// Returns how many sessions were revoked.
+    async fn revoke_all<'c, E: Executor<'c, Db>>(
+        db: E,
+        credential_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Hard deletes revoked sessions and the ones that expired before `before`.
+    /// Returns how many sessions were deleted.
+    async fn purge<'c, E: Executor<'c, Db>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+}
+
 #[async_trait::async_trait]
 impl
     EntityRepository<
@@ -44,115 +104,191 @@ impl
         CreateSessionsDAO,
         UpdateSessionsDAO,
         SessionsBy,
-        SessionsWhere,
+        Query<SessionsField>,
     > for SessionsRepository
 {
-    async fn insert(
-        db: &Pool<Postgres>,
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
         input: CreateSessionsDAO,
     ) -> Result<SessionsDAO, DatabaseError> {
-        sqlx::query_as::<_, SessionsDAO>("INSERT INTO sessions (expires_at, credential_id) VALUES ($1, $2) RETURNING id, created_at, expires_at, credential_id, active;")
+        let mut conn = db.acquire().await?;
+        sqlx::query_as::<_, SessionsDAO>("INSERT INTO sessions (expires_at, credential_id, user_agent, ip_address, device_label, identity_provider) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
             .bind(input.expires_at)
             .bind(input.credential_id)
-            .fetch_one(db)
+            .bind(input.user_agent)
+            .bind(input.ip_address)
+            .bind(input.device_label)
+            .bind(input.identity_provider)
+            .fetch_one(&mut *conn)
             .await
             .map_err(DatabaseError::from)
     }
 
-    async fn delete(db: &Pool<Postgres>, key: SessionsBy) -> Result<SessionsDAO, DatabaseError> {
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: SessionsBy,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let missing = not_found("Session", &key);
+        let mut conn = db.acquire().await?;
         match key {
             SessionsBy::Id(uuid) => {
-                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active;")
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
                     .bind(uuid)
-                    .fetch_one(db)
+                    .fetch_one(&mut *conn)
                     .await
-                    .map_err(DatabaseError::from)
+                    .map_err(missing)
             },
             SessionsBy::CredentialId(uuid) => {
-                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET active = false WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active;")
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET active = false WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
                     .bind(uuid)
-                    .fetch_one(db)
+                    .fetch_one(&mut *conn)
                     .await
-                    .map_err(DatabaseError::from)
+                    .map_err(missing)
             },
 
         }
     }
 
-    async fn update(
-        _db: &Pool<Postgres>,
-        _key: SessionsBy,
-        _update: UpdateSessionsDAO,
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: SessionsBy,
+        update: UpdateSessionsDAO,
     ) -> Result<SessionsDAO, DatabaseError> {
-        unreachable!("")
+        let missing = not_found("Session", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            SessionsBy::Id(uuid) => {
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET expires_at = $2 WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
+                    .bind(uuid)
+                    .bind(update.expires_at)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            },
+            SessionsBy::CredentialId(uuid) => {
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET expires_at = $2 WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
+                    .bind(uuid)
+                    .bind(update.expires_at)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(missing)
+            },
+        }
     }
 
-    async fn get(db: &Pool<Postgres>, key: SessionsBy) -> Result<SessionsDAO, DatabaseError> {
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: SessionsBy,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let missing = not_found("Session", &key);
+        let mut conn = db.acquire().await?;
         match key {
             SessionsBy::Id(id) => sqlx::query_as::<_, SessionsDAO>(
-                "SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE id = $1 LIMIT 1;",
+                "SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE id = $1 LIMIT 1;",
             )
             .bind(id)
-            .fetch_one(db)
+            .fetch_one(&mut *conn)
             .await
-            .map_err(DatabaseError::from),
+            .map_err(missing),
             SessionsBy::CredentialId(uuid) => sqlx::query_as::<_, SessionsDAO>(
-                "SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE credential_id = $1 LIMIT 1;",
+                "SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE credential_id = $1 LIMIT 1;",
             )
                 .bind(uuid)
-                .fetch_one(db)
+                .fetch_one(&mut *conn)
                 .await
-                .map_err(DatabaseError::from),
+                .map_err(missing),
         }
     }
 
-    async fn try_get(
-        db: &Pool<Postgres>,
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
         key: SessionsBy,
     ) -> Result<Option<SessionsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
         match key {
             SessionsBy::Id(uuid) => {
-                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE id = $1 LIMIT 1;")
+                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE id = $1 LIMIT 1;")
                     .bind(uuid)
-                    .fetch_optional(db)
+                    .fetch_optional(&mut *conn)
                     .await
                     .map_err(DatabaseError::from)
             },
             SessionsBy::CredentialId(uuid) => {
-                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE credential_id = $1 LIMIT 1;")
+                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE credential_id = $1 LIMIT 1;")
                     .bind(uuid)
-                    .fetch_optional(db)
+                    .fetch_optional(&mut *conn)
                     .await
                     .map_err(DatabaseError::from)
             }
         }
     }
 
-    async fn get_all(
-        _db: &Pool<Postgres>,
-        _key: SessionsWhere,
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        query: Query<SessionsField>,
     ) -> Result<Vec<SessionsDAO>, DatabaseError> {
-        todo!()
+        let mut select = QueryBuilder::new("SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions");
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<SessionsDAO>()
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionsRepositoryExt<Postgres> for SessionsRepository {
+    async fn revoke_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        credential_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query("UPDATE sessions SET active = false WHERE credential_id = $1 AND active = true AND ($2::uuid IS NULL OR id <> $2);")
+            .bind(credential_id)
+            .bind(except)
+            .execute(&mut *conn)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(DatabaseError::from)
+    }
+
+    async fn purge<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query("DELETE FROM sessions WHERE active = false OR expires_at < $1;")
+            .bind(before)
+            .execute(&mut *conn)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(DatabaseError::from)
     }
 }
 
 #[cfg(feature = "integration")]
 #[cfg(test)]
 mod tests {
-    use crate::connection::PgPool;
-    use crate::entities::credentials::{CreateCredentialsDAO, CredentialsRepository};
-    use crate::entities::sessions::{CreateSessionsDAO, SessionsBy, SessionsRepository};
-    use crate::traits::EntityRepository;
-    use database::types::Utc;
-    use dotenv;
+    use crate::entities::credentials::{
+        CreateCredentialsDAO, CredentialsBy, CredentialsRepository,
+    };
+    use crate::entities::sessions::{
+        CreateSessionsDAO, SessionsBy, SessionsField, SessionsRepository, SessionsRepositoryExt,
+        UpdateSessionsDAO,
+    };
+    use crate::query::{Direction, Filter, Query};
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use crate::unit_of_work::unit_of_work;
+    use database::types::{Utc, Uuid};
     use std::time::Duration;
 
     #[tokio::test]
     async fn test_db() {
-        dotenv::dotenv().ok();
-        let url =
-            std::env::var("TEST_AUTH_DATABASE_URL").expect("TEST_AUTH_DATABASE_URL must be set");
-        let pool = PgPool::connect(&url).await.unwrap();
+        let pool = test_pool().await;
 
         // create credential
         let response = CredentialsRepository::insert(
@@ -160,6 +296,8 @@ mod tests {
             CreateCredentialsDAO {
                 email: "Kira".to_string(),
                 password: String::from("password"),
+                idempotency_key: None,
+                has_password: true,
             },
         )
         .await
@@ -170,6 +308,10 @@ mod tests {
             CreateSessionsDAO {
                 expires_at: Utc::now() + Duration::from_secs(60 * 5),
                 credential_id: response.id,
+                user_agent: Some("Mozilla/5.0 (X11; Linux x86_64) Firefox/121.0".to_string()),
+                ip_address: Some("127.0.0.1".to_string()),
+                device_label: Some("Firefox on Linux".to_string()),
+                identity_provider: Some("google".to_string()),
             },
         )
         .await
@@ -177,6 +319,9 @@ mod tests {
 
         assert_eq!(session.credential_id, response.id);
         assert!(session.active);
+        assert_eq!(session.ip_address.as_deref(), Some("127.0.0.1"));
+        assert_eq!(session.device_label.as_deref(), Some("Firefox on Linux"));
+        assert_eq!(session.identity_provider.as_deref(), Some("google"));
 
         // get session
         let found = SessionsRepository::get(&pool, SessionsBy::Id(session.id))
@@ -195,11 +340,163 @@ mod tests {
 
         assert_eq!(session.id, found.id);
 
+        // extend expiry
+        let expires_at = session.expires_at + Duration::from_secs(60);
+        let updated = SessionsRepository::update(
+            &pool,
+            SessionsBy::Id(session.id),
+            UpdateSessionsDAO { expires_at },
+        )
+        .await
+        .expect("Could not update session");
+        assert_eq!(
+            updated.expires_at.timestamp_micros(),
+            expires_at.timestamp_micros()
+        );
+
         // delete
         let deleted = SessionsRepository::delete(&pool, SessionsBy::Id(session.id))
             .await
             .expect("Could not delete a session");
         assert_eq!(session.id, deleted.id);
         assert!(!deleted.active);
+
+        // revoke all sessions but the current one
+        let current = SessionsRepository::insert(
+            &pool,
+            CreateSessionsDAO {
+                expires_at: Utc::now() + Duration::from_secs(60 * 5),
+                credential_id: response.id,
+                user_agent: None,
+                ip_address: None,
+                device_label: None,
+                identity_provider: None,
+            },
+        )
+        .await
+        .unwrap();
+        let other = SessionsRepository::insert(
+            &pool,
+            CreateSessionsDAO {
+                expires_at: Utc::now() + Duration::from_secs(60 * 5),
+                credential_id: response.id,
+                user_agent: None,
+                ip_address: None,
+                device_label: None,
+                identity_provider: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let revoked = SessionsRepository::revoke_all(&pool, response.id, Some(current.id))
+            .await
+            .expect("Could not revoke sessions");
+        assert_eq!(revoked, 1);
+
+        let found = SessionsRepository::get(&pool, SessionsBy::Id(current.id))
+            .await
+            .unwrap();
+        assert!(found.active);
+        let found = SessionsRepository::get(&pool, SessionsBy::Id(other.id))
+            .await
+            .unwrap();
+        assert!(!found.active);
+
+        // list every session of the credential, newest first
+        let newest_first = Query::new()
+            .filter(Filter::eq(SessionsField::CredentialId, response.id))
+            .order_by(SessionsField::CreatedAt, Direction::Desc);
+        let all = SessionsRepository::get_all(&pool, newest_first.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            all.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![other.id, current.id, session.id]
+        );
+        let active = SessionsRepository::get_all(
+            &pool,
+            newest_first.filter(Filter::eq(SessionsField::Active, true)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            active.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![current.id]
+        );
+
+        // purge drops revoked and expired sessions only
+        let purged = SessionsRepository::purge(&pool, Utc::now())
+            .await
+            .expect("Could not purge sessions");
+        assert!(purged >= 2);
+        assert!(SessionsRepository::try_get(&pool, SessionsBy::Id(other.id))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(
+            SessionsRepository::try_get(&pool, SessionsBy::Id(current.id))
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unit_of_work() {
+        let pool = test_pool().await;
+        let email = format!("{}@unit.of.work", Uuid::new_v4());
+        let create = |email: String| CreateCredentialsDAO {
+            email,
+            password: String::from("password"),
+            idempotency_key: None,
+            has_password: true,
+        };
+        let session_for = |credential_id| CreateSessionsDAO {
+            expires_at: Utc::now() + Duration::from_secs(60 * 5),
+            credential_id,
+            user_agent: None,
+            ip_address: None,
+            device_label: None,
+            identity_provider: None,
+        };
+
+        // nothing is kept when a step fails
+        let input = create(email.clone());
+        let failed = unit_of_work(&pool, |tx| {
+            Box::pin(async move {
+                let credential = CredentialsRepository::insert(&mut *tx, input).await?;
+                SessionsRepository::insert(&mut *tx, session_for(credential.id)).await?;
+                Err::<(), _>(DatabaseError::Unknown("step failed".to_string()))
+            })
+        })
+        .await;
+        assert!(failed.is_err());
+        assert!(
+            CredentialsRepository::try_get(&pool, CredentialsBy::Email(email.clone()))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // everything is kept when all steps succeed
+        let input = create(email.clone());
+        let (credential, session) = unit_of_work(&pool, |tx| {
+            Box::pin(async move {
+                let credential = CredentialsRepository::insert(&mut *tx, input).await?;
+                let session =
+                    SessionsRepository::insert(&mut *tx, session_for(credential.id)).await?;
+                Ok((credential, session))
+            })
+        })
+        .await
+        .unwrap();
+        assert_eq!(session.credential_id, credential.id);
+        assert!(
+            SessionsRepository::try_get(&pool, SessionsBy::Id(session.id))
+                .await
+                .unwrap()
+                .is_some()
+        );
     }
 }
@@ -0,0 +1,193 @@
+use super::{
+    CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsField, SessionsRepository,
+    SessionsRepositoryExt, UpdateSessionsDAO,
+};
+use crate::{
+    connection::Sqlite,
+    query::Query,
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+use sqlx::QueryBuilder;
+
+#[async_trait::async_trait]
+impl
+    EntityRepository<
+        Sqlite,
+        SessionsDAO,
+        CreateSessionsDAO,
+        UpdateSessionsDAO,
+        SessionsBy,
+        Query<SessionsField>,
+    > for SessionsRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let session = sqlx::query_as::<_, SessionsDAO>("INSERT INTO sessions (id, created_at, expires_at, credential_id, user_agent, ip_address, device_label, identity_provider) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
+            .bind(Uuid::new_v4())
+            .bind(Utc::now())
+            .bind(input.expires_at)
+            .bind(input.credential_id)
+            .bind(input.user_agent)
+            .bind(input.ip_address)
+            .bind(input.device_label)
+            .bind(input.identity_provider)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(session)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: SessionsBy,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let missing = not_found("Session", &key);
+        let mut tx = db.begin().await?;
+        let session = match key {
+            SessionsBy::Id(uuid) => {
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
+                    .bind(uuid)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            },
+            SessionsBy::CredentialId(uuid) => {
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET active = false WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
+                    .bind(uuid)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            },
+
+        };
+        tx.commit().await?;
+        Ok(session)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: SessionsBy,
+        update: UpdateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let missing = not_found("Session", &key);
+        let mut tx = db.begin().await?;
+        let session = match key {
+            SessionsBy::Id(uuid) => {
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET expires_at = $2 WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
+                    .bind(uuid)
+                    .bind(update.expires_at)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            },
+            SessionsBy::CredentialId(uuid) => {
+                sqlx::query_as::<_, SessionsDAO>("UPDATE sessions SET expires_at = $2 WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider;")
+                    .bind(uuid)
+                    .bind(update.expires_at)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(missing)?
+            },
+        };
+        tx.commit().await?;
+        Ok(session)
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: SessionsBy,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let missing = not_found("Session", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            SessionsBy::Id(id) => sqlx::query_as::<_, SessionsDAO>(
+                "SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE id = $1 LIMIT 1;",
+            )
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+            SessionsBy::CredentialId(uuid) => sqlx::query_as::<_, SessionsDAO>(
+                "SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE credential_id = $1 LIMIT 1;",
+            )
+                .bind(uuid)
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: SessionsBy,
+    ) -> Result<Option<SessionsDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            SessionsBy::Id(uuid) => {
+                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE id = $1 LIMIT 1;")
+                    .bind(uuid)
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .map_err(DatabaseError::from)
+            },
+            SessionsBy::CredentialId(uuid) => {
+                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions WHERE credential_id = $1 LIMIT 1;")
+                    .bind(uuid)
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .map_err(DatabaseError::from)
+            }
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        query: Query<SessionsField>,
+    ) -> Result<Vec<SessionsDAO>, DatabaseError> {
+        let mut select = QueryBuilder::new("SELECT id, created_at, expires_at, credential_id, active, user_agent, ip_address, device_label, identity_provider FROM sessions");
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<SessionsDAO>()
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionsRepositoryExt<Sqlite> for SessionsRepository {
+    async fn revoke_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        credential_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let revoked = sqlx::query("UPDATE sessions SET active = false WHERE credential_id = $1 AND active = true AND ($2 IS NULL OR id <> $2);")
+            .bind(credential_id)
+            .bind(except)
+            .execute(&mut *tx)
+            .await
+            .map(|r| r.rows_affected())?;
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn purge<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let purged = sqlx::query("DELETE FROM sessions WHERE active = false OR expires_at < $1;")
+            .bind(before)
+            .execute(&mut *tx)
+            .await
+            .map(|r| r.rows_affected())?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+}
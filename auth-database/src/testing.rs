@@ -0,0 +1,22 @@
+//! Database the integration tests run on: the one at `TEST_AUTH_DATABASE_URL`,
+//! or a fresh SQLite file per test with the `sqlite` feature
+
+#[cfg(not(feature = "sqlite"))]
+pub async fn test_pool() -> crate::connection::PgPool {
+    dotenv::dotenv().ok();
+    let url = std::env::var("TEST_AUTH_DATABASE_URL").expect("TEST_AUTH_DATABASE_URL must be set");
+    crate::connection::PgPool::connect(&url).await.unwrap()
+}
+
+#[cfg(feature = "sqlite")]
+pub async fn test_pool() -> crate::connection::SqlitePool {
+    use crate::connection::{SqliteConnectOptions, SqlitePool};
+
+    let path = std::env::temp_dir().join(format!("auth-database-{}.db", uuid::Uuid::new_v4()));
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await.unwrap();
+    crate::SQLITE_MIGRATOR.run(&pool).await.unwrap();
+    pool
+}
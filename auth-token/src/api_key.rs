@@ -0,0 +1,81 @@
+use crate::API_KEY_PREFIX;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Plain API key as handed to its owner, `rfx_<prefix>_<secret>`.
+/// It's only ever shown once, the database keeps the prefix and a hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub prefix: String,
+    pub key: String,
+}
+
+impl ApiKey {
+    pub fn generate() -> Self {
+        let mut prefix = [0u8; 6];
+        OsRng.fill_bytes(&mut prefix);
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+
+        let prefix = prefix
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let key = format!(
+            "{}{}_{}",
+            API_KEY_PREFIX,
+            prefix,
+            URL_SAFE_NO_PAD.encode(secret)
+        );
+        Self { prefix, key }
+    }
+
+    /// Lookup prefix of a key, `None` when it doesn't look like one of ours
+    pub fn prefix_of(key: &str) -> Option<&str> {
+        let (prefix, secret) = key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+        if prefix.is_empty() || secret.is_empty() {
+            return None;
+        }
+        Some(prefix)
+    }
+
+    /// Keys are random enough that a fast hash is fine, unlike passwords
+    pub fn hash(key: &str) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_bytes()))
+    }
+
+    /// Comparing digests leaks nothing useful about the stored key through timing
+    pub fn verify(key: &str, hash: &str) -> bool {
+        Self::hash(key) == hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let api_key = ApiKey::generate();
+        assert!(api_key.key.starts_with(API_KEY_PREFIX));
+        assert_eq!(
+            ApiKey::prefix_of(&api_key.key),
+            Some(api_key.prefix.as_str())
+        );
+        assert_ne!(ApiKey::generate().prefix, api_key.prefix);
+
+        let hash = ApiKey::hash(&api_key.key);
+        assert!(ApiKey::verify(&api_key.key, &hash));
+        assert!(!ApiKey::verify(&ApiKey::generate().key, &hash));
+    }
+
+    #[test]
+    fn test_prefix_of_malformed() {
+        assert_eq!(ApiKey::prefix_of("eyJhbGciOiJFZERTQSJ9.e30.sig"), None);
+        assert_eq!(ApiKey::prefix_of("rfx_"), None);
+        assert_eq!(ApiKey::prefix_of("rfx_abc"), None);
+        assert_eq!(ApiKey::prefix_of("rfx__secret"), None);
+        assert_eq!(ApiKey::prefix_of("rfx_abc_"), None);
+    }
+}
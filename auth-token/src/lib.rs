@@ -0,0 +1,316 @@
+//! Tokens issued by the auth service and their verification, lets other services
+//! authenticate requests locally against the published key set instead of
+//! asking the auth service about every session.
+
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use thiserror::Error;
+
+pub use jsonwebtoken::jwk;
+
+/// Shared with the admin tooling, which issues keys without going through the auth service
+pub mod api_key;
+
+/// `iss` claim of every token signed by the auth service
+pub const ISSUER: &str = "rustflix-auth";
+/// Where the auth API publishes its public keys
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+/// Every API key starts with it, tells them apart from access tokens in a bearer header
+pub const API_KEY_PREFIX: &str = "rfx_";
+
+/// What an API key can be granted, signed in users can do everything
+/// but the privileged ones, which credentials hold as roles of the same name.
+pub mod scope {
+    pub const MOVIES_READ: &str = "movies:read";
+    /// Looking up other users. Left out of `ALL`, only credentials holding it as a role
+    /// can grant it to their keys.
+    pub const USERS_READ: &str = "users:read";
+    /// Editing the catalog, left out of `ALL` as well
+    pub const MOVIES_WRITE: &str = "movies:write";
+    /// Administering the service, e.g. reading who changed what. Left out of `ALL` too.
+    pub const ADMIN: &str = "admin";
+
+    pub const ALL: &[&str] = &[MOVIES_READ];
+}
+
+/// Redis pub/sub channel the auth service announces revoked sessions on,
+/// services caching sessions listen to it to drop them.
+pub const REVOCATIONS_CHANNEL: &str = "auth:revocations";
+
+/// Hours a session lives since sign in, unless [`SESSION_ABSOLUTE_TIMEOUT_ENV`] says otherwise.
+/// The auth service ends sessions then, services holding the session cookie let it expire then.
+pub const SESSION_ABSOLUTE_TIMEOUT_HOURS: u64 = 24 * 7;
+/// Environment variable every service reads the absolute session timeout from
+pub const SESSION_ABSOLUTE_TIMEOUT_ENV: &str = "SESSION_ABSOLUTE_TIMEOUT_HOURS";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Revocation {
+    /// A single session, by id
+    Session(String),
+    /// Every session of a credential, by credential id
+    Credential(String),
+}
+
+impl Revocation {
+    pub fn to_message(&self) -> String {
+        match self {
+            Revocation::Session(id) => format!("session:{}", id),
+            Revocation::Credential(id) => format!("credential:{}", id),
+        }
+    }
+
+    pub fn from_message(message: &str) -> Option<Self> {
+        match message.split_once(':')? {
+            ("session", id) => Some(Revocation::Session(id.to_string())),
+            ("credential", id) => Some(Revocation::Credential(id.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    pub iss: String,
+    /// Credential the token was issued to
+    pub sub: String,
+    /// Session the token was issued for
+    pub sid: String,
+    pub jti: String,
+    pub typ: TokenType,
+    pub iat: u64,
+    pub exp: u64,
+    /// Roles of the credential when the token was issued, changes show once it's refreshed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("invalid token: {0}")]
+    Invalid(String),
+    #[error("could not fetch signing keys: {0}")]
+    Fetch(String),
+}
+
+impl From<jsonwebtoken::errors::Error> for TokenError {
+    fn from(value: jsonwebtoken::errors::Error) -> Self {
+        TokenError::Invalid(value.to_string())
+    }
+}
+
+impl From<reqwest::Error> for TokenError {
+    fn from(value: reqwest::Error) -> Self {
+        TokenError::Fetch(value.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub struct TokenVerifier {
+    jwks_uri: Option<String>,
+    http: reqwest::Client,
+    keys: RwLock<JwkSet>,
+}
+
+impl TokenVerifier {
+    /// Verifier for a fixed key set
+    pub fn new(keys: JwkSet) -> Self {
+        Self {
+            jwks_uri: None,
+            http: reqwest::Client::new(),
+            keys: RwLock::new(keys),
+        }
+    }
+
+    /// Verifier that follows the key set published at `jwks_uri`,
+    /// keys are fetched again whenever a token is signed by an unknown one.
+    pub async fn from_jwks_uri(jwks_uri: &str) -> Result<Self, TokenError> {
+        let verifier = Self {
+            jwks_uri: Some(jwks_uri.to_string()),
+            http: reqwest::Client::new(),
+            keys: RwLock::new(JwkSet { keys: vec![] }),
+        };
+        verifier.refresh_keys().await?;
+        Ok(verifier)
+    }
+
+    async fn refresh_keys(&self) -> Result<(), TokenError> {
+        let Some(jwks_uri) = &self.jwks_uri else {
+            return Ok(());
+        };
+
+        let keys = self
+            .http
+            .get(jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<JwkSet>()
+            .await?;
+        if let Ok(mut current) = self.keys.write() {
+            *current = keys;
+        }
+        Ok(())
+    }
+
+    fn find(&self, kid: &str) -> Option<Jwk> {
+        self.keys
+            .read()
+            .ok()
+            .and_then(|keys| keys.find(kid).cloned())
+    }
+
+    async fn key(&self, kid: &str) -> Result<Jwk, TokenError> {
+        if let Some(jwk) = self.find(kid) {
+            return Ok(jwk);
+        }
+
+        // The auth service may have rotated its keys since we last looked.
+        self.refresh_keys().await?;
+        self.find(kid)
+            .ok_or_else(|| TokenError::Invalid("unknown signing key".to_string()))
+    }
+
+    /// Checks signature, issuer, expiry and that the token is of the expected type
+    pub async fn verify(&self, token: &str, typ: TokenType) -> Result<Claims, TokenError> {
+        let header = decode_header(token)?;
+        if !matches!(header.alg, Algorithm::EdDSA | Algorithm::ES256) {
+            return Err(TokenError::Invalid("unsupported algorithm".to_string()));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| TokenError::Invalid("missing kid".to_string()))?;
+        let jwk = self.key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[ISSUER]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+
+        let claims = decode::<Claims>(token, &DecodingKey::from_jwk(&jwk)?, &validation)?.claims;
+        if claims.typ != typ {
+            return Err(TokenError::Invalid("unexpected token type".to_string()));
+        }
+
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+
+    const KEY: &str = include_str!("../tests/fixtures/ed25519_test_key.pem");
+    const JWKS: &str = include_str!("../tests/fixtures/ed25519_test_jwks.json");
+
+    fn verifier() -> TokenVerifier {
+        TokenVerifier::new(serde_json::from_str(JWKS).unwrap())
+    }
+
+    fn claims(typ: TokenType, exp: u64) -> Claims {
+        Claims {
+            iss: ISSUER.to_string(),
+            sub: "credential".to_string(),
+            sid: "session".to_string(),
+            jti: "token".to_string(),
+            typ,
+            iat: get_current_timestamp(),
+            exp,
+            roles: vec![],
+        }
+    }
+
+    fn sign(claims: &Claims, kid: &str) -> String {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(kid.to_string());
+        encode(
+            &header,
+            claims,
+            &EncodingKey::from_ed_pem(KEY.as_bytes()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let expected = claims(TokenType::Access, get_current_timestamp() + 60);
+        let token = sign(&expected, "test-key");
+
+        let result = verifier().verify(&token, TokenType::Access).await;
+        assert_eq!(result, Ok(expected));
+    }
+
+    #[tokio::test]
+    async fn test_verify_wrong_type() {
+        let token = sign(
+            &claims(TokenType::Refresh, get_current_timestamp() + 60),
+            "test-key",
+        );
+
+        let result = verifier().verify(&token, TokenType::Access).await;
+        assert!(matches!(result, Err(TokenError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_expired() {
+        let token = sign(
+            &claims(TokenType::Access, get_current_timestamp() - 600),
+            "test-key",
+        );
+
+        let result = verifier().verify(&token, TokenType::Access).await;
+        assert!(matches!(result, Err(TokenError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_unknown_key() {
+        let token = sign(
+            &claims(TokenType::Access, get_current_timestamp() + 60),
+            "other-key",
+        );
+
+        let result = verifier().verify(&token, TokenType::Access).await;
+        assert!(matches!(result, Err(TokenError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_revocation_message() {
+        for revocation in [
+            Revocation::Session("6f0ba1a6-0c8f-4c5e-8d4a-1e0d0f7e0b3a".to_string()),
+            Revocation::Credential("9d3c7a1e-2b4f-4c6d-8e0a-5f1b2c3d4e5f".to_string()),
+        ] {
+            assert_eq!(
+                Revocation::from_message(&revocation.to_message()),
+                Some(revocation)
+            );
+        }
+        assert_eq!(Revocation::from_message("token:abc"), None);
+        assert_eq!(Revocation::from_message("session"), None);
+    }
+
+    #[tokio::test]
+    async fn test_verify_shared_secret() {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("test-key".to_string());
+        let token = encode(
+            &header,
+            &claims(TokenType::Access, get_current_timestamp() + 60),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        let result = verifier().verify(&token, TokenType::Access).await;
+        assert!(matches!(result, Err(TokenError::Invalid(_))));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::auth::{AuthServiceError, SessionClient};
+use auth_database::entities::sessions::SessionsDAO;
+use auth_database::types::Uuid;
+use std::str::FromStr;
+
+pub const OUTCOME_SUCCESS: &str = "success";
+pub const OUTCOME_FAILURE: &str = "failure";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthEventType {
+    Authenticate,
+    SignIn,
+    SignInWithIdentity,
+    CreateAccount,
+    DeleteCredential,
+    ChangePassword,
+    ChangeEmail,
+    ConfirmEmailChange,
+    CloseAccount,
+    ExportCredential,
+    ListSessions,
+    RevokeSession,
+    IssueTokens,
+    RefreshTokens,
+    RevokeRefreshToken,
+    CreateApiKey,
+    ListApiKeys,
+    RevokeApiKey,
+    AuthenticateApiKey,
+    ListAuthEvents,
+}
+
+impl AuthEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthEventType::Authenticate => "authenticate",
+            AuthEventType::SignIn => "sign_in",
+            AuthEventType::SignInWithIdentity => "sign_in_with_identity",
+            AuthEventType::CreateAccount => "create_account",
+            AuthEventType::DeleteCredential => "delete_credential",
+            AuthEventType::ChangePassword => "change_password",
+            AuthEventType::ChangeEmail => "change_email",
+            AuthEventType::ConfirmEmailChange => "confirm_email_change",
+            AuthEventType::CloseAccount => "close_account",
+            AuthEventType::ExportCredential => "export_credential",
+            AuthEventType::ListSessions => "list_sessions",
+            AuthEventType::RevokeSession => "revoke_session",
+            AuthEventType::IssueTokens => "issue_tokens",
+            AuthEventType::RefreshTokens => "refresh_tokens",
+            AuthEventType::RevokeRefreshToken => "revoke_refresh_token",
+            AuthEventType::CreateApiKey => "create_api_key",
+            AuthEventType::ListApiKeys => "list_api_keys",
+            AuthEventType::RevokeApiKey => "revoke_api_key",
+            AuthEventType::AuthenticateApiKey => "authenticate_api_key",
+            AuthEventType::ListAuthEvents => "list_auth_events",
+        }
+    }
+}
+
+/// What is known about an operation, filled in as it runs and recorded once it's over.
+/// When the credential is never resolved, e.g. a wrong password, the session id
+/// or email the caller presented is used to find it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub event_type: AuthEventType,
+    pub credential_id: Option<Uuid>,
+    pub client: SessionClient,
+    pub session_id: Option<Uuid>,
+    pub email: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(event_type: AuthEventType) -> Self {
+        Self {
+            event_type,
+            credential_id: None,
+            client: SessionClient::default(),
+            session_id: None,
+            email: None,
+        }
+    }
+
+    pub fn with_session_id(mut self, session_id: &str) -> Self {
+        self.session_id = Uuid::from_str(session_id).ok();
+        self
+    }
+
+    pub fn with_email(mut self, email: &str) -> Self {
+        self.email = Some(email.to_string());
+        self
+    }
+
+    pub fn with_client(mut self, client: &SessionClient) -> Self {
+        self.client = client.clone();
+        self
+    }
+
+    /// Operations made through a session are attributed to the device that signed in
+    pub fn session(&mut self, session: &SessionsDAO) {
+        self.credential_id = Some(session.credential_id);
+        self.session_id = Some(session.id);
+        if self.client == SessionClient::default() {
+            self.client = SessionClient {
+                user_agent: session.user_agent.clone(),
+                ip_address: session.ip_address.clone(),
+                device_label: session.device_label.clone(),
+            };
+        }
+    }
+}
+
+/// Outcome and detail columns of an event
+pub fn outcome<T>(result: &Result<T, AuthServiceError>) -> (&'static str, Option<String>) {
+    match result {
+        Ok(_) => (OUTCOME_SUCCESS, None),
+        Err(AuthServiceError::InvalidCredentials) => {
+            (OUTCOME_FAILURE, Some("invalid credentials".to_string()))
+        }
+        Err(AuthServiceError::InvalidInput { message })
+        | Err(AuthServiceError::NotFound { message })
+        | Err(AuthServiceError::AlreadyExists { message }) => {
+            (OUTCOME_FAILURE, Some(message.clone()))
+        }
+        Err(AuthServiceError::Conflict) => (OUTCOME_FAILURE, Some("conflict".to_string())),
+        Err(AuthServiceError::Forbidden) => (OUTCOME_FAILURE, Some("forbidden".to_string())),
+        Err(AuthServiceError::Unavailable) => {
+            (OUTCOME_FAILURE, Some("database unavailable".to_string()))
+        }
+        Err(AuthServiceError::InternalServerError) => {
+            (OUTCOME_FAILURE, Some("internal server error".to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth_database::types::Utc;
+
+    #[test]
+    fn test_session_attribution() {
+        let session = SessionsDAO {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            expires_at: Utc::now(),
+            credential_id: Uuid::new_v4(),
+            active: true,
+            user_agent: Some("curl/8.4.0".to_string()),
+            ip_address: Some("10.0.0.1".to_string()),
+            device_label: Some("curl".to_string()),
+            identity_provider: None,
+        };
+
+        let mut event = AuditEvent::new(AuthEventType::ChangePassword);
+        event.session(&session);
+        assert_eq!(event.credential_id, Some(session.credential_id));
+        assert_eq!(event.client.ip_address.as_deref(), Some("10.0.0.1"));
+
+        // the client of the request wins over the one that signed in
+        let client = SessionClient {
+            ip_address: Some("10.0.0.2".to_string()),
+            ..Default::default()
+        };
+        let mut event = AuditEvent::new(AuthEventType::RefreshTokens).with_client(&client);
+        event.session(&session);
+        assert_eq!(event.client, client);
+    }
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(
+            outcome(&Ok::<(), AuthServiceError>(())),
+            (OUTCOME_SUCCESS, None)
+        );
+        assert_eq!(
+            outcome(&Err::<(), _>(AuthServiceError::InvalidInput {
+                message: "invalid email".to_string()
+            })),
+            (OUTCOME_FAILURE, Some("invalid email".to_string()))
+        );
+    }
+}
@@ -1,15 +1,28 @@
+use crate::audit::{self, AuditEvent, AuthEventType};
+use crate::device;
+use crate::mailer::{Mailer, UnconfiguredMailer};
 use crate::password_helper::PasswordHelper;
-use auth_database::entities::sessions::{CreateSessionsDAO, SessionsBy, SessionsRepository};
+use crate::repository::{AuthRepository, PgAuthRepository};
+use crate::revocations::{Revocations, SilentRevocations};
+use crate::tokens::{TokenIssuer, TokenPair};
+use auth_database::entities::api_keys::{
+    ApiKeysBy, ApiKeysDAO, CreateApiKeysDAO, UpdateApiKeysDAO,
+};
+use auth_database::entities::auth_events::{AuthEventsDAO, AuthEventsFilter, CreateAuthEventsDAO};
+use auth_database::entities::email_changes::CreateEmailChangesDAO;
+use auth_database::entities::identities::{CreateIdentitiesDAO, IdentitiesBy, UpdateIdentitiesDAO};
+use auth_database::entities::sessions::{CreateSessionsDAO, UpdateSessionsDAO};
 use auth_database::types::Uuid;
 use auth_database::{
-    connection::{Pool, Postgres},
     entities::{
-        credentials::{CreateCredentialsDAO, CredentialsBy, CredentialsRepository},
+        credentials::{CreateCredentialsDAO, CredentialsBy, CredentialsDAO, UpdateCredentialsDAO},
         sessions::SessionsDAO,
     },
-    traits::{DatabaseError, EntityRepository},
-    types::{DateTime, Utc},
+    traits::DatabaseError,
+    types::{DateTime, TimeZone, Utc},
 };
+use auth_token::api_key::ApiKey;
+use auth_token::{jwk::JwkSet, scope, Revocation, TokenType, SESSION_ABSOLUTE_TIMEOUT_HOURS};
 use mockall::mock;
 use regex::Regex;
 use std::str::FromStr;
@@ -17,18 +30,59 @@ use std::sync::Arc;
 use std::time::Duration;
 
 const ONE_DAY_IN_SECONDS: u32 = 60 * 60 * 24;
+const DEFAULT_TOKEN_KEY_ID: &str = "auth";
+// Skip the write when a renewal would barely move the expiry, e.g. bursts of requests.
+const RENEWAL_THRESHOLD_IN_SECONDS: u64 = 60;
+const DEFAULT_AUTH_EVENTS_PAGE_SIZE: u32 = 50;
+const MAX_AUTH_EVENTS_PAGE_SIZE: u32 = 500;
+// How long a provider sign in stands in for the password, e.g. to close the account.
+const REAUTHENTICATION_WINDOW: Duration = Duration::from_secs(60 * 5);
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum AuthServiceError {
-    InvalidInput { message: String },
+    InvalidInput {
+        message: String,
+    },
     InvalidCredentials,
+    NotFound {
+        message: String,
+    },
+    AlreadyExists {
+        message: String,
+    },
+    /// Lost to a concurrent change, retrying may succeed
+    Conflict,
+    /// The database can't be reached right now
+    Unavailable,
+    /// Authenticated, but the credential lacks the role
+    Forbidden,
     InternalServerError,
 }
 
 impl From<DatabaseError> for AuthServiceError {
     fn from(value: DatabaseError) -> Self {
-        eprintln!("Database failed: {:?}", value);
-        Self::InternalServerError
+        match value {
+            // The key may be an email, keep it out of responses
+            DatabaseError::NotFound { entity, .. } => Self::NotFound {
+                message: format!("{} not found", entity),
+            },
+            DatabaseError::UniqueViolation { constraint } => Self::AlreadyExists {
+                message: constraint,
+            },
+            e @ (DatabaseError::ForeignKeyViolation { .. }
+            | DatabaseError::CheckViolation { .. }
+            | DatabaseError::InvalidQuery(_)) => Self::InvalidInput {
+                message: e.to_string(),
+            },
+            DatabaseError::SerializationFailure | DatabaseError::Deadlock => Self::Conflict,
+            DatabaseError::CommunicationError
+            | DatabaseError::ConnectionFailed
+            | DatabaseError::ConnectionNotAvailable => Self::Unavailable,
+            e => {
+                eprintln!("Database failed: {:?}", e);
+                Self::InternalServerError
+            }
+        }
     }
 }
 
@@ -60,169 +114,1421 @@ impl From<&SessionsDAO> for SignInResponse {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct AuthenticatedSession {
+    pub credential_id: String,
+    pub expires_at: DateTime<Utc>,
+    pub roles: Vec<String>,
+}
+
+/// Where a sign in comes from, recorded on the session so users can recognize their devices
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_label: Option<String>,
+}
+
+/// User as vouched for by an external identity provider after an OIDC login
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SessionResponse {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub active: bool,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_label: Option<String>,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
+impl From<&SessionsDAO> for SessionResponse {
+    fn from(value: &SessionsDAO) -> Self {
+        Self {
+            id: value.id.to_string(),
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+            active: value.active,
+            user_agent: value.user_agent.clone(),
+            ip_address: value.ip_address.clone(),
+            device_label: value.device_label.clone(),
+            current: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CredentialExport {
+    pub id: String,
+    pub email: String,
+    pub sessions: Vec<SessionResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<&ApiKeysDAO> for ApiKeyResponse {
+    fn from(value: &ApiKeysDAO) -> Self {
+        Self {
+            id: value.id.to_string(),
+            name: value.name.clone(),
+            prefix: value.prefix.clone(),
+            scopes: value.scopes.clone(),
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+            last_used_at: value.last_used_at,
+        }
+    }
+}
+
+/// A new API key, `key` can't be retrieved again after this
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreatedApiKey {
+    pub key: String,
+    pub api_key: ApiKeyResponse,
+}
+
+/// How the caller of a privileged call signed in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    Session(String),
+    AccessToken(String),
+    ApiKey(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedApiKey {
+    pub credential_id: String,
+    pub scopes: Vec<String>,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthEventResponse {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub event_type: String,
+    pub credential_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub outcome: String,
+    pub detail: Option<String>,
+}
+
+impl From<AuthEventsDAO> for AuthEventResponse {
+    fn from(value: AuthEventsDAO) -> Self {
+        Self {
+            id: value.id.to_string(),
+            created_at: value.created_at,
+            event_type: value.event_type,
+            credential_id: value.credential_id.map(|id| id.to_string()),
+            ip_address: value.ip_address,
+            user_agent: value.user_agent,
+            outcome: value.outcome,
+            detail: value.detail,
+        }
+    }
+}
+
+/// Sessions expire after `idle_timeout` without activity, and never live
+/// longer than `absolute_timeout` since sign in no matter how active they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionPolicy {
+    pub idle_timeout: Duration,
+    pub absolute_timeout: Duration,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(ONE_DAY_IN_SECONDS as u64),
+            absolute_timeout: Duration::from_secs(SESSION_ABSOLUTE_TIMEOUT_HOURS * 60 * 60),
+        }
+    }
+}
+
+impl SessionPolicy {
+    /// Expiry of a session created at `created_at` that was last used at `now`
+    pub fn expires_at(&self, created_at: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+        (now + self.idle_timeout).min(created_at + self.absolute_timeout)
+    }
+}
+
 #[derive(Debug)]
-pub struct AuthService {
-    db: Arc<Pool<Postgres>>,
+pub struct AuthService<R = PgAuthRepository> {
+    repository: Arc<R>,
+    mailer: Arc<dyn Mailer>,
+    revocations: Arc<dyn Revocations>,
+    session_policy: SessionPolicy,
+    tokens: Arc<TokenIssuer>,
 }
 
 #[async_trait::async_trait]
 pub trait AuthServiceTrait: Send + Sync + Clone {
-    async fn authenticate(&self, session_id: String) -> Result<(), AuthServiceError>;
+    async fn authenticate(
+        &self,
+        session_id: String,
+    ) -> Result<AuthenticatedSession, AuthServiceError>;
     async fn sign_in(
         &self,
         email: String,
         password: String,
+        client: SessionClient,
+    ) -> Result<SignInResponse, AuthServiceError>;
+    async fn sign_in_with_identity(
+        &self,
+        identity: ExternalIdentity,
+        client: SessionClient,
     ) -> Result<SignInResponse, AuthServiceError>;
+    /// Client retries carrying the same `idempotency_key` get the credential back instead of an error
     async fn create_account(
         &self,
         email: String,
         password: String,
+        idempotency_key: Option<String>,
     ) -> Result<String, AuthServiceError>;
+    /// Undoes `create_account` when the rest of the account could not be created.
+    /// Only credentials that never signed in can go, a missing one counts as deleted.
+    async fn delete_credential(&self, credential_id: String) -> Result<(), AuthServiceError>;
+    async fn change_password(
+        &self,
+        session_id: String,
+        current_password: String,
+        new_password: String,
+    ) -> Result<(), AuthServiceError>;
+    async fn change_email(
+        &self,
+        session_id: String,
+        current_password: String,
+        new_email: String,
+    ) -> Result<(), AuthServiceError>;
+    async fn confirm_email_change(&self, token: String) -> Result<(), AuthServiceError>;
+    async fn close_account(
+        &self,
+        session_id: String,
+        password: String,
+    ) -> Result<(), AuthServiceError>;
+    async fn export_credential(
+        &self,
+        session_id: String,
+    ) -> Result<CredentialExport, AuthServiceError>;
+    async fn list_sessions(
+        &self,
+        session_id: String,
+    ) -> Result<Vec<SessionResponse>, AuthServiceError>;
+    async fn revoke_session(
+        &self,
+        session_id: String,
+        target_session_id: String,
+    ) -> Result<(), AuthServiceError>;
+    /// Access and refresh tokens for an active session
+    async fn issue_tokens(&self, session_id: String) -> Result<TokenPair, AuthServiceError>;
+    /// Trades a refresh token for a new pair, the old refresh token can't be used again
+    async fn refresh_tokens(&self, refresh_token: String) -> Result<TokenPair, AuthServiceError>;
+    async fn revoke_refresh_token(&self, refresh_token: String) -> Result<(), AuthServiceError>;
+    /// Public keys the tokens can be verified with
+    fn jwks(&self) -> JwkSet;
+    async fn create_api_key(
+        &self,
+        session_id: String,
+        name: String,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<CreatedApiKey, AuthServiceError>;
+    async fn list_api_keys(
+        &self,
+        session_id: String,
+    ) -> Result<Vec<ApiKeyResponse>, AuthServiceError>;
+    async fn revoke_api_key(
+        &self,
+        session_id: String,
+        api_key_id: String,
+    ) -> Result<(), AuthServiceError>;
+    async fn authenticate_api_key(
+        &self,
+        key: String,
+    ) -> Result<AuthenticatedApiKey, AuthServiceError>;
+    /// Audit log of every operation above, newest first
+    /// For callers whose credential holds the admin role
+    async fn list_auth_events(
+        &self,
+        caller: Caller,
+        filter: AuthEventsFilter,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<AuthEventResponse>, AuthServiceError>;
 }
 
-impl AuthService {
-    pub fn new(db: Arc<Pool<Postgres>>) -> Self {
-        Self { db }
+impl<R: AuthRepository> AuthService<R> {
+    pub fn new(repository: R) -> Self {
+        Self {
+            repository: Arc::new(repository),
+            mailer: Arc::new(UnconfiguredMailer),
+            revocations: Arc::new(SilentRevocations),
+            session_policy: SessionPolicy::default(),
+            tokens: Arc::new(
+                TokenIssuer::generate(DEFAULT_TOKEN_KEY_ID).expect("Could not generate token key"),
+            ),
+        }
     }
-}
 
-impl Clone for AuthService {
-    fn clone(&self) -> Self {
-        Self {
-            db: Arc::clone(&self.db),
+    pub fn with_token_issuer(mut self, tokens: Arc<TokenIssuer>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    pub fn with_session_policy(mut self, session_policy: SessionPolicy) -> Self {
+        self.session_policy = session_policy;
+        self
+    }
+
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    pub fn with_revocations(mut self, revocations: Arc<dyn Revocations>) -> Self {
+        self.revocations = revocations;
+        self
+    }
+
+    /// Hard deletes closed accounts once their grace period is over.
+    pub async fn erase_closed_accounts(&self, grace: Duration) -> Result<u64, AuthServiceError> {
+        let before = Utc::now() - grace;
+        Ok(self
+            .repository
+            .erase_deactivated_credentials(before)
+            .await?)
+    }
+
+    /// Hard deletes expired and revoked sessions.
+    pub async fn purge_sessions(&self) -> Result<u64, AuthServiceError> {
+        Ok(self.repository.purge_sessions(Utc::now()).await?)
+    }
+
+    /// Drops denylisted refresh tokens that expired anyway.
+    pub async fn purge_revoked_tokens(&self) -> Result<u64, AuthServiceError> {
+        Ok(self.repository.purge_revoked_tokens(Utc::now()).await?)
+    }
+
+    /// Credential an earlier request with the same idempotency key created
+    async fn created_with(
+        &self,
+        idempotency_key: &str,
+        email: &str,
+    ) -> Result<Option<CredentialsDAO>, AuthServiceError> {
+        match self
+            .repository
+            .try_get_credential_created_with(idempotency_key)
+            .await?
+        {
+            Some(credential) if credential.email == email => Ok(Some(credential)),
+            Some(_) => Err(AuthServiceError::InvalidInput {
+                message: "idempotency key was used for another account".to_string(),
+            }),
+            None => Ok(None),
         }
     }
-}
 
-#[async_trait::async_trait]
-impl AuthServiceTrait for AuthService {
-    async fn authenticate(&self, session_id: String) -> Result<(), AuthServiceError> {
-        let uuid = Uuid::from_str(&session_id).map_err(|_| {
+    async fn active_session(&self, session_id: &str) -> Result<SessionsDAO, AuthServiceError> {
+        let uuid = Uuid::from_str(session_id).map_err(|_| {
             eprintln!("invalid session id format: {:?}", session_id);
             AuthServiceError::InvalidInput {
                 message: "invalid session id format".to_string(),
             }
         })?;
         // TODO - Create access role validation
-        if let Some(session) = SessionsRepository::try_get(&self.db, SessionsBy::Id(uuid)).await? {
-            if Utc::now() > session.expires_at {
+        match self.repository.try_get_session(uuid).await? {
+            Some(session) if session.active && Utc::now() <= session.expires_at => Ok(session),
+            _ => Err(AuthServiceError::InvalidCredentials),
+        }
+    }
+
+    async fn create_session(
+        &self,
+        credential_id: Uuid,
+        client: SessionClient,
+        identity_provider: Option<String>,
+    ) -> Result<SessionsDAO, AuthServiceError> {
+        let now = Utc::now();
+        let session = self
+            .repository
+            .insert_session(CreateSessionsDAO {
+                expires_at: self.session_policy.expires_at(now, now),
+                credential_id,
+                device_label: client
+                    .device_label
+                    .or_else(|| client.user_agent.as_deref().map(device::describe)),
+                user_agent: client.user_agent,
+                ip_address: client.ip_address,
+                identity_provider,
+            })
+            .await?;
+
+        Ok(session)
+    }
+
+    /// Credential behind an external identity, linking it on the first sign in.
+    /// New identities are matched to credentials by email, which is only trusted
+    /// when the provider verified it.
+    async fn identity_credential(
+        &self,
+        identity: ExternalIdentity,
+    ) -> Result<CredentialsDAO, AuthServiceError> {
+        let key = IdentitiesBy::Subject {
+            provider: identity.provider.clone(),
+            subject: identity.subject.clone(),
+        };
+        if let Some(linked) = self.repository.try_get_identity(key).await? {
+            if !linked.active {
                 return Err(AuthServiceError::InvalidCredentials);
             }
-            return Ok(());
+            if linked.email != identity.email {
+                self.repository
+                    .update_identity(
+                        linked.id,
+                        UpdateIdentitiesDAO {
+                            email: identity.email,
+                        },
+                    )
+                    .await?;
+            }
+            return Ok(self.repository.get_credential(linked.credential_id).await?);
+        }
+
+        let email = match identity.email.clone() {
+            Some(email) if identity.email_verified => email,
+            _ => {
+                return Err(AuthServiceError::InvalidInput {
+                    message: "provider did not share a verified email".to_string(),
+                })
+            }
+        };
+        let credential = match self
+            .repository
+            .try_get_credential(CredentialsBy::Email(email.clone()))
+            .await?
+        {
+            Some(credential) => credential,
+            None => {
+                // Nobody knows this password, the account signs in through the provider.
+                let password = PasswordHelper::hash_password(&Uuid::new_v4().to_string())?;
+                self.repository
+                    .insert_credential(CreateCredentialsDAO {
+                        email,
+                        password,
+                        idempotency_key: None,
+                        has_password: false,
+                    })
+                    .await?
+            }
+        };
+
+        self.repository
+            .insert_identity(CreateIdentitiesDAO {
+                credential_id: credential.id,
+                provider: identity.provider,
+                subject: identity.subject,
+                email: identity.email,
+            })
+            .await?;
+
+        Ok(credential)
+    }
+
+    /// Writes the event to the audit log, failing to do so doesn't fail the operation.
+    async fn audit<T: Sync>(&self, mut event: AuditEvent, result: &Result<T, AuthServiceError>) {
+        if event.credential_id.is_none() {
+            self.attribute(&mut event).await;
+        }
+        let (outcome, detail) = audit::outcome(result);
+
+        let recorded = self
+            .repository
+            .insert_auth_event(CreateAuthEventsDAO {
+                event_type: event.event_type.as_str().to_string(),
+                credential_id: event.credential_id,
+                ip_address: event.client.ip_address,
+                user_agent: event.client.user_agent,
+                outcome: outcome.to_string(),
+                detail,
+            })
+            .await;
+        if let Err(e) = recorded {
+            eprintln!("could not record {:?} event: {:?}", event.event_type, e);
+        }
+    }
+
+    /// Finds who a failed operation was aimed at from what the caller presented
+    async fn attribute(&self, event: &mut AuditEvent) {
+        if let Some(session_id) = event.session_id {
+            if let Ok(Some(session)) = self.repository.try_get_session(session_id).await {
+                event.session(&session);
+                return;
+            }
+        }
+        if let Some(email) = event.email.clone() {
+            if let Ok(Some(credential)) = self
+                .repository
+                .try_get_credential(CredentialsBy::Email(email))
+                .await
+            {
+                event.credential_id = Some(credential.id);
+            }
+        }
+    }
+
+    /// Credential of the caller, which must hold `role` right now. Access tokens
+    /// only count while their session is active, API keys need the scope as well.
+    async fn authorize(&self, caller: Caller, role: &str) -> Result<Uuid, AuthServiceError> {
+        let (credential_id, scopes) = match caller {
+            Caller::Session(session_id) => {
+                (self.active_session(&session_id).await?.credential_id, None)
+            }
+            Caller::AccessToken(token) => {
+                let claims = self
+                    .tokens
+                    .verify(&token, TokenType::Access)
+                    .await
+                    .map_err(|_| AuthServiceError::InvalidCredentials)?;
+                let session = self.active_session(&claims.sid).await?;
+                if session.credential_id.to_string() != claims.sub {
+                    return Err(AuthServiceError::InvalidCredentials);
+                }
+                (session.credential_id, None)
+            }
+            Caller::ApiKey(key) => {
+                let api_key = self.authenticate_api_key(key).await?;
+                let credential_id = Uuid::from_str(&api_key.credential_id)
+                    .map_err(|_| AuthServiceError::InternalServerError)?;
+                (credential_id, Some(api_key.scopes))
+            }
+        };
+
+        let roles = self.repository.list_roles(credential_id).await?;
+        if !roles.iter().any(|r| r == role)
+            || scopes.is_some_and(|scopes| !scopes.iter().any(|s| s == role))
+        {
+            return Err(AuthServiceError::Forbidden);
+        }
+        Ok(credential_id)
+    }
+
+    /// Resolves the session and checks the current password of its credential,
+    /// every sensitive change on a credential goes through here. Accounts created
+    /// through a provider have no password anyone knows, a session the provider
+    /// signed in moments ago vouches for them instead.
+    async fn verified_credential(
+        &self,
+        session_id: &str,
+        current_password: &str,
+    ) -> Result<(SessionsDAO, CredentialsDAO), AuthServiceError> {
+        let session = self.active_session(session_id).await?;
+        let credential = self
+            .repository
+            .get_credential(session.credential_id)
+            .await?;
+
+        // Only for accounts created through a provider, linking one to a password account
+        // mustn't let whoever controls the email at the provider skip the password.
+        let reauthenticated = !credential.has_password
+            && session.identity_provider.is_some()
+            && session.created_at >= Utc::now() - REAUTHENTICATION_WINDOW;
+        if !credential.active
+            || !(reauthenticated || PasswordHelper::verify(&credential.password, current_password)?)
+        {
+            return Err(AuthServiceError::InvalidCredentials);
+        }
+
+        Ok((session, credential))
+    }
+}
+
+impl<R> Clone for AuthService<R> {
+    fn clone(&self) -> Self {
+        Self {
+            repository: Arc::clone(&self.repository),
+            mailer: Arc::clone(&self.mailer),
+            revocations: Arc::clone(&self.revocations),
+            session_policy: self.session_policy,
+            tokens: Arc::clone(&self.tokens),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: AuthRepository> AuthServiceTrait for AuthService<R> {
+    /// Every failure is audited. Successes come with every request, so only the ones
+    /// moving the sliding expiry are, at most one a minute per session.
+    async fn authenticate(
+        &self,
+        session_id: String,
+    ) -> Result<AuthenticatedSession, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::Authenticate).with_session_id(&session_id);
+        let mut renewed = false;
+        let result: Result<AuthenticatedSession, AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+
+            // Sliding expiry, activity pushes the session forward up to its absolute timeout.
+            let expires_at = self
+                .session_policy
+                .expires_at(session.created_at, Utc::now());
+            let session = if expires_at
+                < session.expires_at + Duration::from_secs(RENEWAL_THRESHOLD_IN_SECONDS)
+            {
+                session
+            } else {
+                renewed = true;
+                self.repository
+                    .update_session(session.id, UpdateSessionsDAO { expires_at })
+                    .await?
+            };
+
+            Ok(AuthenticatedSession {
+                credential_id: session.credential_id.to_string(),
+                expires_at: session.expires_at,
+                roles: self.repository.list_roles(session.credential_id).await?,
+            })
+        }
+        .await;
+
+        if result.is_err() || renewed {
+            self.audit(event, &result).await;
         }
-        Err(AuthServiceError::InvalidCredentials)
+        result
     }
 
     async fn sign_in(
         &self,
         email: String,
         password: String,
+        client: SessionClient,
     ) -> Result<SignInResponse, AuthServiceError> {
-        valid_email(&email)?;
+        let mut event = AuditEvent::new(AuthEventType::SignIn)
+            .with_email(&email)
+            .with_client(&client);
+        let result: Result<SignInResponse, AuthServiceError> = async {
+            valid_email(&email)?;
 
-        if let Some(credential) =
-            CredentialsRepository::try_get(&self.db, CredentialsBy::Email(email)).await?
-        {
-            if !PasswordHelper::verify(&credential.password, &password)? {
-                return Err(AuthServiceError::InvalidCredentials);
-            };
+            if let Some(credential) = self
+                .repository
+                .try_get_credential(CredentialsBy::Email(email))
+                .await?
+            {
+                event.credential_id = Some(credential.id);
+                if !credential.active || !PasswordHelper::verify(&credential.password, &password)? {
+                    return Err(AuthServiceError::InvalidCredentials);
+                };
 
-            let session = SessionsRepository::insert(
-                &self.db,
-                CreateSessionsDAO {
-                    expires_at: Utc::now() + Duration::from_secs(ONE_DAY_IN_SECONDS as u64),
-                    credential_id: credential.id,
-                },
-            )
-            .await?;
+                let session = self.create_session(credential.id, client, None).await?;
+                Ok((&session).into())
+            } else {
+                Err(AuthServiceError::InvalidCredentials)
+            }
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn sign_in_with_identity(
+        &self,
+        identity: ExternalIdentity,
+        client: SessionClient,
+    ) -> Result<SignInResponse, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::SignInWithIdentity).with_client(&client);
+        let result: Result<SignInResponse, AuthServiceError> = async {
+            let provider = identity.provider.clone();
+            let credential = self.identity_credential(identity).await?;
+            event.credential_id = Some(credential.id);
+            if !credential.active {
+                return Err(AuthServiceError::InvalidCredentials);
+            }
 
+            let session = self
+                .create_session(credential.id, client, Some(provider))
+                .await?;
             Ok((&session).into())
-        } else {
-            return Err(AuthServiceError::InvalidCredentials);
         }
+        .await;
+
+        self.audit(event, &result).await;
+        result
     }
 
     async fn create_account(
         &self,
         email: String,
         password: String,
+        idempotency_key: Option<String>,
     ) -> Result<String, AuthServiceError> {
-        valid_email(&email)?;
-        let exists = CredentialsRepository::try_get(&self.db, CredentialsBy::Email(email.clone()))
-            .await?
-            .is_some();
+        let mut event = AuditEvent::new(AuthEventType::CreateAccount).with_email(&email);
+        let result: Result<String, AuthServiceError> = async {
+            valid_email(&email)?;
+            if let Some(key) = &idempotency_key {
+                if let Some(created) = self.created_with(key, &email).await? {
+                    event.credential_id = Some(created.id);
+                    return Ok(created.id.to_string());
+                }
+            }
 
-        if exists {
-            return Err(AuthServiceError::InvalidCredentials);
-        };
+            let exists = self
+                .repository
+                .try_get_credential(CredentialsBy::Email(email.clone()))
+                .await?
+                .is_some();
 
-        let dao = CreateCredentialsDAO {
-            email,
-            password: PasswordHelper::hash_password(&password)?,
-        };
+            if exists {
+                return Err(AuthServiceError::InvalidCredentials);
+            };
 
-        let res = CredentialsRepository::insert(&self.db, dao).await?;
+            let dao = CreateCredentialsDAO {
+                email: email.clone(),
+                password: PasswordHelper::hash_password(&password)?,
+                idempotency_key: idempotency_key.clone(),
+                has_password: true,
+            };
 
-        Ok(res.id.to_string())
-    }
-}
+            let res = match self.repository.insert_credential(dao).await {
+                Ok(res) => res,
+                Err(e) => {
+                    // A concurrent retry with the same key got there first.
+                    let created = match &idempotency_key {
+                        Some(key) => self.created_with(key, &email).await?,
+                        None => None,
+                    };
+                    created.ok_or_else(|| AuthServiceError::from(e))?
+                }
+            };
+            event.credential_id = Some(res.id);
 
-mock! {
-    pub AuthService {}
+            Ok(res.id.to_string())
+        }
+        .await;
 
-    #[async_trait::async_trait]
-    impl AuthServiceTrait for AuthService {
-        async fn authenticate(&self, session_id: String) -> Result<(), AuthServiceError>;
-        async fn sign_in(
-            &self,
-            email: String,
-            password: String,
-        ) -> Result<SignInResponse, AuthServiceError>;
-        async fn create_account(
-            &self,
-            email: String,
-            password: String,
-        ) -> Result<String, AuthServiceError>;
+        self.audit(event, &result).await;
+        result
     }
 
-    impl Clone for AuthService {
-        fn clone(&self) -> Self;
-    }
-}
+    async fn change_password(
+        &self,
+        session_id: String,
+        current_password: String,
+        new_password: String,
+    ) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::ChangePassword).with_session_id(&session_id);
+        let result: Result<(), AuthServiceError> = async {
+            let (session, credential) = self
+                .verified_credential(&session_id, &current_password)
+                .await?;
+            event.session(&session);
 
-#[cfg(feature = "integration")]
-#[cfg(test)]
-mod test {
-    use crate::auth::{AuthService, AuthServiceError, AuthServiceTrait};
-    use crate::password_helper::PasswordHelper;
-    use auth_database::connection::{PgPool, Pool, Postgres};
-    use auth_database::entities::credentials::{CreateCredentialsDAO, CredentialsRepository};
-    use auth_database::entities::sessions::{CreateSessionsDAO, SessionsRepository};
-    use auth_database::traits::EntityRepository;
-    use auth_database::types::Utc;
-    use std::sync::Arc;
-    use std::time::Duration;
+            let update = UpdateCredentialsDAO {
+                email: credential.email,
+                password: PasswordHelper::hash_password(&new_password)?,
+                active: credential.active,
+                has_password: true,
+            };
+            // Whoever else knew the old password must sign in again.
+            self.repository
+                .change_password(credential.id, update, session.id)
+                .await?;
+            self.revocations
+                .publish(Revocation::Credential(credential.id.to_string()))
+                .await;
 
-    pub async fn setup_test() -> (AuthService, Arc<Pool<Postgres>>) {
-        dotenv::dotenv().ok();
-        let url =
-            std::env::var("TEST_AUTH_DATABASE_URL").expect("TEST_AUTH_DATABASE_URL must be set");
-        let pool = Arc::new(PgPool::connect(&url).await.unwrap());
-        let auth_service = AuthService::new(pool.clone());
-        (auth_service, pool)
+            Ok(())
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
     }
-    #[tokio::test]
-    async fn test_authenticate() {
-        let (auth_service, pool) = setup_test().await;
 
-        // Invalid uuid
-        let result = auth_service
-            .authenticate("f1ac1576-bd47-4fd3-a9af 49cd400c2cd7".to_string())
-            .await
-            .unwrap_err();
-        assert_eq!(
-            AuthServiceError::InvalidInput {
-                message: "invalid session id format".to_string()
+    async fn change_email(
+        &self,
+        session_id: String,
+        current_password: String,
+        new_email: String,
+    ) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::ChangeEmail).with_session_id(&session_id);
+        let result: Result<(), AuthServiceError> = async {
+            valid_email(&new_email)?;
+            let (session, credential) = self
+                .verified_credential(&session_id, &current_password)
+                .await?;
+            event.session(&session);
+
+            let exists = self
+                .repository
+                .try_get_credential(CredentialsBy::Email(new_email.clone()))
+                .await?
+                .is_some();
+
+            if exists {
+                return Err(AuthServiceError::InvalidCredentials);
+            };
+
+            let change = self
+                .repository
+                .insert_email_change(CreateEmailChangesDAO {
+                    expires_at: Utc::now() + Duration::from_secs(ONE_DAY_IN_SECONDS as u64),
+                    credential_id: credential.id,
+                    email: new_email,
+                })
+                .await?;
+
+            self.mailer
+                .send_email_verification(&change.email, &change.id.to_string())
+                .await
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn confirm_email_change(&self, token: String) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::ConfirmEmailChange);
+        let result: Result<(), AuthServiceError> = async {
+            let uuid = Uuid::from_str(&token).map_err(|_| AuthServiceError::InvalidInput {
+                message: "invalid token format".to_string(),
+            })?;
+
+            let change = match self.repository.try_get_email_change(uuid).await? {
+                Some(change) if change.active && Utc::now() <= change.expires_at => change,
+                _ => return Err(AuthServiceError::InvalidCredentials),
+            };
+            event.credential_id = Some(change.credential_id);
+
+            // The address might have been taken since the change was requested.
+            let exists = self
+                .repository
+                .try_get_credential(CredentialsBy::Email(change.email.clone()))
+                .await?
+                .is_some();
+
+            if exists {
+                return Err(AuthServiceError::InvalidCredentials);
+            };
+
+            let credential = self.repository.get_credential(change.credential_id).await?;
+
+            self.repository
+                .confirm_email_change(
+                    change.id,
+                    credential.id,
+                    UpdateCredentialsDAO {
+                        email: change.email,
+                        password: credential.password,
+                        active: credential.active,
+                        has_password: credential.has_password,
+                    },
+                )
+                .await?;
+
+            Ok(())
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn delete_credential(&self, credential_id: String) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::DeleteCredential);
+        let result: Result<(), AuthServiceError> = async {
+            let id =
+                Uuid::from_str(&credential_id).map_err(|_| AuthServiceError::InvalidInput {
+                    message: "invalid credential id format".to_string(),
+                })?;
+            event.credential_id = Some(id);
+
+            if self.repository.erase_unused_credential(id).await? {
+                return Ok(());
+            }
+            match self
+                .repository
+                .try_get_credential(CredentialsBy::Id(id))
+                .await?
+            {
+                Some(_) => Err(AuthServiceError::InvalidInput {
+                    message: "credential is in use".to_string(),
+                }),
+                None => Ok(()),
+            }
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn close_account(
+        &self,
+        session_id: String,
+        password: String,
+    ) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::CloseAccount).with_session_id(&session_id);
+        let result: Result<(), AuthServiceError> = async {
+            let (session, credential) = self.verified_credential(&session_id, &password).await?;
+            event.session(&session);
+
+            self.repository.close_credential(credential.id).await?;
+            self.revocations
+                .publish(Revocation::Credential(credential.id.to_string()))
+                .await;
+
+            Ok(())
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn export_credential(
+        &self,
+        session_id: String,
+    ) -> Result<CredentialExport, AuthServiceError> {
+        let mut event =
+            AuditEvent::new(AuthEventType::ExportCredential).with_session_id(&session_id);
+        let result: Result<CredentialExport, AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+            let credential = self
+                .repository
+                .get_credential(session.credential_id)
+                .await?;
+            let sessions = self.repository.list_sessions(credential.id).await?;
+
+            Ok(CredentialExport {
+                id: credential.id.to_string(),
+                email: credential.email,
+                sessions: sessions
+                    .iter()
+                    .map(|s| SessionResponse {
+                        current: s.id == session.id,
+                        ..SessionResponse::from(s)
+                    })
+                    .collect(),
+            })
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn list_sessions(
+        &self,
+        session_id: String,
+    ) -> Result<Vec<SessionResponse>, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::ListSessions).with_session_id(&session_id);
+        let result: Result<Vec<SessionResponse>, AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+            let now = Utc::now();
+            let sessions = self
+                .repository
+                .list_sessions(session.credential_id)
+                .await?
+                .iter()
+                .filter(|s| s.active && now <= s.expires_at)
+                .map(|s| SessionResponse {
+                    current: s.id == session.id,
+                    ..SessionResponse::from(s)
+                })
+                .collect();
+
+            Ok(sessions)
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: String,
+        target_session_id: String,
+    ) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::RevokeSession).with_session_id(&session_id);
+        let result: Result<(), AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+            let target =
+                Uuid::from_str(&target_session_id).map_err(|_| AuthServiceError::InvalidInput {
+                    message: "invalid session id format".to_string(),
+                })?;
+
+            // Users can only revoke their own sessions.
+            match self.repository.try_get_session(target).await? {
+                Some(found) if found.credential_id == session.credential_id => {
+                    self.repository.revoke_session(found.id).await?;
+                    self.revocations
+                        .publish(Revocation::Session(found.id.to_string()))
+                        .await;
+                    Ok(())
+                }
+                _ => Err(AuthServiceError::InvalidCredentials),
+            }
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn issue_tokens(&self, session_id: String) -> Result<TokenPair, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::IssueTokens).with_session_id(&session_id);
+        let result: Result<TokenPair, AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+            let roles = self.repository.list_roles(session.credential_id).await?;
+            self.tokens.issue(
+                &session.credential_id.to_string(),
+                &session.id.to_string(),
+                &roles,
+            )
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn refresh_tokens(&self, refresh_token: String) -> Result<TokenPair, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::RefreshTokens);
+        let result: Result<TokenPair, AuthServiceError> = async {
+            let claims = self
+                .tokens
+                .verify(&refresh_token, TokenType::Refresh)
+                .await
+                .map_err(|_| AuthServiceError::InvalidCredentials)?;
+            event.credential_id = Uuid::from_str(&claims.sub).ok();
+            event.session_id = Uuid::from_str(&claims.sid).ok();
+            let jti =
+                Uuid::from_str(&claims.jti).map_err(|_| AuthServiceError::InvalidCredentials)?;
+            let expires_at = Utc
+                .timestamp_opt(claims.exp as i64, 0)
+                .single()
+                .ok_or(AuthServiceError::InvalidCredentials)?;
+
+            // Rotation, a refresh token works once. Seeing it twice means it leaked,
+            // so the whole session goes away with it.
+            if !self.repository.revoke_token(jti, expires_at).await? {
+                if let Ok(session_id) = Uuid::from_str(&claims.sid) {
+                    self.repository.revoke_session(session_id).await?;
+                    self.revocations
+                        .publish(Revocation::Session(session_id.to_string()))
+                        .await;
+                }
+                return Err(AuthServiceError::InvalidCredentials);
+            }
+
+            // Refreshing counts as activity on the session.
+            let session = self.authenticate(claims.sid.clone()).await?;
+            self.tokens
+                .issue(&session.credential_id, &claims.sid, &session.roles)
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn revoke_refresh_token(&self, refresh_token: String) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::RevokeRefreshToken);
+        let result: Result<(), AuthServiceError> = async {
+            let claims = self
+                .tokens
+                .verify(&refresh_token, TokenType::Refresh)
+                .await
+                .map_err(|_| AuthServiceError::InvalidCredentials)?;
+            event.credential_id = Uuid::from_str(&claims.sub).ok();
+            let jti =
+                Uuid::from_str(&claims.jti).map_err(|_| AuthServiceError::InvalidCredentials)?;
+            let expires_at = Utc
+                .timestamp_opt(claims.exp as i64, 0)
+                .single()
+                .ok_or(AuthServiceError::InvalidCredentials)?;
+
+            self.repository.revoke_token(jti, expires_at).await?;
+            Ok(())
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    fn jwks(&self) -> JwkSet {
+        self.tokens.jwks().clone()
+    }
+
+    async fn create_api_key(
+        &self,
+        session_id: String,
+        name: String,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<CreatedApiKey, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::CreateApiKey).with_session_id(&session_id);
+        let result: Result<CreatedApiKey, AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+            if name.trim().is_empty() {
+                return Err(AuthServiceError::InvalidInput {
+                    message: "api key name is required".to_string(),
+                });
+            }
+            // Privileged scopes are only handed out to credentials holding them as roles.
+            let roles = self.repository.list_roles(session.credential_id).await?;
+            if scopes.is_empty()
+                || scopes
+                    .iter()
+                    .any(|s| !scope::ALL.contains(&s.as_str()) && !roles.contains(s))
+            {
+                return Err(AuthServiceError::InvalidInput {
+                    message: "invalid api key scopes".to_string(),
+                });
+            }
+            if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+                return Err(AuthServiceError::InvalidInput {
+                    message: "api key expiry is in the past".to_string(),
+                });
+            }
+
+            let api_key = ApiKey::generate();
+            let created = self
+                .repository
+                .insert_api_key(CreateApiKeysDAO {
+                    credential_id: session.credential_id,
+                    name: name.trim().to_string(),
+                    key_hash: ApiKey::hash(&api_key.key),
+                    prefix: api_key.prefix,
+                    scopes,
+                    expires_at,
+                })
+                .await?;
+
+            Ok(CreatedApiKey {
+                key: api_key.key,
+                api_key: ApiKeyResponse::from(&created),
+            })
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn list_api_keys(
+        &self,
+        session_id: String,
+    ) -> Result<Vec<ApiKeyResponse>, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::ListApiKeys).with_session_id(&session_id);
+        let result: Result<Vec<ApiKeyResponse>, AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+            let keys = self
+                .repository
+                .list_api_keys(session.credential_id)
+                .await?
+                .iter()
+                .filter(|k| k.active)
+                .map(ApiKeyResponse::from)
+                .collect();
+
+            Ok(keys)
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    async fn revoke_api_key(
+        &self,
+        session_id: String,
+        api_key_id: String,
+    ) -> Result<(), AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::RevokeApiKey).with_session_id(&session_id);
+        let result: Result<(), AuthServiceError> = async {
+            let session = self.active_session(&session_id).await?;
+            event.session(&session);
+            let id = Uuid::from_str(&api_key_id).map_err(|_| AuthServiceError::InvalidInput {
+                message: "invalid api key id format".to_string(),
+            })?;
+
+            // Users can only revoke their own keys.
+            match self.repository.try_get_api_key(ApiKeysBy::Id(id)).await? {
+                Some(found) if found.credential_id == session.credential_id => {
+                    self.repository.revoke_api_key(found.id).await?;
+                    Ok(())
+                }
+                _ => Err(AuthServiceError::InvalidCredentials),
+            }
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+
+    /// Audited like `authenticate`, successes only when `last_used_at` moves
+    async fn authenticate_api_key(
+        &self,
+        key: String,
+    ) -> Result<AuthenticatedApiKey, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::AuthenticateApiKey);
+        let mut stale = false;
+        let result: Result<AuthenticatedApiKey, AuthServiceError> = async {
+            let prefix = ApiKey::prefix_of(&key).ok_or(AuthServiceError::InvalidCredentials)?;
+            let now = Utc::now();
+            let api_key = match self
+                .repository
+                .try_get_api_key(ApiKeysBy::Prefix(prefix.to_string()))
+                .await?
+            {
+                Some(found)
+                    if found.active
+                        && found.expires_at.is_none_or(|expires_at| now <= expires_at)
+                        && ApiKey::verify(&key, &found.key_hash) =>
+                {
+                    found
+                }
+                _ => return Err(AuthServiceError::InvalidCredentials),
+            };
+            event.credential_id = Some(api_key.credential_id);
+
+            // Keys can be hit many times a second, a minute of precision is plenty.
+            stale = api_key.last_used_at.is_none_or(|last_used_at| {
+                last_used_at + Duration::from_secs(RENEWAL_THRESHOLD_IN_SECONDS) < now
+            });
+            if stale {
+                self.repository
+                    .update_api_key(
+                        api_key.id,
+                        UpdateApiKeysDAO {
+                            last_used_at: Some(now),
+                        },
+                    )
+                    .await?;
+            }
+
+            Ok(AuthenticatedApiKey {
+                credential_id: api_key.credential_id.to_string(),
+                scopes: api_key.scopes,
+                roles: self.repository.list_roles(api_key.credential_id).await?,
+            })
+        }
+        .await;
+
+        if result.is_err() || stale {
+            self.audit(event, &result).await;
+        }
+        result
+    }
+
+    async fn list_auth_events(
+        &self,
+        caller: Caller,
+        filter: AuthEventsFilter,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<AuthEventResponse>, AuthServiceError> {
+        let mut event = AuditEvent::new(AuthEventType::ListAuthEvents);
+        let result: Result<Vec<AuthEventResponse>, AuthServiceError> = async {
+            let credential_id = self.authorize(caller, scope::ADMIN).await?;
+            event.credential_id = Some(credential_id);
+            let limit = match limit {
+                0 => DEFAULT_AUTH_EVENTS_PAGE_SIZE,
+                limit if limit > MAX_AUTH_EVENTS_PAGE_SIZE => {
+                    return Err(AuthServiceError::InvalidInput {
+                        message: format!("limit can't be over {}", MAX_AUTH_EVENTS_PAGE_SIZE),
+                    })
+                }
+                limit => limit,
+            };
+
+            let events = self
+                .repository
+                .list_auth_events(filter, offset, limit)
+                .await?
+                .into_iter()
+                .map(AuthEventResponse::from)
+                .collect();
+            Ok(events)
+        }
+        .await;
+
+        self.audit(event, &result).await;
+        result
+    }
+}
+
+mock! {
+    pub AuthService {}
+
+    #[async_trait::async_trait]
+    impl AuthServiceTrait for AuthService {
+        async fn authenticate(&self, session_id: String)
+        -> Result<AuthenticatedSession, AuthServiceError>;
+        async fn sign_in(
+            &self,
+            email: String,
+            password: String,
+            client: SessionClient,
+        ) -> Result<SignInResponse, AuthServiceError>;
+        async fn sign_in_with_identity(
+            &self,
+            identity: ExternalIdentity,
+            client: SessionClient,
+        ) -> Result<SignInResponse, AuthServiceError>;
+        async fn create_account(
+            &self,
+            email: String,
+            password: String,
+            idempotency_key: Option<String>,
+        ) -> Result<String, AuthServiceError>;
+        async fn delete_credential(&self, credential_id: String) -> Result<(), AuthServiceError>;
+        async fn change_password(
+            &self,
+            session_id: String,
+            current_password: String,
+            new_password: String,
+        ) -> Result<(), AuthServiceError>;
+        async fn change_email(
+            &self,
+            session_id: String,
+            current_password: String,
+            new_email: String,
+        ) -> Result<(), AuthServiceError>;
+        async fn confirm_email_change(&self, token: String) -> Result<(), AuthServiceError>;
+        async fn close_account(
+            &self,
+            session_id: String,
+            password: String,
+        ) -> Result<(), AuthServiceError>;
+        async fn export_credential(
+            &self,
+            session_id: String,
+        ) -> Result<CredentialExport, AuthServiceError>;
+        async fn list_sessions(
+            &self,
+            session_id: String,
+        ) -> Result<Vec<SessionResponse>, AuthServiceError>;
+        async fn revoke_session(
+            &self,
+            session_id: String,
+            target_session_id: String,
+        ) -> Result<(), AuthServiceError>;
+        async fn issue_tokens(&self, session_id: String) -> Result<TokenPair, AuthServiceError>;
+        async fn refresh_tokens(&self, refresh_token: String) -> Result<TokenPair, AuthServiceError>;
+        async fn revoke_refresh_token(&self, refresh_token: String) -> Result<(), AuthServiceError>;
+        fn jwks(&self) -> JwkSet;
+        async fn create_api_key(
+            &self,
+            session_id: String,
+            name: String,
+            scopes: Vec<String>,
+            expires_at: Option<DateTime<Utc>>,
+        ) -> Result<CreatedApiKey, AuthServiceError>;
+        async fn list_api_keys(
+            &self,
+            session_id: String,
+        ) -> Result<Vec<ApiKeyResponse>, AuthServiceError>;
+        async fn revoke_api_key(
+            &self,
+            session_id: String,
+            api_key_id: String,
+        ) -> Result<(), AuthServiceError>;
+        async fn authenticate_api_key(
+            &self,
+            key: String,
+        ) -> Result<AuthenticatedApiKey, AuthServiceError>;
+        async fn list_auth_events(
+            &self,
+            caller: Caller,
+            filter: AuthEventsFilter,
+            offset: u32,
+            limit: u32,
+        ) -> Result<Vec<AuthEventResponse>, AuthServiceError>;
+    }
+
+    impl Clone for AuthService {
+        fn clone(&self) -> Self;
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod test {
+    use crate::auth::{
+        AuthService, AuthServiceError, AuthServiceTrait, Caller, ExternalIdentity, SessionClient,
+        SessionPolicy,
+    };
+    use crate::mailer::RecordingMailer;
+    use crate::password_helper::PasswordHelper;
+    use crate::repository::PgAuthRepository;
+    use crate::revocations::RecordingRevocations;
+    use auth_database::connection::{PgPool, Pool, Postgres};
+    use auth_database::entities::auth_events::AuthEventsFilter;
+    use auth_database::entities::credential_roles::{
+        CreateCredentialRolesDAO, CredentialRolesRepository,
+    };
+    use auth_database::entities::credentials::{CreateCredentialsDAO, CredentialsRepository};
+    use auth_database::entities::sessions::{CreateSessionsDAO, SessionsBy, SessionsRepository};
+    use auth_database::router::DatabaseRouter;
+    use auth_database::traits::EntityRepository;
+    use auth_database::types::{Utc, Uuid};
+    use auth_token::Revocation;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    pub async fn setup_test() -> (AuthService, Arc<Pool<Postgres>>) {
+        dotenv::dotenv().ok();
+        let url =
+            std::env::var("TEST_AUTH_DATABASE_URL").expect("TEST_AUTH_DATABASE_URL must be set");
+        let pool = Arc::new(PgPool::connect(&url).await.unwrap());
+        let auth_service =
+            AuthService::new(PgAuthRepository::new(DatabaseRouter::new((*pool).clone())));
+        (auth_service, pool)
+    }
+    #[tokio::test]
+    async fn test_authenticate() {
+        let (auth_service, pool) = setup_test().await;
+
+        // Invalid uuid
+        let result = auth_service
+            .authenticate("f1ac1576-bd47-4fd3-a9af 49cd400c2cd7".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            AuthServiceError::InvalidInput {
+                message: "invalid session id format".to_string()
             },
             result
         );
@@ -236,19 +1542,25 @@ mod test {
 
         // session_expired
         let credential = CredentialsRepository::insert(
-            &pool,
+            &*pool,
             CreateCredentialsDAO {
                 email: "test@gmail.com".to_string(),
                 password: "123456".to_string(),
+                idempotency_key: None,
+                has_password: true,
             },
         )
         .await
         .unwrap();
         let session = SessionsRepository::insert(
-            &pool,
+            &*pool,
             CreateSessionsDAO {
                 credential_id: credential.id,
                 expires_at: Utc::now() + Duration::from_secs(10),
+                user_agent: None,
+                ip_address: None,
+                device_label: None,
+                identity_provider: None,
             },
         )
         .await
@@ -264,17 +1576,169 @@ mod test {
 
         // success
         let session = SessionsRepository::insert(
-            &pool,
+            &*pool,
             CreateSessionsDAO {
                 credential_id: credential.id,
                 expires_at: Utc::now() + Duration::from_secs(60),
+                user_agent: None,
+                ip_address: None,
+                device_label: None,
+                identity_provider: None,
             },
         )
         .await
         .unwrap();
 
-        let result = auth_service.authenticate(session.id.to_string()).await;
-        assert!(result.is_ok());
+        let result = auth_service
+            .authenticate(session.id.to_string())
+            .await
+            .unwrap();
+        // activity slides the expiry forward
+        assert!(result.expires_at > session.expires_at);
+    }
+
+    #[tokio::test]
+    async fn test_session_policy() {
+        let (auth_service, pool) = setup_test().await;
+        let auth_service = auth_service.with_session_policy(SessionPolicy {
+            idle_timeout: Duration::from_secs(60 * 60),
+            absolute_timeout: Duration::from_secs(60 * 5),
+        });
+        let credential = CredentialsRepository::insert(
+            &*pool,
+            CreateCredentialsDAO {
+                email: "session_policy@gmail.com".to_string(),
+                password: PasswordHelper::hash_password("123456").unwrap(),
+                idempotency_key: None,
+                has_password: true,
+            },
+        )
+        .await
+        .unwrap();
+
+        // new sessions are capped by the absolute timeout
+        let signed_in = auth_service
+            .sign_in(
+                "session_policy@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+        assert!(signed_in.expires_at <= Utc::now() + Duration::from_secs(60 * 5));
+
+        // renewal never goes past created_at + absolute timeout
+        let session = SessionsRepository::insert(
+            &*pool,
+            CreateSessionsDAO {
+                credential_id: credential.id,
+                expires_at: Utc::now() + Duration::from_secs(10),
+                user_agent: None,
+                ip_address: None,
+                device_label: None,
+                identity_provider: None,
+            },
+        )
+        .await
+        .unwrap();
+        let result = auth_service
+            .authenticate(session.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            result.expires_at.timestamp(),
+            (session.created_at + Duration::from_secs(60 * 5)).timestamp()
+        );
+
+        // expired and revoked sessions are purged
+        SessionsRepository::delete(&*pool, SessionsBy::Id(session.id))
+            .await
+            .unwrap();
+        assert!(auth_service.purge_sessions().await.unwrap() >= 1);
+        assert!(
+            SessionsRepository::try_get(&*pool, SessionsBy::Id(session.id))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sign_in_with_identity() {
+        let (auth_service, pool) = setup_test().await;
+        let subject = Uuid::new_v4().to_string();
+        let email = format!("{}@gmail.com", subject);
+        let identity = ExternalIdentity {
+            provider: "test".to_string(),
+            subject: subject.clone(),
+            email: Some(email.clone()),
+            email_verified: false,
+        };
+
+        // unverified emails can't be linked
+        let result = auth_service
+            .sign_in_with_identity(identity.clone(), SessionClient::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            AuthServiceError::InvalidInput {
+                message: "provider did not share a verified email".to_string()
+            },
+            result
+        );
+
+        // first sign in creates the credential
+        let identity = ExternalIdentity {
+            email_verified: true,
+            ..identity
+        };
+        let first = auth_service
+            .sign_in_with_identity(identity.clone(), SessionClient::default())
+            .await
+            .unwrap();
+        let first = auth_service.authenticate(first.id).await.unwrap();
+
+        // the provider subject keeps pointing to it, even if the email changes
+        let second = auth_service
+            .sign_in_with_identity(
+                ExternalIdentity {
+                    email: Some("changed@gmail.com".to_string()),
+                    email_verified: false,
+                    ..identity
+                },
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+        let second = auth_service.authenticate(second.id).await.unwrap();
+        assert_eq!(first.credential_id, second.credential_id);
+
+        // existing accounts are linked by their verified email
+        let credential = CredentialsRepository::insert(
+            &*pool,
+            CreateCredentialsDAO {
+                email: format!("linked-{}", email),
+                password: PasswordHelper::hash_password("123456").unwrap(),
+                idempotency_key: None,
+                has_password: true,
+            },
+        )
+        .await
+        .unwrap();
+        let linked = auth_service
+            .sign_in_with_identity(
+                ExternalIdentity {
+                    provider: "test".to_string(),
+                    subject: Uuid::new_v4().to_string(),
+                    email: Some(credential.email.clone()),
+                    email_verified: true,
+                },
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+        let linked = auth_service.authenticate(linked.id).await.unwrap();
+        assert_eq!(linked.credential_id, credential.id.to_string());
     }
 
     #[tokio::test]
@@ -283,7 +1747,11 @@ mod test {
 
         // invalid email
         let result = auth_service
-            .sign_in("test.com".to_string(), "123456".to_string())
+            .sign_in(
+                "test.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
             .await
             .unwrap_err();
         assert_eq!(
@@ -295,7 +1763,11 @@ mod test {
 
         // credential not found
         let result = auth_service
-            .sign_in("t@gmail.com".to_string(), "123456".to_string())
+            .sign_in(
+                "t@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
             .await
             .unwrap_err();
         assert_eq!(AuthServiceError::InvalidCredentials, result);
@@ -303,23 +1775,33 @@ mod test {
         // invalid_password
         let hash = PasswordHelper::hash_password("123456").unwrap();
         let _ = CredentialsRepository::insert(
-            &pool,
+            &*pool,
             CreateCredentialsDAO {
                 email: "test22@gmail.com".to_string(),
                 password: hash.to_string(),
+                idempotency_key: None,
+                has_password: true,
             },
         )
         .await
         .unwrap();
         let result = auth_service
-            .sign_in("test22@gmail.com".to_string(), "other password".to_string())
+            .sign_in(
+                "test22@gmail.com".to_string(),
+                "other password".to_string(),
+                SessionClient::default(),
+            )
             .await
             .unwrap_err();
         assert_eq!(AuthServiceError::InvalidCredentials, result);
 
         // success
         let result = auth_service
-            .sign_in("test22@gmail.com".to_string(), "123456".to_string())
+            .sign_in(
+                "test22@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
             .await;
         assert!(result.is_ok());
     }
@@ -329,7 +1811,7 @@ mod test {
         let (auth_service, _) = setup_test().await;
         // invalid email
         let result = auth_service
-            .create_account("test.com".to_string(), "123456".to_string())
+            .create_account("test.com".to_string(), "123456".to_string(), None)
             .await
             .unwrap_err();
         assert_eq!(
@@ -341,15 +1823,1280 @@ mod test {
 
         // create account success
         let result = auth_service
-            .create_account("test3@gmail.com".to_string(), "123456".to_string())
+            .create_account("test3@gmail.com".to_string(), "123456".to_string(), None)
             .await;
         assert!(result.is_ok());
 
         // account already exists
         let result = auth_service
-            .create_account("test3@gmail.com".to_string(), "123456".to_string())
+            .create_account("test3@gmail.com".to_string(), "123456".to_string(), None)
             .await
             .unwrap_err();
         assert_eq!(AuthServiceError::InvalidCredentials, result);
     }
+
+    #[tokio::test]
+    async fn test_create_account_idempotency_key() {
+        let (auth_service, _) = setup_test().await;
+        let key = Uuid::new_v4().to_string();
+        let email = format!("{}@idempotent.com", key);
+
+        let created = auth_service
+            .create_account(email.clone(), "123456".to_string(), Some(key.clone()))
+            .await
+            .unwrap();
+        // a retry gets the same credential back
+        let retried = auth_service
+            .create_account(email.clone(), "123456".to_string(), Some(key.clone()))
+            .await
+            .unwrap();
+        assert_eq!(created, retried);
+
+        // the key belongs to that account
+        let result = auth_service
+            .create_account(
+                format!("other-{}", email),
+                "123456".to_string(),
+                Some(key.clone()),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(result, AuthServiceError::InvalidInput { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_delete_credential() {
+        let (auth_service, _) = setup_test().await;
+        let email = format!("{}@delete.com", Uuid::new_v4());
+
+        // compensating an account creation frees the email
+        let created = auth_service
+            .create_account(email.clone(), "123456".to_string(), None)
+            .await
+            .unwrap();
+        auth_service
+            .delete_credential(created.clone())
+            .await
+            .unwrap();
+        // already gone
+        auth_service.delete_credential(created).await.unwrap();
+
+        // credentials in use stay
+        auth_service
+            .create_account(email.clone(), "123456".to_string(), None)
+            .await
+            .unwrap();
+        let session = auth_service
+            .sign_in(email, "123456".to_string(), SessionClient::default())
+            .await
+            .unwrap();
+        let credential = auth_service.authenticate(session.id.clone()).await.unwrap();
+        let result = auth_service
+            .delete_credential(credential.credential_id)
+            .await
+            .unwrap_err();
+        assert!(matches!(result, AuthServiceError::InvalidInput { .. }));
+        assert!(auth_service.authenticate(session.id).await.is_ok());
+
+        let result = auth_service
+            .delete_credential("not a uuid".to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(result, AuthServiceError::InvalidInput { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let (auth_service, _) = setup_test().await;
+        let revocations = Arc::new(RecordingRevocations::default());
+        let auth_service = auth_service.with_revocations(revocations.clone());
+        auth_service
+            .create_account(
+                "change_password@gmail.com".to_string(),
+                "123456".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        let current = auth_service
+            .sign_in(
+                "change_password@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+        let other = auth_service
+            .sign_in(
+                "change_password@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+
+        // wrong current password
+        let result = auth_service
+            .change_password(
+                current.id.clone(),
+                "wrong".to_string(),
+                "654321".to_string(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // success, other sessions are revoked
+        auth_service
+            .change_password(
+                current.id.clone(),
+                "123456".to_string(),
+                "654321".to_string(),
+            )
+            .await
+            .unwrap();
+        let authenticated = auth_service.authenticate(current.id).await.unwrap();
+        assert_eq!(
+            AuthServiceError::InvalidCredentials,
+            auth_service.authenticate(other.id).await.unwrap_err()
+        );
+        assert_eq!(
+            *revocations.published.lock().unwrap(),
+            vec![Revocation::Credential(authenticated.credential_id)]
+        );
+
+        // old password no longer works
+        let result = auth_service
+            .sign_in(
+                "change_password@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+        assert!(auth_service
+            .sign_in(
+                "change_password@gmail.com".to_string(),
+                "654321".to_string(),
+                SessionClient::default()
+            )
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_change_email() {
+        let (auth_service, _) = setup_test().await;
+        let mailer = Arc::new(RecordingMailer::default());
+        let auth_service = auth_service.with_mailer(mailer.clone());
+        auth_service
+            .create_account(
+                "change_email@gmail.com".to_string(),
+                "123456".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        auth_service
+            .create_account(
+                "taken_email@gmail.com".to_string(),
+                "123456".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        let session = auth_service
+            .sign_in(
+                "change_email@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+
+        // invalid email
+        let result = auth_service
+            .change_email(session.id.clone(), "123456".to_string(), "new".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            AuthServiceError::InvalidInput {
+                message: "invalid email".to_string()
+            },
+            result
+        );
+
+        // email already in use
+        let result = auth_service
+            .change_email(
+                session.id.clone(),
+                "123456".to_string(),
+                "taken_email@gmail.com".to_string(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // the new address must be verified before it's used
+        auth_service
+            .change_email(
+                session.id.clone(),
+                "123456".to_string(),
+                "changed_email@gmail.com".to_string(),
+            )
+            .await
+            .unwrap();
+        let (email, token) = mailer.sent.lock().unwrap().pop().unwrap();
+        assert_eq!(email, "changed_email@gmail.com");
+        assert!(auth_service
+            .sign_in(
+                "change_email@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default()
+            )
+            .await
+            .is_ok());
+
+        auth_service
+            .confirm_email_change(token.clone())
+            .await
+            .unwrap();
+        assert!(auth_service
+            .sign_in(
+                "changed_email@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default()
+            )
+            .await
+            .is_ok());
+
+        // tokens can only be used once
+        let result = auth_service.confirm_email_change(token).await.unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+    }
+
+    #[tokio::test]
+    async fn test_export_credential() {
+        let (auth_service, _) = setup_test().await;
+        let id = auth_service
+            .create_account("export@gmail.com".to_string(), "123456".to_string(), None)
+            .await
+            .unwrap();
+        let first = auth_service
+            .sign_in(
+                "export@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+        let second = auth_service
+            .sign_in(
+                "export@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+
+        let authenticated = auth_service.authenticate(first.id.clone()).await.unwrap();
+        assert_eq!(authenticated.credential_id, id);
+
+        let export = auth_service
+            .export_credential(first.id.clone())
+            .await
+            .unwrap();
+        assert_eq!(export.id, id);
+        assert_eq!(export.email, "export@gmail.com");
+        assert_eq!(
+            export.sessions.iter().map(|s| &s.id).collect::<Vec<_>>(),
+            vec![&second.id, &first.id]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_and_revoke_sessions() {
+        let (auth_service, _) = setup_test().await;
+        let revocations = Arc::new(RecordingRevocations::default());
+        let auth_service = auth_service.with_revocations(revocations.clone());
+        auth_service
+            .create_account("sessions@gmail.com".to_string(), "123456".to_string(), None)
+            .await
+            .unwrap();
+        let laptop = auth_service
+            .sign_in(
+                "sessions@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient {
+                    user_agent: Some(
+                        "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0"
+                            .to_string(),
+                    ),
+                    ip_address: Some("10.0.0.1".to_string()),
+                    device_label: None,
+                },
+            )
+            .await
+            .unwrap();
+        let phone = auth_service
+            .sign_in(
+                "sessions@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient {
+                    user_agent: None,
+                    ip_address: Some("10.0.0.2".to_string()),
+                    device_label: Some("My phone".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+
+        let sessions = auth_service.list_sessions(laptop.id.clone()).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, phone.id);
+        assert_eq!(sessions[0].device_label.as_deref(), Some("My phone"));
+        assert!(!sessions[0].current);
+        assert_eq!(sessions[1].id, laptop.id);
+        assert_eq!(
+            sessions[1].device_label.as_deref(),
+            Some("Firefox on Linux")
+        );
+        assert_eq!(sessions[1].ip_address.as_deref(), Some("10.0.0.1"));
+        assert!(sessions[1].current);
+
+        // can't revoke sessions of other users
+        auth_service
+            .create_account(
+                "sessions2@gmail.com".to_string(),
+                "123456".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        let stranger = auth_service
+            .sign_in(
+                "sessions2@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+        let result = auth_service
+            .revoke_session(stranger.id, phone.id.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // revoke the phone from the laptop
+        auth_service
+            .revoke_session(laptop.id.clone(), phone.id.clone())
+            .await
+            .unwrap();
+        assert!(auth_service.authenticate(phone.id.clone()).await.is_err());
+        assert_eq!(
+            *revocations.published.lock().unwrap(),
+            vec![Revocation::Session(phone.id)]
+        );
+        let sessions = auth_service.list_sessions(laptop.id).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let (auth_service, _) = setup_test().await;
+        let credential_id = auth_service
+            .create_account("apikeys@gmail.com".to_string(), "123456".to_string(), None)
+            .await
+            .unwrap();
+        let session = auth_service
+            .sign_in(
+                "apikeys@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+
+        // unknown scopes are rejected
+        let result = auth_service
+            .create_api_key(
+                session.id.clone(),
+                "catalog sync".to_string(),
+                vec!["movies:write".to_string()],
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(result, AuthServiceError::InvalidInput { .. }));
+
+        let created = auth_service
+            .create_api_key(
+                session.id.clone(),
+                "catalog sync".to_string(),
+                vec!["movies:read".to_string()],
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(created.key.contains(&created.api_key.prefix));
+
+        let authenticated = auth_service
+            .authenticate_api_key(created.key.clone())
+            .await
+            .unwrap();
+        assert_eq!(authenticated.credential_id, credential_id);
+        assert_eq!(authenticated.scopes, vec!["movies:read".to_string()]);
+
+        // a guessed secret for a known prefix
+        let forged = format!("rfx_{}_guess", created.api_key.prefix);
+        let result = auth_service.authenticate_api_key(forged).await.unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        let keys = auth_service
+            .list_api_keys(session.id.clone())
+            .await
+            .unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].last_used_at.is_some());
+
+        auth_service
+            .revoke_api_key(session.id.clone(), created.api_key.id)
+            .await
+            .unwrap();
+        let result = auth_service
+            .authenticate_api_key(created.key)
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+        assert!(auth_service
+            .list_api_keys(session.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_close_account() {
+        let (auth_service, _) = setup_test().await;
+        auth_service
+            .create_account("close@gmail.com".to_string(), "123456".to_string(), None)
+            .await
+            .unwrap();
+        let session = auth_service
+            .sign_in(
+                "close@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+
+        // wrong password
+        let result = auth_service
+            .close_account(session.id.clone(), "wrong".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // success, sessions are revoked and the credential can't sign in anymore
+        auth_service
+            .close_account(session.id.clone(), "123456".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            AuthServiceError::InvalidCredentials,
+            auth_service.authenticate(session.id).await.unwrap_err()
+        );
+        assert_eq!(
+            AuthServiceError::InvalidCredentials,
+            auth_service
+                .sign_in(
+                    "close@gmail.com".to_string(),
+                    "123456".to_string(),
+                    SessionClient::default()
+                )
+                .await
+                .unwrap_err()
+        );
+
+        // erased once the grace period is over
+        assert!(
+            auth_service
+                .erase_closed_accounts(Duration::from_secs(0))
+                .await
+                .unwrap()
+                >= 1
+        );
+        assert!(auth_service
+            .create_account("close@gmail.com".to_string(), "123456".to_string(), None)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let (auth_service, pool) = setup_test().await;
+        let since = Utc::now();
+        let email = format!("{}@gmail.com", Uuid::new_v4());
+        let client = SessionClient {
+            ip_address: Some("10.0.0.7".to_string()),
+            ..Default::default()
+        };
+        auth_service
+            .create_account(email.clone(), "123456".to_string(), None)
+            .await
+            .unwrap();
+
+        // a failed sign in is attributed to the credential it targeted
+        auth_service
+            .sign_in(email.clone(), "wrong".to_string(), client.clone())
+            .await
+            .unwrap_err();
+        let session = auth_service
+            .sign_in(email.clone(), "123456".to_string(), client)
+            .await
+            .unwrap();
+        let authenticated = auth_service.authenticate(session.id.clone()).await.unwrap();
+        let credential_id = Uuid::parse_str(&authenticated.credential_id).unwrap();
+        let admin = || Caller::Session(session.id.clone());
+
+        // only admins read the log
+        let result = auth_service
+            .list_auth_events(admin(), AuthEventsFilter::default(), 0, 0)
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::Forbidden, result);
+        CredentialRolesRepository::insert(
+            &*pool,
+            CreateCredentialRolesDAO {
+                credential_id,
+                role: "admin".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let events = auth_service
+            .list_auth_events(
+                admin(),
+                AuthEventsFilter {
+                    credential_id: Some(credential_id),
+                    since: Some(since),
+                    ..Default::default()
+                },
+                0,
+                0,
+            )
+            .await
+            .unwrap();
+        let events = events
+            .iter()
+            .map(|e| (e.event_type.as_str(), e.outcome.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                ("list_auth_events", "failure"),
+                ("sign_in", "success"),
+                ("sign_in", "failure"),
+                ("create_account", "success")
+            ]
+        );
+
+        // filter by ip address
+        let failures = auth_service
+            .list_auth_events(
+                admin(),
+                AuthEventsFilter {
+                    ip_address: Some("10.0.0.7".to_string()),
+                    outcome: Some("failure".to_string()),
+                    since: Some(since),
+                    ..Default::default()
+                },
+                0,
+                10,
+            )
+            .await
+            .unwrap();
+        assert!(failures
+            .iter()
+            .any(|e| e.credential_id == Some(credential_id.to_string())));
+
+        // page size is bounded
+        assert!(auth_service
+            .list_auth_events(admin(), AuthEventsFilter::default(), 0, 10_000)
+            .await
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod in_memory_test {
+    use crate::audit::{OUTCOME_FAILURE, OUTCOME_SUCCESS};
+    use crate::auth::{
+        AuthService, AuthServiceError, AuthServiceTrait, Caller, ExternalIdentity, SessionClient,
+    };
+    use crate::mailer::RecordingMailer;
+    use crate::repository::{AuthRepository, InMemoryAuthRepository};
+    use crate::revocations::RecordingRevocations;
+    use auth_database::entities::auth_events::AuthEventsFilter;
+    use auth_database::entities::credentials::CredentialsBy;
+    use auth_database::entities::sessions::UpdateSessionsDAO;
+    use auth_database::types::{Utc, Uuid};
+    use auth_token::{scope, Revocation, TokenType};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    type TestAuthService = AuthService<InMemoryAuthRepository>;
+
+    fn setup() -> (TestAuthService, InMemoryAuthRepository) {
+        let repository = InMemoryAuthRepository::new();
+        (AuthService::new(repository.clone()), repository)
+    }
+
+    /// Signs up a credential holding the admin role, returns its session
+    async fn sign_up_admin(
+        auth_service: &TestAuthService,
+        repository: &InMemoryAuthRepository,
+        email: &str,
+    ) -> String {
+        let session_id = sign_up(auth_service, email, "123456").await;
+        let credential = repository
+            .try_get_credential(CredentialsBy::Email(email.to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        repository.grant_role(credential.id, scope::ADMIN);
+        session_id
+    }
+
+    async fn sign_up(auth_service: &TestAuthService, email: &str, password: &str) -> String {
+        auth_service
+            .create_account(email.to_string(), password.to_string(), None)
+            .await
+            .unwrap();
+        auth_service
+            .sign_in(
+                email.to_string(),
+                password.to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap()
+            .id
+    }
+
+    #[tokio::test]
+    async fn test_account_lifecycle() {
+        let (auth_service, _) = setup();
+        let session_id = sign_up(&auth_service, "lifecycle@gmail.com", "123456").await;
+
+        let authenticated = auth_service.authenticate(session_id.clone()).await.unwrap();
+        let export = auth_service
+            .export_credential(session_id.clone())
+            .await
+            .unwrap();
+        assert_eq!(export.id, authenticated.credential_id);
+        assert_eq!(export.email, "lifecycle@gmail.com");
+        assert_eq!(export.sessions.len(), 1);
+        assert!(export.sessions[0].current);
+
+        // the address is taken
+        let result = auth_service
+            .create_account(
+                "lifecycle@gmail.com".to_string(),
+                "654321".to_string(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // wrong password
+        let result = auth_service
+            .sign_in(
+                "lifecycle@gmail.com".to_string(),
+                "654321".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        auth_service
+            .close_account(session_id.clone(), "123456".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            AuthServiceError::InvalidCredentials,
+            auth_service.authenticate(session_id).await.unwrap_err()
+        );
+        let result = auth_service
+            .sign_in(
+                "lifecycle@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // erased once the grace period is over, the address can be used again
+        assert_eq!(
+            0,
+            auth_service
+                .erase_closed_accounts(Duration::from_secs(60))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            auth_service
+                .erase_closed_accounts(Duration::ZERO)
+                .await
+                .unwrap()
+        );
+        sign_up(&auth_service, "lifecycle@gmail.com", "654321").await;
+    }
+
+    #[tokio::test]
+    async fn test_identity_account_lifecycle() {
+        let (auth_service, _) = setup();
+        let identity = || ExternalIdentity {
+            provider: "google".to_string(),
+            subject: "110169484474386276334".to_string(),
+            email: Some("oidc@gmail.com".to_string()),
+            email_verified: true,
+        };
+        let session = auth_service
+            .sign_in_with_identity(identity(), SessionClient::default())
+            .await
+            .unwrap();
+
+        // nobody knows the password, the provider sign in vouches for the account
+        auth_service
+            .change_password(session.id.clone(), String::new(), "123456".to_string())
+            .await
+            .unwrap();
+        let password_session = auth_service
+            .sign_in(
+                "oidc@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+        let result = auth_service
+            .close_account(password_session.id, String::new())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // once there's a password the provider doesn't stand in for it anymore
+        let result = auth_service
+            .close_account(session.id.clone(), String::new())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+        auth_service
+            .close_account(session.id.clone(), "123456".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            AuthServiceError::InvalidCredentials,
+            auth_service.authenticate(session.id).await.unwrap_err()
+        );
+        let result = auth_service
+            .sign_in_with_identity(identity(), SessionClient::default())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+    }
+
+    #[tokio::test]
+    async fn test_linked_identity_needs_the_password() {
+        let (auth_service, _) = setup();
+        sign_up(&auth_service, "linked@gmail.com", "123456").await;
+
+        // whoever controls the email at the provider gets linked to the account
+        let session = auth_service
+            .sign_in_with_identity(
+                ExternalIdentity {
+                    provider: "google".to_string(),
+                    subject: "linked".to_string(),
+                    email: Some("linked@gmail.com".to_string()),
+                    email_verified: true,
+                },
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+
+        // but still needs the password for sensitive changes
+        let result = auth_service
+            .change_password(session.id.clone(), String::new(), "654321".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+        let result = auth_service
+            .close_account(session.id.clone(), String::new())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+        auth_service
+            .change_password(session.id, "123456".to_string(), "654321".to_string())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_account_idempotency_key() {
+        let (auth_service, _) = setup();
+        let key = Some("retry".to_string());
+
+        let created = auth_service
+            .create_account(
+                "retry@gmail.com".to_string(),
+                "123456".to_string(),
+                key.clone(),
+            )
+            .await
+            .unwrap();
+        let retried = auth_service
+            .create_account(
+                "retry@gmail.com".to_string(),
+                "123456".to_string(),
+                key.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(created, retried);
+
+        let result = auth_service
+            .create_account("other@gmail.com".to_string(), "123456".to_string(), key)
+            .await
+            .unwrap_err();
+        assert!(matches!(result, AuthServiceError::InvalidInput { .. }));
+
+        // never signed in, so it can be undone
+        auth_service.delete_credential(created).await.unwrap();
+        sign_up(&auth_service, "retry@gmail.com", "123456").await;
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let (auth_service, _) = setup();
+        let revocations = Arc::new(RecordingRevocations::default());
+        let auth_service = auth_service.with_revocations(revocations.clone());
+        let session_id = sign_up(&auth_service, "password@gmail.com", "123456").await;
+        let other = auth_service
+            .sign_in(
+                "password@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap();
+
+        let result = auth_service
+            .change_password(
+                session_id.clone(),
+                "wrong".to_string(),
+                "654321".to_string(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        auth_service
+            .change_password(
+                session_id.clone(),
+                "123456".to_string(),
+                "654321".to_string(),
+            )
+            .await
+            .unwrap();
+        let authenticated = auth_service.authenticate(session_id).await.unwrap();
+        assert!(auth_service.authenticate(other.id).await.is_err());
+        assert_eq!(
+            *revocations.published.lock().unwrap(),
+            vec![Revocation::Credential(authenticated.credential_id)]
+        );
+        assert!(auth_service
+            .sign_in(
+                "password@gmail.com".to_string(),
+                "654321".to_string(),
+                SessionClient::default()
+            )
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_change_email() {
+        let (auth_service, _) = setup();
+        let mailer = Arc::new(RecordingMailer::default());
+        let auth_service = auth_service.with_mailer(mailer.clone());
+        let session_id = sign_up(&auth_service, "old@gmail.com", "123456").await;
+
+        auth_service
+            .change_email(
+                session_id,
+                "123456".to_string(),
+                "new@gmail.com".to_string(),
+            )
+            .await
+            .unwrap();
+        let (email, token) = mailer.sent.lock().unwrap().pop().unwrap();
+        assert_eq!(email, "new@gmail.com");
+
+        auth_service
+            .confirm_email_change(token.clone())
+            .await
+            .unwrap();
+        assert!(auth_service
+            .sign_in(
+                "new@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient::default()
+            )
+            .await
+            .is_ok());
+        let result = auth_service.confirm_email_change(token).await.unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let (auth_service, _) = setup();
+        let laptop = sign_up(&auth_service, "sessions@gmail.com", "123456").await;
+        let phone = auth_service
+            .sign_in(
+                "sessions@gmail.com".to_string(),
+                "123456".to_string(),
+                SessionClient {
+                    device_label: Some("phone".to_string()),
+                    ..SessionClient::default()
+                },
+            )
+            .await
+            .unwrap();
+        let stranger = sign_up(&auth_service, "stranger@gmail.com", "123456").await;
+
+        let sessions = auth_service.list_sessions(laptop.clone()).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].device_label, Some("phone".to_string()));
+
+        // only the owner can revoke a session
+        let result = auth_service
+            .revoke_session(stranger, phone.id.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        auth_service
+            .revoke_session(laptop.clone(), phone.id.clone())
+            .await
+            .unwrap();
+        assert!(auth_service.authenticate(phone.id).await.is_err());
+        assert_eq!(auth_service.list_sessions(laptop).await.unwrap().len(), 1);
+        assert_eq!(1, auth_service.purge_sessions().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_tokens() {
+        let (auth_service, _) = setup();
+        let session_id = sign_up(&auth_service, "tokens@gmail.com", "123456").await;
+
+        let tokens = auth_service.issue_tokens(session_id.clone()).await.unwrap();
+        let refreshed = auth_service
+            .refresh_tokens(tokens.refresh_token.clone())
+            .await
+            .unwrap();
+        assert_ne!(tokens.refresh_token, refreshed.refresh_token);
+
+        // a refresh token seen twice leaked, the session goes away with it
+        let result = auth_service
+            .refresh_tokens(tokens.refresh_token)
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+        assert!(auth_service.authenticate(session_id).await.is_err());
+        assert_eq!(0, auth_service.purge_revoked_tokens().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let (auth_service, _) = setup();
+        let session_id = sign_up(&auth_service, "keys@gmail.com", "123456").await;
+
+        let created = auth_service
+            .create_api_key(
+                session_id.clone(),
+                "ci".to_string(),
+                vec![scope::MOVIES_READ.to_string()],
+                None,
+            )
+            .await
+            .unwrap();
+        let authenticated = auth_service
+            .authenticate_api_key(created.key.clone())
+            .await
+            .unwrap();
+        assert_eq!(authenticated.scopes, vec![scope::MOVIES_READ.to_string()]);
+        assert!(auth_service
+            .list_api_keys(session_id.clone())
+            .await
+            .unwrap()[0]
+            .last_used_at
+            .is_some());
+
+        auth_service
+            .revoke_api_key(session_id.clone(), created.api_key.id)
+            .await
+            .unwrap();
+        assert!(auth_service
+            .authenticate_api_key(created.key)
+            .await
+            .is_err());
+        assert!(auth_service
+            .list_api_keys(session_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_roles() {
+        let (auth_service, repository) = setup();
+        let session_id = sign_up(&auth_service, "roles@gmail.com", "123456").await;
+        let admin_key = || {
+            auth_service.create_api_key(
+                session_id.clone(),
+                "admin".to_string(),
+                vec![scope::ADMIN.to_string()],
+                None,
+            )
+        };
+
+        // privileged scopes need the role
+        let result = admin_key().await.unwrap_err();
+        assert!(matches!(result, AuthServiceError::InvalidInput { .. }));
+
+        let authenticated = auth_service.authenticate(session_id.clone()).await.unwrap();
+        assert!(authenticated.roles.is_empty());
+        let credential_id = Uuid::from_str(&authenticated.credential_id).unwrap();
+        repository.grant_role(credential_id, scope::ADMIN);
+
+        // sessions, tokens and keys all carry it
+        let authenticated = auth_service.authenticate(session_id.clone()).await.unwrap();
+        assert_eq!(authenticated.roles, vec![scope::ADMIN.to_string()]);
+        let tokens = auth_service.issue_tokens(session_id.clone()).await.unwrap();
+        let claims = auth_service
+            .tokens
+            .verify(&tokens.access_token, TokenType::Access)
+            .await
+            .unwrap();
+        assert_eq!(claims.roles, vec![scope::ADMIN.to_string()]);
+        let refreshed = auth_service
+            .refresh_tokens(tokens.refresh_token)
+            .await
+            .unwrap();
+        let claims = auth_service
+            .tokens
+            .verify(&refreshed.access_token, TokenType::Access)
+            .await
+            .unwrap();
+        assert_eq!(claims.roles, vec![scope::ADMIN.to_string()]);
+        let created = admin_key().await.unwrap();
+        let authenticated = auth_service
+            .authenticate_api_key(created.key)
+            .await
+            .unwrap();
+        assert_eq!(authenticated.scopes, vec![scope::ADMIN.to_string()]);
+        assert_eq!(authenticated.roles, vec![scope::ADMIN.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let (auth_service, repository) = setup();
+        let session_id = sign_up(&auth_service, "audit@gmail.com", "123456").await;
+        let credential_id = auth_service
+            .authenticate(session_id.clone())
+            .await
+            .unwrap()
+            .credential_id;
+        repository.grant_role(Uuid::from_str(&credential_id).unwrap(), scope::ADMIN);
+        auth_service
+            .sign_in(
+                "audit@gmail.com".to_string(),
+                "wrong".to_string(),
+                SessionClient::default(),
+            )
+            .await
+            .unwrap_err();
+
+        let events = auth_service
+            .list_auth_events(
+                Caller::Session(session_id),
+                AuthEventsFilter {
+                    event_type: Some("sign_in".to_string()),
+                    ..AuthEventsFilter::default()
+                },
+                0,
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].outcome, OUTCOME_FAILURE);
+        assert_eq!(events[1].outcome, OUTCOME_SUCCESS);
+        assert!(events
+            .iter()
+            .all(|e| e.credential_id.as_ref() == Some(&credential_id)));
+    }
+
+    #[tokio::test]
+    async fn test_list_auth_events_needs_admin() {
+        let (auth_service, repository) = setup();
+        let user = sign_up(&auth_service, "user@gmail.com", "123456").await;
+        let admin = sign_up_admin(&auth_service, &repository, "admin@gmail.com").await;
+        let list = |caller: Caller| {
+            auth_service.list_auth_events(caller, AuthEventsFilter::default(), 0, 0)
+        };
+        let key = |session_id: &str, scopes: &[&str]| {
+            auth_service.create_api_key(
+                session_id.to_string(),
+                "audit".to_string(),
+                scopes.iter().map(|s| s.to_string()).collect(),
+                None,
+            )
+        };
+
+        let result = list(Caller::Session(user.clone())).await.unwrap_err();
+        assert_eq!(AuthServiceError::Forbidden, result);
+        let tokens = auth_service.issue_tokens(user.clone()).await.unwrap();
+        let result = list(Caller::AccessToken(tokens.access_token))
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::Forbidden, result);
+        let user_key = key(&user, &[scope::MOVIES_READ]).await.unwrap();
+        let result = list(Caller::ApiKey(user_key.key)).await.unwrap_err();
+        assert_eq!(AuthServiceError::Forbidden, result);
+        let result = list(Caller::Session(Uuid::new_v4().to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+
+        // admins however they sign in, keys need the scope too
+        assert!(list(Caller::Session(admin.clone())).await.is_ok());
+        let tokens = auth_service.issue_tokens(admin.clone()).await.unwrap();
+        assert!(list(Caller::AccessToken(tokens.access_token.clone()))
+            .await
+            .is_ok());
+        let admin_key = key(&admin, &[scope::ADMIN]).await.unwrap();
+        assert!(list(Caller::ApiKey(admin_key.key)).await.is_ok());
+        let reader_key = key(&admin, &[scope::MOVIES_READ]).await.unwrap();
+        let result = list(Caller::ApiKey(reader_key.key)).await.unwrap_err();
+        assert_eq!(AuthServiceError::Forbidden, result);
+
+        // access tokens go with their session
+        auth_service
+            .revoke_session(admin.clone(), admin)
+            .await
+            .unwrap();
+        let result = list(Caller::AccessToken(tokens.access_token))
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::InvalidCredentials, result);
+    }
+
+    #[tokio::test]
+    async fn test_audit_authentications() {
+        let (auth_service, repository) = setup();
+        let session_id = sign_up(&auth_service, "sampled@gmail.com", "123456").await;
+        let admin = sign_up_admin(&auth_service, &repository, "sampled.admin@gmail.com").await;
+        let events = |event_type: &str| {
+            auth_service.list_auth_events(
+                Caller::Session(admin.clone()),
+                AuthEventsFilter {
+                    event_type: Some(event_type.to_string()),
+                    ..AuthEventsFilter::default()
+                },
+                0,
+                0,
+            )
+        };
+
+        // the sign in just set the expiry, there's nothing to renew
+        let credential_id = auth_service
+            .authenticate(session_id.clone())
+            .await
+            .unwrap()
+            .credential_id;
+        assert!(events("authenticate").await.unwrap().is_empty());
+
+        // renewing the expiry is recorded, the requests right after aren't
+        let session = Uuid::from_str(&session_id).unwrap();
+        repository
+            .update_session(
+                session,
+                UpdateSessionsDAO {
+                    expires_at: Utc::now() + Duration::from_secs(60),
+                },
+            )
+            .await
+            .unwrap();
+        auth_service.authenticate(session_id.clone()).await.unwrap();
+        auth_service.authenticate(session_id.clone()).await.unwrap();
+        auth_service
+            .authenticate(Uuid::new_v4().to_string())
+            .await
+            .unwrap_err();
+        let authenticated = events("authenticate").await.unwrap();
+        assert_eq!(authenticated.len(), 2);
+        assert_eq!(authenticated[0].outcome, OUTCOME_FAILURE);
+        assert_eq!(authenticated[1].outcome, OUTCOME_SUCCESS);
+        assert_eq!(authenticated[1].credential_id, Some(credential_id));
+
+        // keys are recorded when their last use moves
+        let created = auth_service
+            .create_api_key(
+                session_id,
+                "ci".to_string(),
+                vec![scope::MOVIES_READ.to_string()],
+                None,
+            )
+            .await
+            .unwrap();
+        auth_service
+            .authenticate_api_key(created.key.clone())
+            .await
+            .unwrap();
+        auth_service
+            .authenticate_api_key(created.key)
+            .await
+            .unwrap();
+        let authenticated = events("authenticate_api_key").await.unwrap();
+        assert_eq!(authenticated.len(), 1);
+        assert_eq!(authenticated[0].outcome, OUTCOME_SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn test_unavailable() {
+        let (auth_service, repository) = setup();
+        let session_id = sign_up(&auth_service, "unavailable@gmail.com", "123456").await;
+
+        repository.set_available(false);
+        let result = auth_service
+            .authenticate(session_id.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(AuthServiceError::Unavailable, result);
+
+        repository.set_available(true);
+        assert!(auth_service.authenticate(session_id).await.is_ok());
+    }
 }
@@ -1,7 +1,18 @@
-use crate::auth::{AuthServiceError, AuthServiceTrait};
+use crate::auth::{
+    ApiKeyResponse, AuthEventResponse, AuthServiceError, AuthServiceTrait, Caller, SessionResponse,
+};
+use auth_database::entities::auth_events::AuthEventsFilter;
+use auth_database::types::{DateTime, TimeZone, Utc, Uuid};
 use grpc_interfaces::auth::{
-    auth_server::Auth, AuthenticateRequest, CreateCredentialsRequest, CreateCredentialsResponse,
+    auth_server::Auth, ApiKey, AuthEvent, AuthenticateApiKeyRequest, AuthenticateApiKeyResponse,
+    AuthenticateRequest, AuthenticateResponse, ChangeEmailRequest, ChangePasswordRequest,
+    CloseAccountRequest, ConfirmEmailChangeRequest, CreateApiKeyRequest, CreateApiKeyResponse,
+    CreateCredentialsRequest, CreateCredentialsResponse, DeleteCredentialRequest,
+    ExportCredentialRequest, ExportCredentialResponse, ListApiKeysRequest, ListApiKeysResponse,
+    ListAuthEventsRequest, ListAuthEventsResponse, ListSessionsRequest, ListSessionsResponse,
+    RevokeApiKeyRequest, RevokeSessionRequest, Session,
 };
+use std::str::FromStr;
 use tonic::{Request, Response, Status};
 
 impl From<AuthServiceError> for Status {
@@ -9,11 +20,72 @@ impl From<AuthServiceError> for Status {
         match value {
             AuthServiceError::InvalidCredentials => Status::unauthenticated("Invalid Credentials"),
             AuthServiceError::InvalidInput { message } => Status::invalid_argument(message),
+            AuthServiceError::NotFound { message } => Status::not_found(message),
+            AuthServiceError::AlreadyExists { message } => Status::already_exists(message),
+            AuthServiceError::Conflict => Status::aborted("Conflict"),
+            AuthServiceError::Unavailable => Status::unavailable("Unavailable"),
+            AuthServiceError::Forbidden => Status::permission_denied("Permission Denied"),
             AuthServiceError::InternalServerError => Status::unknown("Internal Server Error"),
         }
     }
 }
 
+impl From<SessionResponse> for Session {
+    fn from(value: SessionResponse) -> Self {
+        Self {
+            id: value.id,
+            created_at: value.created_at.timestamp(),
+            expires_at: value.expires_at.timestamp(),
+            active: value.active,
+            user_agent: value.user_agent.unwrap_or_default(),
+            ip_address: value.ip_address.unwrap_or_default(),
+            device_label: value.device_label.unwrap_or_default(),
+            current: value.current,
+        }
+    }
+}
+
+impl From<ApiKeyResponse> for ApiKey {
+    fn from(value: ApiKeyResponse) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            prefix: value.prefix,
+            scopes: value.scopes,
+            created_at: value.created_at.timestamp(),
+            expires_at: value.expires_at.map_or(0, |t| t.timestamp()),
+            last_used_at: value.last_used_at.map_or(0, |t| t.timestamp()),
+        }
+    }
+}
+
+impl From<AuthEventResponse> for AuthEvent {
+    fn from(value: AuthEventResponse) -> Self {
+        Self {
+            id: value.id,
+            created_at: value.created_at.timestamp(),
+            event_type: value.event_type,
+            credential_id: value.credential_id.unwrap_or_default(),
+            ip_address: value.ip_address.unwrap_or_default(),
+            user_agent: value.user_agent.unwrap_or_default(),
+            outcome: value.outcome,
+            detail: value.detail.unwrap_or_default(),
+        }
+    }
+}
+
+// grpc sends empty strings and 0 for missing values
+fn non_empty(value: String) -> Option<String> {
+    Some(value).filter(|v| !v.is_empty())
+}
+
+fn timestamp(value: i64) -> Option<Option<DateTime<Utc>>> {
+    match value {
+        0 => Some(None),
+        value => Utc.timestamp_opt(value, 0).single().map(Some),
+    }
+}
+
 #[derive(Debug)]
 pub struct GRPCAuthService<T: AuthServiceTrait> {
     service: T,
@@ -40,32 +112,257 @@ where
         let input = request.into_inner();
         let id = self
             .service
-            .create_account(input.email, input.password)
+            .create_account(
+                input.email,
+                input.password,
+                non_empty(input.idempotency_key),
+            )
             .await
             .map_err(Status::from)?;
         let response = CreateCredentialsResponse { user_id: id };
         Ok(Response::new(response))
     }
 
+    async fn delete_credential(
+        &self,
+        request: Request<DeleteCredentialRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.service
+            .delete_credential(request.into_inner().credential_id)
+            .await?;
+
+        Ok(Response::new(()))
+    }
+
     async fn authenticate(
         &self,
         request: Request<AuthenticateRequest>,
+    ) -> Result<Response<AuthenticateResponse>, Status> {
+        let session = self
+            .service
+            .authenticate(request.into_inner().session_id)
+            .await?;
+
+        Ok(Response::new(AuthenticateResponse {
+            credential_id: session.credential_id,
+            expires_at: session.expires_at.timestamp(),
+            roles: session.roles,
+        }))
+    }
+
+    async fn change_password(
+        &self,
+        request: Request<ChangePasswordRequest>,
     ) -> Result<Response<()>, Status> {
+        let input = request.into_inner();
         self.service
-            .authenticate(request.into_inner().session_id)
+            .change_password(input.session_id, input.current_password, input.new_password)
+            .await?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn change_email(
+        &self,
+        request: Request<ChangeEmailRequest>,
+    ) -> Result<Response<()>, Status> {
+        let input = request.into_inner();
+        self.service
+            .change_email(input.session_id, input.current_password, input.new_email)
+            .await?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn confirm_email_change(
+        &self,
+        request: Request<ConfirmEmailChangeRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.service
+            .confirm_email_change(request.into_inner().token)
+            .await?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn close_account(
+        &self,
+        request: Request<CloseAccountRequest>,
+    ) -> Result<Response<()>, Status> {
+        let input = request.into_inner();
+        self.service
+            .close_account(input.session_id, input.password)
+            .await?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn export_credential(
+        &self,
+        request: Request<ExportCredentialRequest>,
+    ) -> Result<Response<ExportCredentialResponse>, Status> {
+        let export = self
+            .service
+            .export_credential(request.into_inner().session_id)
+            .await?;
+
+        Ok(Response::new(ExportCredentialResponse {
+            credential_id: export.id,
+            email: export.email,
+            sessions: export.sessions.into_iter().map(Session::from).collect(),
+        }))
+    }
+
+    async fn list_sessions(
+        &self,
+        request: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsResponse>, Status> {
+        let sessions = self
+            .service
+            .list_sessions(request.into_inner().session_id)
+            .await?;
+
+        Ok(Response::new(ListSessionsResponse {
+            sessions: sessions.into_iter().map(Session::from).collect(),
+        }))
+    }
+
+    async fn revoke_session(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<()>, Status> {
+        let input = request.into_inner();
+        self.service
+            .revoke_session(input.session_id, input.target_session_id)
+            .await?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn create_api_key(
+        &self,
+        request: Request<CreateApiKeyRequest>,
+    ) -> Result<Response<CreateApiKeyResponse>, Status> {
+        let input = request.into_inner();
+        let expires_at = timestamp(input.expires_at)
+            .ok_or_else(|| Status::invalid_argument("invalid api key expiry"))?;
+        let created = self
+            .service
+            .create_api_key(input.session_id, input.name, input.scopes, expires_at)
+            .await?;
+
+        Ok(Response::new(CreateApiKeyResponse {
+            key: created.key,
+            api_key: Some(ApiKey::from(created.api_key)),
+        }))
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: Request<ListApiKeysRequest>,
+    ) -> Result<Response<ListApiKeysResponse>, Status> {
+        let api_keys = self
+            .service
+            .list_api_keys(request.into_inner().session_id)
+            .await?;
+
+        Ok(Response::new(ListApiKeysResponse {
+            api_keys: api_keys.into_iter().map(ApiKey::from).collect(),
+        }))
+    }
+
+    async fn revoke_api_key(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<()>, Status> {
+        let input = request.into_inner();
+        self.service
+            .revoke_api_key(input.session_id, input.api_key_id)
             .await?;
 
         Ok(Response::new(()))
     }
+
+    async fn authenticate_api_key(
+        &self,
+        request: Request<AuthenticateApiKeyRequest>,
+    ) -> Result<Response<AuthenticateApiKeyResponse>, Status> {
+        let api_key = self
+            .service
+            .authenticate_api_key(request.into_inner().key)
+            .await?;
+
+        Ok(Response::new(AuthenticateApiKeyResponse {
+            credential_id: api_key.credential_id,
+            scopes: api_key.scopes,
+            roles: api_key.roles,
+        }))
+    }
+
+    async fn list_auth_events(
+        &self,
+        request: Request<ListAuthEventsRequest>,
+    ) -> Result<Response<ListAuthEventsResponse>, Status> {
+        let input = request.into_inner();
+        let caller = match (
+            non_empty(input.session_id),
+            non_empty(input.access_token),
+            non_empty(input.api_key),
+        ) {
+            (Some(session_id), None, None) => Caller::Session(session_id),
+            (None, Some(token), None) => Caller::AccessToken(token),
+            (None, None, Some(key)) => Caller::ApiKey(key),
+            (None, None, None) => return Err(AuthServiceError::InvalidCredentials.into()),
+            _ => {
+                return Err(Status::invalid_argument(
+                    "only one of session_id, access_token or api_key",
+                ))
+            }
+        };
+        let credential_id = non_empty(input.credential_id)
+            .map(|id| Uuid::from_str(&id))
+            .transpose()
+            .map_err(|_| Status::invalid_argument("invalid credential id format"))?;
+        let filter = AuthEventsFilter {
+            event_type: non_empty(input.event_type),
+            credential_id,
+            ip_address: non_empty(input.ip_address),
+            outcome: non_empty(input.outcome),
+            since: timestamp(input.since)
+                .ok_or_else(|| Status::invalid_argument("invalid since"))?,
+            until: timestamp(input.until)
+                .ok_or_else(|| Status::invalid_argument("invalid until"))?,
+        };
+        let events = self
+            .service
+            .list_auth_events(caller, filter, input.offset, input.limit)
+            .await?;
+
+        Ok(Response::new(ListAuthEventsResponse {
+            events: events.into_iter().map(AuthEvent::from).collect(),
+        }))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::auth::{AuthServiceError, MockAuthService};
+    use crate::auth::{
+        ApiKeyResponse, AuthEventResponse, AuthServiceError, AuthenticatedApiKey,
+        AuthenticatedSession, Caller, CreatedApiKey, CredentialExport, MockAuthService,
+        SessionResponse,
+    };
     use crate::grpc::GRPCAuthService;
+    use auth_database::entities::auth_events::AuthEventsFilter;
+    use auth_database::traits::DatabaseError;
+    use auth_database::types::{TimeZone, Utc};
     use grpc_interfaces::auth::auth_server::Auth;
-    use grpc_interfaces::auth::{AuthenticateRequest, CreateCredentialsRequest};
-    use mockall::predicate::eq;
+    use grpc_interfaces::auth::{
+        AuthenticateApiKeyRequest, AuthenticateRequest, ChangeEmailRequest, ChangePasswordRequest,
+        CloseAccountRequest, ConfirmEmailChangeRequest, CreateApiKeyRequest,
+        CreateCredentialsRequest, DeleteCredentialRequest, ExportCredentialRequest,
+        ListAuthEventsRequest, ListSessionsRequest, RevokeSessionRequest,
+    };
+    use mockall::predicate::{always, eq};
     use tonic::{Code, Request};
 
     #[tokio::test]
@@ -73,8 +370,12 @@ mod test {
         let mut mock = MockAuthService::new();
 
         mock.expect_create_account()
-            .with(eq("test@gmail.com".to_string()), eq("123456".to_string()))
-            .returning(|_, _| Ok("id".to_string()))
+            .with(
+                eq("test@gmail.com".to_string()),
+                eq("123456".to_string()),
+                eq(Some("retry-1".to_string())),
+            )
+            .returning(|_, _, _| Ok("id".to_string()))
             .times(1);
 
         let grpc = GRPCAuthService::new(mock);
@@ -82,6 +383,7 @@ mod test {
         let request = Request::new(CreateCredentialsRequest {
             email: "test@gmail.com".to_string(),
             password: "123456".to_string(),
+            idempotency_key: "retry-1".to_string(),
         });
 
         let response = grpc.create_credential(request).await.unwrap();
@@ -93,8 +395,12 @@ mod test {
         let mut mock = MockAuthService::new();
 
         mock.expect_create_account()
-            .with(eq("test.com".to_string()), eq("123456".to_string()))
-            .returning(|_, _| {
+            .with(
+                eq("test.com".to_string()),
+                eq("123456".to_string()),
+                eq(None),
+            )
+            .returning(|_, _, _| {
                 Err(AuthServiceError::InvalidInput {
                     message: "invalid email".to_string(),
                 })
@@ -106,6 +412,7 @@ mod test {
         let request = Request::new(CreateCredentialsRequest {
             email: "test.com".to_string(),
             password: "123456".to_string(),
+            idempotency_key: String::new(),
         });
 
         let response = grpc.create_credential(request).await.unwrap_err();
@@ -118,8 +425,12 @@ mod test {
         let mut mock = MockAuthService::new();
 
         mock.expect_create_account()
-            .with(eq("test@gmail.com".to_string()), eq("123456".to_string()))
-            .returning(|_, _| Err(AuthServiceError::InvalidCredentials))
+            .with(
+                eq("test@gmail.com".to_string()),
+                eq("123456".to_string()),
+                eq(None),
+            )
+            .returning(|_, _, _| Err(AuthServiceError::InvalidCredentials))
             .times(1);
 
         let grpc = GRPCAuthService::new(mock);
@@ -127,6 +438,7 @@ mod test {
         let request = Request::new(CreateCredentialsRequest {
             email: "test@gmail.com".to_string(),
             password: "123456".to_string(),
+            idempotency_key: String::new(),
         });
 
         let response = grpc.create_credential(request).await.unwrap_err();
@@ -139,8 +451,12 @@ mod test {
         let mut mock = MockAuthService::new();
 
         mock.expect_create_account()
-            .with(eq("test@gmail.com".to_string()), eq("123456".to_string()))
-            .returning(|_, _| Err(AuthServiceError::InternalServerError))
+            .with(
+                eq("test@gmail.com".to_string()),
+                eq("123456".to_string()),
+                eq(None),
+            )
+            .returning(|_, _, _| Err(AuthServiceError::InternalServerError))
             .times(1);
 
         let grpc = GRPCAuthService::new(mock);
@@ -148,6 +464,7 @@ mod test {
         let request = Request::new(CreateCredentialsRequest {
             email: "test@gmail.com".to_string(),
             password: "123456".to_string(),
+            idempotency_key: String::new(),
         });
 
         let response = grpc.create_credential(request).await.unwrap_err();
@@ -155,21 +472,68 @@ mod test {
         assert_eq!(response.code(), Code::Unknown);
     }
 
+    #[tokio::test]
+    async fn test_delete_credential() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_delete_credential()
+            .with(eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()))
+            .returning(|_| Ok(()))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(DeleteCredentialRequest {
+            credential_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+        });
+        assert!(grpc.delete_credential(request).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_delete_credential_in_use() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_delete_credential()
+            .returning(|_| {
+                Err(AuthServiceError::InvalidInput {
+                    message: "credential is in use".to_string(),
+                })
+            })
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(DeleteCredentialRequest {
+            credential_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+        });
+        let response = grpc.delete_credential(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::InvalidArgument);
+    }
+
     #[tokio::test]
     async fn test_authenticate_success() {
         let mut mock = MockAuthService::new();
 
         mock.expect_authenticate()
             .with(eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()))
-            .returning(|_| Ok(()))
+            .returning(|_| {
+                Ok(AuthenticatedSession {
+                    credential_id: "b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d".to_string(),
+                    expires_at: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+                    roles: vec!["admin".to_string()],
+                })
+            })
             .times(1);
 
         let grpc = GRPCAuthService::new(mock);
         let request = Request::new(AuthenticateRequest {
             session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
         });
-        let response = grpc.authenticate(request).await;
-        assert!(response.is_ok());
+        let response = grpc.authenticate(request).await.unwrap().into_inner();
+        assert_eq!(
+            response.credential_id,
+            "b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d"
+        );
+        assert_eq!(response.expires_at, 1_700_000_000);
+        assert_eq!(response.roles, vec!["admin".to_string()]);
     }
 
     #[tokio::test]
@@ -211,4 +575,466 @@ mod test {
         assert_eq!(response.code(), Code::InvalidArgument);
         assert_eq!(response.message(), "invalid session id format");
     }
+
+    #[tokio::test]
+    async fn test_change_password_success() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_change_password()
+            .with(
+                eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()),
+                eq("123456".to_string()),
+                eq("654321".to_string()),
+            )
+            .returning(|_, _, _| Ok(()))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ChangePasswordRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            current_password: "123456".to_string(),
+            new_password: "654321".to_string(),
+        });
+        let response = grpc.change_password(request).await;
+        assert!(response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_change_password_invalid_credentials() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_change_password()
+            .returning(|_, _, _| Err(AuthServiceError::InvalidCredentials))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ChangePasswordRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            current_password: "wrong".to_string(),
+            new_password: "654321".to_string(),
+        });
+        let response = grpc.change_password(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::Unauthenticated);
+        assert_eq!(response.message(), "Invalid Credentials");
+    }
+
+    #[tokio::test]
+    async fn test_change_email_success() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_change_email()
+            .with(
+                eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()),
+                eq("123456".to_string()),
+                eq("new@gmail.com".to_string()),
+            )
+            .returning(|_, _, _| Ok(()))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ChangeEmailRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            current_password: "123456".to_string(),
+            new_email: "new@gmail.com".to_string(),
+        });
+        let response = grpc.change_email(request).await;
+        assert!(response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_change_email_invalid_email() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_change_email()
+            .returning(|_, _, _| {
+                Err(AuthServiceError::InvalidInput {
+                    message: "invalid email".to_string(),
+                })
+            })
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ChangeEmailRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            current_password: "123456".to_string(),
+            new_email: "new".to_string(),
+        });
+        let response = grpc.change_email(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::InvalidArgument);
+        assert_eq!(response.message(), "invalid email");
+    }
+
+    #[tokio::test]
+    async fn test_confirm_email_change() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_confirm_email_change()
+            .with(eq("6f0ba1a6-0c8f-4c5e-8d4a-1e0d0f7e0b3a".to_string()))
+            .returning(|_| Ok(()))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ConfirmEmailChangeRequest {
+            token: "6f0ba1a6-0c8f-4c5e-8d4a-1e0d0f7e0b3a".to_string(),
+        });
+        let response = grpc.confirm_email_change(request).await;
+        assert!(response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_close_account() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_close_account()
+            .with(
+                eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()),
+                eq("123456".to_string()),
+            )
+            .returning(|_, _| Ok(()))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(CloseAccountRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            password: "123456".to_string(),
+        });
+        let response = grpc.close_account(request).await;
+        assert!(response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_close_account_invalid_credentials() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_close_account()
+            .returning(|_, _| Err(AuthServiceError::InvalidCredentials))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(CloseAccountRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            password: "wrong".to_string(),
+        });
+        let response = grpc.close_account(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_export_credential() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_export_credential()
+            .with(eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()))
+            .returning(|_| {
+                Ok(CredentialExport {
+                    id: "b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d".to_string(),
+                    email: "test@gmail.com".to_string(),
+                    sessions: vec![SessionResponse {
+                        id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+                        created_at: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+                        expires_at: Utc.timestamp_opt(1_700_086_400, 0).unwrap(),
+                        active: true,
+                        user_agent: None,
+                        ip_address: Some("127.0.0.1".to_string()),
+                        device_label: Some("Firefox on Linux".to_string()),
+                        current: true,
+                    }],
+                })
+            })
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ExportCredentialRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+        });
+        let response = grpc.export_credential(request).await.unwrap().into_inner();
+        assert_eq!(response.email, "test@gmail.com");
+        assert_eq!(response.sessions.len(), 1);
+        assert_eq!(response.sessions[0].created_at, 1_700_000_000);
+        assert_eq!(response.sessions[0].expires_at, 1_700_086_400);
+        assert!(response.sessions[0].active);
+        assert!(response.sessions[0].current);
+        assert_eq!(response.sessions[0].user_agent, "");
+        assert_eq!(response.sessions[0].ip_address, "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_list_sessions() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_list_sessions()
+            .with(eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()))
+            .returning(|_| {
+                Ok(vec![SessionResponse {
+                    id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+                    created_at: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+                    expires_at: Utc.timestamp_opt(1_700_086_400, 0).unwrap(),
+                    active: true,
+                    user_agent: Some("curl/8.4.0".to_string()),
+                    ip_address: None,
+                    device_label: Some("curl".to_string()),
+                    current: true,
+                }])
+            })
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ListSessionsRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+        });
+        let response = grpc.list_sessions(request).await.unwrap().into_inner();
+        assert_eq!(response.sessions.len(), 1);
+        assert_eq!(response.sessions[0].device_label, "curl");
+        assert_eq!(response.sessions[0].ip_address, "");
+    }
+
+    #[tokio::test]
+    async fn test_revoke_session_not_owned() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_revoke_session()
+            .with(
+                eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()),
+                eq("b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d".to_string()),
+            )
+            .returning(|_, _| Err(AuthServiceError::InvalidCredentials))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(RevokeSessionRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            target_session_id: "b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d".to_string(),
+        });
+        let response = grpc.revoke_session(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_create_api_key() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_create_api_key()
+            .with(
+                eq("84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string()),
+                eq("catalog sync".to_string()),
+                eq(vec!["movies:read".to_string()]),
+                eq(None),
+            )
+            .returning(|_, name, scopes, _| {
+                Ok(CreatedApiKey {
+                    key: "rfx_0a1b2c3d4e5f_secret".to_string(),
+                    api_key: ApiKeyResponse {
+                        id: "b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d".to_string(),
+                        name,
+                        prefix: "0a1b2c3d4e5f".to_string(),
+                        scopes,
+                        created_at: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+                        expires_at: None,
+                        last_used_at: None,
+                    },
+                })
+            })
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(CreateApiKeyRequest {
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            name: "catalog sync".to_string(),
+            scopes: vec!["movies:read".to_string()],
+            expires_at: 0,
+        });
+        let response = grpc.create_api_key(request).await.unwrap().into_inner();
+        assert_eq!(response.key, "rfx_0a1b2c3d4e5f_secret");
+        let api_key = response.api_key.unwrap();
+        assert_eq!(api_key.prefix, "0a1b2c3d4e5f");
+        assert_eq!(api_key.created_at, 1_700_000_000);
+        assert_eq!(api_key.expires_at, 0);
+        assert_eq!(api_key.last_used_at, 0);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_api_key_invalid_credentials() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_authenticate_api_key()
+            .with(eq("rfx_0a1b2c3d4e5f_wrong".to_string()))
+            .returning(|_| Err(AuthServiceError::InvalidCredentials))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(AuthenticateApiKeyRequest {
+            key: "rfx_0a1b2c3d4e5f_wrong".to_string(),
+        });
+        let response = grpc.authenticate_api_key(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_api_key() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_authenticate_api_key()
+            .with(eq("rfx_0a1b2c3d4e5f_secret".to_string()))
+            .returning(|_| {
+                Ok(AuthenticatedApiKey {
+                    credential_id: "b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d".to_string(),
+                    scopes: vec!["movies:read".to_string()],
+                    roles: vec![],
+                })
+            })
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(AuthenticateApiKeyRequest {
+            key: "rfx_0a1b2c3d4e5f_secret".to_string(),
+        });
+        let response = grpc
+            .authenticate_api_key(request)
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.credential_id,
+            "b3c1d1b6-54a5-4a39-9bbf-4f0a7c1f3f4d"
+        );
+        assert_eq!(response.scopes, vec!["movies:read".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_list_auth_events() {
+        let mut mock = MockAuthService::new();
+
+        mock.expect_list_auth_events()
+            .with(
+                eq(Caller::Session(
+                    "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+                )),
+                eq(AuthEventsFilter {
+                    event_type: Some("sign_in".to_string()),
+                    outcome: Some("failure".to_string()),
+                    since: Some(Utc.timestamp_opt(1_700_000_000, 0).unwrap()),
+                    ..Default::default()
+                }),
+                eq(20),
+                eq(10),
+            )
+            .returning(|_, _, _, _| {
+                Ok(vec![AuthEventResponse {
+                    id: "6f0ba1a6-0c8f-4c5e-8d4a-1e0d0f7e0b3a".to_string(),
+                    created_at: Utc.timestamp_opt(1_700_000_100, 0).unwrap(),
+                    event_type: "sign_in".to_string(),
+                    credential_id: None,
+                    ip_address: Some("10.0.0.1".to_string()),
+                    user_agent: None,
+                    outcome: "failure".to_string(),
+                    detail: Some("invalid credentials".to_string()),
+                }])
+            })
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ListAuthEventsRequest {
+            event_type: "sign_in".to_string(),
+            outcome: "failure".to_string(),
+            since: 1_700_000_000,
+            offset: 20,
+            limit: 10,
+            session_id: "84c36fe0-1b4d-41d1-8968-9d5af5883537".to_string(),
+            ..Default::default()
+        });
+        let response = grpc.list_auth_events(request).await.unwrap().into_inner();
+        assert_eq!(response.events.len(), 1);
+        assert_eq!(response.events[0].credential_id, "");
+        assert_eq!(response.events[0].ip_address, "10.0.0.1");
+        assert_eq!(response.events[0].created_at, 1_700_000_100);
+    }
+
+    #[tokio::test]
+    async fn test_list_auth_events_invalid_credential_id() {
+        let mut mock = MockAuthService::new();
+        mock.expect_list_auth_events().times(0);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ListAuthEventsRequest {
+            credential_id: "not a uuid".to_string(),
+            api_key: "rfx_0a1b2c3d4e5f_secret".to_string(),
+            ..Default::default()
+        });
+        let response = grpc.list_auth_events(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_list_auth_events_needs_a_caller() {
+        let mut mock = MockAuthService::new();
+        mock.expect_list_auth_events().times(0);
+
+        let grpc = GRPCAuthService::new(mock);
+        let response = grpc
+            .list_auth_events(Request::new(ListAuthEventsRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_list_auth_events_forbidden() {
+        let mut mock = MockAuthService::new();
+        mock.expect_list_auth_events()
+            .with(
+                eq(Caller::AccessToken("token".to_string())),
+                always(),
+                always(),
+                always(),
+            )
+            .returning(|_, _, _, _| Err(AuthServiceError::Forbidden))
+            .times(1);
+
+        let grpc = GRPCAuthService::new(mock);
+        let request = Request::new(ListAuthEventsRequest {
+            access_token: "token".to_string(),
+            ..Default::default()
+        });
+        let response = grpc.list_auth_events(request).await.unwrap_err();
+        assert_eq!(response.code(), Code::PermissionDenied);
+    }
+
+    #[test]
+    fn database_errors_map_to_status_codes() {
+        let code = |e: DatabaseError| tonic::Status::from(AuthServiceError::from(e)).code();
+
+        assert_eq!(
+            code(DatabaseError::NotFound {
+                entity: "Credential".to_string(),
+                key: "Email(\"test@gmail.com\")".to_string(),
+            }),
+            Code::NotFound
+        );
+        assert_eq!(
+            code(DatabaseError::UniqueViolation {
+                constraint: "credentials_email_key".to_string(),
+            }),
+            Code::AlreadyExists
+        );
+        assert_eq!(
+            code(DatabaseError::ForeignKeyViolation {
+                constraint: "sessions_credential_id_fkey".to_string(),
+            }),
+            Code::InvalidArgument
+        );
+        assert_eq!(code(DatabaseError::Deadlock), Code::Aborted);
+        assert_eq!(code(DatabaseError::SerializationFailure), Code::Aborted);
+        assert_eq!(
+            code(DatabaseError::ConnectionNotAvailable),
+            Code::Unavailable
+        );
+        assert_eq!(code(DatabaseError::NotImplemented), Code::Unknown);
+
+        // the key may be an email, it stays in the logs
+        let status = tonic::Status::from(AuthServiceError::from(DatabaseError::NotFound {
+            entity: "Credential".to_string(),
+            key: "Email(\"test@gmail.com\")".to_string(),
+        }));
+        assert_eq!(status.message(), "Credential not found");
+    }
 }
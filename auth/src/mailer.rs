@@ -0,0 +1,218 @@
+use crate::auth::AuthServiceError;
+use reqwest::Url;
+use serde_json::json;
+use std::fmt::Debug;
+#[cfg(test)]
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long the email API gets to accept a message
+const MAILER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[async_trait::async_trait]
+pub trait Mailer: Debug + Send + Sync {
+    async fn send_email_verification(
+        &self,
+        email: &str,
+        token: &str,
+    ) -> Result<(), AuthServiceError>;
+}
+
+/// No mailer was configured, flows that need one fail instead of losing the message
+#[derive(Debug, Default)]
+pub struct UnconfiguredMailer;
+
+#[async_trait::async_trait]
+impl Mailer for UnconfiguredMailer {
+    async fn send_email_verification(
+        &self,
+        _email: &str,
+        _token: &str,
+    ) -> Result<(), AuthServiceError> {
+        eprintln!("no mailer is configured, the email verification was not sent");
+        Err(AuthServiceError::Unavailable)
+    }
+}
+
+/// Local development mailer, only used with `--dev`. Prints the messages, tokens
+/// included, instead of delivering them.
+#[derive(Debug, Default)]
+pub struct ConsoleMailer;
+
+#[async_trait::async_trait]
+impl Mailer for ConsoleMailer {
+    async fn send_email_verification(
+        &self,
+        email: &str,
+        token: &str,
+    ) -> Result<(), AuthServiceError> {
+        println!("email verification for {email}: {token}");
+        Ok(())
+    }
+}
+
+/// Delivers the messages through an HTTP email API, posting them as
+/// `{"from", "to", "subject", "text"}` JSON with an optional bearer token.
+#[derive(Debug)]
+pub struct HttpMailer {
+    http: reqwest::Client,
+    endpoint: Url,
+    api_key: Option<String>,
+    from: String,
+    /// Web front end page confirming an email change, gets the token as `?token=`
+    confirm_email_url: String,
+}
+
+impl HttpMailer {
+    pub fn new(
+        endpoint: Url,
+        api_key: Option<String>,
+        from: String,
+        confirm_email_url: String,
+    ) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            endpoint,
+            api_key,
+            from,
+            confirm_email_url,
+        }
+    }
+
+    async fn send(&self, to: &str, subject: &str, text: String) -> Result<(), AuthServiceError> {
+        let mut request = self
+            .http
+            .post(self.endpoint.clone())
+            .timeout(MAILER_TIMEOUT)
+            .json(&json!({
+                "from": self.from,
+                "to": to,
+                "subject": subject,
+                "text": text,
+            }));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                eprintln!("could not send {:?} email: {}", subject, e);
+                Err(AuthServiceError::Unavailable)
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for HttpMailer {
+    async fn send_email_verification(
+        &self,
+        email: &str,
+        token: &str,
+    ) -> Result<(), AuthServiceError> {
+        let mut link = Url::parse(&self.confirm_email_url).map_err(|e| {
+            eprintln!("invalid confirm email url: {}", e);
+            AuthServiceError::InternalServerError
+        })?;
+        link.query_pairs_mut().append_pair("token", token);
+
+        self.send(
+            email,
+            "Confirm your new email address",
+            format!(
+                "Open {} to use this address with Rustflix. The link expires in a day, ignore this email if you didn't ask for the change.",
+                link
+            ),
+        )
+        .await
+    }
+}
+
+/// Keeps the messages instead of delivering them, for tests to read
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingMailer {
+    pub sent: Mutex<Vec<(String, String)>>,
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Mailer for RecordingMailer {
+    async fn send_email_verification(
+        &self,
+        email: &str,
+        token: &str,
+    ) -> Result<(), AuthServiceError> {
+        self.sent
+            .lock()
+            .unwrap()
+            .push((email.to_string(), token.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn mailer(server: &MockServer) -> HttpMailer {
+        HttpMailer::new(
+            Url::parse(&format!("{}/send", server.uri())).unwrap(),
+            Some("secret".to_string()),
+            "no-reply@rustflix.test".to_string(),
+            "http://localhost:3000/confirm-email".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_http_mailer() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/send"))
+            .and(header("authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        mailer(&server)
+            .send_email_verification("new@rustflix.test", "b1946ac9")
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let message: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(message["from"], "no-reply@rustflix.test");
+        assert_eq!(message["to"], "new@rustflix.test");
+        assert!(message["text"]
+            .as_str()
+            .unwrap()
+            .contains("http://localhost:3000/confirm-email?token=b1946ac9"));
+    }
+
+    #[tokio::test]
+    async fn test_http_mailer_failure() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        assert_eq!(
+            mailer(&server)
+                .send_email_verification("new@rustflix.test", "b1946ac9")
+                .await,
+            Err(AuthServiceError::Unavailable)
+        );
+        assert_eq!(
+            UnconfiguredMailer
+                .send_email_verification("new@rustflix.test", "b1946ac9")
+                .await,
+            Err(AuthServiceError::Unavailable)
+        );
+    }
+}
@@ -1,9 +1,26 @@
+use auth::SessionPolicy;
+use auth_database::router::DatabaseConfig;
+use auth_token::{SESSION_ABSOLUTE_TIMEOUT_ENV, SESSION_ABSOLUTE_TIMEOUT_HOURS};
 use clap::Parser;
+use mailer::{ConsoleMailer, HttpMailer, Mailer};
+use oidc::OidcProviders;
+use reqwest::Url;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokens::TokenIssuer;
 
+mod audit;
 mod auth;
+mod device;
 mod grpc;
+mod mailer;
+mod oidc;
 mod password_helper;
+mod repository;
+mod revocations;
 mod server;
+mod tokens;
 
 #[derive(Parser, Debug)]
 struct Cli {
@@ -19,6 +36,42 @@ struct Cli {
     #[arg(env = "AUTH_POSTGRES_URL")]
     database_url: String,
 
+    /// Read replicas of the auth database, comma separated
+    #[arg(long, env = "AUTH_POSTGRES_REPLICA_URLS", value_delimiter = ',')]
+    database_replica_urls: Vec<String>,
+
+    /// Connections kept open to each database even when idle
+    #[arg(long, env = "AUTH_DATABASE_MIN_CONNECTIONS", default_value_t = 0)]
+    database_min_connections: u32,
+
+    /// Connections open to each database at most
+    #[arg(long, env = "AUTH_DATABASE_MAX_CONNECTIONS", default_value_t = 10)]
+    database_max_connections: u32,
+
+    /// Seconds a query waits for a free connection before failing
+    #[arg(
+        long,
+        env = "AUTH_DATABASE_ACQUIRE_TIMEOUT_SECONDS",
+        default_value_t = 30
+    )]
+    database_acquire_timeout_seconds: u64,
+
+    /// Seconds before idle connections are closed, 0 keeps them open
+    #[arg(
+        long,
+        env = "AUTH_DATABASE_IDLE_TIMEOUT_SECONDS",
+        default_value_t = 600
+    )]
+    database_idle_timeout_seconds: u64,
+
+    /// Prepared statements cached per connection, 0 disables them, e.g. behind pgbouncer
+    #[arg(
+        long,
+        env = "AUTH_DATABASE_STATEMENT_CACHE_CAPACITY",
+        default_value_t = 100
+    )]
+    database_statement_cache_capacity: usize,
+
     /// Web front-end url
     #[arg(env = "AUTH_WEB_FRONT_END_URL")]
     web_front_end_url: String,
@@ -30,20 +83,135 @@ struct Cli {
     /// Private key for session storage
     #[arg(env = "PRIVATE_SESSION_KEY")]
     session_private_key: String,
+
+    /// Days a closed account is kept before it's erased
+    #[arg(long, env = "ACCOUNT_ERASURE_GRACE_DAYS", default_value_t = 30)]
+    account_erasure_grace_days: u64,
+
+    /// Minutes without activity before a session expires
+    #[arg(long, env = "SESSION_IDLE_TIMEOUT_MINUTES", default_value_t = 60 * 24)]
+    session_idle_timeout_minutes: u64,
+
+    /// Hours a session can live since sign in, regardless of activity
+    #[arg(long, env = SESSION_ABSOLUTE_TIMEOUT_ENV, default_value_t = SESSION_ABSOLUTE_TIMEOUT_HOURS)]
+    session_absolute_timeout_hours: u64,
+
+    /// TOML file with the OpenID Connect providers users can sign in with
+    #[arg(long, env = "OIDC_PROVIDERS_FILE")]
+    oidc_providers_file: Option<PathBuf>,
+
+    /// PKCS#8 Ed25519 key that signs access tokens, required unless `--dev` generates a throwaway one
+    #[arg(long, env = "TOKEN_SIGNING_KEY_FILE")]
+    token_signing_key_file: Option<PathBuf>,
+
+    /// Id of the signing key in the published JWKS, change it when rotating the key
+    #[arg(long, env = "TOKEN_KEY_ID", default_value = "auth")]
+    token_key_id: String,
+
+    /// Seconds an access token is valid for
+    #[arg(long, env = "ACCESS_TOKEN_TTL_SECONDS", default_value_t = 60 * 5)]
+    access_token_ttl_seconds: u64,
+
+    /// HTTP email API the verification emails are posted to, required unless `--dev`
+    #[arg(long, env = "MAILER_URL")]
+    mailer_url: Option<Url>,
+
+    /// Bearer token of the email API
+    #[arg(long, env = "MAILER_API_KEY")]
+    mailer_api_key: Option<String>,
+
+    /// Sender of the emails
+    #[arg(
+        long,
+        env = "MAILER_FROM",
+        default_value = "Rustflix <no-reply@rustflix.local>"
+    )]
+    mailer_from: String,
+
+    /// Apply pending database migrations before serving, otherwise they are only reported
+    #[arg(long)]
+    migrate: bool,
+
+    /// Local development: emails are printed, tokens included, instead of sent and a
+    /// throwaway token signing key is generated when none is given
+    #[arg(long, env = "AUTH_DEV")]
+    dev: bool,
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().unwrap();
     let args = Cli::parse();
+    let oidc_providers = match &args.oidc_providers_file {
+        Some(path) => OidcProviders::load(path).expect("Could not load OIDC providers"),
+        None => OidcProviders::default(),
+    };
+    let session_policy = SessionPolicy {
+        idle_timeout: Duration::from_secs(args.session_idle_timeout_minutes * 60),
+        absolute_timeout: Duration::from_secs(args.session_absolute_timeout_hours * 60 * 60),
+    };
+    let token_issuer = match &args.token_signing_key_file {
+        Some(path) => {
+            let pem = std::fs::read_to_string(path).expect("Could not read token signing key");
+            TokenIssuer::from_pkcs8_pem(&args.token_key_id, &pem)
+        }
+        None if args.dev => {
+            eprintln!("TOKEN_SIGNING_KEY_FILE is not set, tokens won't survive a restart");
+            TokenIssuer::generate(&args.token_key_id)
+        }
+        None => {
+            eprintln!("TOKEN_SIGNING_KEY_FILE is required, or --dev to generate a throwaway key");
+            std::process::exit(2);
+        }
+    }
+    .expect("Could not load token signing key")
+    // a refresh token is useless once its session is over
+    .with_ttls(
+        Duration::from_secs(args.access_token_ttl_seconds),
+        session_policy.absolute_timeout,
+    );
+
+    let mailer: Arc<dyn Mailer> = match (&args.mailer_url, args.dev) {
+        (Some(url), _) => Arc::new(HttpMailer::new(
+            url.clone(),
+            args.mailer_api_key.clone(),
+            args.mailer_from.clone(),
+            format!(
+                "{}/confirm-email",
+                args.web_front_end_url.trim_end_matches('/')
+            ),
+        )),
+        (None, true) => Arc::new(ConsoleMailer),
+        (None, false) => {
+            eprintln!("MAILER_URL is required, or --dev to print emails instead");
+            std::process::exit(2);
+        }
+    };
+
+    let database = DatabaseConfig {
+        replica_urls: args.database_replica_urls.clone(),
+        min_connections: args.database_min_connections,
+        max_connections: args.database_max_connections,
+        acquire_timeout: Duration::from_secs(args.database_acquire_timeout_seconds),
+        idle_timeout: (args.database_idle_timeout_seconds > 0)
+            .then(|| Duration::from_secs(args.database_idle_timeout_seconds)),
+        statement_cache_capacity: args.database_statement_cache_capacity,
+        ..DatabaseConfig::new(&args.database_url)
+    };
 
     if let Err(e) = server::run_server(
         &args.grpc_port,
-        &args.database_url,
+        database,
         &args.web_front_end_url,
         &args.redis_session_storage_url,
         &args.session_private_key,
         args.auth_api_port,
+        Duration::from_secs(args.account_erasure_grace_days * 60 * 60 * 24),
+        session_policy,
+        oidc_providers,
+        token_issuer,
+        mailer,
+        args.migrate,
     )
     .await
     {
@@ -0,0 +1,490 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::RwLock;
+use thiserror::Error;
+
+const DEFAULT_SCOPES: [&str; 3] = ["openid", "email", "profile"];
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("unknown provider")]
+    UnknownProvider,
+    #[error("state does not match the pending login")]
+    InvalidState,
+    #[error("provider request failed: {0}")]
+    Request(String),
+    #[error("invalid id token: {0}")]
+    InvalidToken(String),
+}
+
+impl From<reqwest::Error> for OidcError {
+    fn from(value: reqwest::Error) -> Self {
+        OidcError::Request(value.to_string())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for OidcError {
+    fn from(value: jsonwebtoken::errors::Error) -> Self {
+        OidcError::InvalidToken(value.to_string())
+    }
+}
+
+/// A "Sign in with X" provider as declared in the providers file.
+/// Endpoints are configured explicitly instead of discovered, so a local
+/// issuer can stand in for the real one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub redirect_uri: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_scopes() -> Vec<String> {
+    DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcProvidersFile {
+    /// Where the browser lands once an external sign in succeeds
+    #[serde(default = "default_login_redirect")]
+    login_redirect: String,
+    #[serde(default)]
+    providers: Vec<OidcProviderConfig>,
+}
+
+fn default_login_redirect() -> String {
+    "/".to_string()
+}
+
+/// Login started by `authorize`, kept in the user's session until the provider calls back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub provider: String,
+    pub state: String,
+    pub nonce: String,
+    pub verifier: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub nonce: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// S256 code challenge for a PKCE verifier
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+#[derive(Debug)]
+pub struct OidcProvider {
+    config: OidcProviderConfig,
+    http: reqwest::Client,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcProviderConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            jwks: RwLock::new(None),
+        }
+    }
+
+    /// Url the user is sent to, along with what has to be remembered to finish the login
+    pub fn authorize(&self) -> Result<(Url, PendingLogin), OidcError> {
+        let pending = PendingLogin {
+            provider: self.config.name.clone(),
+            state: random_token(),
+            nonce: random_token(),
+            verifier: random_token(),
+        };
+        let url = Url::parse_with_params(
+            &self.config.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("scope", &self.config.scopes.join(" ")),
+                ("state", &pending.state),
+                ("nonce", &pending.nonce),
+                ("code_challenge", &pkce_challenge(&pending.verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| OidcError::Request(e.to_string()))?;
+
+        Ok((url, pending))
+    }
+
+    /// Finishes the login once the provider redirects back with `code` and `state`
+    pub async fn complete(
+        &self,
+        pending: &PendingLogin,
+        code: &str,
+        state: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        if pending.provider != self.config.name || pending.state != state {
+            return Err(OidcError::InvalidState);
+        }
+
+        let id_token = self.exchange_code(code, &pending.verifier).await?;
+        self.verify_id_token(&id_token, &pending.nonce).await
+    }
+
+    async fn exchange_code(&self, code: &str, verifier: &str) -> Result<String, OidcError> {
+        let response = self
+            .http
+            .post(&self.config.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("client_id", &self.config.client_id),
+                ("client_secret", &self.config.client_secret),
+                ("code_verifier", verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+
+        Ok(response.id_token)
+    }
+
+    pub async fn verify_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let header = decode_header(id_token)?;
+        // Only asymmetric signatures, the client secret is not a signing key.
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+                | Algorithm::EdDSA
+        ) {
+            return Err(OidcError::InvalidToken("unsupported algorithm".to_string()));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| OidcError::InvalidToken("missing kid".to_string()))?;
+        let jwk = self.jwk(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims =
+            decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(&jwk)?, &validation)?.claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::InvalidToken("nonce mismatch".to_string()));
+        }
+
+        Ok(claims)
+    }
+
+    /// Signing key by id, the key set is fetched again when the provider rotated its keys
+    async fn jwk(&self, kid: &str) -> Result<Jwk, OidcError> {
+        let cached = self
+            .jwks
+            .read()
+            .ok()
+            .and_then(|jwks| jwks.as_ref().and_then(|set| set.find(kid).cloned()));
+        if let Some(jwk) = cached {
+            return Ok(jwk);
+        }
+
+        let set = self
+            .http
+            .get(&self.config.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<JwkSet>()
+            .await?;
+        let jwk = set.find(kid).cloned();
+        if let Ok(mut jwks) = self.jwks.write() {
+            *jwks = Some(set);
+        }
+
+        jwk.ok_or_else(|| OidcError::InvalidToken("unknown signing key".to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub struct OidcProviders {
+    pub login_redirect: String,
+    providers: HashMap<String, OidcProvider>,
+}
+
+impl Default for OidcProviders {
+    fn default() -> Self {
+        Self {
+            login_redirect: default_login_redirect(),
+            providers: HashMap::new(),
+        }
+    }
+}
+
+impl OidcProviders {
+    pub fn from_toml(content: &str) -> Result<Self, toml::de::Error> {
+        let file: OidcProvidersFile = toml::from_str(content)?;
+        Ok(Self {
+            login_redirect: file.login_redirect,
+            providers: file
+                .providers
+                .into_iter()
+                .map(|config| (config.name.clone(), OidcProvider::new(config)))
+                .collect(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(Self::from_toml(&content)?)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&OidcProvider, OidcError> {
+        self.providers.get(name).ok_or(OidcError::UnknownProvider)
+    }
+}
+
+#[cfg(test)]
+pub mod test_issuer {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    pub const CLIENT_ID: &str = "rustflix";
+    const KEY: &str = include_str!("../tests/fixtures/oidc_test_key.pem");
+    const JWKS: &str = include_str!("../tests/fixtures/oidc_test_jwks.json");
+
+    /// Local OIDC issuer serving the test key set, tokens are minted with `id_token`
+    pub struct TestIssuer {
+        pub server: MockServer,
+    }
+
+    impl TestIssuer {
+        pub async fn start() -> Self {
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .and(path("/jwks"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(JWKS, "application/json"))
+                .mount(&server)
+                .await;
+            Self { server }
+        }
+
+        pub fn providers_toml(&self) -> String {
+            format!(
+                r#"
+                login_redirect = "http://localhost:3000"
+
+                [[providers]]
+                name = "test"
+                issuer = "{uri}"
+                client_id = "{CLIENT_ID}"
+                client_secret = "secret"
+                authorization_endpoint = "{uri}/authorize"
+                token_endpoint = "{uri}/token"
+                jwks_uri = "{uri}/jwks"
+                redirect_uri = "http://localhost/oidc/test/callback"
+                "#,
+                uri = self.server.uri()
+            )
+        }
+
+        pub fn id_token(&self, sub: &str, email: &str, nonce: &str) -> String {
+            self.sign(json!({
+                "iss": self.server.uri(),
+                "aud": CLIENT_ID,
+                "sub": sub,
+                "email": email,
+                "email_verified": true,
+                "nonce": nonce,
+                "exp": jsonwebtoken::get_current_timestamp() + 60,
+            }))
+        }
+
+        pub fn sign(&self, claims: serde_json::Value) -> String {
+            let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+            header.kid = Some("test-key".to_string());
+            encode(
+                &header,
+                &claims,
+                &EncodingKey::from_rsa_pem(KEY.as_bytes()).unwrap(),
+            )
+            .unwrap()
+        }
+
+        /// Answers the code exchange with `id_token`
+        pub async fn issue(&self, id_token: String) {
+            Mock::given(method("POST"))
+                .and(path("/token"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "access_token": "access",
+                    "token_type": "Bearer",
+                    "id_token": id_token,
+                })))
+                .mount(&self.server)
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_issuer::TestIssuer;
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pkce_challenge() {
+        // RFC 7636, appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_unknown_provider() {
+        let providers = OidcProviders::from_toml("").unwrap();
+        assert_eq!(providers.login_redirect, "/");
+        assert!(matches!(
+            providers.get("test"),
+            Err(OidcError::UnknownProvider)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let issuer = TestIssuer::start().await;
+        let providers = OidcProviders::from_toml(&issuer.providers_toml()).unwrap();
+        let (url, pending) = providers.get("test").unwrap().authorize().unwrap();
+
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], "rustflix");
+        assert_eq!(params["scope"], "openid email profile");
+        assert_eq!(params["state"], pending.state);
+        assert_eq!(params["nonce"], pending.nonce);
+        assert_eq!(params["code_challenge"], pkce_challenge(&pending.verifier));
+        assert_eq!(params["code_challenge_method"], "S256");
+    }
+
+    #[tokio::test]
+    async fn test_complete() {
+        let issuer = TestIssuer::start().await;
+        let providers = OidcProviders::from_toml(&issuer.providers_toml()).unwrap();
+        let provider = providers.get("test").unwrap();
+        let (_, pending) = provider.authorize().unwrap();
+        issuer
+            .issue(issuer.id_token("user-1", "user@gmail.com", &pending.nonce))
+            .await;
+
+        // state from another login
+        let result = provider.complete(&pending, "code", "forged").await;
+        assert!(matches!(result, Err(OidcError::InvalidState)));
+
+        let claims = provider
+            .complete(&pending, "code", &pending.state)
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.email.as_deref(), Some("user@gmail.com"));
+        assert!(claims.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_verify_id_token() {
+        let issuer = TestIssuer::start().await;
+        let providers = OidcProviders::from_toml(&issuer.providers_toml()).unwrap();
+        let provider = providers.get("test").unwrap();
+
+        // replayed token from another login
+        let token = issuer.id_token("user-1", "user@gmail.com", "other nonce");
+        let result = provider.verify_id_token(&token, "nonce").await;
+        assert!(matches!(result, Err(OidcError::InvalidToken(_))));
+
+        // issued for another client
+        let token = issuer.sign(json!({
+            "iss": issuer.server.uri(),
+            "aud": "someone-else",
+            "sub": "user-1",
+            "nonce": "nonce",
+            "exp": jsonwebtoken::get_current_timestamp() + 60,
+        }));
+        let result = provider.verify_id_token(&token, "nonce").await;
+        assert!(matches!(result, Err(OidcError::InvalidToken(_))));
+
+        // expired
+        let token = issuer.sign(json!({
+            "iss": issuer.server.uri(),
+            "aud": "rustflix",
+            "sub": "user-1",
+            "nonce": "nonce",
+            "exp": jsonwebtoken::get_current_timestamp() - 600,
+        }));
+        let result = provider.verify_id_token(&token, "nonce").await;
+        assert!(matches!(result, Err(OidcError::InvalidToken(_))));
+
+        // signed with a shared secret
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &json!({ "sub": "user-1" }),
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        let result = provider.verify_id_token(&token, "nonce").await;
+        assert!(matches!(result, Err(OidcError::InvalidToken(_))));
+
+        let token = issuer.id_token("user-1", "user@gmail.com", "nonce");
+        let claims = provider.verify_id_token(&token, "nonce").await.unwrap();
+        assert_eq!(claims.sub, "user-1");
+    }
+}
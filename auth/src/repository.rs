@@ -0,0 +1,1004 @@
+use auth_database::entities::api_keys::{
+    ApiKeysBy, ApiKeysDAO, ApiKeysRepository, ApiKeysRepositoryExt, ApiKeysWhere, CreateApiKeysDAO,
+    UpdateApiKeysDAO,
+};
+use auth_database::entities::auth_events::{
+    AuthEventsDAO, AuthEventsFilter, AuthEventsRepository, AuthEventsWhere, CreateAuthEventsDAO,
+};
+use auth_database::entities::credential_roles::{CredentialRolesRepository, CredentialRolesWhere};
+use auth_database::entities::credentials::{
+    CreateCredentialsDAO, CredentialsBy, CredentialsDAO, CredentialsRepository,
+    CredentialsRepositoryExt, UpdateCredentialsDAO,
+};
+use auth_database::entities::email_changes::{
+    CreateEmailChangesDAO, EmailChangesBy, EmailChangesDAO, EmailChangesRepository,
+};
+use auth_database::entities::identities::{
+    CreateIdentitiesDAO, IdentitiesBy, IdentitiesDAO, IdentitiesRepository, UpdateIdentitiesDAO,
+};
+use auth_database::entities::revoked_tokens::{
+    RevokedTokensRepository, RevokedTokensRepositoryExt,
+};
+use auth_database::entities::sessions::{
+    CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsField, SessionsRepository,
+    SessionsRepositoryExt, UpdateSessionsDAO,
+};
+use auth_database::{
+    query::{Direction, Filter, Query},
+    router::DatabaseRouter,
+    traits::{DatabaseError, EntityRepository},
+    types::{DateTime, Utc, Uuid},
+    unit_of_work::unit_of_work,
+};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Storage `AuthService` works with. Operations touching several entities are atomic.
+#[async_trait::async_trait]
+pub trait AuthRepository: Send + Sync {
+    async fn insert_credential(
+        &self,
+        credential: CreateCredentialsDAO,
+    ) -> Result<CredentialsDAO, DatabaseError>;
+    async fn get_credential(&self, credential_id: Uuid) -> Result<CredentialsDAO, DatabaseError>;
+    async fn try_get_credential(
+        &self,
+        key: CredentialsBy,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError>;
+    /// Credential created by the request carrying `idempotency_key`
+    async fn try_get_credential_created_with(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError>;
+    /// Updates the credential and revokes every session of it but `keep_session_id`
+    async fn change_password(
+        &self,
+        credential_id: Uuid,
+        update: UpdateCredentialsDAO,
+        keep_session_id: Uuid,
+    ) -> Result<(), DatabaseError>;
+    /// Updates the credential and uses up the email change that asked for it
+    async fn confirm_email_change(
+        &self,
+        change_id: Uuid,
+        credential_id: Uuid,
+        update: UpdateCredentialsDAO,
+    ) -> Result<(), DatabaseError>;
+    /// Revokes the sessions and API keys of the credential and deactivates it
+    async fn close_credential(&self, credential_id: Uuid) -> Result<(), DatabaseError>;
+    /// Permanently removes a credential that never signed in, returns whether it was removed
+    async fn erase_unused_credential(&self, credential_id: Uuid) -> Result<bool, DatabaseError>;
+    /// Permanently removes credentials deactivated before `before` and everything they own,
+    /// returns how many were erased
+    async fn erase_deactivated_credentials(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+
+    async fn insert_session(
+        &self,
+        session: CreateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError>;
+    async fn try_get_session(&self, session_id: Uuid)
+        -> Result<Option<SessionsDAO>, DatabaseError>;
+    async fn update_session(
+        &self,
+        session_id: Uuid,
+        update: UpdateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError>;
+    async fn revoke_session(&self, session_id: Uuid) -> Result<SessionsDAO, DatabaseError>;
+    /// Newest first, revoked and expired ones included
+    async fn list_sessions(&self, credential_id: Uuid) -> Result<Vec<SessionsDAO>, DatabaseError>;
+    /// Hard deletes revoked sessions and the ones that expired before `before`
+    async fn purge_sessions(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError>;
+
+    /// Adds a refresh token to the denylist, false when it was there already
+    async fn revoke_token(
+        &self,
+        jti: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError>;
+    async fn purge_revoked_tokens(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError>;
+
+    async fn insert_identity(
+        &self,
+        identity: CreateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError>;
+    async fn try_get_identity(
+        &self,
+        key: IdentitiesBy,
+    ) -> Result<Option<IdentitiesDAO>, DatabaseError>;
+    async fn update_identity(
+        &self,
+        identity_id: Uuid,
+        update: UpdateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError>;
+
+    async fn insert_email_change(
+        &self,
+        change: CreateEmailChangesDAO,
+    ) -> Result<EmailChangesDAO, DatabaseError>;
+    async fn try_get_email_change(
+        &self,
+        change_id: Uuid,
+    ) -> Result<Option<EmailChangesDAO>, DatabaseError>;
+
+    async fn insert_api_key(&self, api_key: CreateApiKeysDAO) -> Result<ApiKeysDAO, DatabaseError>;
+    async fn try_get_api_key(&self, key: ApiKeysBy) -> Result<Option<ApiKeysDAO>, DatabaseError>;
+    async fn update_api_key(
+        &self,
+        api_key_id: Uuid,
+        update: UpdateApiKeysDAO,
+    ) -> Result<ApiKeysDAO, DatabaseError>;
+    async fn revoke_api_key(&self, api_key_id: Uuid) -> Result<ApiKeysDAO, DatabaseError>;
+    /// Oldest first, revoked ones included
+    async fn list_api_keys(&self, credential_id: Uuid) -> Result<Vec<ApiKeysDAO>, DatabaseError>;
+
+    /// Roles granted to the credential, sorted by name
+    async fn list_roles(&self, credential_id: Uuid) -> Result<Vec<String>, DatabaseError>;
+
+    async fn insert_auth_event(
+        &self,
+        event: CreateAuthEventsDAO,
+    ) -> Result<AuthEventsDAO, DatabaseError>;
+    /// Newest first
+    async fn list_auth_events(
+        &self,
+        filter: AuthEventsFilter,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<AuthEventsDAO>, DatabaseError>;
+}
+
+/// Backed by the auth database through its entity repositories.
+/// Only listings read from replicas, lookups deciding access stay on the primary
+/// as a replica may still have a session or key revoked moments ago.
+#[derive(Debug, Clone)]
+pub struct PgAuthRepository {
+    db: DatabaseRouter,
+}
+
+impl PgAuthRepository {
+    pub fn new(db: DatabaseRouter) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthRepository for PgAuthRepository {
+    async fn insert_credential(
+        &self,
+        credential: CreateCredentialsDAO,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        CredentialsRepository::insert(self.db.write(), credential).await
+    }
+
+    async fn get_credential(&self, credential_id: Uuid) -> Result<CredentialsDAO, DatabaseError> {
+        CredentialsRepository::get(self.db.write(), CredentialsBy::Id(credential_id)).await
+    }
+
+    async fn try_get_credential(
+        &self,
+        key: CredentialsBy,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        CredentialsRepository::try_get(self.db.write(), key).await
+    }
+
+    async fn try_get_credential_created_with(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        CredentialsRepository::try_get_created_with(self.db.write(), idempotency_key).await
+    }
+
+    async fn change_password(
+        &self,
+        credential_id: Uuid,
+        update: UpdateCredentialsDAO,
+        keep_session_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        unit_of_work(self.db.write(), |tx| {
+            Box::pin(async move {
+                CredentialsRepository::update(&mut *tx, CredentialsBy::Id(credential_id), update)
+                    .await?;
+                SessionsRepository::revoke_all(&mut *tx, credential_id, Some(keep_session_id))
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn confirm_email_change(
+        &self,
+        change_id: Uuid,
+        credential_id: Uuid,
+        update: UpdateCredentialsDAO,
+    ) -> Result<(), DatabaseError> {
+        unit_of_work(self.db.write(), |tx| {
+            Box::pin(async move {
+                CredentialsRepository::update(&mut *tx, CredentialsBy::Id(credential_id), update)
+                    .await?;
+                EmailChangesRepository::delete(&mut *tx, EmailChangesBy::Id(change_id)).await?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn close_credential(&self, credential_id: Uuid) -> Result<(), DatabaseError> {
+        unit_of_work(self.db.write(), |tx| {
+            Box::pin(async move {
+                SessionsRepository::revoke_all(&mut *tx, credential_id, None).await?;
+                ApiKeysRepository::revoke_all(&mut *tx, credential_id).await?;
+                CredentialsRepository::delete(&mut *tx, CredentialsBy::Id(credential_id)).await?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn erase_unused_credential(&self, credential_id: Uuid) -> Result<bool, DatabaseError> {
+        CredentialsRepository::erase_unused(self.db.write(), credential_id).await
+    }
+
+    async fn erase_deactivated_credentials(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        CredentialsRepository::erase_deactivated(self.db.write(), before).await
+    }
+
+    async fn insert_session(
+        &self,
+        session: CreateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        SessionsRepository::insert(self.db.write(), session).await
+    }
+
+    async fn try_get_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Option<SessionsDAO>, DatabaseError> {
+        SessionsRepository::try_get(self.db.write(), SessionsBy::Id(session_id)).await
+    }
+
+    async fn update_session(
+        &self,
+        session_id: Uuid,
+        update: UpdateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        SessionsRepository::update(self.db.write(), SessionsBy::Id(session_id), update).await
+    }
+
+    async fn revoke_session(&self, session_id: Uuid) -> Result<SessionsDAO, DatabaseError> {
+        SessionsRepository::delete(self.db.write(), SessionsBy::Id(session_id)).await
+    }
+
+    async fn list_sessions(&self, credential_id: Uuid) -> Result<Vec<SessionsDAO>, DatabaseError> {
+        let query = Query::new()
+            .filter(Filter::eq(SessionsField::CredentialId, credential_id))
+            .order_by(SessionsField::CreatedAt, Direction::Desc);
+        SessionsRepository::get_all(self.db.read(), query).await
+    }
+
+    async fn purge_sessions(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        SessionsRepository::purge(self.db.write(), before).await
+    }
+
+    async fn revoke_token(
+        &self,
+        jti: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError> {
+        RevokedTokensRepository::revoke(self.db.write(), jti, expires_at).await
+    }
+
+    async fn purge_revoked_tokens(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        RevokedTokensRepository::purge(self.db.write(), before).await
+    }
+
+    async fn insert_identity(
+        &self,
+        identity: CreateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        IdentitiesRepository::insert(self.db.write(), identity).await
+    }
+
+    async fn try_get_identity(
+        &self,
+        key: IdentitiesBy,
+    ) -> Result<Option<IdentitiesDAO>, DatabaseError> {
+        IdentitiesRepository::try_get(self.db.write(), key).await
+    }
+
+    async fn update_identity(
+        &self,
+        identity_id: Uuid,
+        update: UpdateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        IdentitiesRepository::update(self.db.write(), IdentitiesBy::Id(identity_id), update).await
+    }
+
+    async fn insert_email_change(
+        &self,
+        change: CreateEmailChangesDAO,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        EmailChangesRepository::insert(self.db.write(), change).await
+    }
+
+    async fn try_get_email_change(
+        &self,
+        change_id: Uuid,
+    ) -> Result<Option<EmailChangesDAO>, DatabaseError> {
+        EmailChangesRepository::try_get(self.db.write(), EmailChangesBy::Id(change_id)).await
+    }
+
+    async fn insert_api_key(&self, api_key: CreateApiKeysDAO) -> Result<ApiKeysDAO, DatabaseError> {
+        ApiKeysRepository::insert(self.db.write(), api_key).await
+    }
+
+    async fn try_get_api_key(&self, key: ApiKeysBy) -> Result<Option<ApiKeysDAO>, DatabaseError> {
+        ApiKeysRepository::try_get(self.db.write(), key).await
+    }
+
+    async fn update_api_key(
+        &self,
+        api_key_id: Uuid,
+        update: UpdateApiKeysDAO,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        ApiKeysRepository::update(self.db.write(), ApiKeysBy::Id(api_key_id), update).await
+    }
+
+    async fn revoke_api_key(&self, api_key_id: Uuid) -> Result<ApiKeysDAO, DatabaseError> {
+        ApiKeysRepository::delete(self.db.write(), ApiKeysBy::Id(api_key_id)).await
+    }
+
+    async fn list_api_keys(&self, credential_id: Uuid) -> Result<Vec<ApiKeysDAO>, DatabaseError> {
+        ApiKeysRepository::get_all(self.db.read(), ApiKeysWhere::CredentialId(credential_id)).await
+    }
+
+    async fn list_roles(&self, credential_id: Uuid) -> Result<Vec<String>, DatabaseError> {
+        let roles = CredentialRolesRepository::get_all(
+            self.db.write(),
+            CredentialRolesWhere::CredentialId(credential_id),
+        )
+        .await?;
+        Ok(roles.into_iter().map(|r| r.role).collect())
+    }
+
+    async fn insert_auth_event(
+        &self,
+        event: CreateAuthEventsDAO,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        AuthEventsRepository::insert(self.db.write(), event).await
+    }
+
+    async fn list_auth_events(
+        &self,
+        filter: AuthEventsFilter,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<AuthEventsDAO>, DatabaseError> {
+        AuthEventsRepository::get_all(
+            self.db.read(),
+            AuthEventsWhere::Page {
+                filter,
+                offset,
+                limit,
+            },
+        )
+        .await
+    }
+}
+
+#[derive(Debug)]
+struct StoredCredential {
+    credential: CredentialsDAO,
+    idempotency_key: Option<String>,
+    deactivated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+struct InMemoryState {
+    credentials: Vec<StoredCredential>,
+    sessions: Vec<SessionsDAO>,
+    /// Denylisted refresh tokens with when they expire
+    revoked_tokens: Vec<(Uuid, DateTime<Utc>)>,
+    identities: Vec<IdentitiesDAO>,
+    email_changes: Vec<EmailChangesDAO>,
+    api_keys: Vec<ApiKeysDAO>,
+    /// Granted roles by credential
+    roles: Vec<(Uuid, String)>,
+    auth_events: Vec<AuthEventsDAO>,
+    unavailable: bool,
+}
+
+/// Same error the entity repositories report for a missing row
+fn not_found<K: Debug>(entity: &str, key: K) -> DatabaseError {
+    DatabaseError::NotFound {
+        entity: entity.to_string(),
+        key: format!("{:?}", key),
+    }
+}
+
+fn unique_violation(constraint: &str) -> DatabaseError {
+    DatabaseError::UniqueViolation {
+        constraint: constraint.to_string(),
+    }
+}
+
+impl InMemoryState {
+    /// Rows owned by a credential can't point to one that doesn't exist
+    fn check_credential(&self, credential_id: Uuid) -> Result<(), DatabaseError> {
+        if self
+            .credentials
+            .iter()
+            .any(|c| c.credential.id == credential_id)
+        {
+            return Ok(());
+        }
+        Err(DatabaseError::ForeignKeyViolation {
+            constraint: "fk_credentials".to_string(),
+        })
+    }
+
+    fn update_credential(
+        &mut self,
+        credential_id: Uuid,
+        update: UpdateCredentialsDAO,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        if self
+            .credentials
+            .iter()
+            .any(|c| c.credential.id != credential_id && c.credential.email == update.email)
+        {
+            return Err(unique_violation("credentials_email_key"));
+        }
+        let stored = self
+            .credentials
+            .iter_mut()
+            .find(|c| c.credential.id == credential_id)
+            .ok_or_else(|| not_found("Credential", CredentialsBy::Id(credential_id)))?;
+
+        stored.credential = CredentialsDAO {
+            id: credential_id,
+            email: update.email,
+            password: update.password,
+            active: update.active,
+            has_password: update.has_password,
+        };
+        Ok(stored.credential.clone())
+    }
+
+    /// Removes the credentials and, like the foreign keys cascading, what they own
+    fn erase_credentials(&mut self, erase: impl Fn(&StoredCredential) -> bool) -> u64 {
+        let count = self.credentials.len();
+        self.credentials.retain(|c| !erase(c));
+        let remaining: Vec<Uuid> = self.credentials.iter().map(|c| c.credential.id).collect();
+        self.sessions
+            .retain(|s| remaining.contains(&s.credential_id));
+        self.identities
+            .retain(|i| remaining.contains(&i.credential_id));
+        self.email_changes
+            .retain(|e| remaining.contains(&e.credential_id));
+        self.api_keys
+            .retain(|k| remaining.contains(&k.credential_id));
+        self.roles.retain(|(id, _)| remaining.contains(id));
+        (count - self.credentials.len()) as u64
+    }
+}
+
+/// Keeps everything in memory, for tests and demos without a database
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryAuthRepository {
+    state: Arc<Mutex<InMemoryState>>,
+}
+
+#[allow(dead_code)]
+impl InMemoryAuthRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes every call fail as if the database couldn't be reached, e.g. to test recovery
+    pub fn set_available(&self, available: bool) {
+        self.state.lock().unwrap().unavailable = !available;
+    }
+
+    /// Grants a role like the admin tooling does through the entity repositories
+    pub fn grant_role(&self, credential_id: Uuid, role: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .roles
+            .push((credential_id, role.to_string()));
+    }
+
+    fn state(&self) -> Result<MutexGuard<'_, InMemoryState>, DatabaseError> {
+        let state = self.state.lock().unwrap();
+        if state.unavailable {
+            return Err(DatabaseError::ConnectionNotAvailable);
+        }
+        Ok(state)
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthRepository for InMemoryAuthRepository {
+    async fn insert_credential(
+        &self,
+        credential: CreateCredentialsDAO,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        let mut state = self.state()?;
+        if state
+            .credentials
+            .iter()
+            .any(|c| c.credential.email == credential.email)
+        {
+            return Err(unique_violation("credentials_email_key"));
+        }
+        if credential.idempotency_key.is_some()
+            && state
+                .credentials
+                .iter()
+                .any(|c| c.idempotency_key == credential.idempotency_key)
+        {
+            return Err(unique_violation("credentials_idempotency_key_key"));
+        }
+
+        let stored = StoredCredential {
+            credential: CredentialsDAO {
+                id: Uuid::new_v4(),
+                email: credential.email,
+                password: credential.password,
+                active: true,
+                has_password: credential.has_password,
+            },
+            idempotency_key: credential.idempotency_key,
+            deactivated_at: None,
+        };
+        let created = stored.credential.clone();
+        state.credentials.push(stored);
+        Ok(created)
+    }
+
+    async fn get_credential(&self, credential_id: Uuid) -> Result<CredentialsDAO, DatabaseError> {
+        self.try_get_credential(CredentialsBy::Id(credential_id))
+            .await?
+            .ok_or_else(|| not_found("Credential", CredentialsBy::Id(credential_id)))
+    }
+
+    async fn try_get_credential(
+        &self,
+        key: CredentialsBy,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .credentials
+            .iter()
+            .map(|c| &c.credential)
+            .find(|c| match &key {
+                CredentialsBy::Id(id) => c.id == *id,
+                CredentialsBy::Email(email) => c.email == *email,
+            })
+            .cloned())
+    }
+
+    async fn try_get_credential_created_with(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<CredentialsDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .credentials
+            .iter()
+            .find(|c| c.idempotency_key.as_deref() == Some(idempotency_key))
+            .map(|c| c.credential.clone()))
+    }
+
+    async fn change_password(
+        &self,
+        credential_id: Uuid,
+        update: UpdateCredentialsDAO,
+        keep_session_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        let mut state = self.state()?;
+        state.update_credential(credential_id, update)?;
+        state
+            .sessions
+            .iter_mut()
+            .filter(|s| s.credential_id == credential_id && s.id != keep_session_id)
+            .for_each(|s| s.active = false);
+        Ok(())
+    }
+
+    async fn confirm_email_change(
+        &self,
+        change_id: Uuid,
+        credential_id: Uuid,
+        update: UpdateCredentialsDAO,
+    ) -> Result<(), DatabaseError> {
+        let mut state = self.state()?;
+        let change = state
+            .email_changes
+            .iter()
+            .position(|e| e.id == change_id)
+            .ok_or_else(|| not_found("EmailChange", EmailChangesBy::Id(change_id)))?;
+        state.update_credential(credential_id, update)?;
+        state.email_changes[change].active = false;
+        Ok(())
+    }
+
+    async fn close_credential(&self, credential_id: Uuid) -> Result<(), DatabaseError> {
+        let mut state = self.state()?;
+        let stored = state
+            .credentials
+            .iter_mut()
+            .find(|c| c.credential.id == credential_id)
+            .ok_or_else(|| not_found("Credential", CredentialsBy::Id(credential_id)))?;
+        stored.credential.active = false;
+        stored.deactivated_at = Some(Utc::now());
+
+        state
+            .sessions
+            .iter_mut()
+            .filter(|s| s.credential_id == credential_id)
+            .for_each(|s| s.active = false);
+        state
+            .api_keys
+            .iter_mut()
+            .filter(|k| k.credential_id == credential_id)
+            .for_each(|k| k.active = false);
+        Ok(())
+    }
+
+    async fn erase_unused_credential(&self, credential_id: Uuid) -> Result<bool, DatabaseError> {
+        let mut state = self.state()?;
+        if state
+            .sessions
+            .iter()
+            .any(|s| s.credential_id == credential_id)
+        {
+            return Ok(false);
+        }
+        Ok(state.erase_credentials(|c| c.credential.id == credential_id) > 0)
+    }
+
+    async fn erase_deactivated_credentials(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut state = self.state()?;
+        Ok(state.erase_credentials(|c| {
+            !c.credential.active && c.deactivated_at.is_some_and(|t| t < before)
+        }))
+    }
+
+    async fn insert_session(
+        &self,
+        session: CreateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let mut state = self.state()?;
+        state.check_credential(session.credential_id)?;
+
+        let session = SessionsDAO {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            expires_at: session.expires_at,
+            credential_id: session.credential_id,
+            active: true,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            device_label: session.device_label,
+            identity_provider: session.identity_provider,
+        };
+        state.sessions.push(session.clone());
+        Ok(session)
+    }
+
+    async fn try_get_session(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Option<SessionsDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state.sessions.iter().find(|s| s.id == session_id).cloned())
+    }
+
+    async fn update_session(
+        &self,
+        session_id: Uuid,
+        update: UpdateSessionsDAO,
+    ) -> Result<SessionsDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let session = state
+            .sessions
+            .iter_mut()
+            .find(|s| s.id == session_id)
+            .ok_or_else(|| not_found("Session", SessionsBy::Id(session_id)))?;
+        session.expires_at = update.expires_at;
+        Ok(session.clone())
+    }
+
+    async fn revoke_session(&self, session_id: Uuid) -> Result<SessionsDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let session = state
+            .sessions
+            .iter_mut()
+            .find(|s| s.id == session_id)
+            .ok_or_else(|| not_found("Session", SessionsBy::Id(session_id)))?;
+        session.active = false;
+        Ok(session.clone())
+    }
+
+    async fn list_sessions(&self, credential_id: Uuid) -> Result<Vec<SessionsDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .sessions
+            .iter()
+            .rev()
+            .filter(|s| s.credential_id == credential_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn purge_sessions(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        let mut state = self.state()?;
+        let count = state.sessions.len();
+        state
+            .sessions
+            .retain(|s| s.active && s.expires_at >= before);
+        Ok((count - state.sessions.len()) as u64)
+    }
+
+    async fn revoke_token(
+        &self,
+        jti: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError> {
+        let mut state = self.state()?;
+        if state
+            .revoked_tokens
+            .iter()
+            .any(|(revoked, _)| *revoked == jti)
+        {
+            return Ok(false);
+        }
+        state.revoked_tokens.push((jti, expires_at));
+        Ok(true)
+    }
+
+    async fn purge_revoked_tokens(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        let mut state = self.state()?;
+        let count = state.revoked_tokens.len();
+        state
+            .revoked_tokens
+            .retain(|(_, expires_at)| *expires_at >= before);
+        Ok((count - state.revoked_tokens.len()) as u64)
+    }
+
+    async fn insert_identity(
+        &self,
+        identity: CreateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let mut state = self.state()?;
+        state.check_credential(identity.credential_id)?;
+        if state
+            .identities
+            .iter()
+            .any(|i| i.provider == identity.provider && i.subject == identity.subject)
+        {
+            return Err(unique_violation("uq_identities_provider_subject"));
+        }
+
+        let identity = IdentitiesDAO {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            credential_id: identity.credential_id,
+            provider: identity.provider,
+            subject: identity.subject,
+            email: identity.email,
+            active: true,
+        };
+        state.identities.push(identity.clone());
+        Ok(identity)
+    }
+
+    async fn try_get_identity(
+        &self,
+        key: IdentitiesBy,
+    ) -> Result<Option<IdentitiesDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .identities
+            .iter()
+            .find(|i| match &key {
+                IdentitiesBy::Id(id) => i.id == *id,
+                IdentitiesBy::Subject { provider, subject } => {
+                    i.provider == *provider && i.subject == *subject
+                }
+            })
+            .cloned())
+    }
+
+    async fn update_identity(
+        &self,
+        identity_id: Uuid,
+        update: UpdateIdentitiesDAO,
+    ) -> Result<IdentitiesDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let identity = state
+            .identities
+            .iter_mut()
+            .find(|i| i.id == identity_id)
+            .ok_or_else(|| not_found("Identity", IdentitiesBy::Id(identity_id)))?;
+        identity.email = update.email;
+        Ok(identity.clone())
+    }
+
+    async fn insert_email_change(
+        &self,
+        change: CreateEmailChangesDAO,
+    ) -> Result<EmailChangesDAO, DatabaseError> {
+        let mut state = self.state()?;
+        state.check_credential(change.credential_id)?;
+
+        let change = EmailChangesDAO {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            expires_at: change.expires_at,
+            credential_id: change.credential_id,
+            email: change.email,
+            active: true,
+        };
+        state.email_changes.push(change.clone());
+        Ok(change)
+    }
+
+    async fn try_get_email_change(
+        &self,
+        change_id: Uuid,
+    ) -> Result<Option<EmailChangesDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .email_changes
+            .iter()
+            .find(|e| e.id == change_id)
+            .cloned())
+    }
+
+    async fn insert_api_key(&self, api_key: CreateApiKeysDAO) -> Result<ApiKeysDAO, DatabaseError> {
+        let mut state = self.state()?;
+        state.check_credential(api_key.credential_id)?;
+        if state.api_keys.iter().any(|k| k.prefix == api_key.prefix) {
+            return Err(unique_violation("uq_api_keys_prefix"));
+        }
+
+        let api_key = ApiKeysDAO {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            credential_id: api_key.credential_id,
+            name: api_key.name,
+            prefix: api_key.prefix,
+            key_hash: api_key.key_hash,
+            scopes: api_key.scopes,
+            expires_at: api_key.expires_at,
+            last_used_at: None,
+            active: true,
+        };
+        state.api_keys.push(api_key.clone());
+        Ok(api_key)
+    }
+
+    async fn try_get_api_key(&self, key: ApiKeysBy) -> Result<Option<ApiKeysDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .api_keys
+            .iter()
+            .find(|k| match &key {
+                ApiKeysBy::Id(id) => k.id == *id,
+                ApiKeysBy::Prefix(prefix) => k.prefix == *prefix,
+            })
+            .cloned())
+    }
+
+    async fn update_api_key(
+        &self,
+        api_key_id: Uuid,
+        update: UpdateApiKeysDAO,
+    ) -> Result<ApiKeysDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let api_key = state
+            .api_keys
+            .iter_mut()
+            .find(|k| k.id == api_key_id)
+            .ok_or_else(|| not_found("ApiKey", ApiKeysBy::Id(api_key_id)))?;
+        api_key.last_used_at = update.last_used_at;
+        Ok(api_key.clone())
+    }
+
+    async fn revoke_api_key(&self, api_key_id: Uuid) -> Result<ApiKeysDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let api_key = state
+            .api_keys
+            .iter_mut()
+            .find(|k| k.id == api_key_id)
+            .ok_or_else(|| not_found("ApiKey", ApiKeysBy::Id(api_key_id)))?;
+        api_key.active = false;
+        Ok(api_key.clone())
+    }
+
+    async fn list_api_keys(&self, credential_id: Uuid) -> Result<Vec<ApiKeysDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .api_keys
+            .iter()
+            .filter(|k| k.credential_id == credential_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_roles(&self, credential_id: Uuid) -> Result<Vec<String>, DatabaseError> {
+        let state = self.state()?;
+        let mut roles: Vec<String> = state
+            .roles
+            .iter()
+            .filter(|(id, _)| *id == credential_id)
+            .map(|(_, role)| role.clone())
+            .collect();
+        roles.sort();
+        Ok(roles)
+    }
+
+    async fn insert_auth_event(
+        &self,
+        event: CreateAuthEventsDAO,
+    ) -> Result<AuthEventsDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let event = AuthEventsDAO {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            event_type: event.event_type,
+            credential_id: event.credential_id,
+            ip_address: event.ip_address,
+            user_agent: event.user_agent,
+            outcome: event.outcome,
+            detail: event.detail,
+        };
+        state.auth_events.push(event.clone());
+        Ok(event)
+    }
+
+    async fn list_auth_events(
+        &self,
+        filter: AuthEventsFilter,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<AuthEventsDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .auth_events
+            .iter()
+            .rev()
+            .filter(|e| {
+                filter
+                    .event_type
+                    .as_ref()
+                    .is_none_or(|t| e.event_type == *t)
+                    && filter
+                        .credential_id
+                        .is_none_or(|id| e.credential_id == Some(id))
+                    && filter
+                        .ip_address
+                        .as_ref()
+                        .is_none_or(|ip| e.ip_address.as_ref() == Some(ip))
+                    && filter.outcome.as_ref().is_none_or(|o| e.outcome == *o)
+                    && filter.since.is_none_or(|since| e.created_at >= since)
+                    && filter.until.is_none_or(|until| e.created_at < until)
+            })
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
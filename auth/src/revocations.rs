@@ -0,0 +1,68 @@
+use auth_token::{Revocation, REVOCATIONS_CHANNEL};
+use redis::AsyncCommands;
+use std::fmt::Debug;
+#[cfg(test)]
+use std::sync::Mutex;
+
+/// Tells other services about revoked sessions so they stop trusting cached ones.
+/// Failing to announce isn't an error of the revocation itself, the revocation
+/// is in the database already and caches expire on their own.
+#[async_trait::async_trait]
+pub trait Revocations: Debug + Send + Sync {
+    async fn publish(&self, revocation: Revocation);
+}
+
+/// Nobody is listening, e.g. in tests
+#[derive(Debug, Default)]
+pub struct SilentRevocations;
+
+#[async_trait::async_trait]
+impl Revocations for SilentRevocations {
+    async fn publish(&self, _revocation: Revocation) {}
+}
+
+/// Keeps what was published, for tests to read
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingRevocations {
+    pub published: Mutex<Vec<Revocation>>,
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Revocations for RecordingRevocations {
+    async fn publish(&self, revocation: Revocation) {
+        self.published.lock().unwrap().push(revocation);
+    }
+}
+
+/// Publishes on the `REVOCATIONS_CHANNEL` of a redis instance
+#[derive(Debug)]
+pub struct RedisRevocations {
+    client: redis::Client,
+}
+
+impl RedisRevocations {
+    pub fn new(redis_url: &str) -> redis::RedisResult<Self> {
+        Ok(Self {
+            client: redis::Client::open(redis_url)?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Revocations for RedisRevocations {
+    async fn publish(&self, revocation: Revocation) {
+        let result: redis::RedisResult<()> = async {
+            let mut connection = self.client.get_async_connection().await?;
+            connection
+                .publish(REVOCATIONS_CHANNEL, revocation.to_message())
+                .await
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("could not publish revocation {:?}: {}", revocation, e);
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use crate::auth::{AuthService, AuthServiceError, AuthServiceTrait};
+use crate::auth::{
+    AuthService, AuthServiceError, AuthServiceTrait, ExternalIdentity, SessionClient, SessionPolicy,
+};
 use crate::grpc::GRPCAuthService;
-use auth_database::connection::PgPool;
+use crate::mailer::Mailer;
+use crate::oidc::{OidcError, OidcProviders, PendingLogin};
+use crate::repository::PgAuthRepository;
+use crate::revocations::RedisRevocations;
+use crate::tokens::{TokenIssuer, TokenPair};
+use auth_database::migrations;
+use auth_database::router::DatabaseConfig;
 use grpc_interfaces::auth::auth_server::AuthServer;
 use std::error::Error;
 use std::marker::PhantomData;
@@ -14,11 +22,14 @@ use actix_session::{storage::RedisSessionStore, Session, SessionMiddleware};
 use actix_web::cookie::time::Duration;
 use actix_web::cookie::Key;
 use actix_web::{http, web, web::Data, App, HttpRequest, HttpResponse, HttpServer};
+use auth_token::JWKS_PATH;
 
 use serde::{Deserialize, Serialize};
 
-const SECS_IN_WEEK: i64 = 60 * 60 * 24 * 7;
 const SESSION_KEY: &str = "sid";
+const OIDC_LOGIN_KEY: &str = "oidc";
+const ERASURE_INTERVAL_IN_SECONDS: u64 = 60 * 60;
+const SESSION_PURGE_INTERVAL_IN_SECONDS: u64 = 60 * 15;
 
 impl From<AuthServiceError> for HttpResponse {
     fn from(value: AuthServiceError) -> Self {
@@ -26,6 +37,24 @@ impl From<AuthServiceError> for HttpResponse {
             AuthServiceError::InvalidCredentials => HttpResponse::Unauthorized().finish(),
             AuthServiceError::InternalServerError => HttpResponse::InternalServerError().finish(),
             AuthServiceError::InvalidInput { message } => HttpResponse::BadRequest().body(message),
+            AuthServiceError::NotFound { message } => HttpResponse::NotFound().body(message),
+            AuthServiceError::AlreadyExists { message } => HttpResponse::Conflict().body(message),
+            AuthServiceError::Conflict => HttpResponse::Conflict().finish(),
+            AuthServiceError::Unavailable => HttpResponse::ServiceUnavailable().finish(),
+            AuthServiceError::Forbidden => HttpResponse::Forbidden().finish(),
+        }
+    }
+}
+
+impl From<OidcError> for HttpResponse {
+    fn from(value: OidcError) -> Self {
+        eprintln!("oidc login failed: {:?}", value);
+        match value {
+            OidcError::UnknownProvider => HttpResponse::NotFound().finish(),
+            OidcError::InvalidState | OidcError::InvalidToken(_) => {
+                HttpResponse::Unauthorized().finish()
+            }
+            OidcError::Request(_) => HttpResponse::BadGateway().finish(),
         }
     }
 }
@@ -35,6 +64,7 @@ where
     T: AuthServiceTrait,
 {
     service: T,
+    oidc: Arc<OidcProviders>,
 }
 
 impl<T> AppState<T>
@@ -42,7 +72,30 @@ where
     T: AuthServiceTrait,
 {
     pub fn new(service: T) -> Self {
-        Self { service }
+        Self {
+            service,
+            oidc: Arc::new(OidcProviders::default()),
+        }
+    }
+
+    pub fn with_oidc(mut self, oidc: Arc<OidcProviders>) -> Self {
+        self.oidc = oidc;
+        self
+    }
+}
+
+fn session_client(req: &HttpRequest, device_label: Option<String>) -> SessionClient {
+    SessionClient {
+        user_agent: req
+            .headers()
+            .get(http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        ip_address: req
+            .connection_info()
+            .realip_remote_addr()
+            .map(str::to_string),
+        device_label,
     }
 }
 
@@ -50,17 +103,22 @@ where
 pub struct SignInRequest {
     pub email: String,
     pub password: String,
+    /// Name the user gives to this device, guessed from the user agent when missing
+    #[serde(default)]
+    pub device_label: Option<String>,
 }
 
 async fn sign_in<T: AuthServiceTrait>(
-    _req: HttpRequest,
+    req: HttpRequest,
     state: Data<AppState<T>>,
     session: Session,
     payload: web::Json<SignInRequest>,
 ) -> HttpResponse {
+    let payload = payload.into_inner();
+    let client = session_client(&req, payload.device_label);
     let result = state
         .service
-        .sign_in(payload.email.to_owned(), payload.password.to_owned())
+        .sign_in(payload.email, payload.password, client)
         .await;
 
     match result {
@@ -76,6 +134,229 @@ async fn sign_in<T: AuthServiceTrait>(
     }
 }
 
+/// Starts an OIDC login, the browser is sent to the provider
+async fn oidc_authorize<T: AuthServiceTrait>(
+    state: Data<AppState<T>>,
+    session: Session,
+    provider: web::Path<String>,
+) -> HttpResponse {
+    let (url, pending) = match state.oidc.get(&provider).and_then(|p| p.authorize()) {
+        Ok(authorization) => authorization,
+        Err(error) => return HttpResponse::from(error),
+    };
+
+    if let Err(e) = session.insert(OIDC_LOGIN_KEY, pending) {
+        eprintln!("error inserting pending login {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    };
+
+    HttpResponse::Found()
+        .insert_header((http::header::LOCATION, url.to_string()))
+        .finish()
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OidcCallbackQuery {
+    pub code: String,
+    pub state: String,
+}
+
+/// Where the provider sends the browser back, signs in with the linked credential
+async fn oidc_callback<T: AuthServiceTrait>(
+    req: HttpRequest,
+    state: Data<AppState<T>>,
+    session: Session,
+    provider: web::Path<String>,
+    query: web::Query<OidcCallbackQuery>,
+) -> HttpResponse {
+    // A pending login can only be completed once.
+    let Some(Ok(pending)) = session.remove_as::<PendingLogin>(OIDC_LOGIN_KEY) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let claims = match state.oidc.get(&provider) {
+        Ok(oidc) => oidc.complete(&pending, &query.code, &query.state).await,
+        Err(error) => Err(error),
+    };
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(error) => return HttpResponse::from(error),
+    };
+
+    let identity = ExternalIdentity {
+        provider: provider.into_inner(),
+        subject: claims.sub,
+        email: claims.email,
+        email_verified: claims.email_verified,
+    };
+    let result = state
+        .service
+        .sign_in_with_identity(identity, session_client(&req, None))
+        .await;
+
+    match result {
+        Ok(created_session) => {
+            if let Err(e) = session.insert(SESSION_KEY, created_session.id) {
+                eprintln!("error inserting session {:?}", e);
+                return HttpResponse::InternalServerError().finish();
+            };
+
+            HttpResponse::Found()
+                .insert_header((http::header::LOCATION, state.oidc.login_redirect.clone()))
+                .finish()
+        }
+        Err(error) => HttpResponse::from(error),
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+}
+
+impl From<TokenPair> for TokenResponse {
+    fn from(value: TokenPair) -> Self {
+        Self {
+            access_token: value.access_token,
+            refresh_token: value.refresh_token,
+            token_type: "Bearer".to_string(),
+            expires_in: value.expires_in,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+/// Bearer tokens for the signed in session, for clients that can't rely on the cookie
+async fn issue_tokens<T: AuthServiceTrait>(
+    state: Data<AppState<T>>,
+    session: Session,
+) -> HttpResponse {
+    let Some(session_id) = session_id(&session) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    match state.service.issue_tokens(session_id).await {
+        Ok(tokens) => HttpResponse::Ok().json(TokenResponse::from(tokens)),
+        Err(error) => HttpResponse::from(error),
+    }
+}
+
+async fn refresh_tokens<T: AuthServiceTrait>(
+    state: Data<AppState<T>>,
+    payload: web::Json<RefreshTokenRequest>,
+) -> HttpResponse {
+    match state
+        .service
+        .refresh_tokens(payload.into_inner().refresh_token)
+        .await
+    {
+        Ok(tokens) => HttpResponse::Ok().json(TokenResponse::from(tokens)),
+        Err(error) => HttpResponse::from(error),
+    }
+}
+
+async fn revoke_refresh_token<T: AuthServiceTrait>(
+    state: Data<AppState<T>>,
+    payload: web::Json<RefreshTokenRequest>,
+) -> HttpResponse {
+    match state
+        .service
+        .revoke_refresh_token(payload.into_inner().refresh_token)
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => HttpResponse::from(error),
+    }
+}
+
+async fn jwks<T: AuthServiceTrait>(state: Data<AppState<T>>) -> HttpResponse {
+    HttpResponse::Ok().json(state.service.jwks())
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChangeEmailRequest {
+    pub current_password: String,
+    pub new_email: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ConfirmEmailChangeRequest {
+    pub token: String,
+}
+
+fn session_id(session: &Session) -> Option<String> {
+    session.get::<String>(SESSION_KEY).unwrap_or_else(|e| {
+        eprintln!("error reading session {:?}", e);
+        None
+    })
+}
+
+async fn change_password<T: AuthServiceTrait>(
+    state: Data<AppState<T>>,
+    session: Session,
+    payload: web::Json<ChangePasswordRequest>,
+) -> HttpResponse {
+    let Some(session_id) = session_id(&session) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let payload = payload.into_inner();
+
+    match state
+        .service
+        .change_password(session_id, payload.current_password, payload.new_password)
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => HttpResponse::from(error),
+    }
+}
+
+async fn change_email<T: AuthServiceTrait>(
+    state: Data<AppState<T>>,
+    session: Session,
+    payload: web::Json<ChangeEmailRequest>,
+) -> HttpResponse {
+    let Some(session_id) = session_id(&session) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let payload = payload.into_inner();
+
+    match state
+        .service
+        .change_email(session_id, payload.current_password, payload.new_email)
+        .await
+    {
+        Ok(_) => HttpResponse::Accepted().finish(),
+        Err(error) => HttpResponse::from(error),
+    }
+}
+
+async fn confirm_email_change<T: AuthServiceTrait>(
+    state: Data<AppState<T>>,
+    payload: web::Json<ConfirmEmailChangeRequest>,
+) -> HttpResponse {
+    match state
+        .service
+        .confirm_email_change(payload.into_inner().token)
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => HttpResponse::from(error),
+    }
+}
+
 struct Api<Session, Service>
 where
     Session: SessionStore + 'static,
@@ -96,6 +377,7 @@ where
         state: AppState<Service>,
         session_private_key: &str,
         web_front_end_origin: &str,
+        session_ttl: std::time::Duration,
     ) {
         let cors = Cors::default()
             .allowed_origin(web_front_end_origin)
@@ -105,7 +387,10 @@ where
         let key = Key::derive_from(session_private_key.as_bytes());
         let store = SessionMiddleware::builder(session, key)
             .session_lifecycle(
-                PersistentSession::default().session_ttl(Duration::seconds(SECS_IN_WEEK)),
+                // The cookie lives as long as the longest possible session,
+                // expiry on inactivity is enforced by the auth database.
+                PersistentSession::default()
+                    .session_ttl(Duration::seconds(session_ttl.as_secs() as i64)),
             )
             .cookie_domain(Some("localhost".to_string()))
             .cookie_name(SESSION_KEY.to_string())
@@ -114,11 +399,32 @@ where
             .cookie_secure(true)
             .build();
         cfg.service(
-            web::resource("/signin")
+            web::scope("")
                 .app_data(Data::new(state))
                 .wrap(cors)
                 .wrap(store)
-                .route(web::post().to(sign_in::<Service>)),
+                .route("/signin", web::post().to(sign_in::<Service>))
+                .route(
+                    "/oidc/{provider}/authorize",
+                    web::get().to(oidc_authorize::<Service>),
+                )
+                .route(
+                    "/oidc/{provider}/callback",
+                    web::get().to(oidc_callback::<Service>),
+                )
+                .route("/token", web::post().to(issue_tokens::<Service>))
+                .route("/token/refresh", web::post().to(refresh_tokens::<Service>))
+                .route(
+                    "/token/revoke",
+                    web::post().to(revoke_refresh_token::<Service>),
+                )
+                .route(JWKS_PATH, web::get().to(jwks::<Service>))
+                .route("/password", web::post().to(change_password::<Service>))
+                .route("/email", web::post().to(change_email::<Service>))
+                .route(
+                    "/email/confirm",
+                    web::post().to(confirm_email_change::<Service>),
+                ),
         );
     }
 
@@ -128,6 +434,8 @@ where
         session_private_key: &str,
         web_front_end_origin: &str,
         redis_session_url: &str,
+        session_ttl: std::time::Duration,
+        oidc: Arc<OidcProviders>,
     ) -> () {
         let store = RedisSessionStore::new(redis_session_url)
             .await
@@ -135,7 +443,7 @@ where
         let session_private_key = session_private_key.to_owned();
         let web_front_end_origin = web_front_end_origin.to_owned();
         let app = move || {
-            let state = AppState::new(service.clone());
+            let state = AppState::new(service.clone()).with_oidc(Arc::clone(&oidc));
             App::new().configure(|cfg| {
                 Api::configure_app(
                     cfg,
@@ -143,6 +451,7 @@ where
                     state,
                     &session_private_key,
                     &web_front_end_origin,
+                    session_ttl,
                 )
             })
         };
@@ -157,23 +466,67 @@ where
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_server(
     grpc_address: &str,
-    database_url: &str,
+    database: DatabaseConfig,
     web_front_end_origin: &str,
     redis_session_url: &str,
     session_private_key: &str,
     auth_api_port: u16,
+    account_erasure_grace: std::time::Duration,
+    session_policy: SessionPolicy,
+    oidc_providers: OidcProviders,
+    token_issuer: TokenIssuer,
+    mailer: Arc<dyn Mailer>,
+    migrate: bool,
 ) -> Result<(), Box<dyn Error>> {
     let grpc_address = grpc_address.parse()?;
 
-    let pool = Arc::new(
-        PgPool::connect(&database_url)
-            .await
-            .expect("Could not connect to database"),
-    );
+    let router = database
+        .connect()
+        .await
+        .expect("Could not connect to database");
+    migrations::prepare(&auth_database::MIGRATOR, router.write(), migrate).await?;
+
+    let auth_service = AuthService::new(PgAuthRepository::new(router))
+        .with_session_policy(session_policy)
+        .with_token_issuer(Arc::new(token_issuer))
+        .with_mailer(mailer)
+        .with_revocations(Arc::new(RedisRevocations::new(redis_session_url)?));
 
-    let auth_service = AuthService::new(pool);
+    let eraser = auth_service.clone();
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(ERASURE_INTERVAL_IN_SECONDS));
+        loop {
+            interval.tick().await;
+            match eraser.erase_closed_accounts(account_erasure_grace).await {
+                Ok(0) => {}
+                Ok(erased) => println!("erased {} closed accounts", erased),
+                Err(e) => eprintln!("could not erase closed accounts: {:?}", e),
+            }
+        }
+    });
+    let purger = auth_service.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            SESSION_PURGE_INTERVAL_IN_SECONDS,
+        ));
+        loop {
+            interval.tick().await;
+            match purger.purge_sessions().await {
+                Ok(0) => {}
+                Ok(purged) => println!("purged {} sessions", purged),
+                Err(e) => eprintln!("could not purge sessions: {:?}", e),
+            }
+            match purger.purge_revoked_tokens().await {
+                Ok(0) => {}
+                Ok(purged) => println!("purged {} revoked tokens", purged),
+                Err(e) => eprintln!("could not purge revoked tokens: {:?}", e),
+            }
+        }
+    });
     let web_front_end_origin = web_front_end_origin.to_owned();
     let redis_session_url = redis_session_url.to_owned();
     let session_private_key = session_private_key.to_owned();
@@ -185,6 +538,8 @@ pub async fn run_server(
             &session_private_key,
             &web_front_end_origin,
             &redis_session_url,
+            session_policy.absolute_timeout,
+            Arc::new(oidc_providers),
         )
         .await;
         ()
@@ -203,19 +558,25 @@ pub async fn run_server(
 mod tests {
     use super::*;
     use crate::auth::{AuthServiceError, MockAuthService, SignInResponse};
+    use crate::oidc::test_issuer::TestIssuer;
     use actix_session::storage::CookieSessionStore;
     use actix_web::http::{header, StatusCode};
     use actix_web::{cookie, http::header::ContentType, test};
     use auth_database::types::Utc;
-    use mockall::predicate::eq;
+    use auth_token::jwk::JwkSet;
+    use mockall::predicate::{always, eq};
     use std::str::FromStr;
 
     #[actix_web::test]
     async fn signin_success() {
         let mut mock = MockAuthService::new();
         mock.expect_sign_in()
-            .with(eq("test@gmail.com".to_string()), eq("123456".to_string()))
-            .returning(move |_, _| {
+            .with(
+                eq("test@gmail.com".to_string()),
+                eq("123456".to_string()),
+                always(),
+            )
+            .returning(move |_, _, _| {
                 Ok(SignInResponse {
                     id: "value".to_string(),
                     expires_at: Utc::now(),
@@ -231,12 +592,14 @@ mod tests {
                 state,
                 secret,
                 "localhost",
+                SessionPolicy::default().absolute_timeout,
             )
         }))
         .await;
         let payload = SignInRequest {
             email: "test@gmail.com".to_string(),
             password: "123456".to_string(),
+            device_label: None,
         };
 
         let req = test::TestRequest::post()
@@ -258,6 +621,60 @@ mod tests {
         assert_eq!(cookie.domain().unwrap(), "localhost");
         assert!(cookie.http_only().unwrap());
         assert!(cookie.secure().unwrap());
+        assert_eq!(
+            cookie.max_age(),
+            Some(Duration::seconds(
+                SessionPolicy::default().absolute_timeout.as_secs() as i64
+            ))
+        );
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn signin_records_client() {
+        let mut mock = MockAuthService::new();
+        mock.expect_sign_in()
+            .with(
+                eq("test@gmail.com".to_string()),
+                eq("123456".to_string()),
+                eq(SessionClient {
+                    user_agent: Some("curl/8.4.0".to_string()),
+                    ip_address: Some("10.0.0.1".to_string()),
+                    device_label: Some("CI".to_string()),
+                }),
+            )
+            .returning(move |_, _, _| {
+                Ok(SignInResponse {
+                    id: "value".to_string(),
+                    expires_at: Utc::now(),
+                })
+            })
+            .times(1);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/signin")
+            .peer_addr("10.0.0.1:45678".parse().unwrap())
+            .insert_header((header::USER_AGENT, "curl/8.4.0"))
+            .set_json(SignInRequest {
+                email: "test@gmail.com".to_string(),
+                password: "123456".to_string(),
+                device_label: Some("CI".to_string()),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
 
@@ -265,8 +682,12 @@ mod tests {
     async fn signin_error_invalid_credentials() {
         let mut mock = MockAuthService::new();
         mock.expect_sign_in()
-            .with(eq("test@gmail.com".to_string()), eq("123456".to_string()))
-            .returning(|_, _| Err(AuthServiceError::InvalidCredentials))
+            .with(
+                eq("test@gmail.com".to_string()),
+                eq("123456".to_string()),
+                always(),
+            )
+            .returning(|_, _, _| Err(AuthServiceError::InvalidCredentials))
             .times(1);
         let state = AppState::new(mock);
         let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
@@ -277,12 +698,14 @@ mod tests {
                 state,
                 secret,
                 "localhost",
+                SessionPolicy::default().absolute_timeout,
             )
         }))
         .await;
         let payload = SignInRequest {
             email: "test@gmail.com".to_string(),
             password: "123456".to_string(),
+            device_label: None,
         };
 
         let req = test::TestRequest::post()
@@ -300,8 +723,8 @@ mod tests {
     async fn signin_error_bad_request() {
         let mut mock = MockAuthService::new();
         mock.expect_sign_in()
-            .with(eq("test".to_string()), eq("123456".to_string()))
-            .returning(|_, _| {
+            .with(eq("test".to_string()), eq("123456".to_string()), always())
+            .returning(|_, _, _| {
                 Err(AuthServiceError::InvalidInput {
                     message: "invalid email".to_string(),
                 })
@@ -316,12 +739,14 @@ mod tests {
                 state,
                 secret,
                 "localhost",
+                SessionPolicy::default().absolute_timeout,
             )
         }))
         .await;
         let payload = SignInRequest {
             email: "test".to_string(),
             password: "123456".to_string(),
+            device_label: None,
         };
 
         let req = test::TestRequest::post()
@@ -337,4 +762,451 @@ mod tests {
         let body = test::read_body(resp).await;
         assert_eq!(body, web::Bytes::from_static(b"invalid email"))
     }
+
+    fn expect_sign_in(mock: &mut MockAuthService) {
+        mock.expect_sign_in()
+            .with(
+                eq("test@gmail.com".to_string()),
+                eq("123456".to_string()),
+                always(),
+            )
+            .returning(move |_, _, _| {
+                Ok(SignInResponse {
+                    id: "value".to_string(),
+                    expires_at: Utc::now(),
+                })
+            })
+            .times(1);
+    }
+
+    #[actix_web::test]
+    async fn change_password_success() {
+        let mut mock = MockAuthService::new();
+        expect_sign_in(&mut mock);
+        mock.expect_change_password()
+            .with(
+                eq("value".to_string()),
+                eq("123456".to_string()),
+                eq("654321".to_string()),
+            )
+            .returning(|_, _, _| Ok(()))
+            .times(1);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/signin")
+            .set_json(SignInRequest {
+                email: "test@gmail.com".to_string(),
+                password: "123456".to_string(),
+                device_label: None,
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+
+        let req = test::TestRequest::post()
+            .uri("/password")
+            .cookie(cookie)
+            .set_json(ChangePasswordRequest {
+                current_password: "123456".to_string(),
+                new_password: "654321".to_string(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn change_password_without_session() {
+        let mut mock = MockAuthService::new();
+        mock.expect_change_password().times(0);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/password")
+            .set_json(ChangePasswordRequest {
+                current_password: "123456".to_string(),
+                new_password: "654321".to_string(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn change_email_success() {
+        let mut mock = MockAuthService::new();
+        expect_sign_in(&mut mock);
+        mock.expect_change_email()
+            .with(
+                eq("value".to_string()),
+                eq("123456".to_string()),
+                eq("new@gmail.com".to_string()),
+            )
+            .returning(|_, _, _| Ok(()))
+            .times(1);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/signin")
+            .set_json(SignInRequest {
+                email: "test@gmail.com".to_string(),
+                password: "123456".to_string(),
+                device_label: None,
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+
+        let req = test::TestRequest::post()
+            .uri("/email")
+            .cookie(cookie)
+            .set_json(ChangeEmailRequest {
+                current_password: "123456".to_string(),
+                new_email: "new@gmail.com".to_string(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    }
+
+    #[actix_web::test]
+    async fn confirm_email_change_invalid_token() {
+        let mut mock = MockAuthService::new();
+        mock.expect_confirm_email_change()
+            .with(eq("token".to_string()))
+            .returning(|_| {
+                Err(AuthServiceError::InvalidInput {
+                    message: "invalid token format".to_string(),
+                })
+            })
+            .times(1);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/email/confirm")
+            .set_json(ConfirmEmailChangeRequest {
+                token: "token".to_string(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    fn token_pair() -> TokenPair {
+        TokenPair {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_in: 300,
+        }
+    }
+
+    #[actix_web::test]
+    async fn issue_tokens_success() {
+        let mut mock = MockAuthService::new();
+        expect_sign_in(&mut mock);
+        mock.expect_issue_tokens()
+            .with(eq("value".to_string()))
+            .returning(|_| Ok(token_pair()))
+            .times(1);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/signin")
+            .set_json(SignInRequest {
+                email: "test@gmail.com".to_string(),
+                password: "123456".to_string(),
+                device_label: None,
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+
+        let req = test::TestRequest::post()
+            .uri("/token")
+            .cookie(cookie)
+            .to_request();
+        let resp: TokenResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp.access_token, "access");
+        assert_eq!(resp.refresh_token, "refresh");
+        assert_eq!(resp.token_type, "Bearer");
+        assert_eq!(resp.expires_in, 300);
+    }
+
+    #[actix_web::test]
+    async fn issue_tokens_without_session() {
+        let mut mock = MockAuthService::new();
+        mock.expect_issue_tokens().times(0);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post().uri("/token").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn refresh_tokens_reused() {
+        let mut mock = MockAuthService::new();
+        mock.expect_refresh_tokens()
+            .with(eq("refresh".to_string()))
+            .returning(|_| Err(AuthServiceError::InvalidCredentials))
+            .times(1);
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/token/refresh")
+            .set_json(RefreshTokenRequest {
+                refresh_token: "refresh".to_string(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn jwks_published() {
+        let issuer = TokenIssuer::generate("key").unwrap();
+        let keys = issuer.jwks().clone();
+        let mut mock = MockAuthService::new();
+        mock.expect_jwks().returning(move || keys.clone());
+        let state = AppState::new(mock);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::get().uri(JWKS_PATH).to_request();
+        let resp: JwkSet = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(&resp, issuer.jwks());
+    }
+
+    fn oidc_state(mock: MockAuthService, issuer: &TestIssuer) -> AppState<MockAuthService> {
+        let providers = OidcProviders::from_toml(&issuer.providers_toml()).unwrap();
+        AppState::new(mock).with_oidc(Arc::new(providers))
+    }
+
+    #[actix_web::test]
+    async fn oidc_sign_in() {
+        let issuer = TestIssuer::start().await;
+        let mut mock = MockAuthService::new();
+        mock.expect_sign_in_with_identity()
+            .withf(|identity, _| {
+                identity.provider == "test"
+                    && identity.subject == "user-1"
+                    && identity.email.as_deref() == Some("user@gmail.com")
+                    && identity.email_verified
+            })
+            .returning(|_, _| {
+                Ok(SignInResponse {
+                    id: "value".to_string(),
+                    expires_at: Utc::now(),
+                })
+            })
+            .times(1);
+        let state = oidc_state(mock, &issuer);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/oidc/test/authorize")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+        let location = reqwest::Url::parse(
+            resp.headers()
+                .get(header::LOCATION)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+        )
+        .unwrap();
+        let params: std::collections::HashMap<_, _> = location.query_pairs().into_owned().collect();
+        assert!(location.as_str().starts_with(&issuer.server.uri()));
+
+        issuer
+            .issue(issuer.id_token("user-1", "user@gmail.com", &params["nonce"]))
+            .await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/oidc/test/callback?code=code&state={}",
+                params["state"]
+            ))
+            .cookie(cookie)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(
+            resp.headers().get(header::LOCATION).unwrap(),
+            "http://localhost:3000"
+        );
+        assert!(resp.response().cookies().next().is_some());
+    }
+
+    #[actix_web::test]
+    async fn oidc_callback_with_forged_state() {
+        let issuer = TestIssuer::start().await;
+        let mut mock = MockAuthService::new();
+        mock.expect_sign_in_with_identity().times(0);
+        let state = oidc_state(mock, &issuer);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/oidc/test/authorize")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+
+        let req = test::TestRequest::get()
+            .uri("/oidc/test/callback?code=code&state=forged")
+            .cookie(cookie)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // callback without a login in progress
+        let req = test::TestRequest::get()
+            .uri("/oidc/test/callback?code=code&state=forged")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn oidc_unknown_provider() {
+        let issuer = TestIssuer::start().await;
+        let state = oidc_state(MockAuthService::new(), &issuer);
+        let secret = "r4RXHJ0Ec7UZIhR7MfbzVzQjv2YSxHfb3LUeW2fHf6gZL6Lb7B1zYuNOfd5q8m25";
+        let app = test::init_service(App::new().configure(|cfg| {
+            Api::configure_app(
+                cfg,
+                CookieSessionStore::default(),
+                state,
+                secret,
+                "localhost",
+                SessionPolicy::default().absolute_timeout,
+            )
+        }))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/oidc/other/authorize")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
 }
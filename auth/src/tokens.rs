@@ -0,0 +1,211 @@
+use crate::auth::AuthServiceError;
+use auth_token::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+};
+use auth_token::{Claims, TokenError, TokenType, TokenVerifier, ISSUER};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+const DEFAULT_ACCESS_TOKEN_TTL_IN_SECONDS: u64 = 60 * 5;
+const DEFAULT_REFRESH_TOKEN_TTL_IN_SECONDS: u64 = 60 * 60 * 24 * 7;
+
+#[derive(Debug, Error)]
+pub enum TokenIssuerError {
+    #[error("invalid signing key: {0}")]
+    InvalidKey(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Seconds until the access token expires
+    pub expires_in: u64,
+}
+
+/// Signs access and refresh tokens with an Ed25519 key,
+/// its public half is published as a JWKS so tokens can be checked anywhere.
+pub struct TokenIssuer {
+    key_id: String,
+    encoding_key: EncodingKey,
+    jwks: JwkSet,
+    verifier: TokenVerifier,
+    access_ttl: Duration,
+    refresh_ttl: Duration,
+}
+
+impl Debug for TokenIssuer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenIssuer")
+            .field("key_id", &self.key_id)
+            .field("access_ttl", &self.access_ttl)
+            .field("refresh_ttl", &self.refresh_ttl)
+            .finish()
+    }
+}
+
+impl TokenIssuer {
+    /// Issuer with a throwaway key, tokens don't survive a restart
+    pub fn generate(key_id: &str) -> Result<Self, TokenIssuerError> {
+        let der = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|e| TokenIssuerError::InvalidKey(e.to_string()))?;
+        Self::from_pkcs8_der(key_id, der.as_ref())
+    }
+
+    /// Issuer from a PKCS#8 Ed25519 key, e.g. `openssl genpkey -algorithm ed25519`
+    pub fn from_pkcs8_pem(key_id: &str, pem: &str) -> Result<Self, TokenIssuerError> {
+        let pem = pem::parse(pem).map_err(|e| TokenIssuerError::InvalidKey(e.to_string()))?;
+        Self::from_pkcs8_der(key_id, pem.contents())
+    }
+
+    fn from_pkcs8_der(key_id: &str, der: &[u8]) -> Result<Self, TokenIssuerError> {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+            .map_err(|e| TokenIssuerError::InvalidKey(e.to_string()))?;
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                key_id: Some(key_id.to_string()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+            }),
+        };
+        let jwks = JwkSet { keys: vec![jwk] };
+
+        Ok(Self {
+            key_id: key_id.to_string(),
+            encoding_key: EncodingKey::from_ed_der(der),
+            verifier: TokenVerifier::new(jwks.clone()),
+            jwks,
+            access_ttl: Duration::from_secs(DEFAULT_ACCESS_TOKEN_TTL_IN_SECONDS),
+            refresh_ttl: Duration::from_secs(DEFAULT_REFRESH_TOKEN_TTL_IN_SECONDS),
+        })
+    }
+
+    pub fn with_ttls(mut self, access_ttl: Duration, refresh_ttl: Duration) -> Self {
+        self.access_ttl = access_ttl;
+        self.refresh_ttl = refresh_ttl;
+        self
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    fn sign(
+        &self,
+        credential_id: &str,
+        session_id: &str,
+        roles: &[String],
+        typ: TokenType,
+        ttl: Duration,
+    ) -> Result<String, AuthServiceError> {
+        let now = get_current_timestamp();
+        let claims = Claims {
+            iss: ISSUER.to_string(),
+            sub: credential_id.to_string(),
+            sid: session_id.to_string(),
+            jti: Uuid::new_v4().to_string(),
+            typ,
+            iat: now,
+            exp: now + ttl.as_secs(),
+            roles: roles.to_vec(),
+        };
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.key_id.clone());
+
+        encode(&header, &claims, &self.encoding_key).map_err(|e| {
+            eprintln!("could not sign token: {:?}", e);
+            AuthServiceError::InternalServerError
+        })
+    }
+
+    /// Refresh tokens carry no roles, refreshing looks them up again
+    pub fn issue(
+        &self,
+        credential_id: &str,
+        session_id: &str,
+        roles: &[String],
+    ) -> Result<TokenPair, AuthServiceError> {
+        Ok(TokenPair {
+            access_token: self.sign(
+                credential_id,
+                session_id,
+                roles,
+                TokenType::Access,
+                self.access_ttl,
+            )?,
+            refresh_token: self.sign(
+                credential_id,
+                session_id,
+                &[],
+                TokenType::Refresh,
+                self.refresh_ttl,
+            )?,
+            expires_in: self.access_ttl.as_secs(),
+        })
+    }
+
+    pub async fn verify(&self, token: &str, typ: TokenType) -> Result<Claims, TokenError> {
+        self.verifier.verify(token, typ).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = include_str!("../tests/fixtures/ed25519_test_key.pem");
+    const JWKS: &str = include_str!("../tests/fixtures/ed25519_test_jwks.json");
+
+    #[test]
+    fn test_jwks_from_pem() {
+        let issuer = TokenIssuer::from_pkcs8_pem("test-key", KEY).unwrap();
+        let expected: JwkSet = serde_json::from_str(JWKS).unwrap();
+        assert_eq!(issuer.jwks(), &expected);
+    }
+
+    #[tokio::test]
+    async fn test_issue() {
+        let issuer = TokenIssuer::generate("key").unwrap();
+        let pair = issuer
+            .issue("credential", "session", &["admin".to_string()])
+            .unwrap();
+        assert_eq!(pair.expires_in, DEFAULT_ACCESS_TOKEN_TTL_IN_SECONDS);
+
+        // verifiable with nothing but the published keys
+        let verifier = TokenVerifier::new(issuer.jwks().clone());
+        let access = verifier
+            .verify(&pair.access_token, TokenType::Access)
+            .await
+            .unwrap();
+        assert_eq!(access.sub, "credential");
+        assert_eq!(access.sid, "session");
+        assert_eq!(access.roles, vec!["admin".to_string()]);
+
+        let refresh = verifier
+            .verify(&pair.refresh_token, TokenType::Refresh)
+            .await
+            .unwrap();
+        assert_ne!(access.jti, refresh.jti);
+        assert!(refresh.roles.is_empty());
+        assert!(refresh.exp > access.exp);
+
+        // a refresh token can't be used as an access token
+        assert!(verifier
+            .verify(&pair.refresh_token, TokenType::Access)
+            .await
+            .is_err());
+    }
+}
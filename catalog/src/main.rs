@@ -0,0 +1,151 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use core_database::router::DatabaseConfig;
+use core_database::types::{DateTime, Utc};
+use rustflix_core::catalog::{self, Format, ImportReport};
+use rustflix_core::repository::PgCoreRepository;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Loads the movie catalog into the core database and dumps it back out
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Core database URL
+    #[arg(long, env = "GRAPHQL_DATABASE_URL")]
+    database_url: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inserts the movies of a file and updates the ones whose title is taken.
+    /// Invalid rows are reported and skipped, the others are imported all or none.
+    Import {
+        file: PathBuf,
+        /// Guessed from the extension when missing, `.csv` or `.jsonl`
+        #[arg(long)]
+        format: Option<FileFormat>,
+        /// Reports what the import would do without changing the catalog
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Writes the catalog out as it's read, in a format `import` reads back
+    Export {
+        #[arg(long, default_value = "json-lines")]
+        format: FileFormat,
+        /// Only the movies changed after it, e.g. `2024-03-01T00:00:00Z`, for incremental syncs
+        #[arg(long)]
+        updated_since: Option<DateTime<Utc>>,
+        /// Standard output when missing
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FileFormat {
+    Csv,
+    JsonLines,
+}
+
+impl From<FileFormat> for Format {
+    fn from(value: FileFormat) -> Self {
+        match value {
+            FileFormat::Csv => Format::Csv,
+            FileFormat::JsonLines => Format::JsonLines,
+        }
+    }
+}
+
+fn print_report(report: &ImportReport) {
+    for error in &report.errors {
+        eprintln!("{}", error);
+    }
+    let verb = if report.dry_run { "would be" } else { "were" };
+    println!(
+        "{} inserted, {} updated and {} unchanged movies {} imported, {} rows skipped",
+        report.inserted,
+        report.updated,
+        report.unchanged,
+        verb,
+        report.errors.len()
+    );
+}
+
+async fn connect(database_url: &str) -> PgCoreRepository {
+    let db = DatabaseConfig::new(database_url)
+        .connect()
+        .await
+        .expect("Could not connect to database");
+    PgCoreRepository::new(db)
+}
+
+async fn export(
+    repository: &PgCoreRepository,
+    format: Format,
+    updated_since: Option<DateTime<Utc>>,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut export = catalog::export(repository, format, updated_since).await?;
+    while let Some(chunk) = export.next_chunk().await? {
+        output.write_all(&chunk)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let args = Cli::parse();
+
+    match args.command {
+        Command::Import {
+            file,
+            format,
+            dry_run,
+        } => {
+            let Some(format) = format
+                .map(Format::from)
+                .or_else(|| Format::from_path(&file))
+            else {
+                eprintln!("can't tell the format of {}, pass --format", file.display());
+                std::process::exit(2);
+            };
+            let source = File::open(&file).expect("Could not open the catalog file");
+            let repository = connect(&args.database_url).await;
+
+            match catalog::import(&repository, format, source, dry_run).await {
+                Ok(report) => {
+                    print_report(&report);
+                    if !report.errors.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Export {
+            format,
+            updated_since,
+            output,
+        } => {
+            let mut output: Box<dyn Write> = match output {
+                Some(path) => {
+                    Box::new(File::create(path).expect("Could not create the output file"))
+                }
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let repository = connect(&args.database_url).await;
+            if let Err(e) = export(&repository, format.into(), updated_since, &mut output).await {
+                eprintln!("Could not export the catalog: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
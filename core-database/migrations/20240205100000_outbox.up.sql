@@ -0,0 +1,13 @@
+-- Domain events written in the same transaction as the change they describe,
+-- relayed to downstream consumers in id order and marked once delivered.
+CREATE TABLE IF NOT EXISTS outbox (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    aggregate_type VARCHAR NOT NULL,
+    aggregate_id UUID NOT NULL,
+    event_type VARCHAR NOT NULL,
+    payload JSONB NOT NULL,
+    published_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS outbox_unpublished ON outbox (id) WHERE published_at IS NULL;
\ No newline at end of file
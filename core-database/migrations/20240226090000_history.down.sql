@@ -0,0 +1,8 @@
+DROP TABLE IF EXISTS entity_history;
+DROP TRIGGER IF EXISTS outbox_updated_at ON outbox;
+DROP TRIGGER IF EXISTS users_updated_at ON users;
+DROP TRIGGER IF EXISTS movies_updated_at ON movies;
+DROP FUNCTION IF EXISTS set_updated_at();
+ALTER TABLE outbox DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE users DROP COLUMN IF EXISTS created_at;
+ALTER TABLE movies DROP COLUMN IF EXISTS created_at;
@@ -0,0 +1,32 @@
+-- Every table tells when its rows were created and last changed, `updated_at` is kept by a trigger
+-- so writes from outside the repositories keep it as well.
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE outbox ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE OR REPLACE FUNCTION set_updated_at() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER movies_updated_at BEFORE UPDATE ON movies FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER users_updated_at BEFORE UPDATE ON users FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER outbox_updated_at BEFORE UPDATE ON outbox FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+-- Who changed what and when, written by the repositories in the same transaction as the change.
+-- `before_state` and `after_state` hold the whole row, missing before an insert and after a delete.
+CREATE TABLE IF NOT EXISTS entity_history (
+    id BIGSERIAL PRIMARY KEY,
+    entity_type VARCHAR NOT NULL,
+    entity_id UUID NOT NULL,
+    action VARCHAR NOT NULL,
+    before_state JSONB,
+    after_state JSONB,
+    -- id of the user who made the change, none for the service's own jobs
+    actor VARCHAR,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS entity_history_entity ON entity_history (entity_type, entity_id, id);
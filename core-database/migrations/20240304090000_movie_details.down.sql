@@ -0,0 +1,3 @@
+ALTER TABLE movies DROP COLUMN IF EXISTS available;
+ALTER TABLE movies DROP COLUMN IF EXISTS cast_members;
+ALTER TABLE movies DROP COLUMN IF EXISTS genres;
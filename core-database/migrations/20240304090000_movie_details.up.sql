@@ -0,0 +1,5 @@
+-- What catalog imports carry besides the title and description, lists are JSON arrays of names
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS genres JSONB NOT NULL DEFAULT '[]';
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS cast_members JSONB NOT NULL DEFAULT '[]';
+-- Whether the movie can be watched now, unavailable movies stay in the catalog
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS available BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,27 @@
+-- SQLite counterpart of the Postgres schema in `migrations/`, for local development and demos.
+-- Uuids are stored as blobs and timestamps as RFC 3339 text, both generated by the repositories.
+CREATE TABLE IF NOT EXISTS users (
+    id BLOB NOT NULL PRIMARY KEY,
+    name VARCHAR(60) NOT NULL,
+    active BOOLEAN DEFAULT TRUE,
+    birthday DATETIME NOT NULL,
+    deactivated_at DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS movies (
+    id BLOB NOT NULL PRIMARY KEY,
+    title VARCHAR(60) NOT NULL UNIQUE,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at DATETIME NOT NULL,
+    aggregate_type VARCHAR NOT NULL,
+    aggregate_id BLOB NOT NULL,
+    event_type VARCHAR NOT NULL,
+    payload TEXT NOT NULL CHECK (json_valid(payload)),
+    published_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS outbox_unpublished ON outbox (id) WHERE published_at IS NULL;
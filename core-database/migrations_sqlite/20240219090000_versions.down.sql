@@ -0,0 +1,4 @@
+ALTER TABLE users DROP COLUMN updated_at;
+ALTER TABLE users DROP COLUMN version;
+ALTER TABLE movies DROP COLUMN updated_at;
+ALTER TABLE movies DROP COLUMN version;
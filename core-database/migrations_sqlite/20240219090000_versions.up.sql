@@ -0,0 +1,7 @@
+-- SQLite only adds columns with a constant default, the rows already there are stamped right after
+ALTER TABLE movies ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE movies ADD COLUMN updated_at DATETIME NOT NULL DEFAULT '1970-01-01T00:00:00+00:00';
+ALTER TABLE users ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE users ADD COLUMN updated_at DATETIME NOT NULL DEFAULT '1970-01-01T00:00:00+00:00';
+UPDATE movies SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now');
+UPDATE users SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now');
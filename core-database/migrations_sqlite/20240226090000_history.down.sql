@@ -0,0 +1,7 @@
+DROP TABLE IF EXISTS entity_history;
+DROP TRIGGER IF EXISTS outbox_updated_at;
+DROP TRIGGER IF EXISTS users_updated_at;
+DROP TRIGGER IF EXISTS movies_updated_at;
+ALTER TABLE outbox DROP COLUMN updated_at;
+ALTER TABLE users DROP COLUMN created_at;
+ALTER TABLE movies DROP COLUMN created_at;
@@ -0,0 +1,36 @@
+-- SQLite triggers can't change the row being written, the repositories stamp `updated_at` themselves
+-- and the triggers only catch writes that didn't.
+ALTER TABLE movies ADD COLUMN created_at DATETIME NOT NULL DEFAULT '1970-01-01T00:00:00+00:00';
+ALTER TABLE users ADD COLUMN created_at DATETIME NOT NULL DEFAULT '1970-01-01T00:00:00+00:00';
+ALTER TABLE outbox ADD COLUMN updated_at DATETIME NOT NULL DEFAULT '1970-01-01T00:00:00+00:00';
+UPDATE movies SET created_at = updated_at;
+UPDATE users SET created_at = updated_at;
+UPDATE outbox SET updated_at = COALESCE(published_at, created_at);
+
+CREATE TRIGGER movies_updated_at AFTER UPDATE ON movies FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at
+BEGIN
+    UPDATE movies SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER users_updated_at AFTER UPDATE ON users FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at
+BEGIN
+    UPDATE users SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER outbox_updated_at AFTER UPDATE ON outbox FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at
+BEGIN
+    UPDATE outbox SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TABLE IF NOT EXISTS entity_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    entity_type VARCHAR NOT NULL,
+    entity_id BLOB NOT NULL,
+    action VARCHAR NOT NULL,
+    before_state TEXT CHECK (before_state IS NULL OR json_valid(before_state)),
+    after_state TEXT CHECK (after_state IS NULL OR json_valid(after_state)),
+    actor VARCHAR,
+    changed_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS entity_history_entity ON entity_history (entity_type, entity_id, id);
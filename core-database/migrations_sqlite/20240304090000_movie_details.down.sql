@@ -0,0 +1,3 @@
+ALTER TABLE movies DROP COLUMN available;
+ALTER TABLE movies DROP COLUMN cast_members;
+ALTER TABLE movies DROP COLUMN genres;
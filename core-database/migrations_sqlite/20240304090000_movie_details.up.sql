@@ -0,0 +1,3 @@
+ALTER TABLE movies ADD COLUMN genres TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(genres));
+ALTER TABLE movies ADD COLUMN cast_members TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(cast_members));
+ALTER TABLE movies ADD COLUMN available BOOLEAN NOT NULL DEFAULT TRUE;
@@ -1,2 +1,4 @@
+pub mod history;
 pub mod movies;
+pub mod outbox;
 pub mod users;
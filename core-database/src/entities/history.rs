@@ -0,0 +1,369 @@
+use crate::{
+    connection::{Database, Postgres, Transaction},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+use serde_json::Value as Json;
+use std::future::Future;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+tokio::task_local! {
+    static ACTOR: String;
+}
+
+/// Runs `work` with the changes it makes through the repositories recorded as made by `actor`,
+/// changes made outside of it are recorded without one, as the service's own
+pub async fn acting_as<F: Future>(actor: String, work: F) -> F::Output {
+    ACTOR.scope(actor, work).await
+}
+
+/// Who the changes made now are recorded as made by
+pub fn current_actor() -> Option<String> {
+    ACTOR.try_with(Clone::clone).ok()
+}
+
+/// Rows whose changes are recorded in `entity_history`
+pub trait Audited {
+    const ENTITY: &'static str;
+
+    fn key(&self) -> Uuid;
+
+    /// The whole row, as recorded before and after a change
+    fn to_json(&self) -> Json;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryAction {
+    Insert,
+    Update,
+    Delete,
+    /// Removed for good along with what was recorded of it before
+    Erase,
+}
+
+impl HistoryAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryAction::Insert => "insert",
+            HistoryAction::Update => "update",
+            HistoryAction::Delete => "delete",
+            HistoryAction::Erase => "erase",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityChange {
+    pub entity_type: &'static str,
+    pub entity_id: Uuid,
+    pub action: HistoryAction,
+    pub before: Option<Json>,
+    pub after: Option<Json>,
+}
+
+impl EntityChange {
+    pub fn insert<T: Audited>(after: &T) -> Self {
+        Self::new::<T>(after.key(), HistoryAction::Insert, None, Some(after))
+    }
+
+    pub fn update<T: Audited>(before: &T, after: &T) -> Self {
+        Self::new::<T>(
+            after.key(),
+            HistoryAction::Update,
+            Some(before),
+            Some(after),
+        )
+    }
+
+    pub fn delete<T: Audited>(before: &T) -> Self {
+        Self::new::<T>(before.key(), HistoryAction::Delete, Some(before), None)
+    }
+
+    /// Keeps nothing of the entity but its id
+    pub fn erase<T: Audited>(id: Uuid) -> Self {
+        Self::new::<T>(id, HistoryAction::Erase, None, None)
+    }
+
+    fn new<T: Audited>(
+        entity_id: Uuid,
+        action: HistoryAction,
+        before: Option<&T>,
+        after: Option<&T>,
+    ) -> Self {
+        Self {
+            entity_type: T::ENTITY,
+            entity_id,
+            action,
+            before: before.map(Audited::to_json),
+            after: after.map(Audited::to_json),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct HistoryDAO {
+    pub id: i64,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub action: String,
+    /// JSON document, `None` for inserts
+    pub before_state: Option<String>,
+    /// JSON document, `None` for deletes
+    pub after_state: Option<String>,
+    /// Id of the user who made the change, `None` for the service's own jobs
+    pub actor: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HistoryBy {
+    Id(i64),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HistoryWhere {
+    /// Oldest first
+    Entity {
+        entity_type: String,
+        entity_id: Uuid,
+    },
+}
+
+#[derive(Debug)]
+pub struct HistoryRepository;
+
+/// What the other repositories need of the history besides `EntityRepository`
+#[async_trait::async_trait]
+pub trait HistoryRepositoryExt<Db: Database> {
+    /// Records the change as part of the transaction making it, made by the `acting_as` actor
+    async fn record(
+        tx: &mut Transaction<'_, Db>,
+        change: EntityChange,
+    ) -> Result<HistoryDAO, DatabaseError>;
+
+    /// Removes what was recorded of the entity, for erasing personal data for good.
+    /// Returns how many changes were removed.
+    async fn forget(
+        tx: &mut Transaction<'_, Db>,
+        entity_type: &str,
+        entity_id: Uuid,
+    ) -> Result<u64, DatabaseError>;
+}
+
+#[async_trait::async_trait]
+impl EntityRepository<Postgres, HistoryDAO, EntityChange, (), HistoryBy, HistoryWhere>
+    for HistoryRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: EntityChange,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let change = Self::record(&mut tx, input).await?;
+        tx.commit().await?;
+        Ok(change)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: HistoryBy,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "history is only removed along with the entity, see forget".to_string(),
+        ))
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: HistoryBy,
+        _update: (),
+    ) -> Result<HistoryDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "recorded changes never change".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: HistoryBy,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        let missing = not_found("EntityChange", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            HistoryBy::Id(id) => sqlx::query_as::<_, HistoryDAO>(
+                "SELECT id, entity_type, entity_id, action, before_state::text AS before_state, after_state::text AS after_state, actor, changed_at FROM entity_history WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: HistoryBy,
+    ) -> Result<Option<HistoryDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            HistoryBy::Id(id) => sqlx::query_as::<_, HistoryDAO>(
+                "SELECT id, entity_type, entity_id, action, before_state::text AS before_state, after_state::text AS after_state, actor, changed_at FROM entity_history WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: HistoryWhere,
+    ) -> Result<Vec<HistoryDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            HistoryWhere::Entity {
+                entity_type,
+                entity_id,
+            } => sqlx::query_as::<_, HistoryDAO>(
+                "SELECT id, entity_type, entity_id, action, before_state::text AS before_state, after_state::text AS after_state, actor, changed_at FROM entity_history WHERE entity_type = $1 AND entity_id = $2 ORDER BY id;",
+            )
+            .bind(entity_type)
+            .bind(entity_id)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HistoryRepositoryExt<Postgres> for HistoryRepository {
+    async fn record(
+        tx: &mut Transaction<'_, Postgres>,
+        change: EntityChange,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        sqlx::query_as::<_, HistoryDAO>(
+            "INSERT INTO entity_history (entity_type, entity_id, action, before_state, after_state, actor) VALUES ($1, $2, $3, $4::jsonb, $5::jsonb, $6) RETURNING id, entity_type, entity_id, action, before_state::text AS before_state, after_state::text AS after_state, actor, changed_at;",
+        )
+        .bind(change.entity_type)
+        .bind(change.entity_id)
+        .bind(change.action.as_str())
+        .bind(change.before.map(|state| state.to_string()))
+        .bind(change.after.map(|state| state.to_string()))
+        .bind(current_actor())
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    async fn forget(
+        tx: &mut Transaction<'_, Postgres>,
+        entity_type: &str,
+        entity_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        sqlx::query("DELETE FROM entity_history WHERE entity_type = $1 AND entity_id = $2;")
+            .bind(entity_type)
+            .bind(entity_id)
+            .execute(&mut **tx)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::history::{
+        acting_as, HistoryBy, HistoryRepository, HistoryRepositoryExt, HistoryWhere,
+    };
+    use crate::entities::movies::{CreateMovieDAO, MovieBy, MovieRepository, UpdateMovieDAO};
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use crate::types::Uuid;
+
+    #[tokio::test]
+    async fn test_history() {
+        let pool = test_pool().await;
+        let actor = Uuid::new_v4().to_string();
+
+        let movie = acting_as(
+            actor.clone(),
+            MovieRepository::insert(
+                &pool,
+                CreateMovieDAO {
+                    title: format!("History {}", Uuid::new_v4()),
+                    description: "first".to_string(),
+                    genres: vec![],
+                    cast_members: vec![],
+                    available: true,
+                },
+            ),
+        )
+        .await
+        .unwrap();
+        let updated = acting_as(
+            actor.clone(),
+            MovieRepository::update(
+                &pool,
+                MovieBy::Id(movie.id),
+                UpdateMovieDAO {
+                    title: movie.title.clone(),
+                    description: "second".to_string(),
+                    version: movie.version,
+                },
+            ),
+        )
+        .await
+        .unwrap();
+        // a job of the service, no one in particular
+        MovieRepository::delete(&pool, MovieBy::Id(movie.id))
+            .await
+            .unwrap();
+
+        let of_movie = HistoryWhere::Entity {
+            entity_type: "Movie".to_string(),
+            entity_id: movie.id,
+        };
+        let history = HistoryRepository::get_all(&pool, of_movie).await.unwrap();
+        let actions = history
+            .iter()
+            .map(|c| (c.action.as_str(), c.actor.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            vec![
+                ("insert", Some(actor.as_str())),
+                ("update", Some(actor.as_str())),
+                ("delete", None)
+            ]
+        );
+
+        let state = |json: &Option<String>| -> serde_json::Value {
+            serde_json::from_str(json.as_deref().unwrap()).unwrap()
+        };
+        assert!(history[0].before_state.is_none());
+        assert_eq!(state(&history[0].after_state)["description"], "first");
+        assert_eq!(state(&history[1].before_state)["description"], "first");
+        assert_eq!(state(&history[1].after_state)["description"], "second");
+        assert_eq!(state(&history[1].after_state)["version"], updated.version);
+        assert_eq!(state(&history[2].before_state)["description"], "second");
+        assert!(history[2].after_state.is_none());
+
+        // changes are only removed through forget
+        let update = HistoryRepository::update(&pool, HistoryBy::Id(history[0].id), ()).await;
+        assert!(matches!(update, Err(DatabaseError::Unsupported(_))));
+        let delete = HistoryRepository::delete(&pool, HistoryBy::Id(history[0].id)).await;
+        assert!(matches!(delete, Err(DatabaseError::Unsupported(_))));
+
+        let mut tx = pool.begin().await.unwrap();
+        let forgotten = HistoryRepository::forget(&mut tx, "Movie", movie.id)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(forgotten, 3);
+    }
+}
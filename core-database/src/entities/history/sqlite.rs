@@ -0,0 +1,132 @@
+use super::{
+    current_actor, EntityChange, HistoryBy, HistoryDAO, HistoryRepository, HistoryRepositoryExt,
+    HistoryWhere,
+};
+use crate::{
+    connection::{Sqlite, Transaction},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{Utc, Uuid},
+};
+
+#[async_trait::async_trait]
+impl EntityRepository<Sqlite, HistoryDAO, EntityChange, (), HistoryBy, HistoryWhere>
+    for HistoryRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: EntityChange,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let change = Self::record(&mut tx, input).await?;
+        tx.commit().await?;
+        Ok(change)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: HistoryBy,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "history is only removed along with the entity, see forget".to_string(),
+        ))
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: HistoryBy,
+        _update: (),
+    ) -> Result<HistoryDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "recorded changes never change".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: HistoryBy,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        let missing = not_found("EntityChange", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            HistoryBy::Id(id) => sqlx::query_as::<_, HistoryDAO>(
+                "SELECT id, entity_type, entity_id, action, before_state, after_state, actor, changed_at FROM entity_history WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: HistoryBy,
+    ) -> Result<Option<HistoryDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            HistoryBy::Id(id) => sqlx::query_as::<_, HistoryDAO>(
+                "SELECT id, entity_type, entity_id, action, before_state, after_state, actor, changed_at FROM entity_history WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: HistoryWhere,
+    ) -> Result<Vec<HistoryDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            HistoryWhere::Entity {
+                entity_type,
+                entity_id,
+            } => sqlx::query_as::<_, HistoryDAO>(
+                "SELECT id, entity_type, entity_id, action, before_state, after_state, actor, changed_at FROM entity_history WHERE entity_type = $1 AND entity_id = $2 ORDER BY id;",
+            )
+            .bind(entity_type)
+            .bind(entity_id)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HistoryRepositoryExt<Sqlite> for HistoryRepository {
+    async fn record(
+        tx: &mut Transaction<'_, Sqlite>,
+        change: EntityChange,
+    ) -> Result<HistoryDAO, DatabaseError> {
+        sqlx::query_as::<_, HistoryDAO>(
+            "INSERT INTO entity_history (entity_type, entity_id, action, before_state, after_state, actor, changed_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id, entity_type, entity_id, action, before_state, after_state, actor, changed_at;",
+        )
+        .bind(change.entity_type)
+        .bind(change.entity_id)
+        .bind(change.action.as_str())
+        .bind(change.before.map(|state| state.to_string()))
+        .bind(change.after.map(|state| state.to_string()))
+        .bind(current_actor())
+        .bind(Utc::now())
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    async fn forget(
+        tx: &mut Transaction<'_, Sqlite>,
+        entity_type: &str,
+        entity_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        sqlx::query("DELETE FROM entity_history WHERE entity_type = $1 AND entity_id = $2;")
+            .bind(entity_type)
+            .bind(entity_id)
+            .execute(&mut **tx)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(DatabaseError::from)
+    }
+}
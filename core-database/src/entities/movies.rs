@@ -1,26 +1,52 @@
 use crate::{
-    connection::{Pool, Postgres},
-    traits::{DatabaseError, EntityRepository},
-    types::Uuid,
+    connection::{Database, Pool, Postgres, Transaction},
+    entities::history::{Audited, EntityChange, HistoryRepository, HistoryRepositoryExt},
+    entities::outbox::{DomainEvent, OutboxRepository, OutboxRepositoryExt},
+    query::{Field, Query, Value, ValueKind},
+    traits::{not_found, version_conflict, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
 };
+use serde_json::json;
+use sqlx::{types::Json, QueryBuilder};
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct MovieDAO {
     pub id: Uuid,
     pub title: String,
     pub description: String,
+    #[sqlx(json)]
+    pub genres: Vec<String>,
+    #[sqlx(json)]
+    pub cast_members: Vec<String>,
+    /// Whether it can be watched now, unavailable movies stay in the catalog
+    pub available: bool,
+    /// Bumped by every update
+    pub version: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct CreateMovieDAO {
     pub title: String,
     pub description: String,
+    #[sqlx(json)]
+    pub genres: Vec<String>,
+    #[sqlx(json)]
+    pub cast_members: Vec<String>,
+    pub available: bool,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct UpdateMovieDAO {
     pub title: String,
     pub description: String,
+    /// Version the change was based on, the update fails when the movie moved past it
+    pub version: i64,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -28,113 +54,420 @@ pub enum MovieBy {
     Id(Uuid),
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum MoviesWhere {
-    Page { offset: u32, limit: u32 },
+/// What `upsert_many` did with one of the movies
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Upserted {
+    Inserted(MovieDAO),
+    /// Its title was taken, the movie holding it was updated
+    Updated(MovieDAO),
+    /// Its title was taken by a movie with the same details
+    Unchanged(MovieDAO),
+}
+
+/// Rows written by one statement of `upsert_many`, well below the bind parameter limits
+const UPSERT_CHUNK_SIZE: usize = 1000;
+
+/// Movies read at once by `next_exported`
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+/// An export in progress, see `MovieRepositoryExt::export`. Dropping it ends the export.
+pub struct MovieExport<Db: Database> {
+    /// Keeps the snapshot the export reads from
+    tx: Transaction<'static, Db>,
+    updated_since: Option<DateTime<Utc>>,
+    /// Last movie read, SQLite reads the next batch after it
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    after: Option<(DateTime<Utc>, Uuid)>,
+}
+
+/// Same title twice in an `upsert_many` batch, reported as the title constraint would
+fn repeated_title(movies: &[CreateMovieDAO]) -> Result<(), DatabaseError> {
+    let mut titles = HashSet::new();
+    if movies.iter().all(|m| titles.insert(m.title.as_str())) {
+        return Ok(());
+    }
+    Err(DatabaseError::UniqueViolation {
+        constraint: "movies_title_key".to_string(),
+    })
+}
+
+/// Outcome of each movie of an upserted chunk from the rows it found and wrote by title
+fn outcomes_of(
+    chunk: &[CreateMovieDAO],
+    found: Vec<MovieDAO>,
+    written: Vec<MovieDAO>,
+) -> Vec<(Option<MovieDAO>, Upserted)> {
+    let mut found = found
+        .into_iter()
+        .map(|m| (m.title.clone(), m))
+        .collect::<HashMap<_, _>>();
+    let mut written = written
+        .into_iter()
+        .map(|m| (m.title.clone(), m))
+        .collect::<HashMap<_, _>>();
+    chunk
+        .iter()
+        .filter_map(|movie| {
+            let before = found.remove(&movie.title);
+            match (before, written.remove(&movie.title)) {
+                (None, Some(after)) => Some((None, Upserted::Inserted(after))),
+                (Some(before), Some(after)) => Some((Some(before), Upserted::Updated(after))),
+                (Some(before), None) => Some((None, Upserted::Unchanged(before))),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
+
+/// Records the changes of an upserted chunk the way `insert` and `update` do
+async fn record_upserted<Db: Database>(
+    tx: &mut Transaction<'_, Db>,
+    outcomes: &[(Option<MovieDAO>, Upserted)],
+) -> Result<(), DatabaseError>
+where
+    OutboxRepository: OutboxRepositoryExt<Db>,
+    HistoryRepository: HistoryRepositoryExt<Db>,
+{
+    for outcome in outcomes {
+        match outcome {
+            (_, Upserted::Inserted(movie)) => {
+                OutboxRepository::append(tx, DomainEvent::MovieCreated(movie.clone())).await?;
+                HistoryRepository::record(tx, EntityChange::insert(movie)).await?;
+            }
+            (Some(before), Upserted::Updated(movie)) => {
+                OutboxRepository::append(tx, DomainEvent::MovieUpdated(movie.clone())).await?;
+                HistoryRepository::record(tx, EntityChange::update(before, movie)).await?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieField {
+    Id,
+    Title,
+    Description,
+    Available,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl Field for MovieField {
+    const KEY: Self = MovieField::Id;
+
+    fn column(self) -> &'static str {
+        match self {
+            MovieField::Id => "id",
+            MovieField::Title => "title",
+            MovieField::Description => "description",
+            MovieField::Available => "available",
+            MovieField::CreatedAt => "created_at",
+            MovieField::UpdatedAt => "updated_at",
+        }
+    }
+
+    fn kind(self) -> ValueKind {
+        match self {
+            MovieField::Id => ValueKind::Uuid,
+            MovieField::Title | MovieField::Description => ValueKind::Text,
+            MovieField::Available => ValueKind::Bool,
+            MovieField::CreatedAt | MovieField::UpdatedAt => ValueKind::Timestamp,
+        }
+    }
+}
+
+impl MovieDAO {
+    /// What `field` holds, for running a `Query` in memory
+    pub fn field(&self, field: MovieField) -> Option<Value> {
+        Some(match field {
+            MovieField::Id => self.id.into(),
+            MovieField::Title => self.title.clone().into(),
+            MovieField::Description => self.description.clone().into(),
+            MovieField::Available => self.available.into(),
+            MovieField::CreatedAt => self.created_at.into(),
+            MovieField::UpdatedAt => self.updated_at.into(),
+        })
+    }
+}
+
+impl Audited for MovieDAO {
+    const ENTITY: &'static str = "Movie";
+
+    fn key(&self) -> Uuid {
+        self.id
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id": self.id.to_string(),
+            "title": self.title,
+            "description": self.description,
+            "genres": self.genres,
+            "cast_members": self.cast_members,
+            "available": self.available,
+            "version": self.version,
+            "created_at": self.created_at.to_rfc3339(),
+            "updated_at": self.updated_at.to_rfc3339(),
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct MovieRepository;
 
+/// What catalog imports need of movies besides `EntityRepository`
+#[async_trait::async_trait]
+pub trait MovieRepositoryExt<Db: Database> {
+    /// Inserts the movies and updates the ones whose title is taken instead, all or none.
+    /// Titles must be unique within `movies`, outcomes come in the same order.
+    async fn upsert_many<'c, E: Executor<'c, Db>>(
+        db: E,
+        movies: Vec<CreateMovieDAO>,
+    ) -> Result<Vec<Upserted>, DatabaseError>;
+
+    /// Starts reading the movies updated after `updated_since`, or all of them, least recently
+    /// updated first. They're read a batch at a time and from a single snapshot of the catalog.
+    async fn export(
+        pool: &Pool<Db>,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<MovieExport<Db>, DatabaseError>;
+
+    /// The next batch of the export, empty once every movie was read
+    async fn next_exported(export: &mut MovieExport<Db>) -> Result<Vec<MovieDAO>, DatabaseError>;
+}
+
 #[async_trait::async_trait]
-impl EntityRepository<Postgres, MovieDAO, CreateMovieDAO, UpdateMovieDAO, MovieBy, MoviesWhere>
+impl
+    EntityRepository<Postgres, MovieDAO, CreateMovieDAO, UpdateMovieDAO, MovieBy, Query<MovieField>>
     for MovieRepository
 {
-    async fn insert(db: &Pool<Postgres>, input: CreateMovieDAO) -> Result<MovieDAO, DatabaseError> {
-        sqlx::query_as::<_, MovieDAO>("INSERT INTO movies (title, description) VALUES ($1, $2) RETURNING id, title, description;")
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateMovieDAO,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let movie = sqlx::query_as::<_, MovieDAO>("INSERT INTO movies (title, description, genres, cast_members, available) VALUES ($1, $2, $3, $4, $5) RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;")
             .bind(input.title)
             .bind(input.description)
-            .fetch_one(db)
-            .await
-            .map_err(DatabaseError::from)
+            .bind(Json(input.genres))
+            .bind(Json(input.cast_members))
+            .bind(input.available)
+            .fetch_one(&mut *tx)
+            .await?;
+        OutboxRepository::append(&mut tx, DomainEvent::MovieCreated(movie.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::insert(&movie)).await?;
+        tx.commit().await?;
+        Ok(movie)
     }
 
-    async fn delete(db: &Pool<Postgres>, key: MovieBy) -> Result<MovieDAO, DatabaseError> {
-        match key {
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: MovieBy,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let missing = not_found("Movie", &key);
+        let mut tx = db.begin().await?;
+        let movie = match key {
             MovieBy::Id(uuid) => sqlx::query_as::<_, MovieDAO>(
-                "DELETE FROM movies WHERE id = $1 RETURNING id, title, description;",
+                "DELETE FROM movies WHERE id = $1 RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;",
             )
             .bind(uuid)
-            .fetch_one(db)
+            .fetch_one(&mut *tx)
             .await
-            .map_err(DatabaseError::from),
-        }
+            .map_err(missing)?,
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::MovieDeleted(movie.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::delete(&movie)).await?;
+        tx.commit().await?;
+        Ok(movie)
     }
 
-    async fn update(
-        db: &Pool<Postgres>,
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        db: E,
         key: MovieBy,
         update: UpdateMovieDAO,
     ) -> Result<MovieDAO, DatabaseError> {
-        match key {
-            MovieBy::Id(uuid) => sqlx::query_as::<_, MovieDAO>(
-                "UPDATE movies SET title = $1, description = $2 WHERE id = $3 RETURNING id, title, description;",
-            )
+        let conflict = version_conflict("Movie", &key);
+        let mut tx = db.begin().await?;
+        let (before, movie) = match key {
+            MovieBy::Id(uuid) => {
+                let before = sqlx::query_as::<_, MovieDAO>(
+                    "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE id = $1 FOR UPDATE;",
+                )
+                .bind(uuid)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let before = match before {
+                    Some(before) if before.version == update.version => before,
+                    before => return Err(conflict(before.map(|m| m.version))),
+                };
+                let movie = sqlx::query_as::<_, MovieDAO>(
+                    "UPDATE movies SET title = $1, description = $2, version = version + 1 WHERE id = $3 RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;",
+                )
                 .bind(update.title)
                 .bind(update.description)
                 .bind(uuid)
-                .fetch_one(db)
-                .await
-                .map_err(DatabaseError::from),
-        }
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, movie)
+            }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::MovieUpdated(movie.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &movie)).await?;
+        tx.commit().await?;
+        Ok(movie)
     }
 
-    async fn get(db: &Pool<Postgres>, key: MovieBy) -> Result<MovieDAO, DatabaseError> {
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: MovieBy,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let missing = not_found("Movie", &key);
+        let mut conn = db.acquire().await?;
         match key {
             MovieBy::Id(uuid) => sqlx::query_as::<_, MovieDAO>(
-                "SELECT id, title, description FROM movies WHERE id = $1 LIMIT 1;",
+                "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE id = $1 LIMIT 1;",
             )
             .bind(uuid)
-            .fetch_one(db)
+            .fetch_one(&mut *conn)
             .await
-            .map_err(DatabaseError::from),
+            .map_err(missing),
         }
     }
 
-    async fn try_get(db: &Pool<Postgres>, key: MovieBy) -> Result<Option<MovieDAO>, DatabaseError> {
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: MovieBy,
+    ) -> Result<Option<MovieDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
         match key {
-            MovieBy::Id(uuid) => {
-                sqlx::query_as("SELECT id, title, description FROM movies WHERE id = $1;")
-                    .bind(uuid)
-                    .fetch_optional(db)
-                    .await
-                    .map_err(DatabaseError::from)
-            }
+            MovieBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE id = $1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
         }
     }
 
-    async fn get_all(
-        db: &Pool<Postgres>,
-        key: MoviesWhere,
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        query: Query<MovieField>,
     ) -> Result<Vec<MovieDAO>, DatabaseError> {
-        match key {
-            MoviesWhere::Page { offset, limit } => sqlx::query_as::<_, MovieDAO>(
-                "SELECT id, title, description FROM movies OFFSET $1 LIMIT $2;",
-            )
-            .bind(offset as i32)
-            .bind(limit as i32)
-            .fetch_all(db)
+        let mut select = QueryBuilder::new(
+            "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies",
+        );
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<MovieDAO>()
+            .fetch_all(&mut *conn)
             .await
-            .map_err(DatabaseError::from),
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl MovieRepositoryExt<Postgres> for MovieRepository {
+    async fn upsert_many<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        movies: Vec<CreateMovieDAO>,
+    ) -> Result<Vec<Upserted>, DatabaseError> {
+        repeated_title(&movies)?;
+        let mut tx = db.begin().await?;
+        let mut upserted = Vec::with_capacity(movies.len());
+        for chunk in movies.chunks(UPSERT_CHUNK_SIZE) {
+            let titles = chunk.iter().map(|m| m.title.clone()).collect::<Vec<_>>();
+            let found = sqlx::query_as::<_, MovieDAO>(
+                "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE title = ANY($1) FOR UPDATE;",
+            )
+            .bind(titles)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let mut upsert = QueryBuilder::<Postgres>::new(
+                "INSERT INTO movies (title, description, genres, cast_members, available) ",
+            );
+            upsert.push_values(chunk, |mut row, movie| {
+                row.push_bind(movie.title.clone())
+                    .push_bind(movie.description.clone())
+                    .push_bind(Json(movie.genres.clone()))
+                    .push_bind(Json(movie.cast_members.clone()))
+                    .push_bind(movie.available);
+            });
+            // movies with the same details are left alone, their version doesn't move
+            upsert.push(
+                " ON CONFLICT (title) DO UPDATE SET description = EXCLUDED.description, genres = EXCLUDED.genres, cast_members = EXCLUDED.cast_members, available = EXCLUDED.available, version = movies.version + 1 \
+                WHERE (movies.description, movies.genres, movies.cast_members, movies.available) IS DISTINCT FROM (EXCLUDED.description, EXCLUDED.genres, EXCLUDED.cast_members, EXCLUDED.available) \
+                RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;",
+            );
+            let written = upsert
+                .build_query_as::<MovieDAO>()
+                .fetch_all(&mut *tx)
+                .await?;
+
+            let outcomes = outcomes_of(chunk, found, written);
+            record_upserted(&mut tx, &outcomes).await?;
+            upserted.extend(outcomes.into_iter().map(|(_, outcome)| outcome));
         }
+        tx.commit().await?;
+        Ok(upserted)
+    }
+
+    async fn export(
+        pool: &Pool<Postgres>,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<MovieExport<Postgres>, DatabaseError> {
+        let mut export = MovieExport {
+            tx: pool.begin().await?,
+            updated_since,
+            after: None,
+        };
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY;")
+            .execute(&mut *export.tx)
+            .await?;
+        // a server-side cursor, rows are only sent as they're fetched
+        sqlx::query(
+            "DECLARE movie_export NO SCROLL CURSOR FOR SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE $1::timestamptz IS NULL OR updated_at > $1 ORDER BY updated_at, id;",
+        )
+        .bind(export.updated_since)
+        .execute(&mut *export.tx)
+        .await?;
+        Ok(export)
+    }
+
+    async fn next_exported(
+        export: &mut MovieExport<Postgres>,
+    ) -> Result<Vec<MovieDAO>, DatabaseError> {
+        // not prepared, it names a cursor that's gone once the export ends
+        sqlx::query_as::<_, MovieDAO>(&format!(
+            "FETCH FORWARD {} FROM movie_export;",
+            EXPORT_BATCH_SIZE
+        ))
+        .persistent(false)
+        .fetch_all(&mut *export.tx)
+        .await
+        .map_err(DatabaseError::from)
     }
 }
 
 #[cfg(feature = "integration")]
 #[cfg(test)]
 mod tests {
-    use crate::connection::PgPool;
     use crate::entities::movies::{
-        CreateMovieDAO, MovieBy, MovieRepository, MoviesWhere, UpdateMovieDAO,
+        CreateMovieDAO, MovieBy, MovieField, MovieRepository, MovieRepositoryExt, UpdateMovieDAO,
+        Upserted,
     };
-    use crate::traits::EntityRepository;
-    use dotenv;
+    use crate::query::{Direction, Filter, Query};
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use crate::types::{Utc, Uuid};
+
     #[tokio::test]
     async fn test_db() {
-        dotenv::dotenv().ok();
-        let url =
-            std::env::var("TEST_CORE_DATABASE_URL").expect("TEST_CORE_DATABASE_URL must be set");
-        let pool = PgPool::connect(&url).await.unwrap();
+        let pool = test_pool().await;
 
         // create movie
         let response = MovieRepository::insert(
@@ -142,6 +475,9 @@ mod tests {
             CreateMovieDAO {
                 title: "Avengers infinity war".to_string(),
                 description: "crazy movie".to_string(),
+                genres: vec![],
+                cast_members: vec![],
+                available: true,
             },
         )
         .await
@@ -153,6 +489,9 @@ mod tests {
             CreateMovieDAO {
                 title: "Doctor strange".to_string(),
                 description: "crazy movie".to_string(),
+                genres: vec![],
+                cast_members: vec![],
+                available: true,
             },
         )
         .await
@@ -163,35 +502,43 @@ mod tests {
             CreateMovieDAO {
                 title: "Spider man".to_string(),
                 description: "crazy movie".to_string(),
+                genres: vec![],
+                cast_members: vec![],
+                available: true,
             },
         )
         .await
         .expect("Could not create movie");
 
-        let movies = MovieRepository::get_all(
-            &pool,
-            MoviesWhere::Page {
-                offset: 0,
-                limit: 2,
-            },
-        )
-        .await
-        .unwrap();
+        let crazy = Query::new()
+            .filter(Filter::eq(MovieField::Description, "crazy movie"))
+            .order_by(MovieField::Title, Direction::Asc);
+        let movies = MovieRepository::get_all(&pool, crazy.clone().page(0, 2))
+            .await
+            .unwrap();
         assert_eq!(movies.len(), 2);
         assert_eq!(movies[0].title, "Avengers infinity war");
         assert_eq!(movies[1].title, "Doctor strange");
 
+        let movies = MovieRepository::get_all(&pool, crazy.clone().page(2, 2))
+            .await
+            .unwrap();
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].title, "Spider man");
+
         let movies = MovieRepository::get_all(
             &pool,
-            MoviesWhere::Page {
-                offset: 2,
-                limit: 2,
-            },
+            crazy.filter(
+                Filter::contains(MovieField::Title, "STRANGE")
+                    .or(Filter::contains(MovieField::Title, "man")),
+            ),
         )
         .await
         .unwrap();
-        assert_eq!(movies.len(), 1);
-        assert_eq!(movies[0].title, "Spider man");
+        assert_eq!(
+            movies.iter().map(|m| m.title.as_str()).collect::<Vec<_>>(),
+            vec!["Doctor strange", "Spider man"]
+        );
 
         // get movie
         let found = MovieRepository::get(&pool, MovieBy::Id(response.id))
@@ -219,6 +566,7 @@ mod tests {
             UpdateMovieDAO {
                 title: "Avengers endgame".to_string(),
                 description: "best movie".to_string(),
+                version: response.version,
             },
         )
         .await
@@ -227,11 +575,184 @@ mod tests {
         assert_eq!(response.id, updated.id);
         assert_eq!(updated.title, "Avengers endgame");
         assert_eq!(updated.description, "best movie");
+        assert_eq!(updated.version, response.version + 1);
+
+        // a second editor still holding the first version loses, and learns the current one
+        let stale = MovieRepository::update(
+            &pool,
+            MovieBy::Id(response.id),
+            UpdateMovieDAO {
+                title: "Avengers age of ultron".to_string(),
+                description: "crazy movie".to_string(),
+                version: response.version,
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            stale,
+            DatabaseError::VersionConflict {
+                entity: "Movie".to_string(),
+                key: format!("Id({})", response.id),
+                current_version: updated.version,
+            }
+        );
+        let found = MovieRepository::get(&pool, MovieBy::Id(response.id))
+            .await
+            .unwrap();
+        assert_eq!(found, updated);
 
         // delete
         let deleted = MovieRepository::delete(&pool, MovieBy::Id(response.id))
             .await
             .expect("Could not delete an movie");
         assert_eq!(response.id, deleted.id);
+
+        // missing movies are reported as such
+        let missing = MovieRepository::get(&pool, MovieBy::Id(response.id))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            missing,
+            DatabaseError::NotFound {
+                entity: "Movie".to_string(),
+                key: format!("Id({})", response.id),
+            }
+        );
+        assert!(matches!(
+            MovieRepository::update(
+                &pool,
+                MovieBy::Id(response.id),
+                UpdateMovieDAO {
+                    title: "gone".to_string(),
+                    description: "gone".to_string(),
+                    version: updated.version,
+                },
+            )
+            .await,
+            Err(DatabaseError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_upsert_many() {
+        let pool = test_pool().await;
+        let batch = Uuid::new_v4();
+        let movie = |title: &str, description: &str, genres: &[&str]| CreateMovieDAO {
+            title: format!("{} {}", title, batch),
+            description: description.to_string(),
+            genres: genres.iter().map(|g| g.to_string()).collect(),
+            cast_members: vec!["Someone".to_string()],
+            available: true,
+        };
+
+        let first = MovieRepository::upsert_many(
+            &pool,
+            vec![movie("Ran", "war", &["Drama"]), movie("Heat", "heist", &[])],
+        )
+        .await
+        .unwrap();
+        let (ran, heat) = match &first[..] {
+            [Upserted::Inserted(ran), Upserted::Inserted(heat)] => (ran.clone(), heat.clone()),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(ran.genres, vec!["Drama"]);
+        assert_eq!(ran.cast_members, vec!["Someone"]);
+        assert!(ran.available);
+
+        // taken titles update their movie, unless nothing would change
+        let second = MovieRepository::upsert_many(
+            &pool,
+            vec![
+                movie("Alien", "space", &["Horror"]),
+                movie("Ran", "war", &["Drama"]),
+                movie("Heat", "heist", &["Crime", "Drama"]),
+            ],
+        )
+        .await
+        .unwrap();
+        assert!(matches!(&second[0], Upserted::Inserted(m) if m.title.starts_with("Alien")));
+        assert_eq!(second[1], Upserted::Unchanged(ran.clone()));
+        match &second[2] {
+            Upserted::Updated(updated) => {
+                assert_eq!(updated.id, heat.id);
+                assert_eq!(updated.genres, vec!["Crime", "Drama"]);
+                assert_eq!(updated.version, heat.version + 1);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // a title twice in a batch is rejected as a whole
+        let repeated = MovieRepository::upsert_many(
+            &pool,
+            vec![
+                movie("Solaris", "space", &[]),
+                movie("Solaris", "ocean", &[]),
+            ],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            repeated,
+            DatabaseError::UniqueViolation {
+                constraint: "movies_title_key".to_string(),
+            }
+        );
+        let solaris = MovieRepository::get_all(
+            &pool,
+            Query::new().filter(Filter::eq(MovieField::Title, format!("Solaris {}", batch))),
+        )
+        .await
+        .unwrap();
+        assert!(solaris.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let pool = test_pool().await;
+        let batch = Uuid::new_v4();
+        let before = Utc::now();
+        let movies = (0..3)
+            .map(|i| CreateMovieDAO {
+                title: format!("Export {} {}", i, batch),
+                description: "exported".to_string(),
+                genres: vec![],
+                cast_members: vec![],
+                available: i != 1,
+            })
+            .collect::<Vec<_>>();
+        MovieRepository::upsert_many(&pool, movies).await.unwrap();
+
+        let mut export = MovieRepository::export(&pool, Some(before)).await.unwrap();
+        let mut exported = vec![];
+        loop {
+            let movies = MovieRepository::next_exported(&mut export).await.unwrap();
+            if movies.is_empty() {
+                break;
+            }
+            exported.extend(movies);
+        }
+        drop(export);
+
+        assert!(exported.iter().all(|m| m.updated_at > before));
+        assert!(exported
+            .windows(2)
+            .all(|w| (w[0].updated_at, w[0].id) <= (w[1].updated_at, w[1].id)));
+        let ours = exported
+            .iter()
+            .filter(|m| m.title.ends_with(&batch.to_string()))
+            .map(|m| m.available)
+            .collect::<Vec<_>>();
+        assert_eq!(ours.len(), 3);
+        assert_eq!(ours.iter().filter(|available| !**available).count(), 1);
+
+        // a later export starts over
+        let mut export = MovieRepository::export(&pool, Some(Utc::now()))
+            .await
+            .unwrap();
+        assert!(MovieRepository::next_exported(&mut export)
+            .await
+            .unwrap()
+            .is_empty());
     }
 }
@@ -0,0 +1,232 @@
+use super::{
+    outcomes_of, record_upserted, repeated_title, CreateMovieDAO, MovieBy, MovieDAO, MovieExport,
+    MovieField, MovieRepository, MovieRepositoryExt, UpdateMovieDAO, Upserted, EXPORT_BATCH_SIZE,
+    UPSERT_CHUNK_SIZE,
+};
+use crate::{
+    connection::{Pool, Sqlite},
+    entities::history::{EntityChange, HistoryRepository, HistoryRepositoryExt},
+    entities::outbox::{DomainEvent, OutboxRepository, OutboxRepositoryExt},
+    query::Query,
+    traits::{not_found, version_conflict, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+use serde_json::json;
+use sqlx::{types::Json, QueryBuilder};
+
+#[async_trait::async_trait]
+impl EntityRepository<Sqlite, MovieDAO, CreateMovieDAO, UpdateMovieDAO, MovieBy, Query<MovieField>>
+    for MovieRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateMovieDAO,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let movie = sqlx::query_as::<_, MovieDAO>("INSERT INTO movies (id, title, description, genres, cast_members, available, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $7) RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;")
+            .bind(Uuid::new_v4())
+            .bind(input.title)
+            .bind(input.description)
+            .bind(Json(input.genres))
+            .bind(Json(input.cast_members))
+            .bind(input.available)
+            .bind(Utc::now())
+            .fetch_one(&mut *tx)
+            .await?;
+        OutboxRepository::append(&mut tx, DomainEvent::MovieCreated(movie.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::insert(&movie)).await?;
+        tx.commit().await?;
+        Ok(movie)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: MovieBy,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let missing = not_found("Movie", &key);
+        let mut tx = db.begin().await?;
+        let movie = match key {
+            MovieBy::Id(uuid) => sqlx::query_as::<_, MovieDAO>(
+                "DELETE FROM movies WHERE id = $1 RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(missing)?,
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::MovieDeleted(movie.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::delete(&movie)).await?;
+        tx.commit().await?;
+        Ok(movie)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: MovieBy,
+        update: UpdateMovieDAO,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let conflict = version_conflict("Movie", &key);
+        let mut tx = db.begin().await?;
+        let (before, movie) = match key {
+            MovieBy::Id(uuid) => {
+                // SQLite fails this transaction's write when another one wrote the movie since it read it
+                let before = sqlx::query_as::<_, MovieDAO>(
+                    "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE id = $1;",
+                )
+                .bind(uuid)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let before = match before {
+                    Some(before) if before.version == update.version => before,
+                    before => return Err(conflict(before.map(|m| m.version))),
+                };
+                let movie = sqlx::query_as::<_, MovieDAO>(
+                    "UPDATE movies SET title = $1, description = $2, version = version + 1, updated_at = $4 WHERE id = $3 RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;",
+                )
+                .bind(update.title)
+                .bind(update.description)
+                .bind(uuid)
+                .bind(Utc::now())
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, movie)
+            }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::MovieUpdated(movie.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &movie)).await?;
+        tx.commit().await?;
+        Ok(movie)
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: MovieBy,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let missing = not_found("Movie", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            MovieBy::Id(uuid) => sqlx::query_as::<_, MovieDAO>(
+                "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: MovieBy,
+    ) -> Result<Option<MovieDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            MovieBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE id = $1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        query: Query<MovieField>,
+    ) -> Result<Vec<MovieDAO>, DatabaseError> {
+        let mut select = QueryBuilder::new(
+            "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies",
+        );
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<MovieDAO>()
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl MovieRepositoryExt<Sqlite> for MovieRepository {
+    async fn upsert_many<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        movies: Vec<CreateMovieDAO>,
+    ) -> Result<Vec<Upserted>, DatabaseError> {
+        repeated_title(&movies)?;
+        let mut tx = db.begin().await?;
+        let mut upserted = Vec::with_capacity(movies.len());
+        for chunk in movies.chunks(UPSERT_CHUNK_SIZE) {
+            let titles = chunk.iter().map(|m| m.title.as_str()).collect::<Vec<_>>();
+            let found = sqlx::query_as::<_, MovieDAO>(
+                "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE title IN (SELECT value FROM json_each($1));",
+            )
+            .bind(json!(titles).to_string())
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let now = Utc::now();
+            let mut upsert = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO movies (id, title, description, genres, cast_members, available, created_at, updated_at) ",
+            );
+            upsert.push_values(chunk, |mut row, movie| {
+                row.push_bind(Uuid::new_v4())
+                    .push_bind(movie.title.clone())
+                    .push_bind(movie.description.clone())
+                    .push_bind(Json(movie.genres.clone()))
+                    .push_bind(Json(movie.cast_members.clone()))
+                    .push_bind(movie.available)
+                    .push_bind(now)
+                    .push_bind(now);
+            });
+            // movies with the same details are left alone, their version doesn't move
+            upsert.push(
+                " ON CONFLICT (title) DO UPDATE SET description = excluded.description, genres = excluded.genres, cast_members = excluded.cast_members, available = excluded.available, version = movies.version + 1, updated_at = excluded.updated_at \
+                WHERE (movies.description, movies.genres, movies.cast_members, movies.available) IS NOT (excluded.description, excluded.genres, excluded.cast_members, excluded.available) \
+                RETURNING id, title, description, genres, cast_members, available, version, created_at, updated_at;",
+            );
+            let written = upsert
+                .build_query_as::<MovieDAO>()
+                .fetch_all(&mut *tx)
+                .await?;
+
+            let outcomes = outcomes_of(chunk, found, written);
+            record_upserted(&mut tx, &outcomes).await?;
+            upserted.extend(outcomes.into_iter().map(|(_, outcome)| outcome));
+        }
+        tx.commit().await?;
+        Ok(upserted)
+    }
+    async fn export(
+        pool: &Pool<Sqlite>,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<MovieExport<Sqlite>, DatabaseError> {
+        // no cursors outliving a statement, batches are read one after the other
+        // within a transaction, which sees the catalog as it was at its first read
+        Ok(MovieExport {
+            tx: pool.begin().await?,
+            updated_since,
+            after: None,
+        })
+    }
+
+    async fn next_exported(
+        export: &mut MovieExport<Sqlite>,
+    ) -> Result<Vec<MovieDAO>, DatabaseError> {
+        let (after_updated_at, after_id) = export.after.unzip();
+        let movies = sqlx::query_as::<_, MovieDAO>(
+            "SELECT id, title, description, genres, cast_members, available, version, created_at, updated_at FROM movies WHERE ($1 IS NULL OR updated_at > $1) AND ($2 IS NULL OR updated_at > $2 OR (updated_at = $2 AND id > $3)) ORDER BY updated_at, id LIMIT $4;",
+        )
+        .bind(export.updated_since)
+        .bind(after_updated_at)
+        .bind(after_id)
+        .bind(EXPORT_BATCH_SIZE)
+        .fetch_all(&mut *export.tx)
+        .await?;
+        if let Some(last) = movies.last() {
+            export.after = Some((last.updated_at, last.id));
+        }
+        Ok(movies)
+    }
+}
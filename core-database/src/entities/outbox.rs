@@ -0,0 +1,367 @@
+use crate::{
+    connection::{Database, Postgres, Transaction},
+    entities::{history::Audited, movies::MovieDAO, users::UserDAO},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+use serde_json::json;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Lock id of `OutboxRepository::claim`, only one relay publishes at a time so events keep their order
+const RELAY_LOCK_ID: i64 = 0x6f7574626f78;
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct OutboxDAO {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub aggregate_type: String,
+    pub aggregate_id: Uuid,
+    pub event_type: String,
+    /// JSON document
+    pub payload: String,
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+/// Change to the catalog or the users that other services may react to
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DomainEvent {
+    MovieCreated(MovieDAO),
+    MovieUpdated(MovieDAO),
+    MovieDeleted(MovieDAO),
+    UserCreated(UserDAO),
+    UserUpdated(UserDAO),
+    UserDeactivated(UserDAO),
+    /// A deactivation undone, e.g. the account closure it was part of failed
+    UserReactivated(UserDAO),
+    UserErased(Uuid),
+}
+
+impl DomainEvent {
+    pub fn aggregate_type(&self) -> &'static str {
+        match self {
+            DomainEvent::MovieCreated(_)
+            | DomainEvent::MovieUpdated(_)
+            | DomainEvent::MovieDeleted(_) => "movie",
+            DomainEvent::UserCreated(_)
+            | DomainEvent::UserUpdated(_)
+            | DomainEvent::UserDeactivated(_)
+            | DomainEvent::UserReactivated(_)
+            | DomainEvent::UserErased(_) => "user",
+        }
+    }
+
+    pub fn aggregate_id(&self) -> Uuid {
+        match self {
+            DomainEvent::MovieCreated(movie)
+            | DomainEvent::MovieUpdated(movie)
+            | DomainEvent::MovieDeleted(movie) => movie.id,
+            DomainEvent::UserCreated(user)
+            | DomainEvent::UserUpdated(user)
+            | DomainEvent::UserDeactivated(user)
+            | DomainEvent::UserReactivated(user) => user.id,
+            DomainEvent::UserErased(id) => *id,
+        }
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            DomainEvent::MovieCreated(_) => "MovieCreated",
+            DomainEvent::MovieUpdated(_) => "MovieUpdated",
+            DomainEvent::MovieDeleted(_) => "MovieDeleted",
+            DomainEvent::UserCreated(_) => "UserCreated",
+            DomainEvent::UserUpdated(_) => "UserUpdated",
+            DomainEvent::UserDeactivated(_) => "UserDeactivated",
+            DomainEvent::UserReactivated(_) => "UserReactivated",
+            DomainEvent::UserErased(_) => "UserErased",
+        }
+    }
+
+    pub fn payload(&self) -> String {
+        match self {
+            DomainEvent::MovieCreated(movie)
+            | DomainEvent::MovieUpdated(movie)
+            | DomainEvent::MovieDeleted(movie) => movie.to_json(),
+            DomainEvent::UserCreated(user)
+            | DomainEvent::UserUpdated(user)
+            | DomainEvent::UserDeactivated(user)
+            | DomainEvent::UserReactivated(user) => user.to_json(),
+            DomainEvent::UserErased(id) => json!({ "id": id.to_string() }),
+        }
+        .to_string()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OutboxBy {
+    Id(i64),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OutboxWhere {
+    /// Oldest first
+    Unpublished { limit: u32 },
+}
+
+#[derive(Debug)]
+pub struct OutboxRepository;
+
+/// What the relay and the other repositories need of the outbox besides `EntityRepository`
+#[async_trait::async_trait]
+pub trait OutboxRepositoryExt<Db: Database> {
+    /// Records the event as part of the transaction making the change
+    async fn append(
+        tx: &mut Transaction<'_, Db>,
+        event: DomainEvent,
+    ) -> Result<OutboxDAO, DatabaseError>;
+
+    /// Oldest unpublished events, for the relay holding the transaction.
+    /// `None` when another relay is publishing, it keeps the lock until its transaction ends.
+    async fn claim(
+        tx: &mut Transaction<'_, Db>,
+        limit: u32,
+    ) -> Result<Option<Vec<OutboxDAO>>, DatabaseError>;
+
+    async fn mark_published(
+        tx: &mut Transaction<'_, Db>,
+        ids: &[i64],
+    ) -> Result<u64, DatabaseError>;
+
+    /// Permanently removes events published before `before`, returns how many were removed
+    async fn purge_published<'c, E: Executor<'c, Db>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+}
+
+#[async_trait::async_trait]
+impl EntityRepository<Postgres, OutboxDAO, DomainEvent, (), OutboxBy, OutboxWhere>
+    for OutboxRepository
+{
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: DomainEvent,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let event = Self::append(&mut tx, input).await?;
+        tx.commit().await?;
+        Ok(event)
+    }
+
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: OutboxBy,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "outbox events are marked published, see purge_published".to_string(),
+        ))
+    }
+
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        _db: E,
+        _key: OutboxBy,
+        _update: (),
+    ) -> Result<OutboxDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "outbox events are marked published, see mark_published".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: OutboxBy,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        let missing = not_found("OutboxEvent", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            OutboxBy::Id(id) => sqlx::query_as::<_, OutboxDAO>(
+                "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload::text AS payload, published_at FROM outbox WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: OutboxBy,
+    ) -> Result<Option<OutboxDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            OutboxBy::Id(id) => sqlx::query_as::<_, OutboxDAO>(
+                "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload::text AS payload, published_at FROM outbox WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: OutboxWhere,
+    ) -> Result<Vec<OutboxDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            OutboxWhere::Unpublished { limit } => sqlx::query_as::<_, OutboxDAO>(
+                "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload::text AS payload, published_at FROM outbox WHERE published_at IS NULL ORDER BY id LIMIT $1;",
+            )
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OutboxRepositoryExt<Postgres> for OutboxRepository {
+    async fn append(
+        tx: &mut Transaction<'_, Postgres>,
+        event: DomainEvent,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        sqlx::query_as::<_, OutboxDAO>(
+            "INSERT INTO outbox (aggregate_type, aggregate_id, event_type, payload) VALUES ($1, $2, $3, $4::jsonb) RETURNING id, created_at, aggregate_type, aggregate_id, event_type, payload::text AS payload, published_at;",
+        )
+        .bind(event.aggregate_type())
+        .bind(event.aggregate_id())
+        .bind(event.event_type())
+        .bind(event.payload())
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    async fn claim(
+        tx: &mut Transaction<'_, Postgres>,
+        limit: u32,
+    ) -> Result<Option<Vec<OutboxDAO>>, DatabaseError> {
+        let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock($1);")
+            .bind(RELAY_LOCK_ID)
+            .fetch_one(&mut **tx)
+            .await?;
+        if !locked {
+            return Ok(None);
+        }
+
+        sqlx::query_as::<_, OutboxDAO>(
+            "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload::text AS payload, published_at FROM outbox WHERE published_at IS NULL ORDER BY id LIMIT $1;",
+        )
+        .bind(limit as i64)
+        .fetch_all(&mut **tx)
+        .await
+        .map(Some)
+        .map_err(DatabaseError::from)
+    }
+
+    async fn mark_published(
+        tx: &mut Transaction<'_, Postgres>,
+        ids: &[i64],
+    ) -> Result<u64, DatabaseError> {
+        sqlx::query("UPDATE outbox SET published_at = now() WHERE id = ANY($1);")
+            .bind(ids)
+            .execute(&mut **tx)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(DatabaseError::from)
+    }
+
+    async fn purge_published<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query("DELETE FROM outbox WHERE published_at IS NOT NULL AND published_at < $1;")
+            .bind(before)
+            .execute(&mut *conn)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use crate::entities::movies::{CreateMovieDAO, MovieRepository};
+    use crate::entities::outbox::{OutboxBy, OutboxRepository, OutboxRepositoryExt, OutboxWhere};
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
+    use crate::types::Utc;
+
+    #[tokio::test]
+    async fn test_db() {
+        let pool = test_pool().await;
+
+        // mutations record their events
+        let movie = MovieRepository::insert(
+            &pool,
+            CreateMovieDAO {
+                title: "Outbox".to_string(),
+                description: "events".to_string(),
+                genres: vec![],
+                cast_members: vec![],
+                available: true,
+            },
+        )
+        .await
+        .unwrap();
+        let events = OutboxRepository::get_all(&pool, OutboxWhere::Unpublished { limit: 1000 })
+            .await
+            .unwrap();
+        let created = events
+            .iter()
+            .find(|e| e.aggregate_id == movie.id)
+            .expect("MovieCreated was not recorded");
+        assert_eq!(created.aggregate_type, "movie");
+        assert_eq!(created.event_type, "MovieCreated");
+        let payload: serde_json::Value = serde_json::from_str(&created.payload).unwrap();
+        assert_eq!(payload["title"], "Outbox");
+
+        // claimed by one relay at a time
+        let mut tx = pool.begin().await.unwrap();
+        let claimed = OutboxRepository::claim(&mut tx, 1000)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(claimed.iter().any(|e| e.id == created.id));
+        let mut other = pool.begin().await.unwrap();
+        assert!(OutboxRepository::claim(&mut other, 1000)
+            .await
+            .unwrap()
+            .is_none());
+        other.rollback().await.unwrap();
+
+        OutboxRepository::mark_published(&mut tx, &[created.id])
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        let published = OutboxRepository::get(&pool, OutboxBy::Id(created.id))
+            .await
+            .unwrap();
+        assert!(published.published_at.is_some());
+
+        // rows are only touched through mark_published and purge_published
+        let update = OutboxRepository::update(&pool, OutboxBy::Id(created.id), ()).await;
+        assert!(matches!(update, Err(DatabaseError::Unsupported(_))));
+        let delete = OutboxRepository::delete(&pool, OutboxBy::Id(created.id)).await;
+        assert!(matches!(delete, Err(DatabaseError::Unsupported(_))));
+
+        let purged = OutboxRepository::purge_published(&pool, Utc::now())
+            .await
+            .unwrap();
+        assert!(purged >= 1);
+        assert!(OutboxRepository::try_get(&pool, OutboxBy::Id(created.id))
+            .await
+            .unwrap()
+            .is_none());
+
+        MovieRepository::delete(&pool, crate::entities::movies::MovieBy::Id(movie.id))
+            .await
+            .unwrap();
+    }
+}
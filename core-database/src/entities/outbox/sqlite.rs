@@ -0,0 +1,182 @@
+use super::{DomainEvent, OutboxBy, OutboxDAO, OutboxRepository, OutboxRepositoryExt, OutboxWhere};
+use crate::{
+    connection::{Sqlite, Transaction},
+    traits::{not_found, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc},
+};
+use serde_json::json;
+
+/// Primary result code of a database locked by another connection
+const SQLITE_BUSY: i32 = 5;
+
+#[async_trait::async_trait]
+impl EntityRepository<Sqlite, OutboxDAO, DomainEvent, (), OutboxBy, OutboxWhere>
+    for OutboxRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: DomainEvent,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let event = Self::append(&mut tx, input).await?;
+        tx.commit().await?;
+        Ok(event)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: OutboxBy,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "outbox events are marked published, see purge_published".to_string(),
+        ))
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        _db: E,
+        _key: OutboxBy,
+        _update: (),
+    ) -> Result<OutboxDAO, DatabaseError> {
+        Err(DatabaseError::Unsupported(
+            "outbox events are marked published, see mark_published".to_string(),
+        ))
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: OutboxBy,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        let missing = not_found("OutboxEvent", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            OutboxBy::Id(id) => sqlx::query_as::<_, OutboxDAO>(
+                "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload, published_at FROM outbox WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: OutboxBy,
+    ) -> Result<Option<OutboxDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            OutboxBy::Id(id) => sqlx::query_as::<_, OutboxDAO>(
+                "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload, published_at FROM outbox WHERE id = $1;",
+            )
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: OutboxWhere,
+    ) -> Result<Vec<OutboxDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            OutboxWhere::Unpublished { limit } => sqlx::query_as::<_, OutboxDAO>(
+                "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload, published_at FROM outbox WHERE published_at IS NULL ORDER BY id LIMIT $1;",
+            )
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OutboxRepositoryExt<Sqlite> for OutboxRepository {
+    async fn append(
+        tx: &mut Transaction<'_, Sqlite>,
+        event: DomainEvent,
+    ) -> Result<OutboxDAO, DatabaseError> {
+        sqlx::query_as::<_, OutboxDAO>(
+            "INSERT INTO outbox (created_at, aggregate_type, aggregate_id, event_type, payload) VALUES ($1, $2, $3, $4, $5) RETURNING id, created_at, aggregate_type, aggregate_id, event_type, payload, published_at;",
+        )
+        .bind(Utc::now())
+        .bind(event.aggregate_type())
+        .bind(event.aggregate_id())
+        .bind(event.event_type())
+        .bind(event.payload())
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(DatabaseError::from)
+    }
+
+    /// SQLite has a single writer, so the write lock stands in for the advisory lock.
+    /// Any other writer holding it makes the relay skip a round, not only another relay.
+    async fn claim(
+        tx: &mut Transaction<'_, Sqlite>,
+        limit: u32,
+    ) -> Result<Option<Vec<OutboxDAO>>, DatabaseError> {
+        let busy_timeout: i64 = sqlx::query_scalar("PRAGMA busy_timeout;")
+            .fetch_one(&mut **tx)
+            .await?;
+        sqlx::query("PRAGMA busy_timeout = 0;")
+            .execute(&mut **tx)
+            .await?;
+        let locked = sqlx::query("UPDATE outbox SET id = id WHERE false;")
+            .execute(&mut **tx)
+            .await;
+        sqlx::query(&format!("PRAGMA busy_timeout = {};", busy_timeout))
+            .execute(&mut **tx)
+            .await?;
+        match locked {
+            Err(sqlx::Error::Database(e))
+                if e.code()
+                    .and_then(|code| code.parse::<i32>().ok())
+                    .is_some_and(|code| code & 0xff == SQLITE_BUSY) =>
+            {
+                return Ok(None)
+            }
+            locked => locked?,
+        };
+
+        sqlx::query_as::<_, OutboxDAO>(
+            "SELECT id, created_at, aggregate_type, aggregate_id, event_type, payload, published_at FROM outbox WHERE published_at IS NULL ORDER BY id LIMIT $1;",
+        )
+        .bind(limit as i64)
+        .fetch_all(&mut **tx)
+        .await
+        .map(Some)
+        .map_err(DatabaseError::from)
+    }
+
+    async fn mark_published(
+        tx: &mut Transaction<'_, Sqlite>,
+        ids: &[i64],
+    ) -> Result<u64, DatabaseError> {
+        sqlx::query(
+            "UPDATE outbox SET published_at = $1, updated_at = $1 WHERE id IN (SELECT value FROM json_each($2));",
+        )
+        .bind(Utc::now())
+        .bind(json!(ids).to_string())
+        .execute(&mut **tx)
+        .await
+        .map(|r| r.rows_affected())
+        .map_err(DatabaseError::from)
+    }
+
+    async fn purge_published<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let purged =
+            sqlx::query("DELETE FROM outbox WHERE published_at IS NOT NULL AND published_at < $1;")
+                .bind(before)
+                .execute(&mut *tx)
+                .await
+                .map(|r| r.rows_affected())?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+}
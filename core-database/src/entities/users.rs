@@ -1,8 +1,16 @@
 use crate::{
-    connection::{Pool, Postgres},
-    traits::{DatabaseError, EntityRepository},
+    connection::{Database, Postgres},
+    entities::history::{Audited, EntityChange, HistoryRepository, HistoryRepositoryExt},
+    entities::outbox::{DomainEvent, OutboxRepository, OutboxRepositoryExt},
+    query::{Field, Query, Value, ValueKind},
+    traits::{not_found, version_conflict, DatabaseError, EntityRepository, Executor},
     types::{DateTime, Utc, Uuid},
 };
+use serde_json::json;
+use sqlx::QueryBuilder;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct UserDAO {
@@ -10,11 +18,25 @@ pub struct UserDAO {
     pub name: String,
     pub birthday: DateTime<Utc>,
     pub active: bool,
+    /// Bumped by every update, deactivation included
+    pub version: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateUserDAO {
+    /// Same as the id of the user's credentials
+    pub id: Uuid,
+    pub name: String,
+    pub birthday: DateTime<Utc>,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct UpdateUserDAO {
     pub name: String,
+    /// Version the change was based on, the update fails when the user moved past it
+    pub version: i64,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -22,111 +44,308 @@ pub enum UserBy {
     Id(Uuid),
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum UsersWhere {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserField {
+    Id,
+    Name,
+    Birthday,
+    Active,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl Field for UserField {
+    const KEY: Self = UserField::Id;
+
+    fn column(self) -> &'static str {
+        match self {
+            UserField::Id => "id",
+            UserField::Name => "name",
+            UserField::Birthday => "birthday",
+            UserField::Active => "active",
+            UserField::CreatedAt => "created_at",
+            UserField::UpdatedAt => "updated_at",
+        }
+    }
+
+    fn kind(self) -> ValueKind {
+        match self {
+            UserField::Id => ValueKind::Uuid,
+            UserField::Name => ValueKind::Text,
+            UserField::Birthday | UserField::CreatedAt | UserField::UpdatedAt => {
+                ValueKind::Timestamp
+            }
+            UserField::Active => ValueKind::Bool,
+        }
+    }
+}
+
+impl UserDAO {
+    /// What `field` holds, for running a `Query` in memory
+    pub fn field(&self, field: UserField) -> Option<Value> {
+        Some(match field {
+            UserField::Id => self.id.into(),
+            UserField::Name => self.name.clone().into(),
+            UserField::Birthday => self.birthday.into(),
+            UserField::Active => self.active.into(),
+            UserField::CreatedAt => self.created_at.into(),
+            UserField::UpdatedAt => self.updated_at.into(),
+        })
+    }
+}
+
+impl Audited for UserDAO {
+    const ENTITY: &'static str = "User";
+
+    fn key(&self) -> Uuid {
+        self.id
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id": self.id.to_string(),
+            "name": self.name,
+            "birthday": self.birthday.to_rfc3339(),
+            "active": self.active,
+            "version": self.version,
+            "created_at": self.created_at.to_rfc3339(),
+            "updated_at": self.updated_at.to_rfc3339(),
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct UserRepository;
 
 #[async_trait::async_trait]
-impl EntityRepository<Postgres, UserDAO, UserDAO, UpdateUserDAO, UserBy, UsersWhere>
+pub trait UserRepositoryExt<Db: Database> {
+    /// Permanently removes users deactivated before `before`.
+    /// Returns how many users were erased.
+    async fn erase_deactivated<'c, E: Executor<'c, Db>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Undoes `delete`, e.g. when the account closure it was part of failed.
+    /// Fails with `NotFound` unless the user is deactivated and not erased yet.
+    async fn reactivate<'c, E: Executor<'c, Db>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<UserDAO, DatabaseError>;
+}
+
+#[async_trait::async_trait]
+impl EntityRepository<Postgres, UserDAO, CreateUserDAO, UpdateUserDAO, UserBy, Query<UserField>>
     for UserRepository
 {
-    async fn insert(db: &Pool<Postgres>, input: UserDAO) -> Result<UserDAO, DatabaseError> {
-        sqlx::query_as::<_, UserDAO>("INSERT INTO users (id, name, birthday) VALUES ($1, $2, $3) RETURNING id, name, birthday, active;")
+    async fn insert<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        input: CreateUserDAO,
+    ) -> Result<UserDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let user = sqlx::query_as::<_, UserDAO>("INSERT INTO users (id, name, birthday) VALUES ($1, $2, $3) RETURNING id, name, birthday, active, version, created_at, updated_at;")
             .bind(input.id)
             .bind(input.name)
             .bind(input.birthday)
-            .fetch_one(db)
-            .await
-            .map_err(DatabaseError::from)
+            .fetch_one(&mut *tx)
+            .await?;
+        OutboxRepository::append(&mut tx, DomainEvent::UserCreated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::insert(&user)).await?;
+        tx.commit().await?;
+        Ok(user)
     }
 
-    async fn delete(db: &Pool<Postgres>, key: UserBy) -> Result<UserDAO, DatabaseError> {
-        match key {
+    async fn delete<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<UserDAO, DatabaseError> {
+        let missing = not_found("User", &key);
+        let mut tx = db.begin().await?;
+        let (before, user) = match key {
             UserBy::Id(uuid) => {
-                sqlx::query_as::<_, UserDAO>("UPDATE users SET active = false WHERE id = $1 RETURNING id, name, active, birthday;")
-                    .bind(uuid)
-                    .fetch_one(db)
-                    .await
-                    .map_err(DatabaseError::from)
+                let before = sqlx::query_as::<_, UserDAO>(
+                    "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1 FOR UPDATE;",
+                )
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(missing)?;
+                let user = sqlx::query_as::<_, UserDAO>(
+                    "UPDATE users SET active = false, deactivated_at = now(), version = version + 1 WHERE id = $1 RETURNING id, name, birthday, active, version, created_at, updated_at;",
+                )
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, user)
             }
-        }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::UserDeactivated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &user)).await?;
+        tx.commit().await?;
+        Ok(user)
     }
 
-    async fn update(
-        db: &Pool<Postgres>,
+    async fn update<'c, E: Executor<'c, Postgres>>(
+        db: E,
         key: UserBy,
         update: UpdateUserDAO,
     ) -> Result<UserDAO, DatabaseError> {
+        let conflict = version_conflict("User", &key);
+        let mut tx = db.begin().await?;
+        let (before, user) = match key {
+            UserBy::Id(uuid) => {
+                let before = sqlx::query_as::<_, UserDAO>(
+                    "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1 FOR UPDATE;",
+                )
+                .bind(uuid)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let before = match before {
+                    Some(before) if before.version == update.version => before,
+                    before => return Err(conflict(before.map(|u| u.version))),
+                };
+                let user = sqlx::query_as::<_, UserDAO>(
+                    "UPDATE users SET name = $1, version = version + 1 WHERE id = $2 RETURNING id, name, birthday, active, version, created_at, updated_at;",
+                )
+                .bind(update.name)
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, user)
+            }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::UserUpdated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &user)).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<UserDAO, DatabaseError> {
+        let missing = not_found("User", &key);
+        let mut conn = db.acquire().await?;
         match key {
             UserBy::Id(uuid) => sqlx::query_as::<_, UserDAO>(
-                "UPDATE users SET name = $1 WHERE id = $2 RETURNING id, name, active, birthday;",
+                "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1 LIMIT 1;",
             )
-            .bind(update.name)
             .bind(uuid)
-            .fetch_one(db)
+            .fetch_one(&mut *conn)
             .await
-            .map_err(DatabaseError::from),
+            .map_err(missing),
         }
     }
 
-    async fn get(db: &Pool<Postgres>, key: UserBy) -> Result<UserDAO, DatabaseError> {
+    async fn try_get<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<Option<UserDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
         match key {
-            UserBy::Id(uuid) => sqlx::query_as::<_, UserDAO>(
-                "SELECT id, name, birthday, active FROM users WHERE id = $1 LIMIT 1;",
+            UserBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1;",
             )
             .bind(uuid)
-            .fetch_one(db)
+            .fetch_optional(&mut *conn)
             .await
             .map_err(DatabaseError::from),
         }
     }
 
-    async fn try_get(db: &Pool<Postgres>, key: UserBy) -> Result<Option<UserDAO>, DatabaseError> {
-        match key {
-            UserBy::Id(uuid) => {
-                sqlx::query_as("SELECT id, name, birthday, active FROM users WHERE id = $1;")
-                    .bind(uuid)
-                    .fetch_optional(db)
-                    .await
-                    .map_err(DatabaseError::from)
-            }
+    async fn get_all<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        query: Query<UserField>,
+    ) -> Result<Vec<UserDAO>, DatabaseError> {
+        let mut select = QueryBuilder::new(
+            "SELECT id, name, birthday, active, version, created_at, updated_at FROM users",
+        );
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<UserDAO>()
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl UserRepositoryExt<Postgres> for UserRepository {
+    async fn erase_deactivated<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let erased: Vec<Uuid> = sqlx::query_scalar(
+            "DELETE FROM users WHERE active = false AND deactivated_at IS NOT NULL AND deactivated_at < $1 RETURNING id;",
+        )
+        .bind(before)
+        .fetch_all(&mut *tx)
+        .await?;
+        for id in &erased {
+            OutboxRepository::append(&mut tx, DomainEvent::UserErased(*id)).await?;
+            HistoryRepository::forget(&mut tx, UserDAO::ENTITY, *id).await?;
+            HistoryRepository::record(&mut tx, EntityChange::erase::<UserDAO>(*id)).await?;
         }
+        tx.commit().await?;
+        Ok(erased.len() as u64)
     }
 
-    async fn get_all(
-        _db: &Pool<Postgres>,
-        _key: UsersWhere,
-    ) -> Result<Vec<UserDAO>, DatabaseError> {
-        todo!()
+    async fn reactivate<'c, E: Executor<'c, Postgres>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<UserDAO, DatabaseError> {
+        let missing = not_found("User", &key);
+        let mut tx = db.begin().await?;
+        let (before, user) = match key {
+            UserBy::Id(uuid) => {
+                let before = sqlx::query_as::<_, UserDAO>(
+                    "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1 AND active = false FOR UPDATE;",
+                )
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(missing)?;
+                let user = sqlx::query_as::<_, UserDAO>(
+                    "UPDATE users SET active = true, deactivated_at = NULL, version = version + 1 WHERE id = $1 RETURNING id, name, birthday, active, version, created_at, updated_at;",
+                )
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, user)
+            }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::UserReactivated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &user)).await?;
+        tx.commit().await?;
+        Ok(user)
     }
 }
 
 #[cfg(feature = "integration")]
 #[cfg(test)]
 mod tests {
-    use crate::connection::PgPool;
-    use crate::entities::users::{UpdateUserDAO, UserBy, UserDAO, UserRepository};
-    use crate::traits::EntityRepository;
-    use dotenv;
+    use crate::entities::history::{HistoryRepository, HistoryWhere};
+    use crate::entities::users::{
+        CreateUserDAO, UpdateUserDAO, UserBy, UserField, UserRepository, UserRepositoryExt,
+    };
+    use crate::query::{Filter, Query};
+    use crate::testing::test_pool;
+    use crate::traits::{DatabaseError, EntityRepository};
     use sqlx::types::{chrono::Utc, uuid::Uuid};
 
     #[tokio::test]
     async fn test_db() {
-        dotenv::dotenv().ok();
-        let url =
-            std::env::var("TEST_CORE_DATABASE_URL").expect("TEST_CORE_DATABASE_URL must be set");
-        let pool = PgPool::connect(&url).await.unwrap();
+        let pool = test_pool().await;
 
         // create user
         let response = UserRepository::insert(
             &pool,
-            UserDAO {
+            CreateUserDAO {
                 id: Uuid::new_v4(),
                 name: "akira".to_string(),
                 birthday: Utc::now(),
-                active: true,
             },
         )
         .await
@@ -141,6 +360,7 @@ mod tests {
         assert_eq!(response.name, found.name);
         assert_eq!(response.birthday, found.birthday);
         assert!(response.active);
+        assert_eq!(response.version, 1);
 
         // try_get user, returns none if user isn't found
         let found = UserRepository::try_get(&pool, UserBy::Id(response.id))
@@ -159,6 +379,7 @@ mod tests {
             UserBy::Id(response.id),
             UpdateUserDAO {
                 name: "Masashi".to_string(),
+                version: response.version,
             },
         )
         .await
@@ -167,6 +388,36 @@ mod tests {
         assert_eq!(response.id, updated.id);
         assert_eq!(updated.name, "Masashi");
         assert!(updated.active);
+        assert_eq!(updated.version, 2);
+        assert!(updated.updated_at >= response.updated_at);
+
+        // an update based on the version before is rejected with the current one
+        let stale = UserRepository::update(
+            &pool,
+            UserBy::Id(response.id),
+            UpdateUserDAO {
+                name: "Akira".to_string(),
+                version: response.version,
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            stale,
+            DatabaseError::VersionConflict {
+                entity: "User".to_string(),
+                key: format!("Id({})", response.id),
+                current_version: 2,
+            }
+        );
+
+        // query by id and status
+        let query = Query::new()
+            .filter(Filter::eq(UserField::Id, response.id))
+            .filter(Filter::eq(UserField::Active, true))
+            .filter(Filter::contains(UserField::Name, "masa"));
+        let found = UserRepository::get_all(&pool, query).await.unwrap();
+        assert_eq!(found, vec![updated.clone()]);
 
         // delete
         let deleted = UserRepository::delete(&pool, UserBy::Id(response.id))
@@ -174,5 +425,41 @@ mod tests {
             .expect("Could not delete an user");
         assert_eq!(response.id, deleted.id);
         assert!(!deleted.active);
+        assert_eq!(deleted.version, 3);
+
+        // reactivate, only deactivated users can be
+        let reactivated = UserRepository::reactivate(&pool, UserBy::Id(response.id))
+            .await
+            .expect("Could not reactivate user");
+        assert!(reactivated.active);
+        assert_eq!(reactivated.version, 4);
+        let again = UserRepository::reactivate(&pool, UserBy::Id(response.id))
+            .await
+            .unwrap_err();
+        assert!(matches!(again, DatabaseError::NotFound { .. }));
+        let deleted = UserRepository::delete(&pool, UserBy::Id(response.id))
+            .await
+            .unwrap();
+        assert_eq!(deleted.version, 5);
+
+        // erase deactivated users
+        let erased = UserRepository::erase_deactivated(&pool, Utc::now())
+            .await
+            .expect("Could not erase users");
+        assert!(erased >= 1);
+        let found = UserRepository::try_get(&pool, UserBy::Id(response.id))
+            .await
+            .unwrap();
+        assert!(found.is_none());
+
+        // nothing of what the user was is kept
+        let of_user = HistoryWhere::Entity {
+            entity_type: "User".to_string(),
+            entity_id: response.id,
+        };
+        let history = HistoryRepository::get_all(&pool, of_user).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].action, "erase");
+        assert!(history[0].before_state.is_none() && history[0].after_state.is_none());
     }
 }
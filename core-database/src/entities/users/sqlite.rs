@@ -0,0 +1,206 @@
+use super::{
+    CreateUserDAO, UpdateUserDAO, UserBy, UserDAO, UserField, UserRepository, UserRepositoryExt,
+};
+use crate::{
+    connection::Sqlite,
+    entities::history::{Audited, EntityChange, HistoryRepository, HistoryRepositoryExt},
+    entities::outbox::{DomainEvent, OutboxRepository, OutboxRepositoryExt},
+    query::Query,
+    traits::{not_found, version_conflict, DatabaseError, EntityRepository, Executor},
+    types::{DateTime, Utc, Uuid},
+};
+use sqlx::QueryBuilder;
+
+#[async_trait::async_trait]
+impl EntityRepository<Sqlite, UserDAO, CreateUserDAO, UpdateUserDAO, UserBy, Query<UserField>>
+    for UserRepository
+{
+    async fn insert<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        input: CreateUserDAO,
+    ) -> Result<UserDAO, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let user = sqlx::query_as::<_, UserDAO>("INSERT INTO users (id, name, birthday, created_at, updated_at) VALUES ($1, $2, $3, $4, $4) RETURNING id, name, birthday, active, version, created_at, updated_at;")
+            .bind(input.id)
+            .bind(input.name)
+            .bind(input.birthday)
+            .bind(Utc::now())
+            .fetch_one(&mut *tx)
+            .await?;
+        OutboxRepository::append(&mut tx, DomainEvent::UserCreated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::insert(&user)).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn delete<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<UserDAO, DatabaseError> {
+        let missing = not_found("User", &key);
+        let mut tx = db.begin().await?;
+        let (before, user) = match key {
+            UserBy::Id(uuid) => {
+                let before = sqlx::query_as::<_, UserDAO>(
+                    "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1;",
+                )
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(missing)?;
+                let user = sqlx::query_as::<_, UserDAO>(
+                    "UPDATE users SET active = false, deactivated_at = $1, version = version + 1, updated_at = $1 WHERE id = $2 RETURNING id, name, birthday, active, version, created_at, updated_at;",
+                )
+                .bind(Utc::now())
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, user)
+            }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::UserDeactivated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &user)).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn update<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: UserBy,
+        update: UpdateUserDAO,
+    ) -> Result<UserDAO, DatabaseError> {
+        let conflict = version_conflict("User", &key);
+        let mut tx = db.begin().await?;
+        let (before, user) = match key {
+            UserBy::Id(uuid) => {
+                // SQLite fails this transaction's write when another one wrote the user since it read it
+                let before = sqlx::query_as::<_, UserDAO>(
+                    "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1;",
+                )
+                .bind(uuid)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let before = match before {
+                    Some(before) if before.version == update.version => before,
+                    before => return Err(conflict(before.map(|u| u.version))),
+                };
+                let user = sqlx::query_as::<_, UserDAO>(
+                    "UPDATE users SET name = $1, version = version + 1, updated_at = $3 WHERE id = $2 RETURNING id, name, birthday, active, version, created_at, updated_at;",
+                )
+                .bind(update.name)
+                .bind(uuid)
+                .bind(Utc::now())
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, user)
+            }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::UserUpdated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &user)).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<UserDAO, DatabaseError> {
+        let missing = not_found("User", &key);
+        let mut conn = db.acquire().await?;
+        match key {
+            UserBy::Id(uuid) => sqlx::query_as::<_, UserDAO>(
+                "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1 LIMIT 1;",
+            )
+            .bind(uuid)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(missing),
+        }
+    }
+
+    async fn try_get<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<Option<UserDAO>, DatabaseError> {
+        let mut conn = db.acquire().await?;
+        match key {
+            UserBy::Id(uuid) => sqlx::query_as(
+                "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1;",
+            )
+            .bind(uuid)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(DatabaseError::from),
+        }
+    }
+
+    async fn get_all<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        query: Query<UserField>,
+    ) -> Result<Vec<UserDAO>, DatabaseError> {
+        let mut select = QueryBuilder::new(
+            "SELECT id, name, birthday, active, version, created_at, updated_at FROM users",
+        );
+        query.push_to(&mut select)?;
+        let mut conn = db.acquire().await?;
+        select
+            .build_query_as::<UserDAO>()
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(DatabaseError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl UserRepositoryExt<Sqlite> for UserRepository {
+    async fn erase_deactivated<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        before: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        let mut tx = db.begin().await?;
+        let erased: Vec<Uuid> = sqlx::query_scalar(
+            "DELETE FROM users WHERE active = false AND deactivated_at IS NOT NULL AND deactivated_at < $1 RETURNING id;",
+        )
+        .bind(before)
+        .fetch_all(&mut *tx)
+        .await?;
+        for id in &erased {
+            OutboxRepository::append(&mut tx, DomainEvent::UserErased(*id)).await?;
+            HistoryRepository::forget(&mut tx, UserDAO::ENTITY, *id).await?;
+            HistoryRepository::record(&mut tx, EntityChange::erase::<UserDAO>(*id)).await?;
+        }
+        tx.commit().await?;
+        Ok(erased.len() as u64)
+    }
+
+    async fn reactivate<'c, E: Executor<'c, Sqlite>>(
+        db: E,
+        key: UserBy,
+    ) -> Result<UserDAO, DatabaseError> {
+        let missing = not_found("User", &key);
+        let mut tx = db.begin().await?;
+        let (before, user) = match key {
+            UserBy::Id(uuid) => {
+                let before = sqlx::query_as::<_, UserDAO>(
+                    "SELECT id, name, birthday, active, version, created_at, updated_at FROM users WHERE id = $1 AND active = false;",
+                )
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(missing)?;
+                let user = sqlx::query_as::<_, UserDAO>(
+                    "UPDATE users SET active = true, deactivated_at = NULL, version = version + 1, updated_at = $1 WHERE id = $2 RETURNING id, name, birthday, active, version, created_at, updated_at;",
+                )
+                .bind(Utc::now())
+                .bind(uuid)
+                .fetch_one(&mut *tx)
+                .await?;
+                (before, user)
+            }
+        };
+        OutboxRepository::append(&mut tx, DomainEvent::UserReactivated(user.clone())).await?;
+        HistoryRepository::record(&mut tx, EntityChange::update(&before, &user)).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+}
@@ -1,3 +1,28 @@
 pub mod entities;
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod testing;
 
 pub use database::*;
+
+/// Schema of the core database, embedded at compile time from `migrations/`
+pub static MIGRATOR: migrations::Migrator = sqlx::migrate!();
+
+/// Schema of the core database on SQLite, embedded from `migrations_sqlite/`.
+/// Its history starts over at the schema `migrations/` ends with.
+#[cfg(feature = "sqlite")]
+pub static SQLITE_MIGRATOR: migrations::Migrator = sqlx::migrate!("./migrations_sqlite");
+
+pub async fn migrate(pool: &connection::PgPool) -> Result<(), traits::DatabaseError> {
+    migrations::migrate(&MIGRATOR, pool).await
+}
+
+pub async fn rollback(pool: &connection::PgPool, to: i64) -> Result<(), traits::DatabaseError> {
+    migrations::rollback(&MIGRATOR, pool, to).await
+}
+
+pub async fn status(
+    pool: &connection::PgPool,
+) -> Result<Vec<migrations::MigrationStatus>, traits::DatabaseError> {
+    migrations::status(&MIGRATOR, pool).await
+}
@@ -0,0 +1,184 @@
+//! Concurrent `authenticate` throughput against an in-process auth server that takes
+//! a couple of milliseconds per call, comparing a client behind a global mutex
+//! (how `Core` used to call auth) with `TonicAuthGateway`.
+//!
+//! cargo bench -p core --bench auth_gateway
+
+use core::auth_gateway::{AuthGateway, AuthGatewayConfig, TonicAuthGateway};
+use grpc_interfaces::auth::auth_client::AuthClient;
+use grpc_interfaces::auth::auth_server::{Auth, AuthServer};
+use grpc_interfaces::auth::*;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+use tonic::{Request, Response, Status};
+
+const CALLS: usize = 2_000;
+const CONCURRENCY: &[usize] = &[1, 16, 64];
+const AUTH_LATENCY: Duration = Duration::from_millis(2);
+
+struct SlowAuth;
+
+type Rpc<'a, R> = Pin<Box<dyn Future<Output = Result<Response<R>, Status>> + Send + 'a>>;
+
+/// What `tonic::async_trait` would generate, the macro can't be used here because it
+/// expands to paths in `::core`, which is this crate rather than the standard one.
+macro_rules! rpcs {
+    ($($name:ident($request:ty) -> $response:ty $body:block)*) => {
+        $(
+            fn $name<'life0, 'async_trait>(
+                &'life0 self,
+                _: Request<$request>,
+            ) -> Rpc<'async_trait, $response>
+            where
+                'life0: 'async_trait,
+                Self: 'async_trait,
+            {
+                Box::pin(async move $body)
+            }
+        )*
+    };
+}
+
+impl Auth for SlowAuth {
+    rpcs! {
+        authenticate(AuthenticateRequest) -> AuthenticateResponse {
+            tokio::time::sleep(AUTH_LATENCY).await;
+            Ok(Response::new(AuthenticateResponse {
+                credential_id: "6f0ba1a6-0c8f-4c5e-8d4a-1e0d0f7e0b3a".to_string(),
+                expires_at: 0,
+                roles: vec![],
+            }))
+        }
+        create_credential(CreateCredentialsRequest) -> CreateCredentialsResponse {
+            Err(Status::unimplemented("create_credential"))
+        }
+        delete_credential(DeleteCredentialRequest) -> () {
+            Err(Status::unimplemented("delete_credential"))
+        }
+        change_password(ChangePasswordRequest) -> () {
+            Err(Status::unimplemented("change_password"))
+        }
+        change_email(ChangeEmailRequest) -> () {
+            Err(Status::unimplemented("change_email"))
+        }
+        confirm_email_change(ConfirmEmailChangeRequest) -> () {
+            Err(Status::unimplemented("confirm_email_change"))
+        }
+        close_account(CloseAccountRequest) -> () {
+            Err(Status::unimplemented("close_account"))
+        }
+        export_credential(ExportCredentialRequest) -> ExportCredentialResponse {
+            Err(Status::unimplemented("export_credential"))
+        }
+        list_sessions(ListSessionsRequest) -> ListSessionsResponse {
+            Err(Status::unimplemented("list_sessions"))
+        }
+        revoke_session(RevokeSessionRequest) -> () {
+            Err(Status::unimplemented("revoke_session"))
+        }
+        create_api_key(CreateApiKeyRequest) -> CreateApiKeyResponse {
+            Err(Status::unimplemented("create_api_key"))
+        }
+        list_api_keys(ListApiKeysRequest) -> ListApiKeysResponse {
+            Err(Status::unimplemented("list_api_keys"))
+        }
+        revoke_api_key(RevokeApiKeyRequest) -> () {
+            Err(Status::unimplemented("revoke_api_key"))
+        }
+        authenticate_api_key(AuthenticateApiKeyRequest) -> AuthenticateApiKeyResponse {
+            Err(Status::unimplemented("authenticate_api_key"))
+        }
+        list_auth_events(ListAuthEventsRequest) -> ListAuthEventsResponse {
+            Err(Status::unimplemented("list_auth_events"))
+        }
+    }
+}
+
+async fn serve() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(AuthServer::new(SlowAuth))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    addr
+}
+
+fn request() -> AuthenticateRequest {
+    AuthenticateRequest {
+        session_id: "6f0ba1a6-0c8f-4c5e-8d4a-1e0d0f7e0b3a".to_string(),
+    }
+}
+
+/// Runs `CALLS` calls spread over `concurrency` tasks, returns calls per second
+async fn throughput<F, Fut>(concurrency: usize, call: F) -> f64
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let call = Arc::new(call);
+    let started = Instant::now();
+    let tasks = (0..concurrency)
+        .map(|_| {
+            let call = call.clone();
+            tokio::spawn(async move {
+                for _ in 0..CALLS / concurrency {
+                    call().await;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    (CALLS / concurrency * concurrency) as f64 / started.elapsed().as_secs_f64()
+}
+
+fn main() {
+    // `#[tokio::main]` expands to `::core` paths too
+    tokio::runtime::Runtime::new().unwrap().block_on(run());
+}
+
+async fn run() {
+    let url = format!("http://{}", serve().await);
+
+    let mutex = Arc::new(Mutex::new(
+        AuthClient::<Channel>::connect(url.clone()).await.unwrap(),
+    ));
+    let gateway = TonicAuthGateway::new(AuthGatewayConfig::new(vec![url])).unwrap();
+
+    println!(
+        "{:>12} {:>16} {:>16}",
+        "concurrency", "mutex calls/s", "gateway calls/s"
+    );
+    for &concurrency in CONCURRENCY {
+        let client = mutex.clone();
+        let locked = throughput(concurrency, move || {
+            let client = client.clone();
+            async move {
+                let mut client = client.lock().await;
+                client.authenticate(Request::new(request())).await.unwrap();
+            }
+        })
+        .await;
+
+        let gateway = gateway.clone();
+        let lock_free = throughput(concurrency, move || {
+            let gateway = gateway.clone();
+            async move {
+                gateway.authenticate(request()).await.unwrap();
+            }
+        })
+        .await;
+
+        println!("{:>12} {:>16.0} {:>16.0}", concurrency, locked, lock_free);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::service::CoreError;
+use grpc_interfaces::auth::{
+    auth_client::AuthClient, AuthenticateApiKeyRequest, AuthenticateApiKeyResponse,
+    AuthenticateRequest, AuthenticateResponse, ChangeEmailRequest, ChangePasswordRequest,
+    CloseAccountRequest, ConfirmEmailChangeRequest, CreateApiKeyRequest, CreateApiKeyResponse,
+    CreateCredentialsRequest, CreateCredentialsResponse, DeleteCredentialRequest,
+    ExportCredentialRequest, ExportCredentialResponse, ListApiKeysRequest, ListApiKeysResponse,
+    ListSessionsRequest, ListSessionsResponse, RevokeApiKeyRequest, RevokeSessionRequest,
+};
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::time::Duration;
+use tonic::transport::{Channel, Endpoint, Error as TransportError};
+use tonic::{Code, Request, Response, Status};
+
+/// The auth service as seen by `Core`, one method per RPC it relies on.
+#[async_trait::async_trait]
+pub trait AuthGateway: Send + Sync {
+    async fn create_credential(
+        &self,
+        request: CreateCredentialsRequest,
+    ) -> Result<CreateCredentialsResponse, CoreError>;
+    async fn delete_credential(&self, request: DeleteCredentialRequest) -> Result<(), CoreError>;
+    async fn authenticate(
+        &self,
+        request: AuthenticateRequest,
+    ) -> Result<AuthenticateResponse, CoreError>;
+    async fn change_password(&self, request: ChangePasswordRequest) -> Result<(), CoreError>;
+    async fn change_email(&self, request: ChangeEmailRequest) -> Result<(), CoreError>;
+    async fn confirm_email_change(
+        &self,
+        request: ConfirmEmailChangeRequest,
+    ) -> Result<(), CoreError>;
+    async fn close_account(&self, request: CloseAccountRequest) -> Result<(), CoreError>;
+    async fn export_credential(
+        &self,
+        request: ExportCredentialRequest,
+    ) -> Result<ExportCredentialResponse, CoreError>;
+    async fn list_sessions(
+        &self,
+        request: ListSessionsRequest,
+    ) -> Result<ListSessionsResponse, CoreError>;
+    async fn revoke_session(&self, request: RevokeSessionRequest) -> Result<(), CoreError>;
+    async fn create_api_key(
+        &self,
+        request: CreateApiKeyRequest,
+    ) -> Result<CreateApiKeyResponse, CoreError>;
+    async fn list_api_keys(
+        &self,
+        request: ListApiKeysRequest,
+    ) -> Result<ListApiKeysResponse, CoreError>;
+    async fn revoke_api_key(&self, request: RevokeApiKeyRequest) -> Result<(), CoreError>;
+    async fn authenticate_api_key(
+        &self,
+        request: AuthenticateApiKeyRequest,
+    ) -> Result<AuthenticateApiKeyResponse, CoreError>;
+}
+
+#[cfg(test)]
+mod in_memory;
+#[cfg(test)]
+pub use in_memory::InMemoryAuthGateway;
+
+/// How `TonicAuthGateway` reaches the auth service
+#[derive(Debug, Clone)]
+pub struct AuthGatewayConfig {
+    /// Calls are balanced over every endpoint, e.g. `http://auth:50051`
+    pub endpoints: Vec<String>,
+    pub connect_timeout: Duration,
+    /// Deadline of every attempt of a call
+    pub timeout: Duration,
+    /// Extra attempts of idempotent calls when auth is unreachable or too slow
+    pub retries: u32,
+    /// Wait before the first retry, doubled after every attempt up to `max_backoff`
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl AuthGatewayConfig {
+    pub fn new(endpoints: Vec<String>) -> Self {
+        Self {
+            endpoints,
+            connect_timeout: Duration::from_secs(1),
+            timeout: Duration::from_secs(2),
+            retries: 2,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Configuration `TonicAuthGateway` can't be built from
+#[derive(Debug)]
+pub enum AuthGatewayConfigError {
+    NoEndpoints,
+    InvalidEndpoint(TransportError),
+}
+
+impl Display for AuthGatewayConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthGatewayConfigError::NoEndpoints => {
+                write!(f, "at least one auth endpoint is required")
+            }
+            AuthGatewayConfigError::InvalidEndpoint(e) => write!(f, "invalid auth endpoint: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthGatewayConfigError {}
+
+impl From<TransportError> for AuthGatewayConfigError {
+    fn from(value: TransportError) -> Self {
+        AuthGatewayConfigError::InvalidEndpoint(value)
+    }
+}
+
+/// Talks to the auth service over gRPC. Cloning is cheap and every clone shares
+/// the same connections, which multiplex concurrent calls.
+#[derive(Debug, Clone)]
+pub struct TonicAuthGateway {
+    client: AuthClient<Channel>,
+    timeout: Duration,
+    retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl TonicAuthGateway {
+    /// Connects lazily, the auth service doesn't have to be up yet.
+    /// Must be called from within a tokio runtime.
+    pub fn new(config: AuthGatewayConfig) -> Result<Self, AuthGatewayConfigError> {
+        if config.endpoints.is_empty() {
+            return Err(AuthGatewayConfigError::NoEndpoints);
+        }
+        let endpoints = config
+            .endpoints
+            .iter()
+            .map(|url| {
+                Ok(Endpoint::from_shared(url.clone())?
+                    .connect_timeout(config.connect_timeout)
+                    .timeout(config.timeout))
+            })
+            .collect::<Result<Vec<Endpoint>, TransportError>>()?;
+
+        let channel = match endpoints.as_slice() {
+            [endpoint] => endpoint.connect_lazy(),
+            _ => Channel::balance_list(endpoints.into_iter()),
+        };
+
+        Ok(Self {
+            client: AuthClient::new(channel),
+            timeout: config.timeout,
+            retries: config.retries,
+            initial_backoff: config.initial_backoff,
+            max_backoff: config.max_backoff,
+        })
+    }
+
+    /// The deadline is sent along so auth can give up on calls nobody waits for anymore
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.set_timeout(self.timeout);
+        request
+    }
+
+    /// Makes a call once, for calls that can't safely be repeated
+    async fn call<T, R, F, Fut>(&self, message: T, call: F) -> Result<R, CoreError>
+    where
+        F: FnOnce(AuthClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let response = call(self.client.clone(), self.request(message)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Makes a call, retrying with exponential backoff while auth is unreachable or too slow
+    async fn idempotent<T, R, F, Fut>(&self, message: T, call: F) -> Result<R, CoreError>
+    where
+        T: Clone,
+        F: Fn(AuthClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            match call(self.client.clone(), self.request(message.clone())).await {
+                Err(status) if attempt < self.retries && retryable(&status) => {
+                    attempt += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+                result => return Ok(result?.into_inner()),
+            }
+        }
+    }
+}
+
+/// `Cancelled` is what tonic reports when a call runs past its deadline,
+/// `Aborted` what auth reports when its transaction lost to a concurrent one
+fn retryable(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::DeadlineExceeded | Code::Cancelled | Code::Aborted
+    )
+}
+
+#[async_trait::async_trait]
+impl AuthGateway for TonicAuthGateway {
+    async fn create_credential(
+        &self,
+        request: CreateCredentialsRequest,
+    ) -> Result<CreateCredentialsResponse, CoreError> {
+        let call = |mut client: AuthClient<Channel>, request| async move {
+            client.create_credential(request).await
+        };
+        // Auth hands back the same credential when the key is repeated
+        if request.idempotency_key.is_empty() {
+            self.call(request, call).await
+        } else {
+            self.idempotent(request, call).await
+        }
+    }
+
+    async fn delete_credential(&self, request: DeleteCredentialRequest) -> Result<(), CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.delete_credential(request).await
+        })
+        .await
+    }
+
+    async fn authenticate(
+        &self,
+        request: AuthenticateRequest,
+    ) -> Result<AuthenticateResponse, CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.authenticate(request).await
+        })
+        .await
+    }
+
+    async fn change_password(&self, request: ChangePasswordRequest) -> Result<(), CoreError> {
+        self.call(request, |mut client, request| async move {
+            client.change_password(request).await
+        })
+        .await
+    }
+
+    async fn change_email(&self, request: ChangeEmailRequest) -> Result<(), CoreError> {
+        self.call(request, |mut client, request| async move {
+            client.change_email(request).await
+        })
+        .await
+    }
+
+    async fn confirm_email_change(
+        &self,
+        request: ConfirmEmailChangeRequest,
+    ) -> Result<(), CoreError> {
+        self.call(request, |mut client, request| async move {
+            client.confirm_email_change(request).await
+        })
+        .await
+    }
+
+    async fn close_account(&self, request: CloseAccountRequest) -> Result<(), CoreError> {
+        self.call(request, |mut client, request| async move {
+            client.close_account(request).await
+        })
+        .await
+    }
+
+    async fn export_credential(
+        &self,
+        request: ExportCredentialRequest,
+    ) -> Result<ExportCredentialResponse, CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.export_credential(request).await
+        })
+        .await
+    }
+
+    async fn list_sessions(
+        &self,
+        request: ListSessionsRequest,
+    ) -> Result<ListSessionsResponse, CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.list_sessions(request).await
+        })
+        .await
+    }
+
+    async fn revoke_session(&self, request: RevokeSessionRequest) -> Result<(), CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.revoke_session(request).await
+        })
+        .await
+    }
+
+    async fn create_api_key(
+        &self,
+        request: CreateApiKeyRequest,
+    ) -> Result<CreateApiKeyResponse, CoreError> {
+        self.call(request, |mut client, request| async move {
+            client.create_api_key(request).await
+        })
+        .await
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: ListApiKeysRequest,
+    ) -> Result<ListApiKeysResponse, CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.list_api_keys(request).await
+        })
+        .await
+    }
+
+    async fn revoke_api_key(&self, request: RevokeApiKeyRequest) -> Result<(), CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.revoke_api_key(request).await
+        })
+        .await
+    }
+
+    async fn authenticate_api_key(
+        &self,
+        request: AuthenticateApiKeyRequest,
+    ) -> Result<AuthenticateApiKeyResponse, CoreError> {
+        self.idempotent(request, |mut client, request| async move {
+            client.authenticate_api_key(request).await
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_database::types::Uuid;
+    use std::time::Instant;
+
+    /// Nothing listens there, every call fails as unavailable
+    fn unreachable() -> TonicAuthGateway {
+        TonicAuthGateway::new(AuthGatewayConfig {
+            retries: 2,
+            initial_backoff: Duration::from_millis(100),
+            ..AuthGatewayConfig::new(vec!["http://127.0.0.1:1".to_string()])
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_config_errors() {
+        let result = TonicAuthGateway::new(AuthGatewayConfig::new(vec![]));
+        assert!(matches!(result, Err(AuthGatewayConfigError::NoEndpoints)));
+        let result = TonicAuthGateway::new(AuthGatewayConfig::new(vec!["http://a b".to_string()]));
+        assert!(matches!(
+            result,
+            Err(AuthGatewayConfigError::InvalidEndpoint(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_calls_are_retried() {
+        let gateway = unreachable();
+        let started = Instant::now();
+
+        let result = gateway
+            .authenticate(AuthenticateRequest {
+                session_id: Uuid::new_v4().to_string(),
+            })
+            .await;
+        assert_eq!(result.unwrap_err(), CoreError::Unavailable);
+        // backed off 100ms then 200ms
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_other_calls_are_not_retried() {
+        let gateway = unreachable();
+        let started = Instant::now();
+
+        let result = gateway
+            .create_credential(CreateCredentialsRequest {
+                email: "test@gmail.com".to_string(),
+                password: "123456".to_string(),
+                idempotency_key: String::new(),
+            })
+            .await;
+        assert_eq!(result.unwrap_err(), CoreError::Unavailable);
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_create_credential_with_idempotency_key_is_retried() {
+        let gateway = unreachable();
+        let started = Instant::now();
+
+        let result = gateway
+            .create_credential(CreateCredentialsRequest {
+                email: "test@gmail.com".to_string(),
+                password: "123456".to_string(),
+                idempotency_key: "retry-1".to_string(),
+            })
+            .await;
+        assert_eq!(result.unwrap_err(), CoreError::Unavailable);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() {
+        let config = AuthGatewayConfig::new(vec![
+            "http://auth-1:50051".to_string(),
+            "http://auth-2:50051".to_string(),
+        ]);
+        assert!(TonicAuthGateway::new(config).is_ok());
+
+        let config = AuthGatewayConfig::new(vec!["http://auth 1:50051".to_string()]);
+        assert!(TonicAuthGateway::new(config).is_err());
+    }
+
+    #[test]
+    fn test_retryable() {
+        assert!(retryable(&Status::unavailable("connection refused")));
+        assert!(retryable(&Status::cancelled("Timeout expired")));
+        assert!(!retryable(&Status::unauthenticated("Invalid Credentials")));
+        assert!(!retryable(&Status::invalid_argument("invalid email")));
+    }
+}
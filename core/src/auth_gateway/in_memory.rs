@@ -0,0 +1,506 @@
+use super::AuthGateway;
+use crate::service::CoreError;
+use auth_token::{scope, API_KEY_PREFIX};
+use core_database::types::{DateTime, TimeZone, Utc, Uuid};
+use grpc_interfaces::auth::{
+    ApiKey, AuthenticateApiKeyRequest, AuthenticateApiKeyResponse, AuthenticateRequest,
+    AuthenticateResponse, ChangeEmailRequest, ChangePasswordRequest, CloseAccountRequest,
+    ConfirmEmailChangeRequest, CreateApiKeyRequest, CreateApiKeyResponse, CreateCredentialsRequest,
+    CreateCredentialsResponse, DeleteCredentialRequest, ExportCredentialRequest,
+    ExportCredentialResponse, ListApiKeysRequest, ListApiKeysResponse, ListSessionsRequest,
+    ListSessionsResponse, RevokeApiKeyRequest, RevokeSessionRequest, Session,
+};
+use std::str::FromStr;
+use std::sync::Arc;
+
+const SESSION_LIFETIME_IN_SECONDS: i64 = 60 * 60 * 24;
+
+#[derive(Debug, Clone)]
+struct FakeCredential {
+    id: Uuid,
+    email: String,
+    password: String,
+    active: bool,
+    idempotency_key: Option<String>,
+    roles: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct FakeSession {
+    id: Uuid,
+    credential_id: Uuid,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    active: bool,
+    /// Signed in through an identity provider, which vouches for the account instead of the password
+    identity_provider: bool,
+}
+
+#[derive(Debug, Clone)]
+struct FakeApiKey {
+    id: Uuid,
+    credential_id: Uuid,
+    key: String,
+    api_key: ApiKey,
+    active: bool,
+}
+
+#[derive(Debug, Clone)]
+struct FakeEmailChange {
+    token: String,
+    credential_id: Uuid,
+    new_email: String,
+}
+
+#[derive(Debug, Default)]
+struct FakeAuthState {
+    credentials: Vec<FakeCredential>,
+    sessions: Vec<FakeSession>,
+    api_keys: Vec<FakeApiKey>,
+    email_changes: Vec<FakeEmailChange>,
+}
+
+impl FakeAuthState {
+    /// Active credential behind an active session
+    fn session(&self, session_id: &str) -> Result<(FakeSession, FakeCredential), CoreError> {
+        let session_id = Uuid::from_str(session_id)
+            .map_err(|_| CoreError::InvalidArgument("invalid session id format".to_string()))?;
+        let session = self
+            .sessions
+            .iter()
+            .find(|s| s.id == session_id && s.active && s.expires_at > Utc::now())
+            .ok_or(CoreError::InvalidCredentials)?;
+        let credential = self
+            .credentials
+            .iter()
+            .find(|c| c.id == session.credential_id && c.active)
+            .ok_or(CoreError::InvalidCredentials)?;
+        Ok((session.clone(), credential.clone()))
+    }
+
+    fn check_password(&self, session: &FakeSession, password: &str) -> Result<(), CoreError> {
+        if session.identity_provider {
+            return Ok(());
+        }
+        match self
+            .credentials
+            .iter()
+            .find(|c| c.id == session.credential_id)
+        {
+            Some(credential) if credential.password == password => Ok(()),
+            _ => Err(CoreError::InvalidCredentials),
+        }
+    }
+
+    fn open_session(&mut self, credential_id: Uuid, identity_provider: bool) -> String {
+        let now = Utc::now();
+        let session = FakeSession {
+            id: Uuid::new_v4(),
+            credential_id,
+            created_at: now,
+            expires_at: now + chrono::Duration::seconds(SESSION_LIFETIME_IN_SECONDS),
+            active: true,
+            identity_provider,
+        };
+        let id = session.id.to_string();
+        self.sessions.push(session);
+        id
+    }
+
+    fn credential_mut(&mut self, credential_id: Uuid) -> &mut FakeCredential {
+        self.credentials
+            .iter_mut()
+            .find(|c| c.id == credential_id)
+            .expect("sessions always belong to a credential")
+    }
+
+    fn sessions_of(&self, credential_id: Uuid, current: Uuid) -> Vec<Session> {
+        self.sessions
+            .iter()
+            .filter(|s| s.credential_id == credential_id && s.active)
+            .map(|s| Session {
+                id: s.id.to_string(),
+                created_at: s.created_at.timestamp(),
+                expires_at: s.expires_at.timestamp(),
+                active: s.active,
+                current: s.id == current,
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+/// Auth service kept in memory, for tests that shouldn't need a running one.
+/// Passwords are stored as they are and email changes are confirmed with
+/// `email_change_token` instead of a mail.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryAuthGateway {
+    state: Arc<std::sync::Mutex<FakeAuthState>>,
+}
+
+impl InMemoryAuthGateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a session like signing in through the auth API would, returns its id
+    pub fn sign_in(&self, email: &str, password: &str) -> Result<String, CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let credential = state
+            .credentials
+            .iter()
+            .find(|c| c.email == email && c.active && c.password == password)
+            .ok_or(CoreError::InvalidCredentials)?;
+
+        let credential_id = credential.id;
+        Ok(state.open_session(credential_id, false))
+    }
+
+    /// Opens a session like signing in through an identity provider would, the first
+    /// sign in creates a credential without a password and no user. Returns its id.
+    pub fn sign_in_with_identity(&self, email: &str) -> Result<String, CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let credential_id = match state.credentials.iter().find(|c| c.email == email) {
+            Some(credential) if credential.active => credential.id,
+            Some(_) => return Err(CoreError::InvalidCredentials),
+            None => {
+                let credential = FakeCredential {
+                    id: Uuid::new_v4(),
+                    email: email.to_string(),
+                    password: Uuid::new_v4().to_string(),
+                    active: true,
+                    idempotency_key: None,
+                    roles: vec![],
+                };
+                let id = credential.id;
+                state.credentials.push(credential);
+                id
+            }
+        };
+
+        Ok(state.open_session(credential_id, true))
+    }
+
+    /// Grants a role like the admin CLI would
+    pub fn grant_role(&self, email: &str, role: &str) {
+        let mut state = self.state.lock().unwrap();
+        let credential = state
+            .credentials
+            .iter_mut()
+            .find(|c| c.email == email)
+            .expect("roles are granted to existing credentials");
+        credential.roles.push(role.to_string());
+        credential.roles.sort();
+    }
+
+    /// Token that would have been mailed to confirm the change to `new_email`
+    pub fn email_change_token(&self, new_email: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .email_changes
+            .iter()
+            .rev()
+            .find(|c| c.new_email == new_email)
+            .map(|c| c.token.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthGateway for InMemoryAuthGateway {
+    async fn create_credential(
+        &self,
+        request: CreateCredentialsRequest,
+    ) -> Result<CreateCredentialsResponse, CoreError> {
+        let mut state = self.state.lock().unwrap();
+        if !request.email.contains('@') {
+            return Err(CoreError::InvalidArgument("invalid email".to_string()));
+        }
+        let idempotency_key = Some(request.idempotency_key).filter(|k| !k.is_empty());
+        if let Some(created) = state
+            .credentials
+            .iter()
+            .find(|c| idempotency_key.is_some() && c.idempotency_key == idempotency_key)
+        {
+            if created.email != request.email {
+                return Err(CoreError::InvalidArgument(
+                    "idempotency key was used for another account".to_string(),
+                ));
+            }
+            return Ok(CreateCredentialsResponse {
+                user_id: created.id.to_string(),
+            });
+        }
+        if state.credentials.iter().any(|c| c.email == request.email) {
+            return Err(CoreError::InvalidCredentials);
+        }
+
+        let credential = FakeCredential {
+            id: Uuid::new_v4(),
+            email: request.email,
+            password: request.password,
+            active: true,
+            idempotency_key,
+            roles: vec![],
+        };
+        let user_id = credential.id.to_string();
+        state.credentials.push(credential);
+        Ok(CreateCredentialsResponse { user_id })
+    }
+
+    async fn delete_credential(&self, request: DeleteCredentialRequest) -> Result<(), CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let credential_id = Uuid::from_str(&request.credential_id)
+            .map_err(|_| CoreError::InvalidArgument("invalid credential id format".to_string()))?;
+        if state
+            .sessions
+            .iter()
+            .any(|s| s.credential_id == credential_id)
+        {
+            return Err(CoreError::InvalidArgument(
+                "credential is in use".to_string(),
+            ));
+        }
+
+        state.credentials.retain(|c| c.id != credential_id);
+        Ok(())
+    }
+
+    async fn authenticate(
+        &self,
+        request: AuthenticateRequest,
+    ) -> Result<AuthenticateResponse, CoreError> {
+        let state = self.state.lock().unwrap();
+        let (session, credential) = state.session(&request.session_id)?;
+        Ok(AuthenticateResponse {
+            credential_id: credential.id.to_string(),
+            expires_at: session.expires_at.timestamp(),
+            roles: credential.roles,
+        })
+    }
+
+    async fn change_password(&self, request: ChangePasswordRequest) -> Result<(), CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let (session, credential) = state.session(&request.session_id)?;
+        state.check_password(&session, &request.current_password)?;
+
+        state.credential_mut(credential.id).password = request.new_password;
+        for other in state
+            .sessions
+            .iter_mut()
+            .filter(|s| s.credential_id == credential.id && s.id != session.id)
+        {
+            other.active = false;
+        }
+        Ok(())
+    }
+
+    async fn change_email(&self, request: ChangeEmailRequest) -> Result<(), CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let (session, credential) = state.session(&request.session_id)?;
+        state.check_password(&session, &request.current_password)?;
+        if !request.new_email.contains('@') {
+            return Err(CoreError::InvalidArgument("invalid email".to_string()));
+        }
+
+        state.email_changes.push(FakeEmailChange {
+            token: Uuid::new_v4().to_string(),
+            credential_id: credential.id,
+            new_email: request.new_email,
+        });
+        Ok(())
+    }
+
+    async fn confirm_email_change(
+        &self,
+        request: ConfirmEmailChangeRequest,
+    ) -> Result<(), CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let position = state
+            .email_changes
+            .iter()
+            .position(|c| c.token == request.token)
+            .ok_or(CoreError::InvalidCredentials)?;
+        let change = state.email_changes.remove(position);
+        if state
+            .credentials
+            .iter()
+            .any(|c| c.email == change.new_email)
+        {
+            return Err(CoreError::InvalidCredentials);
+        }
+
+        state.credential_mut(change.credential_id).email = change.new_email;
+        Ok(())
+    }
+
+    async fn close_account(&self, request: CloseAccountRequest) -> Result<(), CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let (session, credential) = state.session(&request.session_id)?;
+        state.check_password(&session, &request.password)?;
+
+        state.credential_mut(credential.id).active = false;
+        for session in state
+            .sessions
+            .iter_mut()
+            .filter(|s| s.credential_id == credential.id)
+        {
+            session.active = false;
+        }
+        for api_key in state
+            .api_keys
+            .iter_mut()
+            .filter(|k| k.credential_id == credential.id)
+        {
+            api_key.active = false;
+        }
+        Ok(())
+    }
+
+    async fn export_credential(
+        &self,
+        request: ExportCredentialRequest,
+    ) -> Result<ExportCredentialResponse, CoreError> {
+        let state = self.state.lock().unwrap();
+        let (session, credential) = state.session(&request.session_id)?;
+        Ok(ExportCredentialResponse {
+            credential_id: credential.id.to_string(),
+            email: credential.email.clone(),
+            sessions: state.sessions_of(credential.id, session.id),
+        })
+    }
+
+    async fn list_sessions(
+        &self,
+        request: ListSessionsRequest,
+    ) -> Result<ListSessionsResponse, CoreError> {
+        let state = self.state.lock().unwrap();
+        let (session, credential) = state.session(&request.session_id)?;
+        Ok(ListSessionsResponse {
+            sessions: state.sessions_of(credential.id, session.id),
+        })
+    }
+
+    async fn revoke_session(&self, request: RevokeSessionRequest) -> Result<(), CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let (_, credential) = state.session(&request.session_id)?;
+        let target_id = Uuid::from_str(&request.target_session_id)
+            .map_err(|_| CoreError::InvalidArgument("invalid session id format".to_string()))?;
+
+        let target = state
+            .sessions
+            .iter_mut()
+            .find(|s| s.id == target_id && s.credential_id == credential.id)
+            .ok_or(CoreError::InvalidCredentials)?;
+        target.active = false;
+        Ok(())
+    }
+
+    async fn create_api_key(
+        &self,
+        request: CreateApiKeyRequest,
+    ) -> Result<CreateApiKeyResponse, CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let (_, credential) = state.session(&request.session_id)?;
+        if request.name.trim().is_empty() {
+            return Err(CoreError::InvalidArgument(
+                "api key name is required".to_string(),
+            ));
+        }
+        if request.scopes.is_empty()
+            || request
+                .scopes
+                .iter()
+                .any(|s| !scope::ALL.contains(&s.as_str()) && !credential.roles.contains(s))
+        {
+            return Err(CoreError::InvalidArgument(
+                "invalid api key scopes".to_string(),
+            ));
+        }
+
+        let id = Uuid::new_v4();
+        let prefix = id.simple().to_string()[..12].to_string();
+        let key = format!("{}{}_{}", API_KEY_PREFIX, prefix, Uuid::new_v4().simple());
+        let api_key = ApiKey {
+            id: id.to_string(),
+            name: request.name,
+            prefix,
+            scopes: request.scopes,
+            created_at: Utc::now().timestamp(),
+            expires_at: request.expires_at,
+            last_used_at: 0,
+        };
+        state.api_keys.push(FakeApiKey {
+            id,
+            credential_id: credential.id,
+            key: key.clone(),
+            api_key: api_key.clone(),
+            active: true,
+        });
+        Ok(CreateApiKeyResponse {
+            key,
+            api_key: Some(api_key),
+        })
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: ListApiKeysRequest,
+    ) -> Result<ListApiKeysResponse, CoreError> {
+        let state = self.state.lock().unwrap();
+        let (_, credential) = state.session(&request.session_id)?;
+        Ok(ListApiKeysResponse {
+            api_keys: state
+                .api_keys
+                .iter()
+                .filter(|k| k.credential_id == credential.id && k.active)
+                .map(|k| k.api_key.clone())
+                .collect(),
+        })
+    }
+
+    async fn revoke_api_key(&self, request: RevokeApiKeyRequest) -> Result<(), CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let (_, credential) = state.session(&request.session_id)?;
+        let api_key_id = Uuid::from_str(&request.api_key_id)
+            .map_err(|_| CoreError::InvalidArgument("invalid api key id format".to_string()))?;
+
+        let api_key = state
+            .api_keys
+            .iter_mut()
+            .find(|k| k.id == api_key_id && k.credential_id == credential.id)
+            .ok_or(CoreError::InvalidCredentials)?;
+        api_key.active = false;
+        Ok(())
+    }
+
+    async fn authenticate_api_key(
+        &self,
+        request: AuthenticateApiKeyRequest,
+    ) -> Result<AuthenticateApiKeyResponse, CoreError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Utc::now();
+        let api_key = state
+            .api_keys
+            .iter_mut()
+            .find(|k| k.key == request.key && k.active)
+            .filter(|k| {
+                k.api_key.expires_at == 0
+                    || Utc.timestamp_opt(k.api_key.expires_at, 0).unwrap() > now
+            })
+            .ok_or(CoreError::InvalidCredentials)?;
+        api_key.api_key.last_used_at = now.timestamp();
+        let api_key = api_key.clone();
+        let roles = state
+            .credentials
+            .iter()
+            .find(|c| c.id == api_key.credential_id)
+            .map(|c| c.roles.clone())
+            .unwrap_or_default();
+
+        Ok(AuthenticateApiKeyResponse {
+            credential_id: api_key.credential_id.to_string(),
+            scopes: api_key.api_key.scopes,
+            roles,
+        })
+    }
+}
@@ -0,0 +1,589 @@
+use crate::repository::{CoreRepository, MovieBatches};
+use core_database::{
+    entities::movies::{CreateMovieDAO, MovieDAO, Upserted},
+    traits::DatabaseError,
+    types::{DateTime, Utc},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Longest title the movies table takes
+const MAX_TITLE_LENGTH: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// With a header row naming the columns `title`, `description` and optionally
+    /// `genres`, `cast` and `available`. Genres and cast are `|` separated.
+    Csv,
+    /// One object per line with `title`, `description` and optionally
+    /// `genres` and `cast` arrays and `available`
+    JsonLines,
+}
+
+impl Format {
+    /// `csv`, `jsonl` or `ndjson`
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::JsonLines),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_extension(path.extension()?.to_str()?)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::JsonLines => "application/x-ndjson",
+        }
+    }
+}
+
+/// A row that won't be imported and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    /// 1-based, CSV headers are line 1
+    pub line: u64,
+    pub message: String,
+}
+
+impl Display for RowError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The file couldn't be read at all
+    Read(std::io::Error),
+    Database(DatabaseError),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Read(e) => write!(f, "Could not read the catalog: {}", e),
+            ImportError::Database(e) => write!(f, "Could not import the catalog: {}", e),
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+impl From<std::io::Error> for ImportError {
+    fn from(value: std::io::Error) -> Self {
+        ImportError::Read(value)
+    }
+}
+
+impl From<DatabaseError> for ImportError {
+    fn from(value: DatabaseError) -> Self {
+        ImportError::Database(value)
+    }
+}
+
+/// The valid rows of a catalog file and what's wrong with the others
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Parsed {
+    pub movies: Vec<CreateMovieDAO>,
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Rows skipped, the valid ones are imported regardless
+    pub errors: Vec<RowError>,
+    /// Nothing was written, the counts are what the import would have done
+    pub dry_run: bool,
+}
+
+/// A row as read, before validation
+#[derive(Deserialize, Debug)]
+struct CsvRow {
+    title: String,
+    description: String,
+    #[serde(default)]
+    genres: String,
+    #[serde(default)]
+    cast: String,
+    #[serde(default)]
+    available: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct JsonRow {
+    title: String,
+    description: String,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    cast: Vec<String>,
+    available: Option<bool>,
+}
+
+/// Trimmed names without the empty ones
+fn names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    names
+        .into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn validate(
+    title: &str,
+    description: &str,
+    genres: Vec<String>,
+    cast_members: Vec<String>,
+    available: bool,
+) -> Result<CreateMovieDAO, String> {
+    let title = title.trim();
+    let description = description.trim();
+    if title.is_empty() {
+        return Err("title is missing".to_string());
+    }
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(format!(
+            "title is longer than {} characters",
+            MAX_TITLE_LENGTH
+        ));
+    }
+    if description.is_empty() {
+        return Err("description is missing".to_string());
+    }
+    Ok(CreateMovieDAO {
+        title: title.to_string(),
+        description: description.to_string(),
+        genres,
+        cast_members,
+        available,
+    })
+}
+
+fn parse_csv_row(row: CsvRow) -> Result<CreateMovieDAO, String> {
+    let available = match row.available.trim().to_lowercase().as_str() {
+        "" | "true" | "yes" | "1" => true,
+        "false" | "no" | "0" => false,
+        other => return Err(format!("available must be true or false, not {:?}", other)),
+    };
+    validate(
+        &row.title,
+        &row.description,
+        names(row.genres.split('|')),
+        names(row.cast.split('|')),
+        available,
+    )
+}
+
+fn parse_json_row(line: &str) -> Result<CreateMovieDAO, String> {
+    let row = serde_json::from_str::<JsonRow>(line).map_err(|e| e.to_string())?;
+    validate(
+        &row.title,
+        &row.description,
+        names(row.genres.iter().map(String::as_str)),
+        names(row.cast.iter().map(String::as_str)),
+        row.available.unwrap_or(true),
+    )
+}
+
+/// A row read from its line, or what's wrong with it
+type Row = (u64, Result<CreateMovieDAO, String>);
+
+fn read_rows<R: Read>(format: Format, source: R) -> Result<Vec<Row>, std::io::Error> {
+    let mut rows = Vec::new();
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(source);
+            let headers = reader.headers()?.clone();
+            for record in reader.records() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) if e.is_io_error() => return Err(e.into()),
+                    Err(e) => {
+                        let line = e.position().map_or(0, |p| p.line());
+                        rows.push((line, Err(e.to_string())));
+                        continue;
+                    }
+                };
+                let line = record.position().map_or(0, |p| p.line());
+                let row = record
+                    .deserialize::<CsvRow>(Some(&headers))
+                    .map_err(|e| e.to_string())
+                    .and_then(parse_csv_row);
+                rows.push((line, row));
+            }
+        }
+        Format::JsonLines => {
+            for (index, line) in BufReader::new(source).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                rows.push((index as u64 + 1, parse_json_row(&line)));
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// Reads and validates every row, titles must be unique within the file
+pub fn parse<R: Read>(format: Format, source: R) -> Result<Parsed, std::io::Error> {
+    let mut parsed = Parsed::default();
+    let mut seen = HashMap::new();
+    for (line, row) in read_rows(format, source)? {
+        let movie = match row {
+            Ok(movie) => movie,
+            Err(message) => {
+                parsed.errors.push(RowError { line, message });
+                continue;
+            }
+        };
+        if let Some(first) = seen.insert(movie.title.clone(), line) {
+            seen.insert(movie.title.clone(), first);
+            parsed.errors.push(RowError {
+                line,
+                message: format!("title {:?} is already on line {}", movie.title, first),
+            });
+            continue;
+        }
+        parsed.movies.push(movie);
+    }
+    Ok(parsed)
+}
+
+/// Upserts the valid movies of the file by title, all of them or none.
+/// A dry run reports what the import would do and leaves the catalog as it is.
+pub async fn import<R: CoreRepository + ?Sized>(
+    repository: &R,
+    format: Format,
+    source: impl Read,
+    dry_run: bool,
+) -> Result<ImportReport, ImportError> {
+    let parsed = parse(format, source)?;
+    let mut report = ImportReport {
+        errors: parsed.errors,
+        dry_run,
+        ..ImportReport::default()
+    };
+    if parsed.movies.is_empty() {
+        return Ok(report);
+    }
+
+    for outcome in repository.upsert_movies(parsed.movies, dry_run).await? {
+        match outcome {
+            Upserted::Inserted(_) => report.inserted += 1,
+            Upserted::Updated(_) => report.updated += 1,
+            Upserted::Unchanged(_) => report.unchanged += 1,
+        }
+    }
+    Ok(report)
+}
+
+/// Columns of exported CSV files, imports read them back and skip the ones they don't know
+const EXPORT_COLUMNS: [&str; 9] = [
+    "id",
+    "title",
+    "description",
+    "genres",
+    "cast",
+    "available",
+    "version",
+    "created_at",
+    "updated_at",
+];
+
+/// The catalog encoded as it's read, see `export`
+pub struct CatalogExport {
+    batches: Box<dyn MovieBatches>,
+    format: Format,
+    started: bool,
+}
+
+impl CatalogExport {
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The next part of the file, `None` once it's complete
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let movies = self.batches.next_batch().await?;
+        let mut chunk = Vec::new();
+        if !self.started && self.format == Format::Csv {
+            write_csv(&mut chunk, EXPORT_COLUMNS);
+        }
+        self.started = true;
+        if movies.is_empty() && chunk.is_empty() {
+            return Ok(None);
+        }
+
+        for movie in movies {
+            match self.format {
+                Format::Csv => write_csv(&mut chunk, csv_record(&movie)),
+                Format::JsonLines => {
+                    chunk.extend(json_line(&movie).to_string().into_bytes());
+                    chunk.push(b'\n');
+                }
+            }
+        }
+        Ok(Some(chunk))
+    }
+}
+
+fn write_csv<const N: usize, T: AsRef<[u8]>>(chunk: &mut Vec<u8>, record: [T; N]) {
+    let mut writer = csv::Writer::from_writer(chunk);
+    writer
+        .write_record(record)
+        .expect("writing to memory can't fail");
+    writer.flush().expect("writing to memory can't fail");
+}
+
+fn csv_record(movie: &MovieDAO) -> [String; 9] {
+    [
+        movie.id.to_string(),
+        movie.title.clone(),
+        movie.description.clone(),
+        movie.genres.join("|"),
+        movie.cast_members.join("|"),
+        movie.available.to_string(),
+        movie.version.to_string(),
+        movie.created_at.to_rfc3339(),
+        movie.updated_at.to_rfc3339(),
+    ]
+}
+
+fn json_line(movie: &MovieDAO) -> serde_json::Value {
+    serde_json::json!({
+        "id": movie.id.to_string(),
+        "title": movie.title,
+        "description": movie.description,
+        "genres": movie.genres,
+        "cast": movie.cast_members,
+        "available": movie.available,
+        "version": movie.version,
+        "created_at": movie.created_at.to_rfc3339(),
+        "updated_at": movie.updated_at.to_rfc3339(),
+    })
+}
+
+/// Reads the movies updated after `updated_since`, or the whole catalog, without holding
+/// all of it in memory. Files it writes can be imported back.
+pub async fn export<R: CoreRepository + ?Sized>(
+    repository: &R,
+    format: Format,
+    updated_since: Option<DateTime<Utc>>,
+) -> Result<CatalogExport, DatabaseError> {
+    Ok(CatalogExport {
+        batches: repository.export_movies(updated_since).await?,
+        format,
+        started: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryCoreRepository;
+    use core_database::query::Query;
+
+    async fn exported(export: &mut CatalogExport) -> String {
+        let mut file = Vec::new();
+        while let Some(chunk) = export.next_chunk().await.unwrap() {
+            file.extend(chunk);
+        }
+        String::from_utf8(file).unwrap()
+    }
+
+    const CSV: &str = "\
+title,description,genres,cast,available
+Alien,In space no one can hear you scream,Horror| Sci-Fi ,Sigourney Weaver,
+Heat,A heist,Crime,Al Pacino|Robert De Niro,no
+,No title,,,
+Ran,,,,
+Alien,Again,,,
+Solaris,An ocean,,,maybe
+";
+
+    #[test]
+    fn test_parse_csv() {
+        let parsed = parse(Format::Csv, CSV.as_bytes()).unwrap();
+
+        assert_eq!(
+            parsed.movies,
+            vec![
+                CreateMovieDAO {
+                    title: "Alien".to_string(),
+                    description: "In space no one can hear you scream".to_string(),
+                    genres: vec!["Horror".to_string(), "Sci-Fi".to_string()],
+                    cast_members: vec!["Sigourney Weaver".to_string()],
+                    available: true,
+                },
+                CreateMovieDAO {
+                    title: "Heat".to_string(),
+                    description: "A heist".to_string(),
+                    genres: vec!["Crime".to_string()],
+                    cast_members: vec!["Al Pacino".to_string(), "Robert De Niro".to_string()],
+                    available: false,
+                },
+            ]
+        );
+        let errors = parsed
+            .errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "line 4: title is missing",
+                "line 5: description is missing",
+                "line 6: title \"Alien\" is already on line 2",
+                "line 7: available must be true or false, not \"maybe\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_lines() {
+        let source = format!(
+            "{}\n\n{}\n{}\n{}\n",
+            r#"{"title": "Ran", "description": "War", "genres": ["Drama", " "], "available": false}"#,
+            r#"{"title": "Heat"}"#,
+            "not json",
+            serde_json::json!({"title": "x".repeat(61), "description": "Long"}),
+        );
+        let parsed = parse(Format::JsonLines, source.as_bytes()).unwrap();
+
+        assert_eq!(
+            parsed.movies,
+            vec![CreateMovieDAO {
+                title: "Ran".to_string(),
+                description: "War".to_string(),
+                genres: vec!["Drama".to_string()],
+                cast_members: vec![],
+                available: false,
+            }]
+        );
+        let lines = parsed.errors.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![3, 4, 5]);
+        assert!(parsed.errors[0].message.contains("description"));
+        assert_eq!(
+            parsed.errors[2].message,
+            "title is longer than 60 characters"
+        );
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            Format::from_path(Path::new("movies.csv")),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_path(Path::new("dump/movies.jsonl")),
+            Some(Format::JsonLines)
+        );
+        assert_eq!(Format::from_path(Path::new("movies.json")), None);
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let repository = InMemoryCoreRepository::new();
+
+        let dry_run = import(&repository, Format::Csv, CSV.as_bytes(), true)
+            .await
+            .unwrap();
+        assert_eq!((dry_run.inserted, dry_run.errors.len()), (2, 4));
+        assert!(dry_run.dry_run);
+        let movies = repository.list_movies(Query::new()).await.unwrap();
+        assert!(movies.is_empty());
+
+        let first = import(&repository, Format::Csv, CSV.as_bytes(), false)
+            .await
+            .unwrap();
+        assert_eq!((first.inserted, first.updated, first.unchanged), (2, 0, 0));
+
+        let changes = r#"{"title": "Heat", "description": "A heist", "genres": ["Crime"], "cast": ["Al Pacino", "Robert De Niro"], "available": false}
+{"title": "Alien", "description": "In space no one can hear you scream", "genres": ["Horror"]}
+{"title": "Ran", "description": "War"}"#;
+        let second = import(&repository, Format::JsonLines, changes.as_bytes(), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            second,
+            ImportReport {
+                inserted: 1,
+                updated: 1,
+                unchanged: 1,
+                errors: vec![],
+                dry_run: false,
+            }
+        );
+        let movies = repository.list_movies(Query::new()).await.unwrap();
+        assert_eq!(movies.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let repository = InMemoryCoreRepository::new();
+        import(&repository, Format::Csv, CSV.as_bytes(), false)
+            .await
+            .unwrap();
+        let since = Utc::now();
+        let solaris =
+            r#"{"title": "Solaris", "description": "An ocean", "genres": ["Sci-Fi", "Drama"]}"#;
+        import(&repository, Format::JsonLines, solaris.as_bytes(), false)
+            .await
+            .unwrap();
+
+        // what's exported imports back as it was
+        let mut everything = export(&repository, Format::Csv, None).await.unwrap();
+        let csv = exported(&mut everything).await;
+        assert!(csv.starts_with("id,title,description,genres,cast,available,version,"));
+        let parsed = parse(Format::Csv, csv.as_bytes()).unwrap();
+        assert!(parsed.errors.is_empty());
+        let mut titles = parsed
+            .movies
+            .iter()
+            .map(|m| m.title.as_str())
+            .collect::<Vec<_>>();
+        titles.sort();
+        assert_eq!(titles, vec!["Alien", "Heat", "Solaris"]);
+        let heat = parsed.movies.iter().find(|m| m.title == "Heat").unwrap();
+        assert_eq!(heat.cast_members, vec!["Al Pacino", "Robert De Niro"]);
+        assert!(!heat.available);
+
+        let mut changes = export(&repository, Format::JsonLines, Some(since))
+            .await
+            .unwrap();
+        let lines = exported(&mut changes).await;
+        let parsed = parse(Format::JsonLines, lines.as_bytes()).unwrap();
+        assert_eq!(parsed.movies.len(), 1);
+        assert_eq!(parsed.movies[0].genres, vec!["Sci-Fi", "Drama"]);
+
+        // headers only when nothing changed
+        let mut nothing = export(&repository, Format::Csv, Some(Utc::now()))
+            .await
+            .unwrap();
+        assert_eq!(exported(&mut nothing).await.lines().count(), 1);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod account;
+pub mod history;
 pub mod movie;
 pub mod user;
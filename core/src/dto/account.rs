@@ -0,0 +1,90 @@
+use crate::dto::user::UserDTO;
+use core_database::types::{DateTime, TimeZone, Utc};
+use grpc_interfaces::auth::{ApiKey, Session};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct SessionDTO {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub active: bool,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_label: Option<String>,
+    pub current: bool,
+}
+
+// grpc sends empty strings for missing values
+fn non_empty(value: String) -> Option<String> {
+    Some(value).filter(|v| !v.is_empty())
+}
+
+impl From<Session> for SessionDTO {
+    fn from(value: Session) -> Self {
+        Self {
+            id: value.id,
+            created_at: Utc
+                .timestamp_opt(value.created_at, 0)
+                .single()
+                .unwrap_or_default(),
+            expires_at: Utc
+                .timestamp_opt(value.expires_at, 0)
+                .single()
+                .unwrap_or_default(),
+            active: value.active,
+            user_agent: non_empty(value.user_agent),
+            ip_address: non_empty(value.ip_address),
+            device_label: non_empty(value.device_label),
+            current: value.current,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyDTO {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// grpc sends 0 for missing timestamps
+fn timestamp(value: i64) -> Option<DateTime<Utc>> {
+    Some(value)
+        .filter(|v| *v != 0)
+        .and_then(|v| Utc.timestamp_opt(v, 0).single())
+}
+
+impl From<ApiKey> for ApiKeyDTO {
+    fn from(value: ApiKey) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            prefix: value.prefix,
+            scopes: value.scopes,
+            created_at: timestamp(value.created_at).unwrap_or_default(),
+            expires_at: timestamp(value.expires_at),
+            last_used_at: timestamp(value.last_used_at),
+        }
+    }
+}
+
+/// A new API key, the plain `key` is only available right after creating it
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyDTO {
+    pub key: String,
+    pub api_key: ApiKeyDTO,
+}
+
+/// Everything we store about an account, as handed out by "download my data".
+#[derive(Debug, Serialize)]
+pub struct AccountExportDTO {
+    pub exported_at: DateTime<Utc>,
+    pub email: String,
+    pub profile: UserDTO,
+    pub sessions: Vec<SessionDTO>,
+}
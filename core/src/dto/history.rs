@@ -0,0 +1,30 @@
+use core_database::{
+    entities::history::HistoryDAO,
+    types::{DateTime, Utc},
+};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntryDTO {
+    /// insert, update, delete or erase
+    pub action: String,
+    /// JSON document of the entity before the change, `None` for inserts
+    pub before: Option<String>,
+    /// JSON document of the entity after the change, `None` for deletes
+    pub after: Option<String>,
+    /// Id of the user who made the change, `None` for the service's own jobs
+    pub actor: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl From<HistoryDAO> for HistoryEntryDTO {
+    fn from(value: HistoryDAO) -> Self {
+        Self {
+            action: value.action,
+            before: value.before_state,
+            after: value.after_state,
+            actor: value.actor,
+            changed_at: value.changed_at,
+        }
+    }
+}
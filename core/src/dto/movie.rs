@@ -5,6 +5,11 @@ pub struct MovieDTO {
     pub id: String,
     pub title: String,
     pub description: String,
+    pub genres: Vec<String>,
+    pub cast_members: Vec<String>,
+    pub available: bool,
+    /// What an update of the movie has to be based on
+    pub version: i64,
 }
 
 impl From<MovieDAO> for MovieDTO {
@@ -13,6 +18,10 @@ impl From<MovieDAO> for MovieDTO {
             id: value.id.to_string(),
             title: value.title,
             description: value.description,
+            genres: value.genres,
+            cast_members: value.cast_members,
+            available: value.available,
+            version: value.version,
         }
     }
 }
@@ -2,13 +2,15 @@ use core_database::{
     entities::users::UserDAO,
     types::{DateTime, Utc, Uuid},
 };
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct UserDTO {
     pub id: Uuid,
     pub name: String,
     pub birthday: DateTime<Utc>,
     pub active: bool,
+    pub version: i64,
 }
 
 impl From<UserDAO> for UserDTO {
@@ -18,6 +20,7 @@ impl From<UserDAO> for UserDTO {
             name: value.name,
             birthday: value.birthday,
             active: value.active,
+            version: value.version,
         }
     }
 }
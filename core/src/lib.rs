@@ -1,2 +1,7 @@
+pub mod auth_gateway;
+pub mod catalog;
 pub mod dto;
+pub mod outbox;
+pub mod repository;
 pub mod service;
+pub mod session_cache;
@@ -0,0 +1,356 @@
+use core_database::{
+    connection::{Pool, Postgres},
+    entities::outbox::{OutboxDAO, OutboxRepository, OutboxRepositoryExt},
+    traits::DatabaseError,
+    types::Utc,
+};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Mutex};
+
+const DEFAULT_BATCH_SIZE: u32 = 100;
+pub const EVENTS_STREAM: &str = "rustflix:events";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SinkError(pub String);
+
+impl Display for SinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sink failed: {}", self.0)
+    }
+}
+
+impl Error for SinkError {}
+
+/// Where relayed events end up. Publishing the same event twice must be harmless,
+/// consumers dedupe on the event id.
+#[async_trait::async_trait]
+pub trait EventSink: Debug + Send + Sync {
+    async fn publish(&self, event: &OutboxDAO) -> Result<(), SinkError>;
+}
+
+/// The event as consumers receive it
+pub fn event_json(event: &OutboxDAO) -> serde_json::Value {
+    serde_json::json!({
+        "id": event.id,
+        "created_at": event.created_at.to_rfc3339(),
+        "aggregate_type": event.aggregate_type,
+        "aggregate_id": event.aggregate_id.to_string(),
+        "event_type": event.event_type,
+        "payload": serde_json::from_str::<serde_json::Value>(&event.payload)
+            .unwrap_or(serde_json::Value::Null),
+    })
+}
+
+/// Appends to a redis stream, one entry per event
+pub struct RedisStreamSink {
+    client: redis::Client,
+    stream: String,
+    connection: Mutex<Option<redis::aio::Connection>>,
+}
+
+impl Debug for RedisStreamSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStreamSink")
+            .field("client", &self.client)
+            .field("stream", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisStreamSink {
+    pub fn new(redis_url: &str, stream: &str) -> redis::RedisResult<Self> {
+        Ok(Self {
+            client: redis::Client::open(redis_url)?,
+            stream: stream.to_string(),
+            connection: Mutex::new(None),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for RedisStreamSink {
+    async fn publish(&self, event: &OutboxDAO) -> Result<(), SinkError> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            let opened = self
+                .client
+                .get_async_connection()
+                .await
+                .map_err(|e| SinkError(e.to_string()))?;
+            *connection = Some(opened);
+        }
+
+        let result: redis::RedisResult<String> = redis::cmd("XADD")
+            .arg(&self.stream)
+            .arg("*")
+            .arg("id")
+            .arg(event.id)
+            .arg("aggregate_type")
+            .arg(&event.aggregate_type)
+            .arg("aggregate_id")
+            .arg(event.aggregate_id.to_string())
+            .arg("event_type")
+            .arg(&event.event_type)
+            .arg("payload")
+            .arg(&event.payload)
+            .arg("created_at")
+            .arg(event.created_at.to_rfc3339())
+            .query_async(connection.as_mut().expect("connection was just opened"))
+            .await;
+
+        result.map(|_| ()).map_err(|e| {
+            // Reconnect on the next event, the connection may be broken
+            *connection = None;
+            SinkError(e.to_string())
+        })
+    }
+}
+
+/// Appends JSON lines to a file
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for FileSink {
+    async fn publish(&self, event: &OutboxDAO) -> Result<(), SinkError> {
+        let mut line = event_json(event).to_string();
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| SinkError(e.to_string()))?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|e| SinkError(e.to_string()))?;
+        file.flush().await.map_err(|e| SinkError(e.to_string()))
+    }
+}
+
+/// Hands events to an in-process receiver, for tests
+#[derive(Debug)]
+pub struct ChannelSink {
+    sender: mpsc::UnboundedSender<OutboxDAO>,
+}
+
+impl ChannelSink {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<OutboxDAO>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { sender }, receiver)
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for ChannelSink {
+    async fn publish(&self, event: &OutboxDAO) -> Result<(), SinkError> {
+        self.sender
+            .send(event.clone())
+            .map_err(|_| SinkError("receiver dropped".to_string()))
+    }
+}
+
+/// Moves events from the outbox table to a sink, at least once and in the order
+/// they were written. An event is only marked published after the sink took it,
+/// when the sink fails the rest of the batch waits for the next run.
+#[derive(Debug, Clone)]
+pub struct OutboxRelay {
+    db: Pool<Postgres>,
+    sink: Arc<dyn EventSink>,
+    batch_size: u32,
+}
+
+impl OutboxRelay {
+    pub fn new(db: Pool<Postgres>, sink: Arc<dyn EventSink>) -> Self {
+        Self {
+            db,
+            sink,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Publishes one batch, returns how many events were delivered.
+    /// Nothing happens while another relay holds the outbox.
+    pub async fn relay_once(&self) -> Result<usize, DatabaseError> {
+        let mut tx = self.db.begin().await?;
+        let Some(events) = OutboxRepository::claim(&mut tx, self.batch_size).await? else {
+            return Ok(0);
+        };
+
+        let mut published = Vec::with_capacity(events.len());
+        for event in &events {
+            match self.sink.publish(event).await {
+                Ok(()) => published.push(event.id),
+                Err(e) => {
+                    eprintln!("could not publish outbox event {}: {}", event.id, e);
+                    break;
+                }
+            }
+        }
+
+        OutboxRepository::mark_published(&mut tx, &published).await?;
+        tx.commit().await?;
+        Ok(published.len())
+    }
+
+    /// Relays forever, draining the outbox every `interval`
+    pub async fn run(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            loop {
+                match self.relay_once().await {
+                    Ok(published) if published == self.batch_size as usize => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        eprintln!("could not relay outbox events: {:?}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes events delivered more than `retention` ago
+    pub async fn purge_published(&self, retention: Duration) -> Result<u64, DatabaseError> {
+        OutboxRepository::purge_published(&self.db, Utc::now() - retention).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_database::types::{TimeZone, Uuid};
+
+    fn event(id: i64) -> OutboxDAO {
+        OutboxDAO {
+            id,
+            created_at: Utc.with_ymd_and_hms(2024, 2, 5, 10, 0, 0).unwrap(),
+            aggregate_type: "movie".to_string(),
+            aggregate_id: Uuid::nil(),
+            event_type: "MovieCreated".to_string(),
+            payload: r#"{"title":"Heat"}"#.to_string(),
+            published_at: None,
+        }
+    }
+
+    #[test]
+    fn test_event_json() {
+        let json = event_json(&event(7));
+        assert_eq!(json["id"], 7);
+        assert_eq!(json["event_type"], "MovieCreated");
+        assert_eq!(json["aggregate_id"], Uuid::nil().to_string());
+        assert_eq!(json["payload"]["title"], "Heat");
+        assert_eq!(json["created_at"], "2024-02-05T10:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn test_channel_sink() {
+        let (sink, mut receiver) = ChannelSink::new();
+        sink.publish(&event(1)).await.unwrap();
+        sink.publish(&event(2)).await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap().id, 1);
+        assert_eq!(receiver.recv().await.unwrap().id, 2);
+
+        drop(receiver);
+        assert!(sink.publish(&event(3)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_sink() {
+        let path = std::env::temp_dir().join(format!("outbox-{}.jsonl", Uuid::new_v4()));
+        let sink = FileSink::new(&path);
+        sink.publish(&event(1)).await.unwrap();
+        sink.publish(&event(2)).await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let ids = content
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+    }
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod integration_tests {
+    use super::*;
+    use core_database::connection::PgPool;
+    use core_database::entities::movies::{
+        CreateMovieDAO, MovieBy, MovieRepository, UpdateMovieDAO,
+    };
+    use core_database::traits::EntityRepository;
+    use core_database::types::Uuid;
+
+    #[tokio::test]
+    async fn test_relay() {
+        dotenv::dotenv().ok();
+        let url =
+            std::env::var("TEST_CORE_DATABASE_URL").expect("TEST_CORE_DATABASE_URL must be set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        let (sink, mut receiver) = ChannelSink::new();
+        let relay = OutboxRelay::new(pool.clone(), Arc::new(sink)).with_batch_size(1000);
+        // whatever earlier tests left behind
+        while relay.relay_once().await.unwrap() > 0 {}
+        while receiver.try_recv().is_ok() {}
+
+        let movie = MovieRepository::insert(
+            &pool,
+            CreateMovieDAO {
+                title: format!("Relay {}", Uuid::new_v4()),
+                description: "first".to_string(),
+                genres: vec![],
+                cast_members: vec![],
+                available: true,
+            },
+        )
+        .await
+        .unwrap();
+        MovieRepository::update(
+            &pool,
+            MovieBy::Id(movie.id),
+            UpdateMovieDAO {
+                title: movie.title.clone(),
+                description: "second".to_string(),
+                version: movie.version,
+            },
+        )
+        .await
+        .unwrap();
+        MovieRepository::delete(&pool, MovieBy::Id(movie.id))
+            .await
+            .unwrap();
+
+        assert_eq!(relay.relay_once().await.unwrap(), 3);
+        let mut types = vec![];
+        while let Ok(event) = receiver.try_recv() {
+            assert_eq!(event.aggregate_id, movie.id);
+            types.push(event.event_type);
+        }
+        assert_eq!(types, vec!["MovieCreated", "MovieUpdated", "MovieDeleted"]);
+
+        // delivered once
+        assert_eq!(relay.relay_once().await.unwrap(), 0);
+    }
+}
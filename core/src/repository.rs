@@ -0,0 +1,515 @@
+use core_database::{
+    connection::Postgres,
+    entities::history::{
+        current_actor, Audited, EntityChange, HistoryDAO, HistoryRepository, HistoryWhere,
+    },
+    entities::movies::{
+        CreateMovieDAO, MovieBy, MovieDAO, MovieExport, MovieField, MovieRepository,
+        MovieRepositoryExt, UpdateMovieDAO, Upserted,
+    },
+    entities::users::{
+        CreateUserDAO, UpdateUserDAO, UserBy, UserDAO, UserField, UserRepository, UserRepositoryExt,
+    },
+    query::Query,
+    router::DatabaseRouter,
+    traits::{version_conflict, DatabaseError, EntityRepository},
+    types::{DateTime, Utc, Uuid},
+};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Movies read in batches, see `CoreRepository::export_movies`
+#[async_trait::async_trait]
+pub trait MovieBatches: Send {
+    /// Empty once every movie was read
+    async fn next_batch(&mut self) -> Result<Vec<MovieDAO>, DatabaseError>;
+}
+
+/// Storage `Core` works with
+#[async_trait::async_trait]
+pub trait CoreRepository: Send + Sync {
+    async fn insert_user(&self, user: CreateUserDAO) -> Result<UserDAO, DatabaseError>;
+    async fn get_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError>;
+    /// Fails with `VersionConflict` when the user moved past `update.version`
+    async fn update_user(
+        &self,
+        user_id: Uuid,
+        update: UpdateUserDAO,
+    ) -> Result<UserDAO, DatabaseError>;
+    async fn deactivate_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError>;
+    /// Undoes `deactivate_user`, fails with `NotFound` unless the user is deactivated
+    async fn reactivate_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError>;
+    /// Permanently removes users deactivated before `before`, returns how many were erased
+    async fn erase_deactivated_users(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError>;
+    async fn list_users(&self, query: Query<UserField>) -> Result<Vec<UserDAO>, DatabaseError>;
+    async fn list_movies(&self, query: Query<MovieField>) -> Result<Vec<MovieDAO>, DatabaseError>;
+    async fn try_get_movie(&self, movie_id: Uuid) -> Result<Option<MovieDAO>, DatabaseError>;
+    /// Fails with `VersionConflict` when the movie moved past `update.version`
+    async fn update_movie(
+        &self,
+        movie_id: Uuid,
+        update: UpdateMovieDAO,
+    ) -> Result<MovieDAO, DatabaseError>;
+    /// Changes made to the movie, oldest first
+    async fn movie_history(&self, movie_id: Uuid) -> Result<Vec<HistoryDAO>, DatabaseError>;
+    /// `MovieRepositoryExt::upsert_many`, with `dry_run` reporting what it would do without doing it
+    async fn upsert_movies(
+        &self,
+        movies: Vec<CreateMovieDAO>,
+        dry_run: bool,
+    ) -> Result<Vec<Upserted>, DatabaseError>;
+    /// Movies updated after `updated_since`, or all of them, least recently updated first,
+    /// read a batch at a time from a single snapshot of the catalog
+    async fn export_movies(
+        &self,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<Box<dyn MovieBatches>, DatabaseError>;
+}
+
+/// Backed by the core database through its entity repositories, reads go to replicas
+#[derive(Debug, Clone)]
+pub struct PgCoreRepository {
+    db: DatabaseRouter,
+}
+
+impl PgCoreRepository {
+    pub fn new(db: DatabaseRouter) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait::async_trait]
+impl CoreRepository for PgCoreRepository {
+    async fn insert_user(&self, user: CreateUserDAO) -> Result<UserDAO, DatabaseError> {
+        UserRepository::insert(self.db.write(), user).await
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError> {
+        UserRepository::get(self.db.read(), UserBy::Id(user_id)).await
+    }
+
+    async fn update_user(
+        &self,
+        user_id: Uuid,
+        update: UpdateUserDAO,
+    ) -> Result<UserDAO, DatabaseError> {
+        UserRepository::update(self.db.write(), UserBy::Id(user_id), update).await
+    }
+
+    async fn deactivate_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError> {
+        UserRepository::delete(self.db.write(), UserBy::Id(user_id)).await
+    }
+
+    async fn reactivate_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError> {
+        UserRepository::reactivate(self.db.write(), UserBy::Id(user_id)).await
+    }
+
+    async fn erase_deactivated_users(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        UserRepository::erase_deactivated(self.db.write(), before).await
+    }
+
+    async fn list_users(&self, query: Query<UserField>) -> Result<Vec<UserDAO>, DatabaseError> {
+        UserRepository::get_all(self.db.read(), query).await
+    }
+
+    async fn list_movies(&self, query: Query<MovieField>) -> Result<Vec<MovieDAO>, DatabaseError> {
+        MovieRepository::get_all(self.db.read(), query).await
+    }
+
+    async fn try_get_movie(&self, movie_id: Uuid) -> Result<Option<MovieDAO>, DatabaseError> {
+        MovieRepository::try_get(self.db.read(), MovieBy::Id(movie_id)).await
+    }
+
+    async fn update_movie(
+        &self,
+        movie_id: Uuid,
+        update: UpdateMovieDAO,
+    ) -> Result<MovieDAO, DatabaseError> {
+        MovieRepository::update(self.db.write(), MovieBy::Id(movie_id), update).await
+    }
+
+    async fn movie_history(&self, movie_id: Uuid) -> Result<Vec<HistoryDAO>, DatabaseError> {
+        let of_movie = HistoryWhere::Entity {
+            entity_type: MovieDAO::ENTITY.to_string(),
+            entity_id: movie_id,
+        };
+        HistoryRepository::get_all(self.db.read(), of_movie).await
+    }
+
+    async fn upsert_movies(
+        &self,
+        movies: Vec<CreateMovieDAO>,
+        dry_run: bool,
+    ) -> Result<Vec<Upserted>, DatabaseError> {
+        let mut tx = self.db.write().begin().await?;
+        let upserted = MovieRepository::upsert_many(&mut *tx, movies).await?;
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(upserted)
+    }
+
+    async fn export_movies(
+        &self,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<Box<dyn MovieBatches>, DatabaseError> {
+        let export = MovieRepository::export(self.db.read(), updated_since).await?;
+        Ok(Box::new(PgMovieBatches(export)))
+    }
+}
+
+struct PgMovieBatches(MovieExport<Postgres>);
+
+#[async_trait::async_trait]
+impl MovieBatches for PgMovieBatches {
+    async fn next_batch(&mut self) -> Result<Vec<MovieDAO>, DatabaseError> {
+        MovieRepository::next_exported(&mut self.0).await
+    }
+}
+
+/// Small, so tests read exports over several batches
+const IN_MEMORY_BATCH_SIZE: usize = 2;
+
+#[derive(Debug, Default, Clone)]
+struct InMemoryState {
+    /// With when they were deactivated
+    users: Vec<(UserDAO, Option<DateTime<Utc>>)>,
+    movies: Vec<MovieDAO>,
+    history: Vec<HistoryDAO>,
+    last_history_id: i64,
+    unavailable: bool,
+    failing_deactivations: bool,
+}
+
+impl InMemoryState {
+    /// Same as `HistoryRepository::record`
+    fn record(&mut self, change: EntityChange) {
+        self.last_history_id += 1;
+        self.history.push(HistoryDAO {
+            id: self.last_history_id,
+            entity_type: change.entity_type.to_string(),
+            entity_id: change.entity_id,
+            action: change.action.as_str().to_string(),
+            before_state: change.before.map(|state| state.to_string()),
+            after_state: change.after.map(|state| state.to_string()),
+            actor: current_actor(),
+            changed_at: Utc::now(),
+        });
+    }
+}
+
+/// Keeps everything in memory, for tests and demos without a database
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryCoreRepository {
+    state: Arc<Mutex<InMemoryState>>,
+}
+
+impl InMemoryCoreRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_movies(movies: Vec<MovieDAO>) -> Self {
+        let repository = Self::default();
+        repository.state.lock().unwrap().movies = movies;
+        repository
+    }
+
+    /// Makes every call fail as if the database couldn't be reached, e.g. to test recovery
+    pub fn set_available(&self, available: bool) {
+        self.state.lock().unwrap().unavailable = !available;
+    }
+
+    /// Makes `deactivate_user` alone fail, e.g. to test what closing an account leaves behind
+    pub fn set_deactivations_failing(&self, failing: bool) {
+        self.state.lock().unwrap().failing_deactivations = failing;
+    }
+
+    fn state(&self) -> Result<MutexGuard<'_, InMemoryState>, DatabaseError> {
+        let state = self.state.lock().unwrap();
+        if state.unavailable {
+            return Err(DatabaseError::ConnectionNotAvailable);
+        }
+        Ok(state)
+    }
+}
+
+/// Same error `UserRepository` reports for a missing user
+fn not_found_user(user_id: Uuid) -> DatabaseError {
+    DatabaseError::NotFound {
+        entity: "User".to_string(),
+        key: format!("{:?}", UserBy::Id(user_id)),
+    }
+}
+
+/// Same errors the repositories report when an update is based on `expected` but the row is at `current`
+fn check_version<K: std::fmt::Debug>(
+    entity: &str,
+    key: K,
+    expected: i64,
+    current: i64,
+) -> Result<(), DatabaseError> {
+    if expected == current {
+        return Ok(());
+    }
+    Err(version_conflict(entity, &key)(Some(current)))
+}
+
+#[async_trait::async_trait]
+impl CoreRepository for InMemoryCoreRepository {
+    async fn insert_user(&self, user: CreateUserDAO) -> Result<UserDAO, DatabaseError> {
+        let mut state = self.state()?;
+        if state.users.iter().any(|(u, _)| u.id == user.id) {
+            return Err(DatabaseError::UniqueViolation {
+                constraint: "users_pkey".to_string(),
+            });
+        }
+
+        let now = Utc::now();
+        let user = UserDAO {
+            id: user.id,
+            name: user.name,
+            birthday: user.birthday,
+            active: true,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+        };
+        state.users.push((user.clone(), None));
+        state.record(EntityChange::insert(&user));
+        Ok(user)
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError> {
+        let state = self.state()?;
+        state
+            .users
+            .iter()
+            .find(|(u, _)| u.id == user_id)
+            .map(|(u, _)| u.clone())
+            .ok_or_else(|| not_found_user(user_id))
+    }
+
+    async fn update_user(
+        &self,
+        user_id: Uuid,
+        update: UpdateUserDAO,
+    ) -> Result<UserDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let (user, _) = state
+            .users
+            .iter_mut()
+            .find(|(u, _)| u.id == user_id)
+            .ok_or_else(|| not_found_user(user_id))?;
+
+        check_version("User", UserBy::Id(user_id), update.version, user.version)?;
+        let before = user.clone();
+        user.name = update.name;
+        user.version += 1;
+        user.updated_at = Utc::now();
+        let user = user.clone();
+        state.record(EntityChange::update(&before, &user));
+        Ok(user)
+    }
+
+    async fn deactivate_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError> {
+        let mut state = self.state()?;
+        if state.failing_deactivations {
+            return Err(DatabaseError::ConnectionNotAvailable);
+        }
+        let (user, deactivated_at) = state
+            .users
+            .iter_mut()
+            .find(|(u, _)| u.id == user_id)
+            .ok_or_else(|| not_found_user(user_id))?;
+
+        let before = user.clone();
+        user.active = false;
+        user.version += 1;
+        user.updated_at = Utc::now();
+        *deactivated_at = Some(user.updated_at);
+        let user = user.clone();
+        state.record(EntityChange::update(&before, &user));
+        Ok(user)
+    }
+
+    async fn reactivate_user(&self, user_id: Uuid) -> Result<UserDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let (user, deactivated_at) = state
+            .users
+            .iter_mut()
+            .find(|(u, _)| u.id == user_id && !u.active)
+            .ok_or_else(|| not_found_user(user_id))?;
+
+        let before = user.clone();
+        user.active = true;
+        user.version += 1;
+        user.updated_at = Utc::now();
+        *deactivated_at = None;
+        let user = user.clone();
+        state.record(EntityChange::update(&before, &user));
+        Ok(user)
+    }
+
+    async fn erase_deactivated_users(&self, before: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        let mut state = self.state()?;
+        let (kept, erased): (Vec<_>, Vec<_>) = std::mem::take(&mut state.users)
+            .into_iter()
+            .partition(|(u, deactivated_at)| {
+                u.active || deactivated_at.is_none_or(|t| t >= before)
+            });
+        state.users = kept;
+        for (user, _) in &erased {
+            state
+                .history
+                .retain(|c| c.entity_type != UserDAO::ENTITY || c.entity_id != user.id);
+            state.record(EntityChange::erase::<UserDAO>(user.id));
+        }
+        Ok(erased.len() as u64)
+    }
+
+    async fn list_users(&self, query: Query<UserField>) -> Result<Vec<UserDAO>, DatabaseError> {
+        let state = self.state()?;
+        query.apply(state.users.iter().map(|(u, _)| u.clone()), UserDAO::field)
+    }
+
+    async fn list_movies(&self, query: Query<MovieField>) -> Result<Vec<MovieDAO>, DatabaseError> {
+        let state = self.state()?;
+        query.apply(state.movies.iter().cloned(), MovieDAO::field)
+    }
+
+    async fn try_get_movie(&self, movie_id: Uuid) -> Result<Option<MovieDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state.movies.iter().find(|m| m.id == movie_id).cloned())
+    }
+
+    async fn update_movie(
+        &self,
+        movie_id: Uuid,
+        update: UpdateMovieDAO,
+    ) -> Result<MovieDAO, DatabaseError> {
+        let mut state = self.state()?;
+        let movie = state
+            .movies
+            .iter_mut()
+            .find(|m| m.id == movie_id)
+            .ok_or_else(|| DatabaseError::NotFound {
+                entity: "Movie".to_string(),
+                key: format!("{:?}", MovieBy::Id(movie_id)),
+            })?;
+
+        check_version(
+            "Movie",
+            MovieBy::Id(movie_id),
+            update.version,
+            movie.version,
+        )?;
+        let before = movie.clone();
+        movie.title = update.title;
+        movie.description = update.description;
+        movie.version += 1;
+        movie.updated_at = Utc::now();
+        let movie = movie.clone();
+        state.record(EntityChange::update(&before, &movie));
+        Ok(movie)
+    }
+
+    async fn movie_history(&self, movie_id: Uuid) -> Result<Vec<HistoryDAO>, DatabaseError> {
+        let state = self.state()?;
+        Ok(state
+            .history
+            .iter()
+            .filter(|c| c.entity_type == MovieDAO::ENTITY && c.entity_id == movie_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn upsert_movies(
+        &self,
+        movies: Vec<CreateMovieDAO>,
+        dry_run: bool,
+    ) -> Result<Vec<Upserted>, DatabaseError> {
+        let mut state = self.state()?;
+        let mut titles = HashSet::new();
+        if !movies.iter().all(|m| titles.insert(m.title.clone())) {
+            return Err(DatabaseError::UniqueViolation {
+                constraint: "movies_title_key".to_string(),
+            });
+        }
+
+        // changes are made to a copy, kept unless it's a dry run
+        let mut draft = state.clone();
+        let mut upserted = Vec::with_capacity(movies.len());
+        for movie in movies {
+            let now = Utc::now();
+            let Some(found) = draft.movies.iter_mut().find(|m| m.title == movie.title) else {
+                let inserted = MovieDAO {
+                    id: Uuid::new_v4(),
+                    title: movie.title,
+                    description: movie.description,
+                    genres: movie.genres,
+                    cast_members: movie.cast_members,
+                    available: movie.available,
+                    version: 1,
+                    created_at: now,
+                    updated_at: now,
+                };
+                draft.movies.push(inserted.clone());
+                draft.record(EntityChange::insert(&inserted));
+                upserted.push(Upserted::Inserted(inserted));
+                continue;
+            };
+
+            let unchanged = found.description == movie.description
+                && found.genres == movie.genres
+                && found.cast_members == movie.cast_members
+                && found.available == movie.available;
+            if unchanged {
+                upserted.push(Upserted::Unchanged(found.clone()));
+                continue;
+            }
+            let before = found.clone();
+            found.description = movie.description;
+            found.genres = movie.genres;
+            found.cast_members = movie.cast_members;
+            found.available = movie.available;
+            found.version += 1;
+            found.updated_at = now;
+            let updated = found.clone();
+            draft.record(EntityChange::update(&before, &updated));
+            upserted.push(Upserted::Updated(updated));
+        }
+        if !dry_run {
+            *state = draft;
+        }
+        Ok(upserted)
+    }
+
+    async fn export_movies(
+        &self,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<Box<dyn MovieBatches>, DatabaseError> {
+        let state = self.state()?;
+        let mut movies = state
+            .movies
+            .iter()
+            .filter(|m| updated_since.is_none_or(|since| m.updated_at > since))
+            .cloned()
+            .collect::<Vec<_>>();
+        movies.sort_by_key(|m| (m.updated_at, m.id));
+        Ok(Box::new(InMemoryMovieBatches(movies)))
+    }
+}
+
+/// What's left of an export, in order
+struct InMemoryMovieBatches(Vec<MovieDAO>);
+
+#[async_trait::async_trait]
+impl MovieBatches for InMemoryMovieBatches {
+    async fn next_batch(&mut self) -> Result<Vec<MovieDAO>, DatabaseError> {
+        let size = self.0.len().min(IN_MEMORY_BATCH_SIZE);
+        Ok(self.0.drain(..size).collect())
+    }
+}
@@ -1,32 +1,57 @@
+use crate::auth_gateway::{AuthGateway, TonicAuthGateway};
+use crate::catalog::{self, CatalogExport, Format};
+use crate::dto::account::{AccountExportDTO, ApiKeyDTO, CreatedApiKeyDTO, SessionDTO};
+use crate::dto::history::HistoryEntryDTO;
 use crate::dto::movie::MovieDTO;
 use crate::dto::user::UserDTO;
-use core_database::entities::movies::{MovieBy, MovieRepository, MoviesWhere};
+use crate::repository::{CoreRepository, PgCoreRepository};
+use crate::session_cache::SessionCache;
+use auth_token::{scope, Revocation, TokenError, TokenType, TokenVerifier};
 use core_database::{
-    connection::{Pool, Postgres},
-    entities::users::{UserDAO, UserRepository},
-    traits::{DatabaseError, EntityRepository},
-    types::{DateTime, Utc, Uuid},
+    entities::history::acting_as,
+    entities::movies::{MovieField, UpdateMovieDAO},
+    entities::users::{CreateUserDAO, UpdateUserDAO, UserField},
+    query::Query,
+    router::read_your_writes,
+    traits::DatabaseError,
+    types::{DateTime, TimeZone, Utc, Uuid},
 };
 use grpc_interfaces::auth::{
-    auth_client::AuthClient, AuthenticateRequest, CreateCredentialsRequest,
+    AuthenticateApiKeyRequest, AuthenticateRequest, ChangeEmailRequest, ChangePasswordRequest,
+    CloseAccountRequest, ConfirmEmailChangeRequest, CreateApiKeyRequest, CreateCredentialsRequest,
+    DeleteCredentialRequest, ExportCredentialRequest, ListApiKeysRequest, ListSessionsRequest,
+    RevokeApiKeyRequest, RevokeSessionRequest,
 };
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tonic::transport::Channel;
-use tonic::{Code, Request, Status};
+use std::time::Duration;
+use tonic::{Code, Status};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum CoreError {
     InternalServerError,
 
     InvalidCredentials,
 
+    Forbidden,
+
     InvalidArgument(String),
 
     NotFound(String),
+
+    AlreadyExists(String),
+
+    /// Lost to a concurrent change, retrying may succeed
+    Conflict,
+
+    /// The update was based on an older version, retrying it as is won't succeed
+    VersionConflict {
+        current_version: i64,
+    },
+
+    Unavailable,
 }
 
 impl Display for CoreError {
@@ -34,8 +59,15 @@ impl Display for CoreError {
         match self {
             CoreError::InternalServerError => write!(f, "Internal Server Error"),
             CoreError::InvalidCredentials => write!(f, "Invalid Credentials"),
+            CoreError::Forbidden => write!(f, "Forbidden"),
             CoreError::InvalidArgument(msg) => write!(f, "Invalid Argument: {:?}", msg),
             CoreError::NotFound(entity) => write!(f, "{:?} Not Found", entity),
+            CoreError::AlreadyExists(what) => write!(f, "{:?} Already Exists", what),
+            CoreError::Conflict => write!(f, "Conflict"),
+            CoreError::VersionConflict { current_version } => {
+                write!(f, "Changed since, now at version {}", current_version)
+            }
+            CoreError::Unavailable => write!(f, "Service Unavailable"),
         }
     }
 }
@@ -46,63 +78,224 @@ impl From<Status> for CoreError {
     fn from(value: Status) -> Self {
         match value.code() {
             Code::Unauthenticated => CoreError::InvalidCredentials,
+            Code::PermissionDenied => CoreError::Forbidden,
             Code::InvalidArgument => CoreError::InvalidArgument(value.message().to_string()),
+            Code::NotFound => CoreError::NotFound(value.message().to_string()),
+            Code::AlreadyExists => CoreError::AlreadyExists(value.message().to_string()),
+            Code::Aborted => CoreError::Conflict,
+            Code::Unavailable => CoreError::Unavailable,
             _ => CoreError::InternalServerError,
         }
     }
 }
 
+impl From<TokenError> for CoreError {
+    fn from(value: TokenError) -> Self {
+        match value {
+            TokenError::Invalid(_) => CoreError::InvalidCredentials,
+            TokenError::Fetch(e) => {
+                eprintln!("could not fetch token signing keys: {}", e);
+                CoreError::InternalServerError
+            }
+        }
+    }
+}
+
 impl From<DatabaseError> for CoreError {
     fn from(value: DatabaseError) -> Self {
         match value {
-            DatabaseError::NotFound(entity) => CoreError::NotFound(entity),
-            _ => CoreError::InternalServerError,
+            DatabaseError::NotFound { entity, .. } => CoreError::NotFound(entity),
+            DatabaseError::UniqueViolation { constraint } => CoreError::AlreadyExists(constraint),
+            e @ (DatabaseError::ForeignKeyViolation { .. }
+            | DatabaseError::CheckViolation { .. }
+            | DatabaseError::InvalidQuery(_)) => CoreError::InvalidArgument(e.to_string()),
+            DatabaseError::SerializationFailure | DatabaseError::Deadlock => CoreError::Conflict,
+            DatabaseError::VersionConflict {
+                current_version, ..
+            } => CoreError::VersionConflict { current_version },
+            DatabaseError::CommunicationError
+            | DatabaseError::ConnectionFailed
+            | DatabaseError::ConnectionNotAvailable => CoreError::Unavailable,
+            e => {
+                eprintln!("Database failed: {:?}", e);
+                CoreError::InternalServerError
+            }
         }
     }
 }
 
-#[derive(Clone)]
-pub struct Core {
-    auth_client: Arc<Mutex<AuthClient<Channel>>>,
-    db: Pool<Postgres>,
+/// Authenticated caller, only obtainable through the `Core::authenticate*` methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    user_id: Uuid,
+    /// `None` for API keys, they act for the user without a session
+    session_id: Option<String>,
+    /// `None` when the user is signed in and can do everything
+    scopes: Option<Vec<String>>,
+    /// Privileges the credential holds, however it signed in
+    roles: Vec<String>,
 }
 
-impl Core {
-    // TODO - Refactor database to be generic
-    pub async fn new(auth_grpc_port: String, db: Pool<Postgres>) -> Self {
-        let auth_client = AuthClient::connect(auth_grpc_port)
-            .await
-            .expect("Could not connect to auth grpc client");
+impl Principal {
+    pub fn user_id(&self) -> Uuid {
+        self.user_id
+    }
+
+    /// Session behind the request, managing the account requires one
+    pub fn session_id(&self) -> Result<&str, CoreError> {
+        self.session_id.as_deref().ok_or(CoreError::Forbidden)
+    }
+
+    pub fn require_scope(&self, scope: &str) -> Result<(), CoreError> {
+        match &self.scopes {
+            Some(scopes) if !scopes.iter().any(|s| s == scope) => Err(CoreError::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// For scopes over other users' data, which signing in doesn't grant.
+    /// The credential needs the role of the same name, API keys the scope as well.
+    pub fn require_granted_scope(&self, scope: &str) -> Result<(), CoreError> {
+        if !self.roles.iter().any(|r| r == scope) {
+            return Err(CoreError::Forbidden);
+        }
+        self.require_scope(scope)
+    }
+}
 
+/// Business logic of the catalog, talks to the auth service through `A`
+/// and stores users and movies through `R`.
+pub struct Core<A = TonicAuthGateway, R = PgCoreRepository> {
+    auth: Arc<A>,
+    repository: Arc<R>,
+    tokens: Option<Arc<TokenVerifier>>,
+    sessions: Option<Arc<SessionCache>>,
+}
+
+impl<A, R> Clone for Core<A, R> {
+    fn clone(&self) -> Self {
         Self {
-            auth_client: Arc::new(Mutex::new(auth_client)),
-            db,
+            auth: self.auth.clone(),
+            repository: self.repository.clone(),
+            tokens: self.tokens.clone(),
+            sessions: self.sessions.clone(),
         }
     }
 }
 
-impl Core {
-    pub async fn authenticate(&self, session_id: String) -> Result<(), CoreError> {
-        let mut auth_client = self.auth_client.lock().await;
-        let request = AuthenticateRequest { session_id };
+impl<A: AuthGateway, R: CoreRepository> Core<A, R> {
+    pub fn new(auth: A, repository: R) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            repository: Arc::new(repository),
+            tokens: None,
+            sessions: None,
+        }
+    }
 
-        auth_client
-            .authenticate(Request::new(request))
-            .await
-            .map(|r| r.into_inner())
-            .map_err(CoreError::from)?;
-        Ok(())
+    /// Enables `authenticate_token`
+    pub fn with_token_verifier(mut self, verifier: TokenVerifier) -> Self {
+        self.tokens = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Lets `authenticate` trust recently validated sessions without asking auth
+    pub fn with_session_cache(mut self, sessions: Arc<SessionCache>) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+}
+
+impl<A: AuthGateway, R: CoreRepository> Core<A, R> {
+    /// Validates the session with the auth service, which also keeps it alive,
+    /// unless the session cache has seen it recently
+    pub async fn authenticate(&self, session_id: String) -> Result<Principal, CoreError> {
+        if let Some((user_id, roles)) = self.sessions.as_ref().and_then(|s| s.get(&session_id)) {
+            return Ok(Principal {
+                user_id,
+                session_id: Some(session_id),
+                scopes: None,
+                roles,
+            });
+        }
+
+        let started = self.sessions.as_ref().map(|s| s.generation());
+        let request = AuthenticateRequest {
+            session_id: session_id.clone(),
+        };
+        let response = self.auth.authenticate(request).await?;
+        let user_id = parse_user_id(&response.credential_id)?;
+
+        if let (Some(sessions), Some(started)) = (&self.sessions, started) {
+            if let Some(expires_at) = Utc.timestamp_opt(response.expires_at, 0).single() {
+                sessions.insert(
+                    session_id.clone(),
+                    user_id,
+                    response.roles.clone(),
+                    expires_at,
+                    started,
+                );
+            }
+        }
+
+        Ok(Principal {
+            user_id,
+            session_id: Some(session_id),
+            scopes: None,
+            roles: response.roles,
+        })
+    }
+
+    /// Forgets cached sessions right away, auth announces the revocation to
+    /// every instance but this one shouldn't have to wait for it
+    fn forget_sessions(&self, revocation: Revocation) {
+        if let Some(sessions) = &self.sessions {
+            sessions.invalidate(&revocation);
+        }
+    }
+
+    /// Validates an access token locally against the auth service keys.
+    /// Revoking the session doesn't invalidate its access tokens, they only live a few minutes,
+    /// the same goes for the roles they carry.
+    pub async fn authenticate_token(&self, token: &str) -> Result<Principal, CoreError> {
+        let Some(verifier) = &self.tokens else {
+            return Err(CoreError::InvalidCredentials);
+        };
+        let claims = verifier.verify(token, TokenType::Access).await?;
+
+        Ok(Principal {
+            user_id: parse_user_id(&claims.sub)?,
+            session_id: Some(claims.sid),
+            scopes: None,
+            roles: claims.roles,
+        })
+    }
+
+    /// Validates a partner or service API key with the auth service
+    pub async fn authenticate_api_key(&self, key: String) -> Result<Principal, CoreError> {
+        let response = self
+            .auth
+            .authenticate_api_key(AuthenticateApiKeyRequest { key })
+            .await?;
+
+        Ok(Principal {
+            user_id: parse_user_id(&response.credential_id)?,
+            session_id: None,
+            scopes: Some(response.scopes),
+            roles: response.roles,
+        })
     }
 
     pub async fn list_movies(
         &self,
-        session_id: String,
-        offset: u32,
-        limit: u32,
+        principal: &Principal,
+        query: Query<MovieField>,
     ) -> Result<Vec<MovieDTO>, CoreError> {
-        self.authenticate(session_id).await?;
+        principal.require_scope(scope::MOVIES_READ)?;
 
-        let movies = MovieRepository::get_all(&self.db, MoviesWhere::Page { offset, limit })
+        let movies = self
+            .repository
+            .list_movies(query)
             .await?
             .into_iter()
             .map(MovieDTO::from)
@@ -111,51 +304,1240 @@ impl Core {
         Ok(movies)
     }
 
+    pub async fn list_users(
+        &self,
+        principal: &Principal,
+        query: Query<UserField>,
+    ) -> Result<Vec<UserDTO>, CoreError> {
+        principal.require_granted_scope(scope::USERS_READ)?;
+
+        let users = self
+            .repository
+            .list_users(query)
+            .await?
+            .into_iter()
+            .map(UserDTO::from)
+            .collect::<Vec<UserDTO>>();
+
+        Ok(users)
+    }
+
     pub async fn movie(
         &self,
-        session_id: String,
+        principal: &Principal,
         movie_id: Uuid,
     ) -> Result<Option<MovieDTO>, CoreError> {
-        self.authenticate(session_id).await?;
-        Ok(MovieRepository::try_get(&self.db, MovieBy::Id(movie_id))
+        principal.require_scope(scope::MOVIES_READ)?;
+        Ok(self
+            .repository
+            .try_get_movie(movie_id)
             .await?
             .map(MovieDTO::from))
     }
 
+    /// Replaces the title and description of the movie, as long as it is still at `version`
+    pub async fn update_movie(
+        &self,
+        principal: &Principal,
+        movie_id: Uuid,
+        title: String,
+        description: String,
+        version: i64,
+    ) -> Result<MovieDTO, CoreError> {
+        principal.require_granted_scope(scope::MOVIES_WRITE)?;
+        let update = UpdateMovieDAO {
+            title,
+            description,
+            version,
+        };
+        let update = self.repository.update_movie(movie_id, update);
+        let movie = acting_as(principal.user_id().to_string(), update).await?;
+        Ok(movie.into())
+    }
+
+    /// The whole catalog, or the movies updated after `updated_since`, encoded as it's read
+    pub async fn export_catalog(
+        &self,
+        principal: &Principal,
+        format: Format,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<CatalogExport, CoreError> {
+        principal.require_scope(scope::MOVIES_READ)?;
+        Ok(catalog::export(self.repository.as_ref(), format, updated_since).await?)
+    }
+
+    /// Every change made to the movie, oldest first, with who made it
+    pub async fn movie_history(
+        &self,
+        principal: &Principal,
+        movie_id: Uuid,
+    ) -> Result<Vec<HistoryEntryDTO>, CoreError> {
+        principal.require_granted_scope(scope::ADMIN)?;
+        Ok(self
+            .repository
+            .movie_history(movie_id)
+            .await?
+            .into_iter()
+            .map(HistoryEntryDTO::from)
+            .collect())
+    }
+
+    /// Creates the credential in auth, then the user. When the user can't be stored the
+    /// credential is deleted again, so a half created account doesn't hold the email.
+    /// Retries carrying the same `idempotency_key` return the account the first attempt created.
     pub async fn create_account(
         &self,
         email: String,
         password: String,
         name: String,
         birthday: DateTime<Utc>,
+        idempotency_key: Option<String>,
     ) -> Result<UserDTO, CoreError> {
-        let mut auth_client = self.auth_client.lock().await;
+        let retryable = idempotency_key.is_some();
+        let request = CreateCredentialsRequest {
+            email,
+            password,
+            idempotency_key: idempotency_key.unwrap_or_default(),
+        };
+        let response = self.auth.create_credential(request).await?;
 
-        let request = CreateCredentialsRequest { email, password };
+        let id = parse_user_id(&response.user_id)?;
+        // the user a previous attempt stored may not have reached the replicas yet
+        if retryable {
+            match read_your_writes(self.repository.get_user(id)).await {
+                Ok(user) => return Ok(user.into()),
+                Err(DatabaseError::NotFound { .. }) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
 
-        let response = auth_client
-            .create_credential(Request::new(request))
-            .await
-            .map(|r| r.into_inner())
-            .map_err(CoreError::from)?;
+        // the user being created makes the change, there is no one signed in yet
+        let insert = self
+            .repository
+            .insert_user(CreateUserDAO { id, birthday, name });
+        let result = acting_as(id.to_string(), insert).await;
 
-        let id = Uuid::from_str(&response.user_id).map_err(|e| {
-            eprintln!("{:?}", e);
+        match result {
+            Ok(user) => Ok(user.into()),
+            Err(e) => {
+                // A concurrent retry stored it first, the credential is in use.
+                if retryable {
+                    if let Ok(user) = read_your_writes(self.repository.get_user(id)).await {
+                        return Ok(user.into());
+                    }
+                }
+
+                eprintln!("user {} could not be stored: {:?}", id, e);
+                let request = DeleteCredentialRequest {
+                    credential_id: id.to_string(),
+                };
+                if let Err(compensation) = self.auth.delete_credential(request).await {
+                    eprintln!("credential {} left without a user: {:?}", id, compensation);
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Creates the user of an account that signed up through an identity provider.
+    /// Auth only creates the credential on the first provider sign in, the web front end
+    /// asks for the profile and completes the sign up with the session it got.
+    pub async fn complete_sign_up(
+        &self,
+        principal: &Principal,
+        name: String,
+        birthday: DateTime<Utc>,
+    ) -> Result<UserDTO, CoreError> {
+        principal.session_id()?;
+        let id = principal.user_id();
+        let insert = self
+            .repository
+            .insert_user(CreateUserDAO { id, birthday, name });
+        let user = acting_as(id.to_string(), insert).await?;
+        Ok(user.into())
+    }
+
+    /// Renames the signed in user, as long as the profile is still at `version`
+    pub async fn update_profile(
+        &self,
+        principal: &Principal,
+        name: String,
+        version: i64,
+    ) -> Result<UserDTO, CoreError> {
+        principal.session_id()?;
+        let update = UpdateUserDAO { name, version };
+        let update = self.repository.update_user(principal.user_id(), update);
+        let user = acting_as(principal.user_id().to_string(), update).await?;
+        Ok(user.into())
+    }
+
+    pub async fn change_password(
+        &self,
+        principal: &Principal,
+        current_password: String,
+        new_password: String,
+    ) -> Result<(), CoreError> {
+        let request = ChangePasswordRequest {
+            session_id: principal.session_id()?.to_string(),
+            current_password,
+            new_password,
+        };
+
+        self.auth.change_password(request).await?;
+        self.forget_sessions(Revocation::Credential(principal.user_id.to_string()));
+        Ok(())
+    }
+
+    /// Starts an email change, the new address only replaces the current one
+    /// after it is confirmed with `confirm_email_change`.
+    pub async fn change_email(
+        &self,
+        principal: &Principal,
+        current_password: String,
+        new_email: String,
+    ) -> Result<(), CoreError> {
+        let request = ChangeEmailRequest {
+            session_id: principal.session_id()?.to_string(),
+            current_password,
+            new_email,
+        };
+
+        self.auth.change_email(request).await?;
+        Ok(())
+    }
+
+    pub async fn confirm_email_change(&self, token: String) -> Result<(), CoreError> {
+        let request = ConfirmEmailChangeRequest { token };
+
+        self.auth.confirm_email_change(request).await?;
+        Ok(())
+    }
+
+    /// Deactivates the user, then closes its credential in auth, which signs it out everywhere.
+    /// When auth refuses, e.g. a wrong password, the user is reactivated, so a failed closure
+    /// never leaves an active user with a dead credential. The account is erased for good by
+    /// `erase_closed_accounts` after the grace period.
+    pub async fn close_account(
+        &self,
+        principal: &Principal,
+        password: String,
+    ) -> Result<(), CoreError> {
+        let user_id = principal.user_id;
+        let request = CloseAccountRequest {
+            session_id: principal.session_id()?.to_string(),
+            password,
+        };
+
+        let deactivate = self.repository.deactivate_user(user_id);
+        // provider sign ups that were never completed have no user to deactivate
+        let deactivated = match acting_as(user_id.to_string(), deactivate).await {
+            Ok(_) => true,
+            Err(DatabaseError::NotFound { .. }) => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Err(e) = self.auth.close_account(request).await {
+            if !deactivated {
+                return Err(e);
+            }
+            let reactivate = self.repository.reactivate_user(user_id);
+            if let Err(compensation) = acting_as(user_id.to_string(), reactivate).await {
+                eprintln!(
+                    "user {} left deactivated with an open credential: {:?}",
+                    user_id, compensation
+                );
+            }
+            return Err(e);
+        }
+        self.forget_sessions(Revocation::Credential(user_id.to_string()));
+
+        Ok(())
+    }
+
+    pub async fn export_account(
+        &self,
+        principal: &Principal,
+    ) -> Result<AccountExportDTO, CoreError> {
+        let request = ExportCredentialRequest {
+            session_id: principal.session_id()?.to_string(),
+        };
+        let user = self.repository.get_user(principal.user_id).await?;
+        let credential = self.auth.export_credential(request).await?;
+
+        Ok(AccountExportDTO {
+            exported_at: Utc::now(),
+            email: credential.email,
+            profile: user.into(),
+            sessions: credential
+                .sessions
+                .into_iter()
+                .map(SessionDTO::from)
+                .collect(),
+        })
+    }
+
+    /// Active sessions of the user, the one making the request is flagged as `current`
+    pub async fn my_sessions(&self, principal: &Principal) -> Result<Vec<SessionDTO>, CoreError> {
+        let request = ListSessionsRequest {
+            session_id: principal.session_id()?.to_string(),
+        };
+        let response = self.auth.list_sessions(request).await?;
+
+        Ok(response
+            .sessions
+            .into_iter()
+            .map(SessionDTO::from)
+            .collect())
+    }
+
+    pub async fn revoke_session(
+        &self,
+        principal: &Principal,
+        target_session_id: String,
+    ) -> Result<(), CoreError> {
+        let request = RevokeSessionRequest {
+            session_id: principal.session_id()?.to_string(),
+            target_session_id: target_session_id.clone(),
+        };
+
+        self.auth.revoke_session(request).await?;
+        self.forget_sessions(Revocation::Session(target_session_id));
+        Ok(())
+    }
+
+    /// The plain key is part of the response, it can't be retrieved again later
+    pub async fn create_api_key(
+        &self,
+        principal: &Principal,
+        name: String,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<CreatedApiKeyDTO, CoreError> {
+        let request = CreateApiKeyRequest {
+            session_id: principal.session_id()?.to_string(),
+            name,
+            scopes,
+            expires_at: expires_at.map_or(0, |t| t.timestamp()),
+        };
+        let response = self.auth.create_api_key(request).await?;
+
+        let api_key = response.api_key.ok_or_else(|| {
+            eprintln!("auth service created an api key without returning it");
             CoreError::InternalServerError
         })?;
+        Ok(CreatedApiKeyDTO {
+            key: response.key,
+            api_key: ApiKeyDTO::from(api_key),
+        })
+    }
+
+    pub async fn my_api_keys(&self, principal: &Principal) -> Result<Vec<ApiKeyDTO>, CoreError> {
+        let request = ListApiKeysRequest {
+            session_id: principal.session_id()?.to_string(),
+        };
+        let response = self.auth.list_api_keys(request).await?;
+
+        Ok(response.api_keys.into_iter().map(ApiKeyDTO::from).collect())
+    }
+
+    pub async fn revoke_api_key(
+        &self,
+        principal: &Principal,
+        api_key_id: String,
+    ) -> Result<(), CoreError> {
+        let request = RevokeApiKeyRequest {
+            session_id: principal.session_id()?.to_string(),
+            api_key_id,
+        };
+
+        self.auth.revoke_api_key(request).await?;
+        Ok(())
+    }
+
+    /// Hard deletes users whose account was closed more than `grace` ago
+    pub async fn erase_closed_accounts(&self, grace: Duration) -> Result<u64, CoreError> {
+        Ok(self
+            .repository
+            .erase_deactivated_users(Utc::now() - grace)
+            .await?)
+    }
+}
+
+fn parse_user_id(id: &str) -> Result<Uuid, CoreError> {
+    Uuid::from_str(id).map_err(|e| {
+        eprintln!("{:?}", e);
+        CoreError::InternalServerError
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth_gateway::InMemoryAuthGateway;
+    use crate::repository::InMemoryCoreRepository;
+    use auth_token::{Claims, ISSUER};
+    use core_database::entities::movies::MovieDAO;
+    use core_database::query::{Direction, Filter};
+    use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+
+    const KEY: &str = include_str!("../../auth-token/tests/fixtures/ed25519_test_key.pem");
+    const JWKS: &str = include_str!("../../auth-token/tests/fixtures/ed25519_test_jwks.json");
+
+    type TestCore = Core<InMemoryAuthGateway, InMemoryCoreRepository>;
+
+    fn movies() -> Vec<MovieDAO> {
+        ["Alien", "Heat", "Ran"]
+            .into_iter()
+            .map(|title| MovieDAO {
+                id: Uuid::new_v4(),
+                title: title.to_string(),
+                description: format!("{} description", title),
+                genres: vec!["Drama".to_string()],
+                cast_members: vec![],
+                available: true,
+                version: 1,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            })
+            .collect()
+    }
+
+    fn setup() -> (TestCore, InMemoryAuthGateway, Vec<MovieDAO>) {
+        let auth = InMemoryAuthGateway::new();
+        let movies = movies();
+        let core = Core::new(
+            auth.clone(),
+            InMemoryCoreRepository::with_movies(movies.clone()),
+        );
+        (core, auth, movies)
+    }
+
+    /// Creates an account and signs in with it
+    async fn sign_up(core: &TestCore, auth: &InMemoryAuthGateway, email: &str) -> Principal {
+        core.create_account(
+            email.to_string(),
+            "123456".to_string(),
+            "Test".to_string(),
+            Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let session_id = auth.sign_in(email, "123456").unwrap();
+        core.authenticate(session_id).await.unwrap()
+    }
+
+    fn access_token(principal: &Principal, typ: TokenType) -> String {
+        let claims = Claims {
+            iss: ISSUER.to_string(),
+            sub: principal.user_id.to_string(),
+            sid: principal.session_id().unwrap().to_string(),
+            jti: Uuid::new_v4().to_string(),
+            typ,
+            iat: get_current_timestamp(),
+            exp: get_current_timestamp() + 60,
+            roles: principal.roles.clone(),
+        };
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some("test-key".to_string());
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_ed_pem(KEY.as_bytes()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "auth@gmail.com").await;
+        assert!(principal.session_id().is_ok());
+        assert!(principal.require_scope(scope::MOVIES_READ).is_ok());
+
+        let result = core
+            .authenticate(Uuid::new_v4().to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+
+        let result = core
+            .authenticate("not a uuid".to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(result, CoreError::InvalidArgument(_)));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_with_session_cache() {
+        let (core, auth, _) = setup();
+        let sessions = Arc::new(SessionCache::new(10, Duration::from_secs(60)));
+        let core = core.with_session_cache(sessions.clone());
+        let principal = sign_up(&core, &auth, "cache@gmail.com").await;
+        let other = auth.sign_in("cache@gmail.com", "123456").unwrap();
+
+        // validated by auth once, then from the cache
+        let cached = core.authenticate(other.clone()).await.unwrap();
+        assert_eq!(cached.user_id(), principal.user_id());
+        assert_eq!(core.authenticate(other.clone()).await.unwrap(), cached);
+        let stats = sessions.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 2));
+
+        core.revoke_session(&principal, other.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            core.authenticate(other).await.unwrap_err(),
+            CoreError::InvalidCredentials
+        );
+
+        // revoked elsewhere, e.g. announced by auth
+        sessions.invalidate(&Revocation::Credential(principal.user_id().to_string()));
+        assert_eq!(sessions.stats().entries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_token() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "token@gmail.com").await;
+        let token = access_token(&principal, TokenType::Access);
+
+        // tokens are rejected until a verifier is configured
+        let result = core.authenticate_token(&token).await.unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+
+        let core =
+            core.with_token_verifier(TokenVerifier::new(serde_json::from_str(JWKS).unwrap()));
+        assert_eq!(core.authenticate_token(&token).await.unwrap(), principal);
+
+        let refresh = access_token(&principal, TokenType::Refresh);
+        let result = core.authenticate_token(&refresh).await.unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_api_key() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "key@gmail.com").await;
+        let created = core
+            .create_api_key(
+                &principal,
+                "catalog sync".to_string(),
+                vec![scope::MOVIES_READ.to_string()],
+                None,
+            )
+            .await
+            .unwrap();
+
+        let api_principal = core
+            .authenticate_api_key(created.key.clone())
+            .await
+            .unwrap();
+        assert_eq!(api_principal.user_id(), principal.user_id());
+        assert_eq!(api_principal.session_id(), Err(CoreError::Forbidden));
+        assert!(api_principal.require_scope(scope::MOVIES_READ).is_ok());
+
+        let result = core
+            .authenticate_api_key("rfx_unknown_key".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+    }
+
+    #[tokio::test]
+    async fn test_list_movies() {
+        let (core, auth, movies) = setup();
+        let principal = sign_up(&core, &auth, "movies@gmail.com").await;
+
+        let by_title = Query::new().order_by(MovieField::Title, Direction::Desc);
+        let page = core
+            .list_movies(&principal, by_title.clone().page(1, 5))
+            .await
+            .unwrap();
+        let titles = page.iter().map(|m| m.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            vec![movies[1].title.as_str(), movies[0].title.as_str()]
+        );
+
+        let found = core
+            .list_movies(
+                &principal,
+                by_title
+                    .clone()
+                    .filter(Filter::contains(MovieField::Description, "RAN ")),
+            )
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, movies[2].id.to_string());
+
+        // filters must fit the field
+        let result = core
+            .list_movies(
+                &principal,
+                Query::new().filter(Filter::eq(MovieField::Title, true)),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(result, CoreError::InvalidArgument(_)));
+
+        // api keys need the scope
+        let unscoped = Principal {
+            scopes: Some(vec![]),
+            ..principal
+        };
+        let result = core.list_movies(&unscoped, by_title).await.unwrap_err();
+        assert_eq!(result, CoreError::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_list_users() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "users@gmail.com").await;
+        let other = sign_up(&core, &auth, "other.users@gmail.com").await;
+        core.close_account(&other, "123456".to_string())
+            .await
+            .unwrap();
+
+        // signing in isn't enough to see other users
+        let active = Query::new().filter(Filter::eq(UserField::Active, true));
+        let result = core
+            .list_users(&principal, active.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::Forbidden);
+
+        auth.grant_role("users@gmail.com", scope::USERS_READ);
+        let operator = core
+            .authenticate(principal.session_id().unwrap().to_string())
+            .await
+            .unwrap();
+        let users = core.list_users(&operator, active.clone()).await.unwrap();
+        assert_eq!(
+            users.iter().map(|u| u.id).collect::<Vec<_>>(),
+            vec![principal.user_id()]
+        );
+
+        // api keys need the scope on top of the role
+        let api_key = Principal {
+            session_id: None,
+            scopes: Some(vec![scope::MOVIES_READ.to_string()]),
+            ..operator
+        };
+        let result = core.list_users(&api_key, active).await.unwrap_err();
+        assert_eq!(result, CoreError::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_movie() {
+        let (core, auth, movies) = setup();
+        let principal = sign_up(&core, &auth, "movie@gmail.com").await;
 
-        let user = UserRepository::insert(
-            &self.db,
-            UserDAO {
-                id,
+        let movie = core.movie(&principal, movies[0].id).await.unwrap().unwrap();
+        assert_eq!(movie.title, movies[0].title);
+        assert!(core
+            .movie(&principal, Uuid::new_v4())
+            .await
+            .unwrap()
+            .is_none());
+
+        let unscoped = Principal {
+            scopes: Some(vec![]),
+            ..principal
+        };
+        let result = core.movie(&unscoped, movies[0].id).await.unwrap_err();
+        assert_eq!(result, CoreError::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_update_movie() {
+        let (core, auth, movies) = setup();
+        let principal = sign_up(&core, &auth, "update.movie@gmail.com").await;
+        let update = |principal: Principal, title: &'static str, version: i64| {
+            let core = core.clone();
+            let id = movies[0].id;
+            async move {
+                core.update_movie(
+                    &principal,
+                    id,
+                    title.to_string(),
+                    "new".to_string(),
+                    version,
+                )
+                .await
+            }
+        };
+
+        // signing in doesn't make an editor
+        let result = update(principal.clone(), "Aliens", 1).await.unwrap_err();
+        assert_eq!(result, CoreError::Forbidden);
+
+        auth.grant_role("update.movie@gmail.com", scope::MOVIES_WRITE);
+        let editor = core
+            .authenticate(principal.session_id().unwrap().to_string())
+            .await
+            .unwrap();
+        let updated = update(editor.clone(), "Aliens", 1).await.unwrap();
+        assert_eq!(updated.title, "Aliens");
+        assert_eq!(updated.version, 2);
+
+        // the other editor started from version 1 as well
+        let result = update(editor.clone(), "Alien 3", 1).await.unwrap_err();
+        assert_eq!(result, CoreError::VersionConflict { current_version: 2 });
+        let movie = core.movie(&editor, movies[0].id).await.unwrap().unwrap();
+        assert_eq!(movie.title, "Aliens");
+
+        let result = core
+            .update_movie(
+                &editor,
+                Uuid::new_v4(),
+                "Gone".to_string(),
+                "".to_string(),
+                1,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::NotFound("Movie".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_movie_history() {
+        let (core, auth, movies) = setup();
+        let principal = sign_up(&core, &auth, "history@gmail.com").await;
+        let session_id = principal.session_id().unwrap().to_string();
+        auth.grant_role("history@gmail.com", scope::MOVIES_WRITE);
+        let editor = core.authenticate(session_id.clone()).await.unwrap();
+        core.update_movie(
+            &editor,
+            movies[1].id,
+            "Heat 2".to_string(),
+            "sequel".to_string(),
+            1,
+        )
+        .await
+        .unwrap();
+
+        // only admins see who changed what
+        let result = core.movie_history(&editor, movies[1].id).await.unwrap_err();
+        assert_eq!(result, CoreError::Forbidden);
+
+        // admins signed in any way
+        auth.grant_role("history@gmail.com", scope::ADMIN);
+        let admin = core.authenticate(session_id).await.unwrap();
+        let core =
+            core.with_token_verifier(TokenVerifier::new(serde_json::from_str(JWKS).unwrap()));
+        let token = access_token(&admin, TokenType::Access);
+        let from_token = core.authenticate_token(&token).await.unwrap();
+        assert!(core.movie_history(&from_token, movies[1].id).await.is_ok());
+        let created = core
+            .create_api_key(
+                &admin,
+                "audit".to_string(),
+                vec![scope::ADMIN.to_string()],
+                None,
+            )
+            .await
+            .unwrap();
+        let from_api_key = core.authenticate_api_key(created.key).await.unwrap();
+        assert!(core
+            .movie_history(&from_api_key, movies[1].id)
+            .await
+            .is_ok());
+
+        let history = core.movie_history(&admin, movies[1].id).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].action, "update");
+        assert_eq!(history[0].actor, Some(principal.user_id().to_string()));
+        let state = |json: &Option<String>| -> serde_json::Value {
+            serde_json::from_str(json.as_deref().unwrap()).unwrap()
+        };
+        assert_eq!(state(&history[0].before)["title"], "Heat");
+        assert_eq!(state(&history[0].after)["title"], "Heat 2");
+        assert_eq!(state(&history[0].after)["version"], 2);
+
+        assert!(core
+            .movie_history(&admin, movies[0].id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_export_catalog() {
+        let (core, auth, movies) = setup();
+        let principal = sign_up(&core, &auth, "export@gmail.com").await;
+
+        let mut export = core
+            .export_catalog(&principal, Format::JsonLines, None)
+            .await
+            .unwrap();
+        let mut lines = 0;
+        while let Some(chunk) = export.next_chunk().await.unwrap() {
+            lines += chunk.iter().filter(|b| **b == b'\n').count();
+        }
+        assert_eq!(lines, movies.len());
+
+        let no_scopes = Principal {
+            session_id: None,
+            scopes: Some(vec![]),
+            ..principal
+        };
+        let result = core.export_catalog(&no_scopes, Format::Csv, None).await;
+        assert!(matches!(result, Err(CoreError::Forbidden)));
+    }
+
+    #[tokio::test]
+    async fn test_update_profile() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "profile@gmail.com").await;
+
+        let user = core
+            .update_profile(&principal, "Renamed".to_string(), 1)
+            .await
+            .unwrap();
+        assert_eq!(user.name, "Renamed");
+        assert_eq!(user.version, 2);
+
+        let result = core
+            .update_profile(&principal, "Stale".to_string(), 1)
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::VersionConflict { current_version: 2 });
+
+        // api keys act without a session
+        let api_key = Principal {
+            session_id: None,
+            scopes: Some(vec![scope::MOVIES_READ.to_string()]),
+            ..principal
+        };
+        let result = core
+            .update_profile(&api_key, "Key".to_string(), 2)
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_create_account() {
+        let (core, _, _) = setup();
+        let birthday = Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap();
+
+        let user = core
+            .create_account(
+                "create@gmail.com".to_string(),
+                "123456".to_string(),
+                "Test".to_string(),
                 birthday,
-                active: true,
-                name,
-            },
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(user.name, "Test");
+        assert_eq!(user.birthday, birthday);
+        assert!(user.active);
+
+        // the email is taken
+        let result = core
+            .create_account(
+                "create@gmail.com".to_string(),
+                "123456".to_string(),
+                "Other".to_string(),
+                birthday,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+
+        let result = core
+            .create_account(
+                "create.com".to_string(),
+                "123456".to_string(),
+                "Test".to_string(),
+                birthday,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(result, CoreError::InvalidArgument(_)));
+    }
+
+    #[tokio::test]
+    async fn test_create_account_compensation_and_retry() {
+        let auth = InMemoryAuthGateway::new();
+        let repository = InMemoryCoreRepository::new();
+        let core = Core::new(auth.clone(), repository.clone());
+        let birthday = Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap();
+        let create = |key: Option<&str>| {
+            core.create_account(
+                "saga@gmail.com".to_string(),
+                "123456".to_string(),
+                "Test".to_string(),
+                birthday,
+                key.map(str::to_string),
+            )
+        };
+
+        // the user can't be stored, the credential is deleted again
+        repository.set_available(false);
+        let result = create(None).await.unwrap_err();
+        assert_eq!(result, CoreError::Unavailable);
+        assert_eq!(
+            auth.sign_in("saga@gmail.com", "123456").unwrap_err(),
+            CoreError::InvalidCredentials
+        );
+
+        // retries with the same key create the account once
+        repository.set_available(true);
+        let user = create(Some("retry-1")).await.unwrap();
+        assert_eq!(create(Some("retry-1")).await.unwrap().id, user.id);
+        assert_eq!(
+            create(Some("retry-2")).await.unwrap_err(),
+            CoreError::InvalidCredentials
+        );
+        assert!(auth.sign_in("saga@gmail.com", "123456").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "password@gmail.com").await;
+
+        let result = core
+            .change_password(&principal, "wrong".to_string(), "654321".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+
+        core.change_password(&principal, "123456".to_string(), "654321".to_string())
+            .await
+            .unwrap();
+        assert!(auth.sign_in("password@gmail.com", "123456").is_err());
+        assert!(auth.sign_in("password@gmail.com", "654321").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_change_and_confirm_email() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "old@gmail.com").await;
+
+        let result = core
+            .change_email(&principal, "wrong".to_string(), "new@gmail.com".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+
+        core.change_email(
+            &principal,
+            "123456".to_string(),
+            "new@gmail.com".to_string(),
         )
         .await
-        .map_err(CoreError::from)?;
+        .unwrap();
+        // nothing changes until the new address is confirmed
+        assert!(auth.sign_in("old@gmail.com", "123456").is_ok());
 
-        Ok(user.into())
+        let result = core
+            .confirm_email_change("unknown token".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+
+        let token = auth.email_change_token("new@gmail.com").unwrap();
+        core.confirm_email_change(token).await.unwrap();
+        assert!(auth.sign_in("old@gmail.com", "123456").is_err());
+        assert!(auth.sign_in("new@gmail.com", "123456").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_close_account() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "close@gmail.com").await;
+
+        let result = core
+            .close_account(&principal, "wrong".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+
+        core.close_account(&principal, "123456".to_string())
+            .await
+            .unwrap();
+        let result = core
+            .authenticate(principal.session_id().unwrap().to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+        assert!(auth.sign_in("close@gmail.com", "123456").is_err());
+        let user = core.repository.get_user(principal.user_id()).await.unwrap();
+        assert!(!user.active);
+    }
+
+    #[tokio::test]
+    async fn test_close_account_is_all_or_nothing() {
+        let auth = InMemoryAuthGateway::new();
+        let repository = InMemoryCoreRepository::new();
+        let core = Core::new(auth.clone(), repository.clone());
+        let principal = sign_up(&core, &auth, "half@gmail.com").await;
+
+        // the user can't be deactivated, the credential is left alone
+        repository.set_deactivations_failing(true);
+        let result = core
+            .close_account(&principal, "123456".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::Unavailable);
+        assert!(auth.sign_in("half@gmail.com", "123456").is_ok());
+        assert!(core
+            .authenticate(principal.session_id().unwrap().to_string())
+            .await
+            .is_ok());
+
+        // auth refuses, the user deactivated first is reactivated
+        repository.set_deactivations_failing(false);
+        let result = core
+            .close_account(&principal, "wrong".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(result, CoreError::InvalidCredentials);
+        let user = repository.get_user(principal.user_id()).await.unwrap();
+        assert!(user.active);
+        // created, deactivated and reactivated
+        assert_eq!(user.version, 3);
+    }
+
+    #[tokio::test]
+    async fn test_identity_sign_up_to_close_account() {
+        let (core, auth, _) = setup();
+        let session_id = auth.sign_in_with_identity("oidc@gmail.com").unwrap();
+        let principal = core.authenticate(session_id.clone()).await.unwrap();
+
+        // only the credential exists until the sign up is completed
+        let result = core.export_account(&principal).await.unwrap_err();
+        assert_eq!(result, CoreError::NotFound("User".to_string()));
+        let birthday = Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap();
+        let user = core
+            .complete_sign_up(&principal, "Oidc".to_string(), birthday)
+            .await
+            .unwrap();
+        assert_eq!(user.id, principal.user_id());
+        let result = core
+            .complete_sign_up(&principal, "Again".to_string(), birthday)
+            .await
+            .unwrap_err();
+        assert!(matches!(result, CoreError::AlreadyExists(_)));
+        assert_eq!(
+            core.export_account(&principal).await.unwrap().email,
+            "oidc@gmail.com"
+        );
+
+        // the provider vouches for the account, there is no password to give
+        core.close_account(&principal, String::new()).await.unwrap();
+        let user = core.repository.get_user(principal.user_id()).await.unwrap();
+        assert!(!user.active);
+        assert_eq!(
+            core.authenticate(session_id).await.unwrap_err(),
+            CoreError::InvalidCredentials
+        );
+        assert!(auth.sign_in_with_identity("oidc@gmail.com").is_err());
+
+        // closing works before the sign up was completed too
+        let session_id = auth.sign_in_with_identity("incomplete@gmail.com").unwrap();
+        let principal = core.authenticate(session_id).await.unwrap();
+        core.close_account(&principal, String::new()).await.unwrap();
+        assert!(auth.sign_in_with_identity("incomplete@gmail.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_account() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "export@gmail.com").await;
+
+        let export = core.export_account(&principal).await.unwrap();
+        assert_eq!(export.email, "export@gmail.com");
+        assert_eq!(export.profile.id, principal.user_id());
+        assert_eq!(export.sessions.len(), 1);
+        assert!(export.sessions[0].current);
+    }
+
+    #[tokio::test]
+    async fn test_my_sessions_and_revoke_session() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "sessions@gmail.com").await;
+        let other = auth.sign_in("sessions@gmail.com", "123456").unwrap();
+
+        let sessions = core.my_sessions(&principal).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        let current = sessions.iter().find(|s| s.current).unwrap();
+        assert_eq!(current.id, principal.session_id().unwrap());
+
+        core.revoke_session(&principal, other.clone())
+            .await
+            .unwrap();
+        assert_eq!(core.my_sessions(&principal).await.unwrap().len(), 1);
+        assert_eq!(
+            core.authenticate(other).await.unwrap_err(),
+            CoreError::InvalidCredentials
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "keys@gmail.com").await;
+
+        let result = core
+            .create_api_key(
+                &principal,
+                "sync".to_string(),
+                vec!["movies:write".to_string()],
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(result, CoreError::InvalidArgument(_)));
+
+        let expires_at = Utc::now() + chrono::Duration::days(30);
+        let created = core
+            .create_api_key(
+                &principal,
+                "sync".to_string(),
+                vec![scope::MOVIES_READ.to_string()],
+                Some(expires_at),
+            )
+            .await
+            .unwrap();
+        assert!(created.key.starts_with(auth_token::API_KEY_PREFIX));
+        assert_eq!(
+            created.api_key.expires_at.map(|t| t.timestamp()),
+            Some(expires_at.timestamp())
+        );
+
+        let keys = core.my_api_keys(&principal).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].id, created.api_key.id);
+
+        // keys can't manage keys
+        let api_principal = core
+            .authenticate_api_key(created.key.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            core.my_api_keys(&api_principal).await.unwrap_err(),
+            CoreError::Forbidden
+        );
+
+        core.revoke_api_key(&principal, created.api_key.id)
+            .await
+            .unwrap();
+        assert!(core.my_api_keys(&principal).await.unwrap().is_empty());
+        assert_eq!(
+            core.authenticate_api_key(created.key).await.unwrap_err(),
+            CoreError::InvalidCredentials
+        );
+    }
+
+    #[tokio::test]
+    async fn test_erase_closed_accounts() {
+        let (core, auth, _) = setup();
+        let principal = sign_up(&core, &auth, "erase@gmail.com").await;
+        sign_up(&core, &auth, "keep@gmail.com").await;
+        core.close_account(&principal, "123456".to_string())
+            .await
+            .unwrap();
+
+        // still in its grace period
+        let erased = core
+            .erase_closed_accounts(Duration::from_secs(60 * 60))
+            .await
+            .unwrap();
+        assert_eq!(erased, 0);
+
+        let erased = core
+            .erase_closed_accounts(Duration::from_secs(0))
+            .await
+            .unwrap();
+        assert_eq!(erased, 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            CoreError::from(DatabaseError::NotFound {
+                entity: "Movie".to_string(),
+                key: "Id(00000000-0000-0000-0000-000000000000)".to_string(),
+            }),
+            CoreError::NotFound("Movie".to_string())
+        );
+        assert_eq!(
+            CoreError::from(DatabaseError::UniqueViolation {
+                constraint: "users_pkey".to_string(),
+            }),
+            CoreError::AlreadyExists("users_pkey".to_string())
+        );
+        assert!(matches!(
+            CoreError::from(DatabaseError::CheckViolation {
+                constraint: "movies_title_check".to_string(),
+            }),
+            CoreError::InvalidArgument(_)
+        ));
+        assert_eq!(
+            CoreError::from(DatabaseError::Deadlock),
+            CoreError::Conflict
+        );
+        assert_eq!(
+            CoreError::from(DatabaseError::VersionConflict {
+                entity: "Movie".to_string(),
+                key: "Id(00000000-0000-0000-0000-000000000000)".to_string(),
+                current_version: 3,
+            }),
+            CoreError::VersionConflict { current_version: 3 }
+        );
+        assert_eq!(
+            CoreError::from(DatabaseError::ConnectionNotAvailable),
+            CoreError::Unavailable
+        );
+
+        // as reported by auth
+        assert_eq!(
+            CoreError::from(Status::already_exists("credentials_email_key")),
+            CoreError::AlreadyExists("credentials_email_key".to_string())
+        );
+        assert_eq!(
+            CoreError::from(Status::not_found("Credential not found")),
+            CoreError::NotFound("Credential not found".to_string())
+        );
+        assert_eq!(
+            CoreError::from(Status::aborted("Conflict")),
+            CoreError::Conflict
+        );
+        assert_eq!(
+            CoreError::from(Status::unavailable("Unavailable")),
+            CoreError::Unavailable
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_user() {
+        let repository = InMemoryCoreRepository::new();
+        let id = Uuid::new_v4();
+        let user = CreateUserDAO {
+            id,
+            name: "Test".to_string(),
+            birthday: Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap(),
+        };
+
+        assert!(matches!(
+            repository.get_user(id).await,
+            Err(DatabaseError::NotFound { entity, .. }) if entity == "User"
+        ));
+        repository.insert_user(user.clone()).await.unwrap();
+        assert_eq!(
+            repository.insert_user(user).await.unwrap_err(),
+            DatabaseError::UniqueViolation {
+                constraint: "users_pkey".to_string()
+            }
+        );
     }
 }
@@ -0,0 +1,428 @@
+use auth_token::{Revocation, REVOCATIONS_CHANNEL};
+use core_database::types::{DateTime, Utc, Uuid};
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+struct CachedSession {
+    user_id: Uuid,
+    roles: Vec<String>,
+    valid_until: Instant,
+}
+
+/// How many revocations the cache had seen, taken before asking auth about a session.
+/// Revocations seen since then win over what auth answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Generation(u64);
+
+#[derive(Debug, Default)]
+struct Entries {
+    sessions: HashMap<String, CachedSession>,
+    generation: u64,
+    revoked_sessions: HashMap<String, u64>,
+    revoked_credentials: HashMap<Uuid, u64>,
+    // Revocations up to this one are no longer told apart
+    forgotten: u64,
+}
+
+impl Entries {
+    fn revoked_since(&self, started: Generation, session_id: &str, user_id: Uuid) -> bool {
+        let Generation(started) = started;
+        self.forgotten > started
+            || self
+                .revoked_sessions
+                .get(session_id)
+                .is_some_and(|&revoked| revoked > started)
+            || self
+                .revoked_credentials
+                .get(&user_id)
+                .is_some_and(|&revoked| revoked > started)
+    }
+
+    fn forget_revocations(&mut self) {
+        self.revoked_sessions.clear();
+        self.revoked_credentials.clear();
+        self.forgotten = self.generation;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SessionCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Sessions the auth service recently validated, so most requests don't need a round trip.
+/// An entry lives `ttl` at most and never past the session's own expiry, revocations
+/// drop it earlier. Skipping the round trip also skips the sliding renewal of the
+/// session, keep `ttl` well below the session idle timeout.
+///
+/// A revocation can arrive while auth is still validating the same session, so validations
+/// take a [`Generation`] before asking and their answer isn't cached if it was revoked since.
+#[derive(Debug)]
+pub struct SessionCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SessionCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: Mutex::new(Entries {
+                sessions: HashMap::with_capacity(capacity),
+                ..Default::default()
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// User behind the session and their roles, if it was validated recently.
+    /// Granting or revoking roles announces a credential revocation, which drops them.
+    pub fn get(&self, session_id: &str) -> Option<(Uuid, Vec<String>)> {
+        let entries = &mut self.entries.lock().unwrap().sessions;
+        let user = match entries.get(session_id) {
+            Some(cached) if cached.valid_until > Instant::now() => {
+                Some((cached.user_id, cached.roles.clone()))
+            }
+            Some(_) => {
+                entries.remove(session_id);
+                None
+            }
+            None => None,
+        };
+
+        let counter = if user.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        user
+    }
+
+    /// Take before validating a session with auth, then pass it to `insert`
+    pub fn generation(&self) -> Generation {
+        Generation(self.entries.lock().unwrap().generation)
+    }
+
+    /// Caches what auth answered for a validation that began at `started`,
+    /// unless the session or its credential was revoked in the meantime
+    pub fn insert(
+        &self,
+        session_id: String,
+        user_id: Uuid,
+        roles: Vec<String>,
+        expires_at: DateTime<Utc>,
+        started: Generation,
+    ) {
+        let remaining = (expires_at - Utc::now()).to_std().unwrap_or_default();
+        let lifetime = self.ttl.min(remaining);
+        if lifetime.is_zero() || self.capacity == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut state = self.entries.lock().unwrap();
+        if state.revoked_since(started, &session_id, user_id) {
+            return;
+        }
+        let entries = &mut state.sessions;
+        if entries.len() >= self.capacity && !entries.contains_key(&session_id) {
+            entries.retain(|_, cached| cached.valid_until > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&session_id) {
+            // Whatever would go away first anyway
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.valid_until)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = soonest {
+                entries.remove(&id);
+            }
+        }
+
+        entries.insert(
+            session_id,
+            CachedSession {
+                user_id,
+                roles,
+                valid_until: now + lifetime,
+            },
+        );
+    }
+
+    pub fn invalidate(&self, revocation: &Revocation) {
+        let mut state = self.entries.lock().unwrap();
+        state.generation += 1;
+        let generation = state.generation;
+        match revocation {
+            Revocation::Session(session_id) => {
+                state.sessions.remove(session_id);
+                state
+                    .revoked_sessions
+                    .insert(session_id.clone(), generation);
+            }
+            Revocation::Credential(credential_id) => match Uuid::from_str(credential_id) {
+                Ok(user_id) => {
+                    state.sessions.retain(|_, cached| cached.user_id != user_id);
+                    state.revoked_credentials.insert(user_id, generation);
+                }
+                // Can't tell which validations it concerns, so none of them
+                Err(_) => {
+                    eprintln!("invalid credential id in revocation: {:?}", credential_id);
+                    state.forget_revocations();
+                }
+            },
+        }
+
+        // Keeps as many revocations as sessions, validations that began before the
+        // oldest forgotten one aren't cached
+        if state.revoked_sessions.len() + state.revoked_credentials.len() > self.capacity {
+            state.forget_revocations();
+        }
+    }
+
+    /// Forgets every session, and what validations in flight answer about them,
+    /// e.g. when revocations may have been missed
+    pub fn clear(&self) {
+        let mut state = self.entries.lock().unwrap();
+        state.sessions.clear();
+        state.generation += 1;
+        state.forget_revocations();
+    }
+
+    pub fn stats(&self) -> SessionCacheStats {
+        SessionCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().sessions.len(),
+        }
+    }
+}
+
+/// Drops cached sessions as the auth service announces revocations, reconnecting forever.
+/// Revocations sent while disconnected are lost, so the cache starts over on every connection.
+pub async fn subscribe_to_revocations(cache: Arc<SessionCache>, redis_url: String) {
+    loop {
+        match listen(&cache, &redis_url).await {
+            Ok(()) => eprintln!("revocations subscription closed"),
+            Err(e) => eprintln!("revocations subscription failed: {}", e),
+        }
+        cache.clear();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(cache: &SessionCache, redis_url: &str) -> redis::RedisResult<()> {
+    let client = redis::Client::open(redis_url)?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(REVOCATIONS_CHANNEL).await?;
+    cache.clear();
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = message.get_payload()?;
+        match Revocation::from_message(&payload) {
+            Some(revocation) => cache.invalidate(&revocation),
+            None => eprintln!("ignoring unknown revocation: {:?}", payload),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_an_hour() -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::hours(1)
+    }
+
+    #[test]
+    fn test_get_and_stats() {
+        let cache = SessionCache::new(10, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let roles = vec!["admin".to_string()];
+        assert_eq!(cache.get("a"), None);
+
+        cache.insert(
+            "a".to_string(),
+            user_id,
+            roles.clone(),
+            in_an_hour(),
+            cache.generation(),
+        );
+        assert_eq!(cache.get("a"), Some((user_id, roles.clone())));
+        assert_eq!(cache.get("a"), Some((user_id, roles)));
+        assert_eq!(
+            cache.stats(),
+            SessionCacheStats {
+                hits: 2,
+                misses: 1,
+                entries: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_capped_at_session_expiry() {
+        let cache = SessionCache::new(10, Duration::from_secs(60));
+        cache.insert(
+            "expired".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            Utc::now() - chrono::Duration::seconds(1),
+            cache.generation(),
+        );
+        assert_eq!(cache.get("expired"), None);
+        assert_eq!(cache.stats().entries, 0);
+
+        let cache = SessionCache::new(10, Duration::ZERO);
+        cache.insert(
+            "a".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            in_an_hour(),
+            cache.generation(),
+        );
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn test_evicts_when_full() {
+        let cache = SessionCache::new(2, Duration::from_secs(60));
+        cache.insert(
+            "soon".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            Utc::now() + chrono::Duration::seconds(10),
+            cache.generation(),
+        );
+        cache.insert(
+            "later".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            in_an_hour(),
+            cache.generation(),
+        );
+        cache.insert(
+            "new".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            in_an_hour(),
+            cache.generation(),
+        );
+
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.get("soon"), None);
+        assert!(cache.get("later").is_some());
+        assert!(cache.get("new").is_some());
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = SessionCache::new(10, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let other_user_id = Uuid::new_v4();
+        cache.insert(
+            "a".to_string(),
+            user_id,
+            vec![],
+            in_an_hour(),
+            cache.generation(),
+        );
+        cache.insert(
+            "b".to_string(),
+            user_id,
+            vec![],
+            in_an_hour(),
+            cache.generation(),
+        );
+        cache.insert(
+            "c".to_string(),
+            other_user_id,
+            vec![],
+            in_an_hour(),
+            cache.generation(),
+        );
+
+        cache.invalidate(&Revocation::Session("a".to_string()));
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some((user_id, vec![])));
+
+        cache.invalidate(&Revocation::Credential(user_id.to_string()));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some((other_user_id, vec![])));
+    }
+
+    #[test]
+    fn test_revoked_while_validating() {
+        let cache = SessionCache::new(10, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+
+        // auth answered before hearing of the revocation, which reached the cache first
+        let started = cache.generation();
+        cache.invalidate(&Revocation::Session("a".to_string()));
+        cache.insert("a".to_string(), user_id, vec![], in_an_hour(), started);
+        assert_eq!(cache.get("a"), None);
+
+        let started = cache.generation();
+        cache.invalidate(&Revocation::Credential(user_id.to_string()));
+        cache.insert("b".to_string(), user_id, vec![], in_an_hour(), started);
+        assert_eq!(cache.get("b"), None);
+
+        // validations that began afterwards, or of other sessions, are cached
+        let started = cache.generation();
+        cache.insert("a".to_string(), user_id, vec![], in_an_hour(), started);
+        assert_eq!(cache.get("a"), Some((user_id, vec![])));
+        let started = cache.generation();
+        cache.invalidate(&Revocation::Session("a".to_string()));
+        cache.insert(
+            "c".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            in_an_hour(),
+            started,
+        );
+        assert!(cache.get("c").is_some());
+
+        // once too many revocations to remember, or missed ones, none of those in flight
+        let started = cache.generation();
+        for _ in 0..10 {
+            cache.invalidate(&Revocation::Session(Uuid::new_v4().to_string()));
+        }
+        cache.insert(
+            "d".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            in_an_hour(),
+            started,
+        );
+        assert_eq!(cache.get("d"), None);
+        let started = cache.generation();
+        cache.clear();
+        cache.insert(
+            "e".to_string(),
+            Uuid::new_v4(),
+            vec![],
+            in_an_hour(),
+            started,
+        );
+        assert_eq!(cache.get("e"), None);
+    }
+}
@@ -1,12 +1,19 @@
+pub mod migrations;
+pub mod query;
+pub mod router;
 pub mod traits;
+pub mod unit_of_work;
 
 pub mod types {
     pub use sqlx::types::{
-        chrono::{DateTime, Utc},
+        chrono::{DateTime, TimeZone, Utc},
         Uuid,
     };
 }
 
 pub mod connection {
-    pub use sqlx::{PgPool, Pool, Postgres};
+    pub use sqlx::{Database, PgPool, Pool, Postgres, Transaction};
+
+    #[cfg(feature = "sqlite")]
+    pub use sqlx::{sqlite::SqliteConnectOptions, Sqlite, SqlitePool};
 }
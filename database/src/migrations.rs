@@ -0,0 +1,113 @@
+use crate::traits::DatabaseError;
+use sqlx::migrate::MigrateError;
+use sqlx::{Database, Pool};
+
+pub use sqlx::migrate::{Migrate, Migrator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Pending,
+    Applied,
+    /// Applied, but the file changed since
+    Modified,
+    /// Started and failed halfway, the schema needs a look before going on
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+impl From<MigrateError> for DatabaseError {
+    fn from(value: MigrateError) -> Self {
+        Self::MigrationFailed(value.to_string())
+    }
+}
+
+/// Applies every pending migration
+pub async fn migrate<Db>(migrator: &Migrator, pool: &Pool<Db>) -> Result<(), DatabaseError>
+where
+    Db: Database,
+    Db::Connection: Migrate,
+{
+    Ok(migrator.run(pool).await?)
+}
+
+/// Reverts the applied migrations newer than `to`, `0` reverts all of them
+pub async fn rollback<Db>(
+    migrator: &Migrator,
+    pool: &Pool<Db>,
+    to: i64,
+) -> Result<(), DatabaseError>
+where
+    Db: Database,
+    Db::Connection: Migrate,
+{
+    Ok(migrator.undo(pool, to).await?)
+}
+
+/// Every migration the binary knows of and whether the database has it.
+/// Only reads, a database that was never migrated has all of them pending.
+pub async fn status<Db>(
+    migrator: &Migrator,
+    pool: &Pool<Db>,
+) -> Result<Vec<MigrationStatus>, DatabaseError>
+where
+    Db: Database,
+    Db::Connection: Migrate,
+{
+    let mut conn = pool.acquire().await?;
+    // the bookkeeping table is only created by the first run, a database error means there is none
+    let (applied, failed) = match conn.list_applied_migrations().await {
+        Ok(applied) => (applied, conn.dirty_version().await?),
+        Err(MigrateError::Execute(sqlx::Error::Database(_))) => (vec![], None),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| {
+            let state = match applied.iter().find(|a| a.version == m.version) {
+                _ if failed == Some(m.version) => MigrationState::Failed,
+                Some(a) if a.checksum != m.checksum => MigrationState::Modified,
+                Some(_) => MigrationState::Applied,
+                None => MigrationState::Pending,
+            };
+            MigrationStatus {
+                version: m.version,
+                description: m.description.to_string(),
+                state,
+            }
+        })
+        .collect())
+}
+
+/// What services run on start: applies pending migrations when `apply`,
+/// otherwise reports the ones the schema lacks so a forgotten migration shows up in the logs
+pub async fn prepare<Db>(
+    migrator: &Migrator,
+    pool: &Pool<Db>,
+    apply: bool,
+) -> Result<(), DatabaseError>
+where
+    Db: Database,
+    Db::Connection: Migrate,
+{
+    if apply {
+        migrate(migrator, pool).await?;
+    }
+
+    for migration in status(migrator, pool).await? {
+        if migration.state != MigrationState::Applied {
+            eprintln!(
+                "migration {} {} is {:?}",
+                migration.version, migration.description, migration.state
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,680 @@
+//! Filtering, ordering and paging for `EntityRepository::get_all`.
+//!
+//! A `Query` names fields through an enum of the entity implementing `Field`, so only
+//! known columns reach the SQL, and every value is sent as a bind parameter.
+//!
+//! ```ignore
+//! let query = Query::new()
+//!     .filter(Filter::contains(UserField::Name, "ann").and(Filter::eq(UserField::Active, true)))
+//!     .order_by(UserField::Birthday, Direction::Desc)
+//!     .page(0, 20);
+//! let users = UserRepository::get_all(&pool, query).await?;
+//! ```
+use crate::traits::DatabaseError;
+use crate::types::{DateTime, Utc, Uuid};
+use sqlx::{Database, Postgres, QueryBuilder};
+use std::cmp::Ordering;
+use std::fmt::Debug;
+
+/// Column of an entity that queries may filter and order by
+pub trait Field: Copy + Debug + PartialEq + Send + Sync {
+    /// Unique field every ordering ends with, so rows that tie keep the same order across pages
+    const KEY: Self;
+
+    /// Name of the column, written into the SQL as is
+    fn column(self) -> &'static str;
+
+    /// What the column holds, predicates comparing it to anything else are rejected
+    fn kind(self) -> ValueKind;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Bool,
+    Int,
+    Text,
+    Uuid,
+    Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Text(String),
+    Uuid(Uuid),
+    Timestamp(DateTime<Utc>),
+}
+
+impl Value {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::Bool(_) => ValueKind::Bool,
+            Value::Int(_) => ValueKind::Int,
+            Value::Text(_) => ValueKind::Text,
+            Value::Uuid(_) => ValueKind::Uuid,
+            Value::Timestamp(_) => ValueKind::Timestamp,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<Uuid> for Value {
+    fn from(value: Uuid) -> Self {
+        Value::Uuid(value)
+    }
+}
+
+impl From<DateTime<Utc>> for Value {
+    fn from(value: DateTime<Utc>) -> Self {
+        Value::Timestamp(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    Eq(Value),
+    Ne(Value),
+    Lt(Value),
+    Le(Value),
+    Gt(Value),
+    Ge(Value),
+    /// Equal to any of them, none matches nothing
+    In(Vec<Value>),
+    /// Case insensitive substring of a text column
+    Contains(String),
+    IsNull,
+    IsNotNull,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter<F> {
+    Field(F, Predicate),
+    /// Matches when all of them do, an empty one matches everything
+    And(Vec<Filter<F>>),
+    /// Matches when any of them does, an empty one matches nothing
+    Or(Vec<Filter<F>>),
+    Not(Box<Filter<F>>),
+}
+
+impl<F: Field> Filter<F> {
+    pub fn eq(field: F, value: impl Into<Value>) -> Self {
+        Filter::Field(field, Predicate::Eq(value.into()))
+    }
+
+    pub fn ne(field: F, value: impl Into<Value>) -> Self {
+        Filter::Field(field, Predicate::Ne(value.into()))
+    }
+
+    pub fn lt(field: F, value: impl Into<Value>) -> Self {
+        Filter::Field(field, Predicate::Lt(value.into()))
+    }
+
+    pub fn le(field: F, value: impl Into<Value>) -> Self {
+        Filter::Field(field, Predicate::Le(value.into()))
+    }
+
+    pub fn gt(field: F, value: impl Into<Value>) -> Self {
+        Filter::Field(field, Predicate::Gt(value.into()))
+    }
+
+    pub fn ge(field: F, value: impl Into<Value>) -> Self {
+        Filter::Field(field, Predicate::Ge(value.into()))
+    }
+
+    pub fn contains(field: F, text: impl Into<String>) -> Self {
+        Filter::Field(field, Predicate::Contains(text.into()))
+    }
+
+    pub fn and(self, other: Filter<F>) -> Self {
+        match self {
+            Filter::And(mut all) => {
+                all.push(other);
+                Filter::And(all)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter<F>) -> Self {
+        match self {
+            Filter::Or(mut any) => {
+                any.push(other);
+                Filter::Or(any)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    fn validate(&self) -> Result<(), DatabaseError> {
+        match self {
+            Filter::Field(field, predicate) => {
+                let kind = field.kind();
+                let invalid = |reason: &str| {
+                    Err(DatabaseError::InvalidQuery(format!(
+                        "{} {}",
+                        field.column(),
+                        reason
+                    )))
+                };
+                match predicate {
+                    Predicate::Contains(_) if kind != ValueKind::Text => {
+                        invalid("is not text, it can't be searched")
+                    }
+                    Predicate::Eq(value)
+                    | Predicate::Ne(value)
+                    | Predicate::Lt(value)
+                    | Predicate::Le(value)
+                    | Predicate::Gt(value)
+                    | Predicate::Ge(value)
+                        if value.kind() != kind =>
+                    {
+                        invalid(&format!("holds {:?} values, not {:?}", kind, value.kind()))
+                    }
+                    Predicate::In(values) if values.iter().any(|v| v.kind() != kind) => {
+                        invalid(&format!("holds {:?} values only", kind))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Filter::And(filters) | Filter::Or(filters) => {
+                filters.iter().try_for_each(Filter::validate)
+            }
+            Filter::Not(filter) => filter.validate(),
+        }
+    }
+
+    fn push_to<'args, Db: Dialect>(&self, sql: &mut QueryBuilder<'args, Db>) {
+        match self {
+            Filter::Field(field, predicate) => {
+                let column = field.column();
+                let comparison = |op: &str, value: &Value, sql: &mut QueryBuilder<'args, Db>| {
+                    sql.push(format_args!("{} {} ", column, op));
+                    Db::push_value(sql, value.clone());
+                };
+                match predicate {
+                    Predicate::Eq(value) => comparison("=", value, sql),
+                    Predicate::Ne(value) => comparison("<>", value, sql),
+                    Predicate::Lt(value) => comparison("<", value, sql),
+                    Predicate::Le(value) => comparison("<=", value, sql),
+                    Predicate::Gt(value) => comparison(">", value, sql),
+                    Predicate::Ge(value) => comparison(">=", value, sql),
+                    Predicate::In(values) if values.is_empty() => {
+                        sql.push("1 = 0");
+                    }
+                    Predicate::In(values) => {
+                        sql.push(format_args!("{} IN (", column));
+                        for (i, value) in values.iter().enumerate() {
+                            if i > 0 {
+                                sql.push(", ");
+                            }
+                            Db::push_value(sql, value.clone());
+                        }
+                        sql.push(")");
+                    }
+                    Predicate::Contains(text) => {
+                        sql.push(format_args!("LOWER({}) LIKE ", column));
+                        Db::push_value(sql, Value::Text(format!("%{}%", like_escaped(text))));
+                        sql.push(" ESCAPE '\\'");
+                    }
+                    Predicate::IsNull => {
+                        sql.push(format_args!("{} IS NULL", column));
+                    }
+                    Predicate::IsNotNull => {
+                        sql.push(format_args!("{} IS NOT NULL", column));
+                    }
+                }
+            }
+            Filter::And(filters) if filters.is_empty() => {
+                sql.push("1 = 1");
+            }
+            Filter::Or(filters) if filters.is_empty() => {
+                sql.push("1 = 0");
+            }
+            Filter::And(filters) | Filter::Or(filters) => {
+                let separator = if matches!(self, Filter::And(_)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                for (i, filter) in filters.iter().enumerate() {
+                    if i > 0 {
+                        sql.push(separator);
+                    }
+                    sql.push("(");
+                    filter.push_to(sql);
+                    sql.push(")");
+                }
+            }
+            Filter::Not(filter) => {
+                sql.push("NOT (");
+                filter.push_to(sql);
+                sql.push(")");
+            }
+        }
+    }
+
+    /// Same answer the database gives, values of `None` standing for NULL and so does the
+    /// result: comparing NULL is unknown, and only rows the filter is true for are returned
+    fn matches(&self, value_of: &impl Fn(F) -> Option<Value>) -> Option<bool> {
+        match self {
+            Filter::Field(field, predicate) => {
+                let value = value_of(*field);
+                let compare = |other: &Value, accept: fn(Ordering) -> bool| {
+                    value.as_ref().map(|v| accept(v.cmp(other)))
+                };
+                match predicate {
+                    Predicate::Eq(other) => compare(other, Ordering::is_eq),
+                    Predicate::Ne(other) => compare(other, Ordering::is_ne),
+                    Predicate::Lt(other) => compare(other, Ordering::is_lt),
+                    Predicate::Le(other) => compare(other, Ordering::is_le),
+                    Predicate::Gt(other) => compare(other, Ordering::is_gt),
+                    Predicate::Ge(other) => compare(other, Ordering::is_ge),
+                    // pushed as `1 = 0`, whatever the value
+                    Predicate::In(values) if values.is_empty() => Some(false),
+                    Predicate::In(values) => value.map(|v| values.contains(&v)),
+                    Predicate::Contains(text) => value.map(|v| {
+                        matches!(v, Value::Text(v) if v.to_lowercase().contains(&text.to_lowercase()))
+                    }),
+                    Predicate::IsNull => Some(value.is_none()),
+                    Predicate::IsNotNull => Some(value.is_some()),
+                }
+            }
+            // false wins over unknown, which wins over true
+            Filter::And(filters) => {
+                let mut all = Some(true);
+                for filter in filters {
+                    match filter.matches(value_of) {
+                        Some(false) => return Some(false),
+                        Some(true) => {}
+                        None => all = None,
+                    }
+                }
+                all
+            }
+            // true wins over unknown, which wins over false
+            Filter::Or(filters) => {
+                let mut any = Some(false);
+                for filter in filters {
+                    match filter.matches(value_of) {
+                        Some(true) => return Some(true),
+                        Some(false) => {}
+                        None => any = None,
+                    }
+                }
+                any
+            }
+            Filter::Not(filter) => filter.matches(value_of).map(|matched| !matched),
+        }
+    }
+}
+
+impl<F> std::ops::Not for Filter<F> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Filter::Not(Box::new(self))
+    }
+}
+
+// `%`, `_` and the escape character itself are wildcards to LIKE
+fn like_escaped(text: &str) -> String {
+    text.to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderBy<F> {
+    pub field: F,
+    pub direction: Direction,
+}
+
+/// What `get_all` returns: rows matching `filter`, sorted by `order_by` and then by
+/// `Field::KEY`, skipping `offset` of them and returning at most `limit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query<F> {
+    pub filter: Option<Filter<F>>,
+    pub order_by: Vec<OrderBy<F>>,
+    pub offset: u32,
+    pub limit: Option<u32>,
+}
+
+impl<F> Default for Query<F> {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            order_by: vec![],
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+impl<F: Field> Query<F> {
+    /// Every row, in `Field::KEY` order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Narrows the rows down further when there's a filter already
+    pub fn filter(mut self, filter: Filter<F>) -> Self {
+        self.filter = Some(match self.filter {
+            Some(current) => current.and(filter),
+            None => filter,
+        });
+        self
+    }
+
+    pub fn order_by(mut self, field: F, direction: Direction) -> Self {
+        self.order_by.push(OrderBy { field, direction });
+        self
+    }
+
+    pub fn page(mut self, offset: u32, limit: u32) -> Self {
+        self.offset = offset;
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Appends ` WHERE .. ORDER BY .. LIMIT .. OFFSET ..` to a select,
+    /// fails without touching it when a predicate doesn't fit its field
+    pub fn push_to<Db: Dialect>(
+        &self,
+        sql: &mut QueryBuilder<'_, Db>,
+    ) -> Result<(), DatabaseError> {
+        if let Some(filter) = &self.filter {
+            filter.validate()?;
+            sql.push(" WHERE ");
+            filter.push_to(sql);
+        }
+
+        sql.push(" ORDER BY ");
+        for order in self.ordering() {
+            let direction = match order.direction {
+                Direction::Asc => "ASC",
+                Direction::Desc => "DESC",
+            };
+            sql.push(format_args!("{} {}, ", order.field.column(), direction));
+        }
+        sql.push(format_args!("{} ASC", F::KEY.column()));
+
+        // SQLite only takes OFFSET after a LIMIT
+        sql.push(" LIMIT ");
+        Db::push_value(sql, Value::Int(self.limit.map_or(i64::MAX, i64::from)));
+        sql.push(" OFFSET ");
+        Db::push_value(sql, Value::Int(self.offset.into()));
+        Ok(())
+    }
+
+    /// Runs the query over rows in memory, e.g. for repositories without a database
+    pub fn apply<T>(
+        &self,
+        rows: impl IntoIterator<Item = T>,
+        value_of: impl Fn(&T, F) -> Option<Value>,
+    ) -> Result<Vec<T>, DatabaseError> {
+        if let Some(filter) = &self.filter {
+            filter.validate()?;
+        }
+        let mut rows = rows
+            .into_iter()
+            .filter(|row| {
+                self.filter
+                    .as_ref()
+                    .is_none_or(|f| f.matches(&|field| value_of(row, field)) == Some(true))
+            })
+            .collect::<Vec<T>>();
+
+        let ordering = self
+            .ordering()
+            .copied()
+            .chain([OrderBy {
+                field: F::KEY,
+                direction: Direction::Asc,
+            }])
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| {
+            ordering
+                .iter()
+                .map(|order| {
+                    // NULLs come last in ascending order, as on Postgres
+                    let ord = match (value_of(a, order.field), value_of(b, order.field)) {
+                        (Some(a), Some(b)) => a.cmp(&b),
+                        (a, b) => b.is_some().cmp(&a.is_some()),
+                    };
+                    match order.direction {
+                        Direction::Asc => ord,
+                        Direction::Desc => ord.reverse(),
+                    }
+                })
+                .find(|ord| ord.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        Ok(rows
+            .into_iter()
+            .skip(self.offset as usize)
+            .take(self.limit.map_or(usize::MAX, |l| l as usize))
+            .collect())
+    }
+
+    // the requested ordering, the key is added by the callers
+    fn ordering(&self) -> impl Iterator<Item = &OrderBy<F>> {
+        self.order_by.iter().filter(|o| o.field != F::KEY)
+    }
+}
+
+/// Databases queries compile for, binds values the way the driver takes them
+pub trait Dialect: Database {
+    fn push_value(sql: &mut QueryBuilder<'_, Self>, value: Value);
+}
+
+macro_rules! dialect {
+    ($db:ty) => {
+        impl Dialect for $db {
+            fn push_value(sql: &mut QueryBuilder<'_, Self>, value: Value) {
+                match value {
+                    Value::Bool(v) => sql.push_bind(v),
+                    Value::Int(v) => sql.push_bind(v),
+                    Value::Text(v) => sql.push_bind(v),
+                    Value::Uuid(v) => sql.push_bind(v),
+                    Value::Timestamp(v) => sql.push_bind(v),
+                };
+            }
+        }
+    };
+}
+
+dialect!(Postgres);
+#[cfg(feature = "sqlite")]
+dialect!(sqlx::Sqlite);
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, Field, Filter, Query, Value, ValueKind};
+    use crate::traits::DatabaseError;
+    use sqlx::{Postgres, QueryBuilder};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum BookField {
+        Id,
+        Title,
+        Pages,
+    }
+
+    impl Field for BookField {
+        const KEY: Self = BookField::Id;
+
+        fn column(self) -> &'static str {
+            match self {
+                BookField::Id => "id",
+                BookField::Title => "title",
+                BookField::Pages => "pages",
+            }
+        }
+
+        fn kind(self) -> ValueKind {
+            match self {
+                BookField::Id | BookField::Pages => ValueKind::Int,
+                BookField::Title => ValueKind::Text,
+            }
+        }
+    }
+
+    fn sql(query: &Query<BookField>) -> Result<String, DatabaseError> {
+        let mut sql = QueryBuilder::<Postgres>::new("SELECT id FROM books");
+        query.push_to(&mut sql)?;
+        Ok(sql.sql().to_string())
+    }
+
+    #[test]
+    fn test_push_to() {
+        assert_eq!(
+            sql(&Query::new()).unwrap(),
+            "SELECT id FROM books ORDER BY id ASC LIMIT $1 OFFSET $2"
+        );
+
+        let query = Query::new()
+            .filter(
+                Filter::contains(BookField::Title, "50%_off")
+                    .or(!Filter::ge(BookField::Pages, 100)),
+            )
+            .filter(Filter::Field(BookField::Id, super::Predicate::In(vec![])))
+            .order_by(BookField::Pages, Direction::Desc)
+            .order_by(BookField::Id, Direction::Desc)
+            .page(20, 10);
+        assert_eq!(
+            sql(&query).unwrap(),
+            "SELECT id FROM books WHERE ((LOWER(title) LIKE $1 ESCAPE '\\') OR (NOT (pages >= $2))) AND (1 = 0) ORDER BY pages DESC, id ASC LIMIT $3 OFFSET $4"
+        );
+
+        // values must fit the field they are compared to
+        let mismatch = Query::new().filter(Filter::eq(BookField::Pages, "many"));
+        assert!(matches!(
+            sql(&mismatch),
+            Err(DatabaseError::InvalidQuery(_))
+        ));
+        let not_text = Query::new().filter(Filter::contains(BookField::Pages, "1"));
+        assert!(matches!(
+            sql(&not_text),
+            Err(DatabaseError::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply() {
+        let books = vec![(1, "Dune", 412), (2, "Emma", 474), (3, "Ubik", 202)];
+        let value_of = |book: &(i64, &str, i64), field| {
+            Some(match field {
+                BookField::Id => Value::Int(book.0),
+                BookField::Title => Value::from(book.1),
+                BookField::Pages => Value::Int(book.2),
+            })
+        };
+
+        let query = Query::new()
+            .filter(Filter::gt(BookField::Pages, 300).or(Filter::contains(BookField::Title, "UB")))
+            .order_by(BookField::Pages, Direction::Desc);
+        let found = query.apply(books.clone(), value_of).unwrap();
+        assert_eq!(
+            found.iter().map(|b| b.1).collect::<Vec<_>>(),
+            vec!["Emma", "Dune", "Ubik"]
+        );
+
+        let page = Query::new().page(1, 1).apply(books, value_of).unwrap();
+        assert_eq!(page, vec![(2, "Emma", 474)]);
+    }
+
+    #[test]
+    fn test_apply_null() {
+        let books = vec![
+            (1, "Dune", Some(412)),
+            (2, "Emma", None),
+            (3, "Ubik", Some(202)),
+        ];
+        let value_of = |book: &(i64, &str, Option<i64>), field| match field {
+            BookField::Id => Some(Value::Int(book.0)),
+            BookField::Title => Some(Value::from(book.1)),
+            BookField::Pages => book.2.map(Value::Int),
+        };
+        let titles = |filter: Filter<BookField>| {
+            Query::new()
+                .filter(filter)
+                .apply(books.clone(), value_of)
+                .unwrap()
+                .iter()
+                .map(|b| b.1)
+                .collect::<Vec<_>>()
+        };
+
+        // comparing NULL is unknown, and so is its negation
+        assert_eq!(
+            titles(Filter::ne(BookField::Pages, 300)),
+            vec!["Dune", "Ubik"]
+        );
+        assert_eq!(titles(!Filter::gt(BookField::Pages, 300)), vec!["Ubik"]);
+        assert_eq!(
+            titles(Filter::Field(BookField::Pages, super::Predicate::IsNull)),
+            vec!["Emma"]
+        );
+        assert_eq!(
+            titles(!Filter::Field(BookField::Pages, super::Predicate::IsNull)),
+            vec!["Dune", "Ubik"]
+        );
+
+        // unknown and true is unknown, unknown and false is false, unknown or true is true
+        assert_eq!(
+            titles(!(Filter::gt(BookField::Pages, 300).and(Filter::eq(BookField::Title, "Emma")))),
+            vec!["Dune", "Ubik"]
+        );
+        assert_eq!(
+            titles(!(Filter::gt(BookField::Pages, 300).and(Filter::eq(BookField::Title, "Dune")))),
+            vec!["Emma", "Ubik"]
+        );
+        assert_eq!(
+            titles(!(Filter::gt(BookField::Pages, 300).or(Filter::eq(BookField::Title, "Emma")))),
+            vec!["Ubik"]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::traits::DatabaseError;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Database, Pool, Postgres};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How a service connects to its database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseConfig {
+    /// Primary, takes every write
+    pub url: String,
+    /// Read replicas of the primary, reads are spread over them when there are any
+    pub replica_urls: Vec<String>,
+    /// Connections kept open per pool even when idle
+    pub min_connections: u32,
+    pub max_connections: u32,
+    /// How long a query waits for a free connection before failing
+    pub acquire_timeout: Duration,
+    /// Idle connections above `min_connections` are closed after it, never when `None`
+    pub idle_timeout: Option<Duration>,
+    /// Prepared statements kept per connection, 0 disables them, e.g. behind pgbouncer
+    pub statement_cache_capacity: usize,
+}
+
+impl DatabaseConfig {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            replica_urls: vec![],
+            min_connections: 0,
+            max_connections: 10,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            statement_cache_capacity: 100,
+        }
+    }
+
+    /// Opens a pool to the primary and one to every replica, fails when any can't be reached
+    pub async fn connect(&self) -> Result<DatabaseRouter, DatabaseError> {
+        let primary = self.pool(&self.url).await?;
+        let mut replicas = Vec::with_capacity(self.replica_urls.len());
+        for url in &self.replica_urls {
+            replicas.push(self.pool(url).await?);
+        }
+        Ok(DatabaseRouter::new(primary).with_replicas(replicas))
+    }
+
+    async fn pool(&self, url: &str) -> Result<Pool<Postgres>, DatabaseError> {
+        let options = PgConnectOptions::from_str(url)?
+            .statement_cache_capacity(self.statement_cache_capacity);
+        Ok(PgPoolOptions::new()
+            .min_connections(self.min_connections)
+            .max_connections(self.max_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .connect_with(options)
+            .await?)
+    }
+}
+
+tokio::task_local! {
+    static READ_YOUR_WRITES: ();
+}
+
+/// Runs `work` with every read going to the primary, for flows that read what they
+/// or the request before them just wrote, which replicas may not have yet
+pub async fn read_your_writes<F: Future>(work: F) -> F::Output {
+    READ_YOUR_WRITES.scope((), work).await
+}
+
+/// Sends writes to the primary and reads to the replicas, in turns.
+/// Without replicas, or inside `read_your_writes`, reads go to the primary as well.
+#[derive(Debug)]
+pub struct DatabaseRouter<Db: Database = Postgres> {
+    primary: Pool<Db>,
+    replicas: Arc<[Pool<Db>]>,
+    next: Arc<AtomicUsize>,
+}
+
+impl<Db: Database> Clone for DatabaseRouter<Db> {
+    fn clone(&self) -> Self {
+        Self {
+            primary: self.primary.clone(),
+            replicas: self.replicas.clone(),
+            next: self.next.clone(),
+        }
+    }
+}
+
+impl<Db: Database> DatabaseRouter<Db> {
+    pub fn new(primary: Pool<Db>) -> Self {
+        Self {
+            primary,
+            replicas: Arc::new([]),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn with_replicas(mut self, replicas: Vec<Pool<Db>>) -> Self {
+        self.replicas = replicas.into();
+        self
+    }
+
+    /// Where inserts, updates, deletes and transactions run
+    pub fn write(&self) -> &Pool<Db> {
+        &self.primary
+    }
+
+    /// Where `get`, `try_get` and `get_all` run
+    pub fn read(&self) -> &Pool<Db> {
+        if self.replicas.is_empty() || READ_YOUR_WRITES.try_with(|_| ()).is_ok() {
+            return &self.primary;
+        }
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.replicas[next % self.replicas.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_your_writes, DatabaseRouter};
+    use sqlx::{PgPool, Pool, Postgres};
+
+    fn pool(database: &str) -> Pool<Postgres> {
+        PgPool::connect_lazy(&format!("postgres://localhost/{}", database)).unwrap()
+    }
+
+    fn database(pool: &Pool<Postgres>) -> String {
+        pool.connect_options().get_database().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_routing() {
+        let router = DatabaseRouter::new(pool("primary"));
+        assert_eq!(database(router.read()), "primary");
+
+        let router = router.with_replicas(vec![pool("first"), pool("second")]);
+        assert_eq!(database(router.write()), "primary");
+        let reads = (0..3).map(|_| database(router.read())).collect::<Vec<_>>();
+        assert_eq!(reads, vec!["first", "second", "first"]);
+
+        let read = read_your_writes(async { database(router.read()) }).await;
+        assert_eq!(read, "primary");
+        assert_eq!(database(router.read()), "second");
+    }
+}
@@ -1,8 +1,35 @@
-use sqlx::{Database, Error as SqlxError, Pool};
+use sqlx::{error::ErrorKind, Acquire, Database, Error as SqlxError};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum DatabaseError {
-    NotFound(String),
+    /// A query that must return a row returned none, e.g. `MovieBy::Id` of a deleted movie
+    NotFound {
+        entity: String,
+        key: String,
+    },
+    UniqueViolation {
+        constraint: String,
+    },
+    ForeignKeyViolation {
+        constraint: String,
+    },
+    CheckViolation {
+        constraint: String,
+    },
+    /// An update based on an older version of the row, someone else changed it since
+    VersionConflict {
+        entity: String,
+        key: String,
+        current_version: i64,
+    },
+    /// Concurrent transactions conflicted, retrying the transaction may succeed
+    SerializationFailure,
+    /// The transaction was chosen as a deadlock victim, retrying it may succeed
+    Deadlock,
     CommunicationError,
     ConnectionFailed,
     ConnectionNotAvailable,
@@ -13,25 +40,151 @@ pub enum DatabaseError {
     Unknown(String),
     DatabaseInconsistence(String),
     MigrationFailed(String),
+    /// A `get_all` query the repository can't run, e.g. comparing a text field to a number
+    InvalidQuery(String),
+    /// An operation the entity doesn't support, e.g. updating an append-only row
+    Unsupported(String),
 }
 
+impl DatabaseError {
+    /// Whether running the same operation again may succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::SerializationFailure
+                | Self::Deadlock
+                | Self::CommunicationError
+                | Self::ConnectionNotAvailable
+        )
+    }
+
+    /// Whether callers are expected to handle it, e.g. a missing row or a violated constraint,
+    /// rather than it being a failure worth logging
+    pub fn is_expected(&self) -> bool {
+        matches!(
+            self,
+            Self::NotFound { .. }
+                | Self::UniqueViolation { .. }
+                | Self::ForeignKeyViolation { .. }
+                | Self::CheckViolation { .. }
+                | Self::VersionConflict { .. }
+                | Self::SerializationFailure
+                | Self::Deadlock
+                | Self::InvalidQuery(_)
+        )
+    }
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound { entity, key } => write!(f, "{} {} not found", entity, key),
+            Self::UniqueViolation { constraint } => write!(f, "unique violation: {}", constraint),
+            Self::ForeignKeyViolation { constraint } => {
+                write!(f, "foreign key violation: {}", constraint)
+            }
+            Self::CheckViolation { constraint } => write!(f, "check violation: {}", constraint),
+            Self::VersionConflict {
+                entity,
+                key,
+                current_version,
+            } => write!(
+                f,
+                "{} {} was changed, it is at version {}",
+                entity, key, current_version
+            ),
+            Self::SerializationFailure => write!(f, "serialization failure"),
+            Self::Deadlock => write!(f, "deadlock detected"),
+            Self::InvalidQuery(reason) => write!(f, "invalid query: {}", reason),
+            Self::Unsupported(reason) => write!(f, "unsupported: {}", reason),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl Error for DatabaseError {}
+
+// SQLSTATE codes of errors that have no `ErrorKind`
+const SERIALIZATION_FAILURE: &str = "40001";
+const DEADLOCK_DETECTED: &str = "40P01";
+
 impl From<SqlxError> for DatabaseError {
     fn from(value: SqlxError) -> Self {
-        eprintln!("sqlx error: {}", value);
-        match value {
-            SqlxError::ColumnNotFound(column_name) => Self::ColumnNotFound(column_name),
+        let error = match &value {
+            SqlxError::RowNotFound => Self::NotFound {
+                entity: "row".to_string(),
+                key: String::new(),
+            },
+            SqlxError::ColumnNotFound(column_name) => Self::ColumnNotFound(column_name.clone()),
             SqlxError::Io(_) | SqlxError::Tls(_) => Self::CommunicationError,
             SqlxError::PoolTimedOut => Self::ConnectionNotAvailable,
-            SqlxError::Database(e) => Self::QueryFailed(e.to_string()),
+            SqlxError::Database(e) => {
+                let constraint = e.constraint().unwrap_or_default().to_string();
+                match (e.kind(), e.code().as_deref()) {
+                    (ErrorKind::UniqueViolation, _) => Self::UniqueViolation { constraint },
+                    (ErrorKind::ForeignKeyViolation, _) => Self::ForeignKeyViolation { constraint },
+                    (ErrorKind::CheckViolation, _) => Self::CheckViolation { constraint },
+                    (_, Some(SERIALIZATION_FAILURE)) => Self::SerializationFailure,
+                    (_, Some(DEADLOCK_DETECTED)) => Self::Deadlock,
+                    _ => Self::QueryFailed(e.to_string()),
+                }
+            }
             SqlxError::Protocol(_) => Self::ProtocolNotSupported,
             SqlxError::TypeNotFound { type_name } => {
                 Self::DatabaseInconsistence(format!("TypeNotFound {type_name}"))
             }
             _ => Self::ConnectionFailed,
+        };
+        if !error.is_expected() {
+            eprintln!("sqlx error: {}", value);
         }
+        error
+    }
+}
+
+/// Error mapper for queries looking up a single `entity` by `key`,
+/// names both when there is no such row
+pub fn not_found<K: Debug>(entity: &str, key: &K) -> impl FnOnce(SqlxError) -> DatabaseError {
+    let entity = entity.to_string();
+    let key = format!("{:?}", key);
+    move |e| match e {
+        SqlxError::RowNotFound => DatabaseError::NotFound { entity, key },
+        e => DatabaseError::from(e),
+    }
+}
+
+/// Error mapper for updates of a single `entity` by `key` guarded by the version they expect,
+/// takes the version the row is at when the update changed nothing, `None` when there is no row
+pub fn version_conflict<K: Debug>(
+    entity: &str,
+    key: &K,
+) -> impl FnOnce(Option<i64>) -> DatabaseError {
+    let entity = entity.to_string();
+    let key = format!("{:?}", key);
+    move |current| match current {
+        Some(current_version) => DatabaseError::VersionConflict {
+            entity,
+            key,
+            current_version,
+        },
+        None => DatabaseError::NotFound { entity, key },
     }
 }
 
+/// Anything repositories run queries on: `&Pool`, `&mut Transaction` or `&mut` connection.
+/// Operations writing several rows open a transaction of their own on it,
+/// a savepoint when it is a transaction already.
+/// On SQLite every write does: sqlx may return before SQLite finished the statement,
+/// and outside a transaction the other connections of the pool could miss the change.
+pub trait Executor<'c, Db: Database>: Acquire<'c, Database = Db> + Send + 'c {}
+
+impl<'c, Db: Database, T: Acquire<'c, Database = Db> + Send + 'c> Executor<'c, Db> for T {}
+
+/// What repository operations outside `EntityRepository` return, boxed so they can
+/// run inside a `unit_of_work`
+pub type DatabaseFuture<'c, T> =
+    Pin<Box<dyn Future<Output = Result<T, DatabaseError>> + Send + 'c>>;
+
 #[async_trait::async_trait]
 pub trait EntityRepository<
     Db: Database,
@@ -42,14 +195,24 @@ pub trait EntityRepository<
     QueryMany: Send + Sync,
 >
 {
-    async fn insert(db: &Pool<Db>, input: CreateInput) -> Result<Entity, DatabaseError>;
-    async fn delete(db: &Pool<Db>, key: QueryOne) -> Result<Entity, DatabaseError>;
-    async fn update(
-        db: &Pool<Db>,
+    async fn insert<'c, E: Executor<'c, Db>>(
+        db: E,
+        input: CreateInput,
+    ) -> Result<Entity, DatabaseError>;
+    async fn delete<'c, E: Executor<'c, Db>>(db: E, key: QueryOne)
+        -> Result<Entity, DatabaseError>;
+    async fn update<'c, E: Executor<'c, Db>>(
+        db: E,
         key: QueryOne,
         update: UpdateInput,
     ) -> Result<Entity, DatabaseError>;
-    async fn get(db: &Pool<Db>, key: QueryOne) -> Result<Entity, DatabaseError>;
-    async fn try_get(db: &Pool<Db>, key: QueryOne) -> Result<Option<Entity>, DatabaseError>;
-    async fn get_all(db: &Pool<Db>, key: QueryMany) -> Result<Vec<Entity>, DatabaseError>;
+    async fn get<'c, E: Executor<'c, Db>>(db: E, key: QueryOne) -> Result<Entity, DatabaseError>;
+    async fn try_get<'c, E: Executor<'c, Db>>(
+        db: E,
+        key: QueryOne,
+    ) -> Result<Option<Entity>, DatabaseError>;
+    async fn get_all<'c, E: Executor<'c, Db>>(
+        db: E,
+        key: QueryMany,
+    ) -> Result<Vec<Entity>, DatabaseError>;
 }
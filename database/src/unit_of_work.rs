@@ -0,0 +1,35 @@
+use crate::traits::{DatabaseError, DatabaseFuture};
+use sqlx::{Database, Pool, Transaction};
+
+/// Runs `work` in a transaction, committed when it succeeds and rolled back when it fails.
+///
+/// ```ignore
+/// let (credential, session) = unit_of_work(&pool, |tx| {
+///     Box::pin(async move {
+///         let credential = CredentialsRepository::insert(&mut *tx, input).await?;
+///         let session = SessionsRepository::insert(&mut *tx, session_for(&credential)).await?;
+///         Ok((credential, session))
+///     })
+/// })
+/// .await?;
+/// ```
+pub async fn unit_of_work<Db, T, F>(pool: &Pool<Db>, work: F) -> Result<T, DatabaseError>
+where
+    Db: Database,
+    T: Send,
+    F: for<'t> FnOnce(&'t mut Transaction<'static, Db>) -> DatabaseFuture<'t, T> + Send,
+{
+    let mut tx = pool.begin().await?;
+    match work(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            if let Err(rollback) = tx.rollback().await {
+                eprintln!("could not roll back: {}", rollback);
+            }
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use core::service::CoreError;
+use juniper::{graphql_value, FieldError, IntoFieldError};
+
+/// What resolvers fail with, the `code` extension tells clients what went wrong
+/// without parsing the message and `retryable` whether sending the request again may help
+#[derive(Debug)]
+pub struct GraphQLError(pub CoreError);
+
+pub type GraphQLResult<T> = Result<T, GraphQLError>;
+
+impl From<CoreError> for GraphQLError {
+    fn from(value: CoreError) -> Self {
+        Self(value)
+    }
+}
+
+impl GraphQLError {
+    pub fn code(&self) -> &'static str {
+        match self.0 {
+            CoreError::InternalServerError => "INTERNAL_SERVER_ERROR",
+            CoreError::InvalidCredentials => "UNAUTHENTICATED",
+            CoreError::Forbidden => "FORBIDDEN",
+            CoreError::InvalidArgument(_) => "BAD_USER_INPUT",
+            CoreError::NotFound(_) => "NOT_FOUND",
+            CoreError::AlreadyExists(_) => "ALREADY_EXISTS",
+            CoreError::Conflict | CoreError::VersionConflict { .. } => "CONFLICT",
+            CoreError::Unavailable => "UNAVAILABLE",
+        }
+    }
+
+    pub fn retryable(&self) -> bool {
+        matches!(self.0, CoreError::Conflict | CoreError::Unavailable)
+    }
+}
+
+impl IntoFieldError for GraphQLError {
+    fn into_field_error(self) -> FieldError {
+        let code = self.code();
+        let retryable = self.retryable();
+        // clients merge their change into the current version and send it again based on it
+        let extensions = match self.0 {
+            CoreError::VersionConflict { current_version } => {
+                let current_version = graphql_version(current_version);
+                graphql_value!({
+                    "code": code,
+                    "retryable": retryable,
+                    "currentVersion": current_version,
+                })
+            }
+            _ => graphql_value!({ "code": code, "retryable": retryable }),
+        };
+        FieldError::new(self.0, extensions)
+    }
+}
+
+/// GraphQL `Int` is 32 bits, versions won't get past it in practice
+pub fn graphql_version(version: i64) -> i32 {
+    i32::try_from(version).unwrap_or(i32::MAX)
+}
@@ -1,5 +1,10 @@
 use chrono::{DateTime, Utc};
-use juniper::GraphQLInputObject;
+use core_database::{
+    entities::movies::MovieField,
+    entities::users::UserField,
+    query::{Direction, Field, Filter, Query},
+};
+use juniper::{GraphQLEnum, GraphQLInputObject};
 
 #[derive(Debug)]
 pub struct Birthday(pub DateTime<Utc>);
@@ -38,6 +43,31 @@ pub struct UserInput {
     pub name: String,
     pub password: String,
     pub birthday: Birthday,
+    /// Any unique value, retrying with the same one returns the account the first attempt created
+    pub idempotency_key: Option<String>,
+}
+
+/// Fails with a `CONFLICT` error carrying `currentVersion` when the movie changed since `expectedVersion`
+#[derive(GraphQLInputObject, Debug)]
+pub struct UpdateMovieInput {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub expected_version: i32,
+}
+
+/// Profile of an account that signed up through an identity provider
+#[derive(GraphQLInputObject, Debug)]
+pub struct CompleteSignUpInput {
+    pub name: String,
+    pub birthday: Birthday,
+}
+
+/// Fails with a `CONFLICT` error carrying `currentVersion` when the profile changed since `expectedVersion`
+#[derive(GraphQLInputObject, Debug)]
+pub struct UpdateProfileInput {
+    pub name: String,
+    pub expected_version: i32,
 }
 
 // TODO - Create scalar type for non negative numbers
@@ -47,3 +77,184 @@ pub struct PaginateInput {
     pub offset: i32,
     pub limit: i32,
 }
+
+impl PaginateInput {
+    /// `query` restricted to the requested page, ordered by `sort` and then by id
+    pub fn query<F: Field>(
+        &self,
+        filter: Option<Filter<F>>,
+        sort: impl IntoIterator<Item = (F, Direction)>,
+    ) -> Query<F> {
+        let mut query = Query::new().page(self.offset as u32, self.limit as u32);
+        query.filter = filter;
+        for (field, direction) in sort {
+            query = query.order_by(field, direction);
+        }
+        query
+    }
+}
+
+#[derive(GraphQLEnum, Debug, Clone, Copy)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl From<SortDirection> for Direction {
+    fn from(value: SortDirection) -> Self {
+        match value {
+            SortDirection::Asc => Direction::Asc,
+            SortDirection::Desc => Direction::Desc,
+        }
+    }
+}
+
+// every given condition must hold, `and` and `or` nest further filters
+fn all_of<F: Field>(
+    conditions: Vec<Option<Filter<F>>>,
+    and: Option<Vec<Filter<F>>>,
+    or: Option<Vec<Filter<F>>>,
+) -> Filter<F> {
+    let mut all = conditions.into_iter().flatten().collect::<Vec<_>>();
+    all.extend(and.unwrap_or_default());
+    all.extend(or.map(Filter::Or));
+    Filter::And(all)
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Movie Filter Input, matches movies meeting every given condition")]
+pub struct MovieFilterInput {
+    pub title: Option<String>,
+    /// Case insensitive
+    pub title_contains: Option<String>,
+    /// Case insensitive
+    pub description_contains: Option<String>,
+    pub available: Option<bool>,
+    pub and: Option<Vec<MovieFilterInput>>,
+    /// Matches when any of them does
+    pub or: Option<Vec<MovieFilterInput>>,
+}
+
+impl From<MovieFilterInput> for Filter<MovieField> {
+    fn from(value: MovieFilterInput) -> Self {
+        all_of(
+            vec![
+                value.title.map(|t| Filter::eq(MovieField::Title, t)),
+                value
+                    .title_contains
+                    .map(|t| Filter::contains(MovieField::Title, t)),
+                value
+                    .description_contains
+                    .map(|t| Filter::contains(MovieField::Description, t)),
+                value
+                    .available
+                    .map(|a| Filter::eq(MovieField::Available, a)),
+            ],
+            value.and.map(|f| f.into_iter().map(Filter::from).collect()),
+            value.or.map(|f| f.into_iter().map(Filter::from).collect()),
+        )
+    }
+}
+
+#[derive(GraphQLEnum, Debug, Clone, Copy)]
+pub enum MovieSortField {
+    Title,
+    Description,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Movie Sort Input")]
+pub struct MovieSortInput {
+    pub field: MovieSortField,
+    /// Ascending when missing
+    pub direction: Option<SortDirection>,
+}
+
+impl From<MovieSortInput> for (MovieField, Direction) {
+    fn from(value: MovieSortInput) -> Self {
+        let field = match value.field {
+            MovieSortField::Title => MovieField::Title,
+            MovieSortField::Description => MovieField::Description,
+        };
+        (field, value.direction.unwrap_or(SortDirection::Asc).into())
+    }
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "User Filter Input, matches users meeting every given condition")]
+pub struct UserFilterInput {
+    /// Case insensitive
+    pub name_contains: Option<String>,
+    pub active: Option<bool>,
+    pub born_after: Option<DateTime<Utc>>,
+    pub born_before: Option<DateTime<Utc>>,
+    pub and: Option<Vec<UserFilterInput>>,
+    /// Matches when any of them does
+    pub or: Option<Vec<UserFilterInput>>,
+}
+
+impl From<UserFilterInput> for Filter<UserField> {
+    fn from(value: UserFilterInput) -> Self {
+        all_of(
+            vec![
+                value
+                    .name_contains
+                    .map(|n| Filter::contains(UserField::Name, n)),
+                value.active.map(|a| Filter::eq(UserField::Active, a)),
+                value.born_after.map(|b| Filter::gt(UserField::Birthday, b)),
+                value
+                    .born_before
+                    .map(|b| Filter::lt(UserField::Birthday, b)),
+            ],
+            value.and.map(|f| f.into_iter().map(Filter::from).collect()),
+            value.or.map(|f| f.into_iter().map(Filter::from).collect()),
+        )
+    }
+}
+
+#[derive(GraphQLEnum, Debug, Clone, Copy)]
+pub enum UserSortField {
+    Name,
+    Birthday,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "User Sort Input")]
+pub struct UserSortInput {
+    pub field: UserSortField,
+    /// Ascending when missing
+    pub direction: Option<SortDirection>,
+}
+
+impl From<UserSortInput> for (UserField, Direction) {
+    fn from(value: UserSortInput) -> Self {
+        let field = match value.field {
+            UserSortField::Name => UserField::Name,
+            UserSortField::Birthday => UserField::Birthday,
+        };
+        (field, value.direction.unwrap_or(SortDirection::Asc).into())
+    }
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Change Password Input")]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Change Email Input")]
+pub struct ChangeEmailInput {
+    pub current_password: String,
+    pub new_email: String,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+#[graphql(description = "Create Api Key Input")]
+pub struct CreateApiKeyInput {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// The key never expires when missing
+    pub expires_at: Option<DateTime<Utc>>,
+}
@@ -16,20 +16,38 @@ use schemas::{create_schema, Schema};
 
 use clap::Parser;
 
-use database::connection::PgPool;
 use juniper::http::graphiql::graphiql_source;
 use send_wrapper::SendWrapper;
 
+mod error;
 mod input;
 pub mod mutation;
 mod output;
 pub mod query;
 pub mod schemas;
 
-use core::service::Core;
+use auth_token::{
+    scope, TokenVerifier, API_KEY_PREFIX, SESSION_ABSOLUTE_TIMEOUT_ENV,
+    SESSION_ABSOLUTE_TIMEOUT_HOURS,
+};
+use core::auth_gateway::{AuthGatewayConfig, TonicAuthGateway};
+use core::catalog::Format;
+use core::outbox::{EventSink, FileSink, OutboxRelay, RedisStreamSink, EVENTS_STREAM};
+use core::repository::PgCoreRepository;
+use core::service::{Core, CoreError, Principal};
+use core::session_cache::{subscribe_to_revocations, SessionCache};
+use core_database::migrations;
+use core_database::router::DatabaseConfig;
+use core_database::traits::DatabaseError;
+use core_database::types::{DateTime, Utc};
+use error::GraphQLResult;
+use serde::Deserialize;
+use tokio::sync::OnceCell;
 
-const SECS_IN_WEEK: i64 = 60 * 60 * 24 * 7;
 const SESSION_KEY: &str = "sid";
+const ERASURE_INTERVAL_IN_SECONDS: u64 = 60 * 60;
+const OUTBOX_RELAY_INTERVAL_IN_MILLISECONDS: u64 = 500;
+const OUTBOX_RETENTION_IN_DAYS: u64 = 7;
 
 /// GraphiQL playground UI
 #[route("/playground", method = "GET")]
@@ -37,6 +55,174 @@ async fn graphql_playground() -> impl Responder {
     Html(graphiql_source("/graphql", None))
 }
 
+/// Hits, misses and size of the session cache, `null` when it's disabled.
+/// For credentials with the `admin` role, their API keys need the scope too.
+#[route("/metrics/session-cache", method = "GET")]
+async fn session_cache_metrics(
+    req: HttpRequest,
+    session: Session,
+    core: Data<Core>,
+    sessions: Data<Option<Arc<SessionCache>>>,
+) -> impl Responder {
+    let principal = match authenticate_request(&req, &session, &core).await {
+        Ok(principal) => principal,
+        Err(response) => return response,
+    };
+    if principal.require_granted_scope(scope::ADMIN).is_err() {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    HttpResponse::Ok().json(sessions.get_ref().as_ref().map(|s| s.stats()))
+}
+
+/// Caller of a plain HTTP route, or the response refusing it
+async fn authenticate_request(
+    req: &HttpRequest,
+    session: &Session,
+    core: &Core,
+) -> std::result::Result<Principal, HttpResponse> {
+    let Some(credentials) = Credentials::from_request(req, session) else {
+        return Err(HttpResponse::Unauthorized().finish());
+    };
+    match credentials.authenticate(core).await {
+        Ok(principal) => Ok(principal),
+        Err(CoreError::InvalidCredentials) => Err(HttpResponse::Unauthorized().finish()),
+        Err(CoreError::Forbidden) => Err(HttpResponse::Forbidden().finish()),
+        Err(e) => {
+            eprintln!("could not authenticate request: {:?}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// "Download my data", the whole account as a JSON archive
+#[route("/me/export", method = "GET")]
+async fn export_account(req: HttpRequest, session: Session, core: Data<Core>) -> impl Responder {
+    let principal = match authenticate_request(&req, &session, &core).await {
+        Ok(principal) => principal,
+        Err(response) => return response,
+    };
+
+    match core.export_account(&principal).await {
+        Ok(export) => HttpResponse::Ok()
+            .insert_header((
+                http::header::CONTENT_DISPOSITION,
+                "attachment; filename=\"rustflix-account.json\"",
+            ))
+            .json(export),
+        Err(CoreError::InvalidCredentials) => HttpResponse::Unauthorized().finish(),
+        Err(CoreError::Forbidden) => HttpResponse::Forbidden().finish(),
+        Err(e) => {
+            eprintln!("could not export account: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CatalogExportParams {
+    /// `jsonl` when missing, or `csv`
+    format: Option<String>,
+    /// RFC 3339, only the movies changed after it are exported
+    updated_since: Option<String>,
+}
+
+/// The movie catalog as a file for partners' syncs, streamed as it's read from the database
+#[route("/catalog/export", method = "GET")]
+async fn export_catalog(
+    req: HttpRequest,
+    session: Session,
+    core: Data<Core>,
+    params: web::Query<CatalogExportParams>,
+) -> impl Responder {
+    let format = match params.format.as_deref() {
+        None => Format::JsonLines,
+        Some(name) => match Format::from_extension(name) {
+            Some(format) => format,
+            None => return HttpResponse::BadRequest().body("format must be jsonl or csv"),
+        },
+    };
+    let updated_since = match params.updated_since.as_deref() {
+        None => None,
+        Some(since) => match DateTime::parse_from_rfc3339(since) {
+            Ok(since) => Some(since.with_timezone(&Utc)),
+            Err(_) => {
+                return HttpResponse::BadRequest()
+                    .body("updated_since must be an RFC 3339 timestamp")
+            }
+        },
+    };
+    let principal = match authenticate_request(&req, &session, &core).await {
+        Ok(principal) => principal,
+        Err(response) => return response,
+    };
+
+    let export = match core.export_catalog(&principal, format, updated_since).await {
+        Ok(export) => export,
+        Err(CoreError::InvalidCredentials) => return HttpResponse::Unauthorized().finish(),
+        Err(CoreError::Forbidden) => return HttpResponse::Forbidden().finish(),
+        Err(e) => {
+            eprintln!("could not export catalog: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    // a failure halfway through cuts the download short, clients see an incomplete body
+    let chunks = futures::stream::try_unfold(export, |mut export| async move {
+        let chunk = export.next_chunk().await?;
+        Ok::<_, DatabaseError>(chunk.map(|chunk| (web::Bytes::from(chunk), export)))
+    });
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            http::header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"rustflix-catalog.{}\"",
+                format.extension()
+            ),
+        ))
+        .streaming(chunks)
+}
+
+/// What the caller proved its identity with
+enum Credentials {
+    AccessToken(String),
+    ApiKey(String),
+    SessionId(String),
+}
+
+impl Credentials {
+    /// A bearer access token or API key wins over the session cookie set by the auth API on sign in
+    fn from_request(req: &HttpRequest, session: &Session) -> Option<Self> {
+        let bearer = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match bearer {
+            Some(key) if key.starts_with(API_KEY_PREFIX) => {
+                return Some(Credentials::ApiKey(key.to_string()))
+            }
+            Some(token) => return Some(Credentials::AccessToken(token.to_string())),
+            None => {}
+        }
+
+        session
+            .get::<String>(SESSION_KEY)
+            .ok()
+            .flatten()
+            .map(Credentials::SessionId)
+    }
+
+    /// Access tokens are verified locally, API keys and sessions are checked with the auth service
+    async fn authenticate(self, core: &Core) -> std::result::Result<Principal, CoreError> {
+        match self {
+            Credentials::AccessToken(token) => core.authenticate_token(&token).await,
+            Credentials::ApiKey(key) => core.authenticate_api_key(key).await,
+            Credentials::SessionId(session_id) => core.authenticate(session_id).await,
+        }
+    }
+}
+
 #[route("/graphql", method = "POST", method = "GET")]
 async fn graphql(
     req: HttpRequest,
@@ -57,7 +243,37 @@ struct Args {
     /// Database URL to connect with core database
     #[arg(env = "GRAPHQL_DATABASE_URL")]
     database_url: String,
-    /// Auth microservice grpc port
+    /// Read replicas of the core database, comma separated
+    #[arg(long, env = "GRAPHQL_DATABASE_REPLICA_URLS", value_delimiter = ',')]
+    database_replica_urls: Vec<String>,
+    /// Connections kept open to each database even when idle
+    #[arg(long, env = "GRAPHQL_DATABASE_MIN_CONNECTIONS", default_value_t = 0)]
+    database_min_connections: u32,
+    /// Connections open to each database at most
+    #[arg(long, env = "GRAPHQL_DATABASE_MAX_CONNECTIONS", default_value_t = 10)]
+    database_max_connections: u32,
+    /// Seconds a query waits for a free connection before failing
+    #[arg(
+        long,
+        env = "GRAPHQL_DATABASE_ACQUIRE_TIMEOUT_SECONDS",
+        default_value_t = 30
+    )]
+    database_acquire_timeout_seconds: u64,
+    /// Seconds before idle connections are closed, 0 keeps them open
+    #[arg(
+        long,
+        env = "GRAPHQL_DATABASE_IDLE_TIMEOUT_SECONDS",
+        default_value_t = 600
+    )]
+    database_idle_timeout_seconds: u64,
+    /// Prepared statements cached per connection, 0 disables them, e.g. behind pgbouncer
+    #[arg(
+        long,
+        env = "GRAPHQL_DATABASE_STATEMENT_CACHE_CAPACITY",
+        default_value_t = 100
+    )]
+    database_statement_cache_capacity: usize,
+    /// Auth microservice grpc endpoints, comma separated to balance calls between replicas
     #[arg(env = "GRAPHQL_AUTH_GRPC_PORT")]
     auth_grpc_port: String,
     /// URL to connect with redis instance
@@ -66,12 +282,41 @@ struct Args {
     /// Private key for session storage
     #[arg(env = "PRIVATE_SESSION_KEY")]
     session_private_key: String,
+    /// Days a closed account is kept before it's erased
+    #[arg(long, env = "ACCOUNT_ERASURE_GRACE_DAYS", default_value_t = 30)]
+    account_erasure_grace_days: u64,
+    /// Hours a session can live since sign in, read from the same variable as the auth service
+    #[arg(long, env = SESSION_ABSOLUTE_TIMEOUT_ENV, default_value_t = SESSION_ABSOLUTE_TIMEOUT_HOURS)]
+    session_absolute_timeout_hours: u64,
+    /// Key set published by the auth API, bearer access tokens are rejected without it
+    #[arg(long, env = "GRAPHQL_AUTH_JWKS_URL")]
+    auth_jwks_url: Option<String>,
+    /// Deadline of every call to the auth microservice
+    #[arg(long, env = "GRAPHQL_AUTH_GRPC_TIMEOUT_MS", default_value_t = 2000)]
+    auth_grpc_timeout_ms: u64,
+    /// Extra attempts of idempotent calls to the auth microservice when it's unavailable
+    #[arg(long, env = "GRAPHQL_AUTH_GRPC_RETRIES", default_value_t = 2)]
+    auth_grpc_retries: u32,
+    /// Seconds a validated session is trusted without asking the auth microservice, 0 disables the cache
+    #[arg(long, env = "GRAPHQL_SESSION_CACHE_TTL_SECONDS", default_value_t = 30)]
+    session_cache_ttl_seconds: u64,
+    /// Sessions kept in the cache at most
+    #[arg(long, env = "GRAPHQL_SESSION_CACHE_CAPACITY", default_value_t = 10_000)]
+    session_cache_capacity: usize,
+    /// Where catalog and user events go: `redis` for the `rustflix:events` stream,
+    /// `file:<path>` for JSON lines or `none` to keep them in the outbox
+    #[arg(long, env = "GRAPHQL_OUTBOX_SINK", default_value = "redis")]
+    outbox_sink: String,
+    /// Apply pending database migrations before serving, otherwise they are only reported
+    #[arg(long)]
+    migrate: bool,
 }
 
 #[derive(Clone)]
 pub struct Context {
     pub request: Option<SendWrapper<HttpRequest>>,
     pub session: Option<SendWrapper<Session>>,
+    principal: OnceCell<Principal>,
 }
 
 impl juniper::Context for Context {}
@@ -81,8 +326,29 @@ impl Context {
         Self {
             request: Some(SendWrapper::new(req)),
             session: Some(SendWrapper::new(session)),
+            principal: OnceCell::new(),
         }
     }
+
+    /// Caller of the request, authenticated once and shared by every resolver
+    pub async fn principal(&self, core: &Core) -> GraphQLResult<Principal> {
+        let (Some(req), Some(session)) = (&self.request, &self.session) else {
+            eprintln!("cannot retrieve request from context");
+            return Err(CoreError::InternalServerError.into());
+        };
+        let credentials = Credentials::from_request(req, session);
+
+        let principal = self
+            .principal
+            .get_or_try_init(|| async move {
+                match credentials {
+                    Some(credentials) => credentials.authenticate(core).await,
+                    None => Err(CoreError::InvalidCredentials),
+                }
+            })
+            .await?;
+        Ok(principal.clone())
+    }
 }
 
 #[actix_web::main]
@@ -90,24 +356,110 @@ async fn main() -> Result<()> {
     dotenv::dotenv().expect("Could not parse environment variables");
     let args = Args::parse();
 
-    let store = RedisSessionStore::new(args.redis_session_storage_url)
+    let store = RedisSessionStore::new(args.redis_session_storage_url.clone())
         .await
         .expect("Could not initialize redis instance");
 
-    let pool = PgPool::connect(&args.database_url)
+    let database = DatabaseConfig {
+        replica_urls: args.database_replica_urls.clone(),
+        min_connections: args.database_min_connections,
+        max_connections: args.database_max_connections,
+        acquire_timeout: std::time::Duration::from_secs(args.database_acquire_timeout_seconds),
+        idle_timeout: (args.database_idle_timeout_seconds > 0)
+            .then(|| std::time::Duration::from_secs(args.database_idle_timeout_seconds)),
+        statement_cache_capacity: args.database_statement_cache_capacity,
+        ..DatabaseConfig::new(&args.database_url)
+    };
+    let router = database
+        .connect()
         .await
         .expect("Could not connect to database");
-    let core = Core::new(args.auth_grpc_port, pool).await;
-    let schema = Arc::new(create_schema(core));
+    migrations::prepare(&core_database::MIGRATOR, router.write(), args.migrate)
+        .await
+        .expect("Could not migrate database");
+    let auth = TonicAuthGateway::new(AuthGatewayConfig {
+        timeout: std::time::Duration::from_millis(args.auth_grpc_timeout_ms),
+        retries: args.auth_grpc_retries,
+        ..AuthGatewayConfig::new(
+            args.auth_grpc_port
+                .split(',')
+                .map(|endpoint| endpoint.trim().to_string())
+                .collect(),
+        )
+    })
+    .expect("Invalid auth grpc configuration");
+    let sink: Option<Arc<dyn EventSink>> = match args.outbox_sink.as_str() {
+        "none" => None,
+        "redis" => Some(Arc::new(
+            RedisStreamSink::new(&args.redis_session_storage_url, EVENTS_STREAM)
+                .expect("Invalid redis url"),
+        )),
+        sink => match sink.strip_prefix("file:") {
+            Some(path) => Some(Arc::new(FileSink::new(path))),
+            None => panic!("Unknown outbox sink {:?}", sink),
+        },
+    };
+    let relay = sink.map(|sink| OutboxRelay::new(router.write().clone(), sink));
+    if let Some(relay) = relay.clone() {
+        actix_web::rt::spawn(relay.run(std::time::Duration::from_millis(
+            OUTBOX_RELAY_INTERVAL_IN_MILLISECONDS,
+        )));
+    }
+
+    let mut core = Core::new(auth, PgCoreRepository::new(router));
+    if let Some(jwks_url) = &args.auth_jwks_url {
+        let verifier = TokenVerifier::from_jwks_uri(jwks_url)
+            .await
+            .expect("Could not fetch auth token keys");
+        core = core.with_token_verifier(verifier);
+    }
+    let sessions = (args.session_cache_ttl_seconds > 0).then(|| {
+        Arc::new(SessionCache::new(
+            args.session_cache_capacity,
+            std::time::Duration::from_secs(args.session_cache_ttl_seconds),
+        ))
+    });
+    if let Some(sessions) = &sessions {
+        core = core.with_session_cache(sessions.clone());
+        actix_web::rt::spawn(subscribe_to_revocations(
+            sessions.clone(),
+            args.redis_session_storage_url.clone(),
+        ));
+    }
+    let schema = Arc::new(create_schema(core.clone()));
+
+    let eraser = core.clone();
+    let grace = std::time::Duration::from_secs(args.account_erasure_grace_days * 60 * 60 * 24);
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(ERASURE_INTERVAL_IN_SECONDS));
+        loop {
+            interval.tick().await;
+            match eraser.erase_closed_accounts(grace).await {
+                Ok(0) => {}
+                Ok(erased) => println!("erased {} closed accounts", erased),
+                Err(e) => eprintln!("could not erase closed accounts: {:?}", e),
+            }
+            if let Some(relay) = &relay {
+                let retention =
+                    std::time::Duration::from_secs(OUTBOX_RETENTION_IN_DAYS * 60 * 60 * 24);
+                match relay.purge_published(retention).await {
+                    Ok(0) => {}
+                    Ok(purged) => println!("purged {} published outbox events", purged),
+                    Err(e) => eprintln!("could not purge outbox events: {:?}", e),
+                }
+            }
+        }
+    });
+    let session_ttl = Duration::hours(args.session_absolute_timeout_hours as i64);
     let app = move || {
         let key = Key::derive_from(args.session_private_key.as_ref());
-        let cors = Cors::default()
-            .supports_credentials()
-            .allowed_headers(vec![http::header::CONTENT_TYPE]);
+        let cors = Cors::default().supports_credentials().allowed_headers(vec![
+            http::header::CONTENT_TYPE,
+            http::header::AUTHORIZATION,
+        ]);
         let session = SessionMiddleware::builder(store.clone(), key)
-            .session_lifecycle(
-                PersistentSession::default().session_ttl(Duration::seconds(SECS_IN_WEEK)),
-            )
+            .session_lifecycle(PersistentSession::default().session_ttl(session_ttl))
             .cookie_domain(Some("localhost".to_string()))
             .cookie_name(SESSION_KEY.to_string())
             .cookie_http_only(true)
@@ -118,8 +470,13 @@ async fn main() -> Result<()> {
             .wrap(session)
             .wrap(cors)
             .app_data(Data::from(Arc::clone(&schema)))
+            .app_data(Data::new(core.clone()))
+            .app_data(Data::new(sessions.clone()))
             .service(graphql)
+            .service(export_account)
+            .service(export_catalog)
             .service(graphql_playground)
+            .service(session_cache_metrics)
     };
 
     HttpServer::new(app)
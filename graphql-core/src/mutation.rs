@@ -1,8 +1,15 @@
-use crate::input::UserInput;
-use crate::output::User;
+use crate::error::GraphQLResult;
+use crate::input::{
+    ChangeEmailInput, ChangePasswordInput, CompleteSignUpInput, CreateApiKeyInput,
+    UpdateMovieInput, UpdateProfileInput, UserInput,
+};
+use crate::output::{CreatedApiKey, User};
+use crate::query::Movie;
 use crate::Context;
-use core::service::Core;
-use juniper::{graphql_object, FieldError, FieldResult};
+use core::service::{Core, CoreError};
+use core_database::types::Uuid;
+use juniper::graphql_object;
+use std::str::FromStr;
 
 pub struct MutationRoot {
     core: Core,
@@ -16,13 +23,140 @@ impl MutationRoot {
 
 #[graphql_object(context = Context)]
 impl MutationRoot {
-    async fn create_account(&self, _ctx: &Context, user: UserInput) -> FieldResult<User> {
+    async fn create_account(&self, _ctx: &Context, user: UserInput) -> GraphQLResult<User> {
         let response = self
             .core
-            .create_account(user.email, user.password, user.name, user.birthday.0)
-            .await
-            .map_err(|e| FieldError::new(e, juniper::Value::null()))?;
+            .create_account(
+                user.email,
+                user.password,
+                user.name,
+                user.birthday.0,
+                user.idempotency_key,
+            )
+            .await?;
 
         Ok(response.into())
     }
+
+    /// After the first sign in through an identity provider, which only creates the credential
+    async fn complete_sign_up(
+        &self,
+        ctx: &Context,
+        input: CompleteSignUpInput,
+    ) -> GraphQLResult<User> {
+        let user = self
+            .core
+            .complete_sign_up(
+                &ctx.principal(&self.core).await?,
+                input.name,
+                input.birthday.0,
+            )
+            .await?;
+        Ok(user.into())
+    }
+
+    async fn update_profile(
+        &self,
+        ctx: &Context,
+        input: UpdateProfileInput,
+    ) -> GraphQLResult<User> {
+        let user = self
+            .core
+            .update_profile(
+                &ctx.principal(&self.core).await?,
+                input.name,
+                input.expected_version.into(),
+            )
+            .await?;
+        Ok(user.into())
+    }
+
+    /// For editors, credentials with the `movies:write` role, their API keys need the scope too
+    async fn update_movie(&self, ctx: &Context, input: UpdateMovieInput) -> GraphQLResult<Movie> {
+        let principal = ctx.principal(&self.core).await?;
+        let id = Uuid::from_str(&input.id)
+            .map_err(|_| CoreError::InvalidArgument(format!("not a movie id: {}", input.id)))?;
+        let movie = self
+            .core
+            .update_movie(
+                &principal,
+                id,
+                input.title,
+                input.description,
+                input.expected_version.into(),
+            )
+            .await?;
+        Ok(Movie::new(&self.core, movie))
+    }
+
+    /// Signs out every other session of the user
+    async fn change_password(
+        &self,
+        ctx: &Context,
+        input: ChangePasswordInput,
+    ) -> GraphQLResult<bool> {
+        self.core
+            .change_password(
+                &ctx.principal(&self.core).await?,
+                input.current_password,
+                input.new_password,
+            )
+            .await?;
+        Ok(true)
+    }
+
+    /// Emails a verification token, the address only changes after `confirmEmailChange`
+    async fn change_email(&self, ctx: &Context, input: ChangeEmailInput) -> GraphQLResult<bool> {
+        self.core
+            .change_email(
+                &ctx.principal(&self.core).await?,
+                input.current_password,
+                input.new_email,
+            )
+            .await?;
+        Ok(true)
+    }
+
+    async fn confirm_email_change(&self, _ctx: &Context, token: String) -> GraphQLResult<bool> {
+        self.core.confirm_email_change(token).await?;
+        Ok(true)
+    }
+
+    /// Closes the account, it's erased for good after the grace period. Sessions signed in
+    /// through an identity provider in the last few minutes don't need the password.
+    async fn close_account(&self, ctx: &Context, password: String) -> GraphQLResult<bool> {
+        let principal = ctx.principal(&self.core).await?;
+        self.core.close_account(&principal, password).await?;
+        if let Some(session) = &ctx.session {
+            session.purge();
+        }
+        Ok(true)
+    }
+
+    /// Signs out one of the user's other sessions, e.g. a lost device
+    async fn revoke_session(&self, ctx: &Context, id: String) -> GraphQLResult<bool> {
+        let principal = ctx.principal(&self.core).await?;
+        self.core.revoke_session(&principal, id).await?;
+        Ok(true)
+    }
+
+    /// Key for `Authorization: Bearer` access without a session, e.g. for partners
+    async fn create_api_key(
+        &self,
+        ctx: &Context,
+        input: CreateApiKeyInput,
+    ) -> GraphQLResult<CreatedApiKey> {
+        let principal = ctx.principal(&self.core).await?;
+        let created = self
+            .core
+            .create_api_key(&principal, input.name, input.scopes, input.expires_at)
+            .await?;
+        Ok(created.into())
+    }
+
+    async fn revoke_api_key(&self, ctx: &Context, id: String) -> GraphQLResult<bool> {
+        let principal = ctx.principal(&self.core).await?;
+        self.core.revoke_api_key(&principal, id).await?;
+        Ok(true)
+    }
 }
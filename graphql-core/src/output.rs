@@ -1,5 +1,10 @@
+use crate::error::graphql_version;
 use crate::input::Birthday;
-use core::dto::user::UserDTO;
+use core::dto::{
+    account::{ApiKeyDTO, CreatedApiKeyDTO, SessionDTO},
+    history::HistoryEntryDTO,
+    user::UserDTO,
+};
 use juniper::graphql_object;
 
 #[derive(Debug)]
@@ -8,6 +13,7 @@ pub struct User {
     pub name: String,
     pub active: bool,
     pub birthday: Birthday,
+    pub version: i64,
 }
 
 #[graphql_object]
@@ -27,6 +33,11 @@ impl User {
     fn birthday(&self) -> &Birthday {
         &self.birthday
     }
+
+    /// What `updateProfile` has to be based on
+    fn version(&self) -> i32 {
+        graphql_version(self.version)
+    }
 }
 
 impl From<UserDTO> for User {
@@ -36,6 +47,196 @@ impl From<UserDTO> for User {
             name: value.name,
             active: value.active,
             birthday: Birthday(value.birthday),
+            version: value.version,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Session {
+    pub id: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub device_label: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub current: bool,
+}
+
+#[graphql_object]
+impl Session {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn expires_at(&self) -> &str {
+        &self.expires_at
+    }
+
+    fn device_label(&self) -> Option<&str> {
+        self.device_label.as_deref()
+    }
+
+    fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    fn ip_address(&self) -> Option<&str> {
+        self.ip_address.as_deref()
+    }
+
+    /// Whether this is the session making the request
+    fn current(&self) -> bool {
+        self.current
+    }
+}
+
+impl From<SessionDTO> for Session {
+    fn from(value: SessionDTO) -> Self {
+        Self {
+            id: value.id,
+            created_at: value.created_at.to_rfc3339(),
+            expires_at: value.expires_at.to_rfc3339(),
+            device_label: value.device_label,
+            user_agent: value.user_agent,
+            ip_address: value.ip_address,
+            current: value.current,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+}
+
+#[graphql_object]
+impl ApiKey {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Public part of the key, enough to tell keys apart
+    fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    fn expires_at(&self) -> Option<&str> {
+        self.expires_at.as_deref()
+    }
+
+    fn last_used_at(&self) -> Option<&str> {
+        self.last_used_at.as_deref()
+    }
+}
+
+impl From<ApiKeyDTO> for ApiKey {
+    fn from(value: ApiKeyDTO) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            prefix: value.prefix,
+            scopes: value.scopes,
+            created_at: value.created_at.to_rfc3339(),
+            expires_at: value.expires_at.map(|t| t.to_rfc3339()),
+            last_used_at: value.last_used_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CreatedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[graphql_object]
+impl CreatedApiKey {
+    /// The plain key, it's only shown this once
+    fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn api_key(&self) -> &ApiKey {
+        &self.api_key
+    }
+}
+
+impl From<CreatedApiKeyDTO> for CreatedApiKey {
+    fn from(value: CreatedApiKeyDTO) -> Self {
+        Self {
+            key: value.key,
+            api_key: value.api_key.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub action: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub actor: Option<String>,
+    pub changed_at: String,
+}
+
+#[graphql_object]
+impl HistoryEntry {
+    /// insert, update, delete or erase
+    fn action(&self) -> &str {
+        &self.action
+    }
+
+    /// JSON document of the entity before the change, missing for inserts
+    fn before(&self) -> Option<&str> {
+        self.before.as_deref()
+    }
+
+    /// JSON document of the entity after the change, missing for deletes
+    fn after(&self) -> Option<&str> {
+        self.after.as_deref()
+    }
+
+    /// Id of the user who made the change, missing for the service's own jobs
+    fn actor(&self) -> Option<&str> {
+        self.actor.as_deref()
+    }
+
+    fn changed_at(&self) -> &str {
+        &self.changed_at
+    }
+}
+
+impl From<HistoryEntryDTO> for HistoryEntry {
+    fn from(value: HistoryEntryDTO) -> Self {
+        Self {
+            action: value.action,
+            before: value.before,
+            after: value.after,
+            actor: value.actor,
+            changed_at: value.changed_at.to_rfc3339(),
         }
     }
 }
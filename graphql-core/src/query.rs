@@ -1,22 +1,34 @@
-use crate::input::PaginateInput;
+use crate::error::{graphql_version, GraphQLResult};
+use crate::input::{
+    MovieFilterInput, MovieSortInput, PaginateInput, UserFilterInput, UserSortInput,
+};
+use crate::output::{ApiKey, HistoryEntry, Session, User};
 use crate::Context;
-use core::{dto::movie::MovieDTO, service::Core};
-use database::types::Uuid;
-use juniper::{graphql_object, FieldError, FieldResult, Value};
+use core::{
+    dto::movie::MovieDTO,
+    service::{Core, CoreError},
+};
+use core_database::types::Uuid;
+use juniper::graphql_object;
 use std::str::FromStr;
 
 pub struct QueryRoot {
     pub core: Core,
 }
 
-#[derive(Debug)]
 pub struct Movie {
     pub id: String,
     pub title: String,
     pub description: String,
+    pub genres: Vec<String>,
+    pub cast: Vec<String>,
+    pub available: bool,
+    pub version: i64,
+    /// For the fields loaded on demand
+    core: Core,
 }
 
-#[graphql_object]
+#[graphql_object(context = Context)]
 impl Movie {
     fn id(&self) -> &str {
         &self.id
@@ -27,14 +39,46 @@ impl Movie {
     fn description(&self) -> &str {
         &self.description
     }
+    fn genres(&self) -> &[String] {
+        &self.genres
+    }
+    fn cast(&self) -> &[String] {
+        &self.cast
+    }
+    /// Whether it can be watched now
+    fn available(&self) -> bool {
+        self.available
+    }
+    /// What `updateMovie` has to be based on
+    fn version(&self) -> i32 {
+        graphql_version(self.version)
+    }
+    /// Every change made to the movie, oldest first. Admins only.
+    async fn history(&self, ctx: &Context) -> GraphQLResult<Vec<HistoryEntry>> {
+        let principal = ctx.principal(&self.core).await?;
+        let id = Uuid::from_str(&self.id).map_err(|_| CoreError::InternalServerError)?;
+        let history = self
+            .core
+            .movie_history(&principal, id)
+            .await?
+            .into_iter()
+            .map(HistoryEntry::from)
+            .collect();
+        Ok(history)
+    }
 }
 
-impl From<MovieDTO> for Movie {
-    fn from(value: MovieDTO) -> Self {
+impl Movie {
+    pub fn new(core: &Core, value: MovieDTO) -> Self {
         Self {
             id: value.id,
             title: value.title,
             description: value.description,
+            genres: value.genres,
+            cast: value.cast_members,
+            available: value.available,
+            version: value.version,
+            core: core.clone(),
         }
     }
 }
@@ -47,38 +91,83 @@ impl QueryRoot {
 
 #[graphql_object(context = Context)]
 impl QueryRoot {
-    async fn movies(&self, ctx: &Context, input: PaginateInput) -> FieldResult<Vec<Movie>> {
-        if let Some(session) = &ctx.session {
-            if let Ok(Some(cookie)) = session.get::<String>("sid") {
-                let movies = self
-                    .core
-                    .list_movies(cookie, input.offset as u32, input.limit as u32)
-                    .await?
-                    .into_iter()
-                    .map(Movie::from)
-                    .collect::<Vec<Movie>>();
-                Ok(movies)
-            } else {
-                Err(FieldError::new("Invalid Credentials", Value::Null))
-            }
-        } else {
-            eprintln!("cannot retrieve session from context");
-            Err(FieldError::new("Internal Server Error", Value::Null))
-        }
+    /// Movies matching `filter`, ordered by `sort` and then by id
+    async fn movies(
+        &self,
+        ctx: &Context,
+        input: PaginateInput,
+        filter: Option<MovieFilterInput>,
+        sort: Option<Vec<MovieSortInput>>,
+    ) -> GraphQLResult<Vec<Movie>> {
+        let query = input.query(
+            filter.map(Into::into),
+            sort.unwrap_or_default().into_iter().map(Into::into),
+        );
+        let movies = self
+            .core
+            .list_movies(&ctx.principal(&self.core).await?, query)
+            .await?
+            .into_iter()
+            .map(|movie| Movie::new(&self.core, movie))
+            .collect::<Vec<Movie>>();
+        Ok(movies)
     }
 
-    async fn movie(&self, ctx: &Context, movie_id: String) -> FieldResult<Option<Movie>> {
-        if let Some(session) = &ctx.session {
-            if let Ok(Some(cookie)) = session.get::<String>("sid") {
-                let uuid = Uuid::from_str(&movie_id).unwrap();
-                let movie = self.core.movie(cookie, uuid).await?.map(Movie::from);
-                Ok(movie)
-            } else {
-                Err(FieldError::new("Invalid Credentials", Value::Null))
-            }
-        } else {
-            eprintln!("cannot retrieve session from context");
-            Err(FieldError::new("Internal Server Error", Value::Null))
-        }
+    /// Users matching `filter`, for credentials with the `users:read` role
+    async fn users(
+        &self,
+        ctx: &Context,
+        input: PaginateInput,
+        filter: Option<UserFilterInput>,
+        sort: Option<Vec<UserSortInput>>,
+    ) -> GraphQLResult<Vec<User>> {
+        let query = input.query(
+            filter.map(Into::into),
+            sort.unwrap_or_default().into_iter().map(Into::into),
+        );
+        let users = self
+            .core
+            .list_users(&ctx.principal(&self.core).await?, query)
+            .await?
+            .into_iter()
+            .map(User::from)
+            .collect();
+        Ok(users)
+    }
+
+    async fn movie(&self, ctx: &Context, movie_id: String) -> GraphQLResult<Option<Movie>> {
+        let principal = ctx.principal(&self.core).await?;
+        let uuid = Uuid::from_str(&movie_id)
+            .map_err(|_| CoreError::InvalidArgument(format!("not a movie id: {}", movie_id)))?;
+        let movie = self
+            .core
+            .movie(&principal, uuid)
+            .await?
+            .map(|movie| Movie::new(&self.core, movie));
+        Ok(movie)
+    }
+
+    /// Sessions the user is currently signed in with
+    async fn my_sessions(&self, ctx: &Context) -> GraphQLResult<Vec<Session>> {
+        let sessions = self
+            .core
+            .my_sessions(&ctx.principal(&self.core).await?)
+            .await?
+            .into_iter()
+            .map(Session::from)
+            .collect();
+        Ok(sessions)
+    }
+
+    /// API keys the user created for partners and services
+    async fn my_api_keys(&self, ctx: &Context) -> GraphQLResult<Vec<ApiKey>> {
+        let api_keys = self
+            .core
+            .my_api_keys(&ctx.principal(&self.core).await?)
+            .await?
+            .into_iter()
+            .map(ApiKey::from)
+            .collect();
+        Ok(api_keys)
     }
 }
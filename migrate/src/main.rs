@@ -0,0 +1,135 @@
+use auth_database::connection::{self, PgPool, Pool};
+use auth_database::migrations::{self, Migrate, MigrationStatus, Migrator};
+use auth_database::traits::DatabaseError;
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// Applies, reverts and lists the migrations embedded in the database crates
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Auth database URL, its migrations are skipped without it.
+    /// A `sqlite:` URL with the `sqlite` feature, like the core one.
+    #[arg(long, env = "AUTH_POSTGRES_URL")]
+    auth_database_url: Option<String>,
+
+    /// Core database URL, its migrations are skipped without it
+    #[arg(long, env = "GRAPHQL_DATABASE_URL")]
+    core_database_url: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Applies every pending migration
+    Run,
+    /// Reverts the migrations of one database newer than `--to`
+    Rollback {
+        database: Database,
+        /// Version to go back to, 0 reverts everything
+        #[arg(long)]
+        to: i64,
+    },
+    /// Lists the migrations and whether they are applied
+    Status,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Database {
+    Auth,
+    Core,
+}
+
+impl Database {
+    fn migrator(self) -> &'static Migrator {
+        match self {
+            Database::Auth => &auth_database::MIGRATOR,
+            Database::Core => &core_database::MIGRATOR,
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn sqlite_migrator(self) -> &'static Migrator {
+        match self {
+            Database::Auth => &auth_database::SQLITE_MIGRATOR,
+            Database::Core => &core_database::SQLITE_MIGRATOR,
+        }
+    }
+}
+
+fn print_status(database: Database, migrations: &[MigrationStatus]) {
+    for migration in migrations {
+        println!(
+            "{:?}\t{}\t{:?}\t{}",
+            database, migration.version, migration.state, migration.description
+        );
+    }
+}
+
+async fn execute<Db>(
+    command: &Command,
+    database: Database,
+    migrator: &Migrator,
+    pool: &Pool<Db>,
+) -> Result<(), DatabaseError>
+where
+    Db: connection::Database,
+    Db::Connection: Migrate,
+{
+    match command {
+        Command::Run => {
+            migrations::migrate(migrator, pool).await?;
+            println!("{:?} database is up to date", database);
+        }
+        Command::Rollback { to, .. } => {
+            migrations::rollback(migrator, pool, *to).await?;
+            println!("{:?} database is back at {}", database, to);
+        }
+        Command::Status => print_status(database, &migrations::status(migrator, pool).await?),
+    }
+    Ok(())
+}
+
+async fn connect_and_execute(
+    command: &Command,
+    database: Database,
+    url: &str,
+) -> Result<(), DatabaseError> {
+    #[cfg(feature = "sqlite")]
+    if url.starts_with("sqlite:") {
+        let pool = connection::SqlitePool::connect(url)
+            .await
+            .expect("Could not connect to database");
+        return execute(command, database, database.sqlite_migrator(), &pool).await;
+    }
+
+    let pool = PgPool::connect(url)
+        .await
+        .expect("Could not connect to database");
+    execute(command, database, database.migrator(), &pool).await
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let args = Cli::parse();
+
+    let databases = match args.command {
+        Command::Rollback { database, .. } => vec![database],
+        _ => vec![Database::Auth, Database::Core],
+    };
+    for database in databases {
+        let url = match database {
+            Database::Auth => &args.auth_database_url,
+            Database::Core => &args.core_database_url,
+        };
+        let Some(url) = url else {
+            eprintln!("no {:?} database url, skipping it", database);
+            continue;
+        };
+        if let Err(e) = connect_and_execute(&args.command, database, url).await {
+            eprintln!("{:?} database: {}", database, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -25,14 +25,11 @@ if [ $? -eq 0 ]; then
     # wait a few seconds for the services to start
     sleep 10
 
-    # run migrations in core database
-    cd ../core-database && sqlx migrate run --database-url $SCRIPTS_CORE_DATABASE_URL
-
-    # run migrations in auth database
-    cd ../auth-database && sqlx migrate run --database-url $SCRIPTS_AUTH_DATABASE_URL
+    # run migrations in core and auth databases
+    cd ../ && cargo run -p migrate -- --core-database-url $SCRIPTS_CORE_DATABASE_URL --auth-database-url $SCRIPTS_AUTH_DATABASE_URL run
 
     # run integration tests
-    cd ../ && cargo t --workspace --exclude grpc-interfaces --exclude database  --features integration
+    cargo t --workspace --exclude grpc-interfaces --exclude database  --features integration
 else
     echo "Failed to start the container."
 fi